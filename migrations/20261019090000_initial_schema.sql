@@ -0,0 +1,24 @@
+-- Schema the server has been running against so far. Later migrations build on it.
+CREATE TABLE IF NOT EXISTS users (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	username VARCHAR(255) NOT NULL,
+	email VARCHAR(255) NOT NULL,
+	password VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tasks (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	name VARCHAR(255) NOT NULL,
+	description TEXT NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TABLE IF NOT EXISTS task_history (
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	start_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	finish_time TIMESTAMP NULL DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES users (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id)
+);
@@ -0,0 +1,26 @@
+CREATE TABLE focus_sessions (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	phase VARCHAR(8) NOT NULL,
+	work_seconds INT NOT NULL,
+	break_seconds INT NOT NULL,
+	phase_started_at TIMESTAMP NOT NULL,
+	completed_pomodoros INT NOT NULL DEFAULT 0,
+	started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	ended_at TIMESTAMP NULL DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES users (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id),
+	INDEX focus_sessions_active (ended_at, phase_started_at)
+);
+
+CREATE TABLE pomodoros (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	focus_session_id INT NOT NULL,
+	completed_at TIMESTAMP NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES users (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id),
+	FOREIGN KEY (focus_session_id) REFERENCES focus_sessions (id)
+);
@@ -0,0 +1,17 @@
+ALTER TABLE tasks ADD COLUMN estimate_seconds INT NULL DEFAULT NULL;
+
+CREATE TABLE tags (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	name VARCHAR(64) NOT NULL,
+	UNIQUE KEY tags_user_name (user_id, name),
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TABLE task_tags (
+	task_id INT NOT NULL,
+	tag_id INT NOT NULL,
+	PRIMARY KEY (task_id, tag_id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id),
+	FOREIGN KEY (tag_id) REFERENCES tags (id)
+);
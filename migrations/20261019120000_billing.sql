@@ -0,0 +1,46 @@
+CREATE TABLE projects (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	name VARCHAR(255) NOT NULL,
+	client VARCHAR(255) NULL DEFAULT NULL,
+	hourly_rate INT NULL DEFAULT NULL,
+	rounding_minutes INT NULL DEFAULT NULL,
+	rounding_mode VARCHAR(8) NOT NULL DEFAULT 'up',
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+ALTER TABLE tasks
+	ADD COLUMN project_id INT NULL DEFAULT NULL,
+	ADD COLUMN billable BOOLEAN NOT NULL DEFAULT FALSE,
+	ADD COLUMN hourly_rate INT NULL DEFAULT NULL,
+	ADD FOREIGN KEY (project_id) REFERENCES projects (id);
+
+ALTER TABLE tags ADD COLUMN hourly_rate INT NULL DEFAULT NULL;
+
+CREATE TABLE invoices (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	client VARCHAR(255) NOT NULL,
+	period_start TIMESTAMP NOT NULL,
+	period_end TIMESTAMP NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TABLE invoice_lines (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	invoice_id INT NOT NULL,
+	task_id INT NOT NULL,
+	description VARCHAR(255) NOT NULL,
+	entries INT NOT NULL,
+	seconds INT NOT NULL,
+	hourly_rate INT NULL DEFAULT NULL,
+	amount INT NOT NULL,
+	FOREIGN KEY (invoice_id) REFERENCES invoices (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id)
+);
+
+-- Entries that have been invoiced are locked.
+ALTER TABLE task_history
+	ADD COLUMN invoice_id INT NULL DEFAULT NULL,
+	ADD FOREIGN KEY (invoice_id) REFERENCES invoices (id);
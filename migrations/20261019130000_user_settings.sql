@@ -0,0 +1,8 @@
+CREATE TABLE user_settings (
+	user_id INT NOT NULL PRIMARY KEY,
+	time_zone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+	locale VARCHAR(35) NOT NULL DEFAULT 'en-US',
+	week_start TINYINT NOT NULL DEFAULT 1, /* ISO day number, 1 is Monday */
+	date_format VARCHAR(16) NOT NULL DEFAULT 'YYYY-MM-DD',
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
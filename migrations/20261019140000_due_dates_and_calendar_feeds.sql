@@ -0,0 +1,11 @@
+ALTER TABLE tasks
+	ADD COLUMN due_at TIMESTAMP NULL DEFAULT NULL,
+	ADD COLUMN priority TINYINT NULL DEFAULT NULL; /* 1 is high, 2 medium, 3 low */
+
+CREATE TABLE calendar_feeds (
+	user_id INT NOT NULL PRIMARY KEY,
+	token_hash CHAR(64) NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	UNIQUE KEY calendar_feeds_token_hash (token_hash),
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
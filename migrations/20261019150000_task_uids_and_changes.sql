@@ -0,0 +1,22 @@
+-- Stable identifiers for tasks outside the server, such as CalDAV resources.
+ALTER TABLE tasks
+	ADD COLUMN uid VARCHAR(255) NULL DEFAULT NULL,
+	ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP;
+
+UPDATE tasks SET uid = CONCAT('task-', id, '@surphury');
+
+ALTER TABLE tasks
+	MODIFY uid VARCHAR(255) NOT NULL,
+	ADD UNIQUE KEY tasks_user_uid (user_id, uid);
+
+-- Every change to a task, so clients can ask for what changed since they last synced.
+CREATE TABLE task_changes (
+	id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	uid VARCHAR(255) NOT NULL,
+	deleted BOOLEAN NOT NULL DEFAULT FALSE,
+	changed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	INDEX task_changes_user (user_id, id),
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
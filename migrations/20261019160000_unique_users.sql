@@ -0,0 +1,30 @@
+-- Usernames and emails identify an account, so they can't be shared. Registering checks the
+-- format, these keys make sure two requests racing for the same name can't both succeed.
+--
+-- Before the keys existed nothing stopped two accounts from sharing a username or an email. Picking
+-- which one keeps it, or merging them, is a decision about real accounts, so instead of renaming
+-- anything this stops the migration with a clear error. Find the duplicates with
+--   SELECT username, COUNT(*) FROM users GROUP BY username HAVING COUNT(*) > 1;
+--   SELECT email, COUNT(*) FROM users GROUP BY email HAVING COUNT(*) > 1;
+-- fix them by hand and run the migrations again.
+DROP PROCEDURE IF EXISTS check_unique_users;
+
+CREATE PROCEDURE check_unique_users()
+BEGIN
+	IF EXISTS (SELECT 1 FROM users GROUP BY username HAVING COUNT(*) > 1) THEN
+		SIGNAL SQLSTATE '45000'
+			SET MESSAGE_TEXT = 'Several users share a username, rename them before adding users_username';
+	END IF;
+	IF EXISTS (SELECT 1 FROM users GROUP BY email HAVING COUNT(*) > 1) THEN
+		SIGNAL SQLSTATE '45000'
+			SET MESSAGE_TEXT = 'Several users share an email, change them before adding users_email';
+	END IF;
+END;
+
+CALL check_unique_users();
+
+DROP PROCEDURE check_unique_users;
+
+ALTER TABLE users
+	ADD UNIQUE KEY users_username (username),
+	ADD UNIQUE KEY users_email (email);
@@ -0,0 +1,3 @@
+-- Tracked segments become addressable as the sessions of a task.
+ALTER TABLE task_history
+	ADD COLUMN id INT NOT NULL AUTO_INCREMENT PRIMARY KEY FIRST;
@@ -0,0 +1,22 @@
+-- Time entries made offline need an id that clients can pick before the server knows about them.
+ALTER TABLE task_history
+	ADD COLUMN uid VARCHAR(255) NOT NULL DEFAULT (CONCAT('entry-', UUID(), '@surphury'));
+
+UPDATE task_history SET uid = CONCAT('entry-', id, '@surphury');
+
+ALTER TABLE task_history ADD UNIQUE KEY task_history_user_uid (user_id, uid);
+
+-- Changes to time entries go in the same log as the ones to tasks, so one cursor covers both.
+ALTER TABLE task_changes
+	ADD COLUMN entity ENUM('task', 'time_entry') NOT NULL DEFAULT 'task' AFTER user_id;
+
+-- The hybrid logical clock of the last write to every field, to merge what clients change offline.
+CREATE TABLE sync_clocks (
+	user_id INT NOT NULL,
+	entity ENUM('task', 'time_entry') NOT NULL,
+	uid VARCHAR(255) NOT NULL,
+	field VARCHAR(32) NOT NULL,
+	hlc VARCHAR(64) NOT NULL,
+	PRIMARY KEY (user_id, entity, uid, field),
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
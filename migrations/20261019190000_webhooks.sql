@@ -0,0 +1,31 @@
+-- Endpoints users register to get their task events. The secret signs the payloads, so it is kept
+-- as it is.
+CREATE TABLE webhooks (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	url VARCHAR(2048) NOT NULL,
+	secret VARCHAR(64) NOT NULL,
+	events VARCHAR(255) NOT NULL,
+	enabled BOOLEAN NOT NULL DEFAULT TRUE,
+	consecutive_failures INT NOT NULL DEFAULT 0,
+	disabled_at TIMESTAMP NULL DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+-- Every payload sent, or still to send, to a webhook.
+CREATE TABLE webhook_deliveries (
+	id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	webhook_id INT NOT NULL,
+	event VARCHAR(32) NOT NULL,
+	payload TEXT NOT NULL,
+	status ENUM('pending', 'delivered', 'failed') NOT NULL DEFAULT 'pending',
+	attempts INT NOT NULL DEFAULT 0,
+	next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_status_code INT NULL DEFAULT NULL,
+	last_error VARCHAR(255) NULL DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	delivered_at TIMESTAMP NULL DEFAULT NULL,
+	INDEX webhook_deliveries_due (status, next_attempt_at),
+	FOREIGN KEY (webhook_id) REFERENCES webhooks (id)
+);
@@ -0,0 +1,4 @@
+-- Deleting a task moves it to the trash; it is purged for good after the retention period
+ALTER TABLE tasks
+	ADD COLUMN deleted_at TIMESTAMP NULL DEFAULT NULL,
+	ADD INDEX tasks_deleted_at (deleted_at);
@@ -0,0 +1,8 @@
+-- Archived tasks are left out of the task lists, their time still counts in the reports
+ALTER TABLE tasks
+	ADD COLUMN archived_at TIMESTAMP NULL DEFAULT NULL,
+	ADD INDEX tasks_archived_at (archived_at);
+
+-- Finished tasks are archived after this many days; NULL turns it off
+ALTER TABLE user_settings
+	ADD COLUMN auto_archive_days INT NULL DEFAULT NULL;
@@ -0,0 +1,8 @@
+-- Lexicographic positions for the manual order of tasks, per project and in the inbox. Existing
+-- tasks keep the order of their ids: fixed width base 36 ids, with a last digit that isn't 0.
+ALTER TABLE tasks
+	ADD COLUMN position VARCHAR(64) CHARACTER SET ascii COLLATE ascii_bin NOT NULL DEFAULT '';
+
+UPDATE tasks SET position = CONCAT(LOWER(LPAD(CONV(id, 10, 36), 8, '0')), 'i');
+
+CREATE INDEX tasks_position ON tasks (user_id, project_id, position);
@@ -0,0 +1,14 @@
+-- Full-text search over tasks. The tag names of a task are copied to tag_text so a single index
+-- covers them; it is rewritten whenever the tags of the task change.
+ALTER TABLE tasks
+	ADD COLUMN tag_text VARCHAR(2048) NOT NULL DEFAULT '';
+
+UPDATE tasks SET tag_text = COALESCE((
+	SELECT GROUP_CONCAT(tags.name ORDER BY tags.name SEPARATOR ' ')
+	FROM task_tags
+	JOIN tags ON tags.id = task_tags.tag_id
+	WHERE task_tags.task_id = tasks.id
+), '');
+
+CREATE FULLTEXT INDEX tasks_search_name ON tasks (name);
+CREATE FULLTEXT INDEX tasks_search ON tasks (name, description, tag_text);
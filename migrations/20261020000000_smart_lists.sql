@@ -0,0 +1,10 @@
+-- Saved filters, shown as lists. The filter is kept as it was written and parsed when it is used.
+CREATE TABLE smart_lists (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	name VARCHAR(255) NOT NULL,
+	filter VARCHAR(1024) NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	UNIQUE KEY smart_lists_name (user_id, name),
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
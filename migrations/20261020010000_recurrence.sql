@@ -0,0 +1,3 @@
+-- How often a task comes back, as an iCalendar RRULE such as FREQ=WEEKLY;BYDAY=MO.
+ALTER TABLE tasks
+	ADD COLUMN recurrence VARCHAR(255) NULL DEFAULT NULL;
@@ -0,0 +1,41 @@
+-- Comments on tasks, in Markdown. A reply points at the comment it answers. Deleted comments keep
+-- their row without a body, so the replies under them still have a thread.
+CREATE TABLE task_comments (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	parent_id INT NULL DEFAULT NULL,
+	body TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	edited_at TIMESTAMP NULL DEFAULT NULL,
+	deleted_at TIMESTAMP NULL DEFAULT NULL,
+	KEY task_comments_task (task_id, created_at),
+	FULLTEXT KEY task_comments_search (body),
+	FOREIGN KEY (user_id) REFERENCES users (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id),
+	FOREIGN KEY (parent_id) REFERENCES task_comments (id)
+);
+
+-- What happened to each task, for its activity feed. Timers aren't logged here, the feed reads
+-- them from task_history. `changes` is a JSON array of the fields an edit changed, with the value
+-- before and after.
+CREATE TABLE task_activity (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	kind VARCHAR(32) NOT NULL,
+	changes TEXT NULL DEFAULT NULL,
+	comment_id INT NULL DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	KEY task_activity_user (user_id, created_at),
+	KEY task_activity_task (task_id, created_at),
+	FOREIGN KEY (user_id) REFERENCES users (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id)
+);
+
+-- Tasks that already exist start their feed with their creation, as far as it is known.
+INSERT INTO task_activity ( user_id, task_id, kind, created_at )
+	SELECT user_id, id, 'task_created', COALESCE((
+		SELECT MIN(changed_at) FROM task_changes WHERE task_changes.task_id = tasks.id
+	), CURRENT_TIMESTAMP)
+	FROM tasks;
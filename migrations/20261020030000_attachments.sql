@@ -0,0 +1,24 @@
+-- The files of attachments, once per content. Attachments of the same file, by any user, share
+-- it, and it is removed from storage once nothing uses it.
+CREATE TABLE attachment_blobs (
+	hash CHAR(64) NOT NULL PRIMARY KEY,
+	size BIGINT NOT NULL,
+	content_type VARCHAR(255) NOT NULL,
+	has_thumbnail BOOLEAN NOT NULL DEFAULT FALSE,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE task_attachments (
+	id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	task_id INT NOT NULL,
+	blob_hash CHAR(64) NOT NULL,
+	filename VARCHAR(255) NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	KEY task_attachments_task (task_id, created_at),
+	KEY task_attachments_user (user_id, blob_hash),
+	FOREIGN KEY (user_id) REFERENCES users (id),
+	FOREIGN KEY (task_id) REFERENCES tasks (id),
+	FOREIGN KEY (blob_hash) REFERENCES attachment_blobs (hash)
+);
@@ -0,0 +1,11 @@
+-- Task events waiting to be turned into webhook deliveries. They are written in the transaction
+-- of the change they describe, so a change can't be saved without its event, and the dispatcher
+-- removes them once their deliveries are queued.
+CREATE TABLE webhook_events (
+	id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+	user_id INT NOT NULL,
+	event VARCHAR(32) NOT NULL,
+	task_id INT NULL DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES users (id)
+);
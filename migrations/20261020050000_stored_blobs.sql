@@ -0,0 +1,6 @@
+-- A file can only be attached once it is in storage. Uploads of the same file at the same time all
+-- claim its row, and this tells them whether one of them has put it there already. The files
+-- registered before were all stored, the ones that failed were forgotten.
+ALTER TABLE attachment_blobs ADD COLUMN stored BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE attachment_blobs SET stored = TRUE;
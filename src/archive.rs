@@ -0,0 +1,30 @@
+use crate::database::auto_archive_tasks;
+use crate::events::EventHub;
+use crate::model::{Db, TaskEventKind};
+
+use actix_web::rt::{spawn, time::interval};
+use actix_web::web::Data;
+
+use std::time::Duration;
+
+/// How often finished tasks are checked against the auto-archive setting of their users.
+const ARCHIVE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// It spawns the background task that archives the finished tasks of the users who turned on
+/// auto-archiving.
+pub fn spawn_auto_archiver(db: Data<Db>, hub: Data<dyn EventHub>) {
+    spawn(async move {
+        let mut ticker = interval(ARCHIVE_EVERY);
+        loop {
+            ticker.tick().await;
+            match auto_archive_tasks(&db).await {
+                Ok(archived) => {
+                    for (user_id, task_id) in archived {
+                        hub.publish(user_id, TaskEventKind::TaskArchived, Some(task_id));
+                    }
+                }
+                Err(err) => println!("{:#?}", err),
+            }
+        }
+    });
+}
@@ -0,0 +1,189 @@
+use crate::database::take_unused_blobs;
+use crate::model::Db;
+use crate::storage::Storage;
+
+use actix_web::rt::{spawn, time::interval};
+use actix_web::web::Data;
+
+use image::{guess_format, ImageFormat, ImageOutputFormat};
+
+use std::env::var;
+use std::io::Cursor;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Largest file that can be attached when ATTACHMENT_MAX_BYTES isn't set: 10 MiB.
+const DEFAULT_MAX_FILE_SIZE: i64 = 10 * 1024 * 1024;
+
+/// Space for the attachments of a user when ATTACHMENT_QUOTA_BYTES isn't set: 100 MiB.
+const DEFAULT_QUOTA: i64 = 100 * 1024 * 1024;
+
+/// Width and height that thumbnails fit in, keeping the proportions of the image.
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// How often files that no attachment uses anymore are removed from storage.
+const SWEEP_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// Files are only removed once they have been unused for this long, so an upload of the same file
+/// that is in progress still finds it.
+pub const UNUSED_FOR: i64 = 60 * 60;
+
+/// Types that can be attached besides images and PDF, which are recognized by their content.
+const ALLOWED_TYPES: [&str; 12] = [
+    "text/plain",
+    "text/csv",
+    "text/markdown",
+    "application/json",
+    "application/zip",
+    "application/msword",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "application/vnd.oasis.opendocument.text",
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.presentation",
+];
+
+fn bytes_from_env(name: &str, default: i64) -> i64 {
+    match var(name) {
+        Ok(bytes) => bytes
+            .parse::<i64>()
+            .unwrap_or_else(|_| panic!("{} must be a number of bytes", name)),
+        Err(_) => default,
+    }
+}
+
+/// Largest file that can be attached, from ATTACHMENT_MAX_BYTES.
+pub fn max_file_size() -> i64 {
+    bytes_from_env("ATTACHMENT_MAX_BYTES", DEFAULT_MAX_FILE_SIZE)
+}
+
+/// Space each user has for attachments, from ATTACHMENT_QUOTA_BYTES.
+pub fn quota() -> i64 {
+    bytes_from_env("ATTACHMENT_QUOTA_BYTES", DEFAULT_QUOTA)
+}
+
+pub fn blob_key(hash: &str) -> String {
+    format!("blobs/{}/{}", &hash[..2], hash)
+}
+
+pub fn thumbnail_key(hash: &str) -> String {
+    format!("thumbnails/{}/{}", &hash[..2], hash)
+}
+
+/// The type a file is stored and served with, or None if it can't be attached. Images and PDF are
+/// recognized by their content; for anything else the type the client sent has to be one of the
+/// allowed ones, and can't claim to be an image.
+pub fn content_type(content: &[u8], declared: Option<&str>) -> Option<&'static str> {
+    match guess_format(content) {
+        Ok(ImageFormat::Png) => return Some("image/png"),
+        Ok(ImageFormat::Jpeg) => return Some("image/jpeg"),
+        Ok(ImageFormat::Gif) => return Some("image/gif"),
+        Ok(ImageFormat::WebP) => return Some("image/webp"),
+        _ => {}
+    }
+    if content.starts_with(b"%PDF-") {
+        return Some("application/pdf");
+    }
+
+    let declared = declared?.split(';').next()?.trim().to_ascii_lowercase();
+    ALLOWED_TYPES
+        .into_iter()
+        .find(|allowed| *allowed == declared)
+}
+
+/// A PNG preview of an image, or None if the file isn't one that can be decoded.
+pub fn thumbnail(content: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(content).ok()?;
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut thumbnail, ImageOutputFormat::Png)
+        .ok()?;
+
+    Some(thumbnail.into_inner())
+}
+
+/// The `filename` of an upload without the folders some browsers send, and not empty.
+pub fn clean_filename(filename: Option<&str>) -> String {
+    let filename = filename
+        .unwrap_or("")
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or("")
+        .trim();
+
+    if filename.is_empty() {
+        String::from("attachment")
+    } else {
+        filename.chars().take(255).collect()
+    }
+}
+
+/// What part of a file a request asks for with its Range header.
+pub enum ByteRange {
+    Whole,
+    Part(Range<u64>),
+    /// The range starts past the end of the file.
+    Unsatisfiable,
+}
+
+/// It reads a Range header for a file of `size` bytes. Only single byte ranges are served; a
+/// header with several of them, or one that can't be read, gets the whole file, as RFC 9110
+/// allows.
+pub fn byte_range(header: Option<&str>, size: u64) -> ByteRange {
+    let spec = match header.and_then(|header| header.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Whole,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Whole,
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        /* The last `end` bytes */
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            size.saturating_sub(suffix)..size
+        }
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        (Ok(start), Ok(end)) if start <= end => start..(end + 1).min(size),
+        _ => return ByteRange::Whole,
+    };
+
+    if range.start >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Part(range)
+    }
+}
+
+/// It spawns the background task that removes from storage the files no attachment uses anymore.
+pub fn spawn_blob_sweeper(db: Data<Db>, storage: Data<dyn Storage>) {
+    spawn(async move {
+        let mut ticker = interval(SWEEP_EVERY);
+        loop {
+            ticker.tick().await;
+            let blobs = match take_unused_blobs(UNUSED_FOR, &db).await {
+                Ok(blobs) => blobs,
+                Err(err) => {
+                    println!("{:#?}", err);
+                    continue;
+                }
+            };
+
+            for (hash, has_thumbnail) in blobs {
+                let mut removed = storage.delete(&blob_key(&hash)).await;
+                if has_thumbnail {
+                    removed = removed.and(storage.delete(&thumbnail_key(&hash)).await);
+                }
+                if let Err(err) = removed {
+                    println!("Could not remove the file {}: {}", hash, err);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,1488 @@
+use crate::database::{
+    add_task, current_sync_token, delete_task, finish_task_and_save_time, get_projects_by_user,
+    get_tasks_by_user, get_user_settings, is_a_hidden_task_uid, task_uids_changed_since,
+    update_task, verify_password,
+};
+
+use crate::error::ApiError;
+
+use crate::events::EventHub;
+
+use crate::ical::{parse_todo, render_todo_object, IcalError, ParsedTodo};
+
+use crate::model::{
+    Db, Login, NewTask, Project, ResponseTask, TaskEventKind, TaskStatus, TaskUpdate,
+};
+
+use crate::timezone::UserClock;
+
+use crate::utils::validate_token;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes, Data, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+
+use roxmltree::{Document, Node};
+
+use sha2::{Digest, Sha256};
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+const SYNC_TOKEN_PREFIX: &str = "http://surphury/sync/";
+
+/// Characters escaped in the path segments of the hrefs we hand out.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Properties returned for `allprop`, or when PROPFIND comes without a body.
+const ALL_PROPS: [(&str, &str); 11] = [
+    (DAV, "resourcetype"),
+    (DAV, "displayname"),
+    (DAV, "current-user-principal"),
+    (DAV, "principal-URL"),
+    (DAV, "owner"),
+    (DAV, "getetag"),
+    (DAV, "getcontenttype"),
+    (DAV, "sync-token"),
+    (CALDAV, "calendar-home-set"),
+    (CALDAV, "supported-calendar-component-set"),
+    (CALENDARSERVER, "getctag"),
+];
+
+/// It mounts the CalDAV server under `/dav`. Every user gets a calendar home with one task list
+/// for the tasks without a project, called the inbox, and one for each of their projects.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.route("/.well-known/caldav", web::route().to(well_known))
+        .route("/dav", web::route().to(dav))
+        .route("/dav/{tail:.*}", web::route().to(dav));
+}
+
+async fn well_known() -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, "/dav/"))
+        .finish()
+}
+
+enum DavPath {
+    Root,
+    Principal(i32),
+    Home(i32),
+    Collection(i32, String),
+    Object(i32, String, String),
+}
+
+impl DavPath {
+    fn parse(path: &str) -> Option<DavPath> {
+        let segments: Vec<String> = path
+            .strip_prefix("/dav")?
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+            .collect();
+
+        match segments.as_slice() {
+            [] => Some(DavPath::Root),
+            [kind, user] if kind == "principals" => Some(DavPath::Principal(user.parse().ok()?)),
+            [kind, user] if kind == "calendars" => Some(DavPath::Home(user.parse().ok()?)),
+            [kind, user, slug] if kind == "calendars" => {
+                Some(DavPath::Collection(user.parse().ok()?, slug.clone()))
+            }
+            [kind, user, slug, name] if kind == "calendars" => Some(DavPath::Object(
+                user.parse().ok()?,
+                slug.clone(),
+                name.clone(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn owner(&self) -> Option<i32> {
+        match self {
+            DavPath::Root => None,
+            DavPath::Principal(user_id)
+            | DavPath::Home(user_id)
+            | DavPath::Collection(user_id, _)
+            | DavPath::Object(user_id, _, _) => Some(*user_id),
+        }
+    }
+}
+
+struct Collection {
+    slug: String,
+    name: String,
+    project_id: Option<i32>,
+}
+
+/// Everything PROPFIND and REPORT need to describe the resources of a user.
+struct Account {
+    user_id: i32,
+    tasks: Vec<ResponseTask>,
+    collections: Vec<Collection>,
+    sync_token: i64,
+}
+
+impl Account {
+    async fn load(user_id: i32, db: &Data<Db>) -> Result<Account, sqlx::Error> {
+        let tasks = get_tasks_by_user(user_id, db).await?;
+        let projects = get_projects_by_user(user_id, db).await?;
+        let sync_token = current_sync_token(user_id, db).await?;
+
+        Ok(Account {
+            user_id,
+            tasks,
+            collections: collections(projects),
+            sync_token,
+        })
+    }
+
+    fn collection(&self, slug: &str) -> Option<&Collection> {
+        self.collections
+            .iter()
+            .find(|collection| collection.slug == slug)
+    }
+
+    fn objects<'a>(&'a self, collection: &'a Collection) -> impl Iterator<Item = &'a ResponseTask> {
+        self.tasks
+            .iter()
+            .filter(move |task| task.project_id == collection.project_id)
+    }
+}
+
+fn collections(projects: Vec<Project>) -> Vec<Collection> {
+    let mut collections = vec![Collection {
+        slug: String::from("inbox"),
+        name: String::from("Inbox"),
+        project_id: None,
+    }];
+
+    collections.extend(projects.into_iter().map(|project| Collection {
+        slug: format!("project-{}", project.id),
+        name: project.name,
+        project_id: Some(project.id),
+    }));
+
+    collections
+}
+
+enum Resource<'a> {
+    Root,
+    Principal,
+    Home,
+    Collection(&'a Collection),
+    Object(&'a ResponseTask),
+}
+
+fn principal_href(user_id: i32) -> String {
+    format!("/dav/principals/{}/", user_id)
+}
+
+fn home_href(user_id: i32) -> String {
+    format!("/dav/calendars/{}/", user_id)
+}
+
+fn collection_href(user_id: i32, collection: &Collection) -> String {
+    format!("{}{}/", home_href(user_id), collection.slug)
+}
+
+fn object_href(user_id: i32, task: &ResponseTask) -> String {
+    let slug = match task.project_id {
+        Some(project_id) => format!("project-{}", project_id),
+        None => String::from("inbox"),
+    };
+    format!(
+        "{}{}/{}.ics",
+        home_href(user_id),
+        slug,
+        utf8_percent_encode(&task.uid, SEGMENT)
+    )
+}
+
+/// Objects are named after the uid of their task.
+fn object_uid(name: &str) -> &str {
+    name.strip_suffix(".ics").unwrap_or(name)
+}
+
+fn etag(calendar_data: &str) -> String {
+    format!(
+        "\"{}\"",
+        hex::encode(Sha256::digest(calendar_data.as_bytes()))
+    )
+}
+
+fn sync_token(token: i64) -> String {
+    format!("{}{}", SYNC_TOKEN_PREFIX, token)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn element(namespace: &str, name: &str, content: &str) -> String {
+    let prefix = match namespace {
+        DAV => "d",
+        CALDAV => "c",
+        CALENDARSERVER => "cs",
+        "" => return format!("<{name} xmlns=\"\">{content}</{name}>"),
+        _ => {
+            return format!(
+                "<x:{name} xmlns:x=\"{}\">{content}</x:{name}>",
+                escape_xml(namespace)
+            )
+        }
+    };
+
+    if content.is_empty() {
+        format!("<{prefix}:{name}/>")
+    } else {
+        format!("<{prefix}:{name}>{content}</{prefix}:{name}>")
+    }
+}
+
+fn href(href: &str) -> String {
+    element(DAV, "href", &escape_xml(href))
+}
+
+/// The value of a property on a resource, or None if the resource doesn't have it.
+fn property(account: &Account, resource: &Resource, namespace: &str, name: &str) -> Option<String> {
+    let user_id = account.user_id;
+
+    match (namespace, name, resource) {
+        (DAV, "resourcetype", Resource::Principal) => Some(element(DAV, "principal", "")),
+        (DAV, "resourcetype", Resource::Root | Resource::Home) => {
+            Some(element(DAV, "collection", ""))
+        }
+        (DAV, "resourcetype", Resource::Collection(_)) => Some(format!(
+            "{}{}",
+            element(DAV, "collection", ""),
+            element(CALDAV, "calendar", "")
+        )),
+        (DAV, "resourcetype", Resource::Object(_)) => Some(String::new()),
+        (DAV, "displayname", Resource::Home) => Some(String::from("Surphury")),
+        (DAV, "displayname", Resource::Collection(collection)) => {
+            Some(escape_xml(&collection.name))
+        }
+        (DAV, "displayname", Resource::Object(task)) => Some(escape_xml(&task.name)),
+        (DAV, "current-user-principal", _) => Some(href(&principal_href(user_id))),
+        (DAV, "principal-URL", Resource::Principal) => Some(href(&principal_href(user_id))),
+        (DAV, "owner", Resource::Home | Resource::Collection(_) | Resource::Object(_)) => {
+            Some(href(&principal_href(user_id)))
+        }
+        (DAV, "getetag", Resource::Object(task)) => {
+            Some(escape_xml(&etag(&render_todo_object(task))))
+        }
+        (DAV, "getcontenttype", Resource::Object(_)) => Some(String::from(
+            "text/calendar; charset=utf-8; component=VTODO",
+        )),
+        (DAV, "sync-token", Resource::Collection(_)) => Some(sync_token(account.sync_token)),
+        (DAV, "supported-report-set", Resource::Collection(_)) => Some(
+            ["calendar-query", "calendar-multiget"]
+                .iter()
+                .map(|report| (CALDAV, *report))
+                .chain([(DAV, "sync-collection")])
+                .map(|(namespace, report)| {
+                    element(
+                        DAV,
+                        "supported-report",
+                        &element(DAV, "report", &element(namespace, report, "")),
+                    )
+                })
+                .collect(),
+        ),
+        (
+            DAV,
+            "current-user-privilege-set",
+            Resource::Home | Resource::Collection(_) | Resource::Object(_),
+        ) => Some(
+            [
+                "read",
+                "write",
+                "write-content",
+                "bind",
+                "unbind",
+                "read-current-user-privilege-set",
+            ]
+            .iter()
+            .map(|privilege| element(DAV, "privilege", &element(DAV, privilege, "")))
+            .collect(),
+        ),
+        (CALDAV, "calendar-home-set", Resource::Root | Resource::Principal) => {
+            Some(href(&home_href(user_id)))
+        }
+        (CALDAV, "supported-calendar-component-set", Resource::Collection(_)) => {
+            Some("<c:comp name=\"VTODO\"/>".to_string())
+        }
+        (CALDAV, "calendar-data", Resource::Object(task)) => {
+            Some(escape_xml(&render_todo_object(task)))
+        }
+        (CALENDARSERVER, "getctag", Resource::Collection(_)) => {
+            Some(escape_xml(&sync_token(account.sync_token)))
+        }
+        _ => None,
+    }
+}
+
+/// A `response` element with a propstat for the properties found and another one for those missing.
+fn prop_response(
+    account: &Account,
+    resource_href: &str,
+    resource: &Resource,
+    requested: &Option<Vec<(String, String)>>,
+) -> String {
+    let mut found = String::new();
+    let mut missing = String::new();
+
+    match requested {
+        Some(requested) => {
+            for (namespace, name) in requested {
+                match property(account, resource, namespace, name) {
+                    Some(value) => found.push_str(&element(namespace, name, &value)),
+                    None => missing.push_str(&element(namespace, name, "")),
+                }
+            }
+        }
+        None => {
+            for (namespace, name) in ALL_PROPS {
+                if let Some(value) = property(account, resource, namespace, name) {
+                    found.push_str(&element(namespace, name, &value));
+                }
+            }
+        }
+    }
+
+    let mut response = href(resource_href);
+    for (props, status) in [(found, "200 OK"), (missing, "404 Not Found")] {
+        if !props.is_empty() {
+            response.push_str(&element(
+                DAV,
+                "propstat",
+                &format!(
+                    "{}{}",
+                    element(DAV, "prop", &props),
+                    element(DAV, "status", &format!("HTTP/1.1 {}", status))
+                ),
+            ));
+        }
+    }
+
+    element(DAV, "response", &response)
+}
+
+fn not_found_response(resource_href: &str) -> String {
+    element(
+        DAV,
+        "response",
+        &format!(
+            "{}{}",
+            href(resource_href),
+            element(DAV, "status", "HTTP/1.1 404 Not Found")
+        ),
+    )
+}
+
+fn multistatus(responses: String) -> HttpResponse {
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"{}\" xmlns:c=\"{}\" xmlns:cs=\"{}\">{}</d:multistatus>",
+            DAV, CALDAV, CALENDARSERVER, responses
+        ))
+}
+
+/// A failed precondition, described with an `error` element as WebDAV asks for.
+fn precondition_failed(status: StatusCode, namespace: &str, name: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("application/xml; charset=utf-8")
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:error xmlns:d=\"{}\" xmlns:c=\"{}\">{}</d:error>",
+            DAV,
+            CALDAV,
+            element(namespace, name, "")
+        ))
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"Surphury\""))
+        .body("Verification failed")
+}
+
+/// Calendar clients only speak Basic authentication, so both that and the API's token are accepted.
+async fn authenticate(req: &HttpRequest, db: &Data<Db>) -> Result<Option<i32>, sqlx::Error> {
+    let authorization = req.headers().get("Authorization");
+
+    let credentials = authorization
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "));
+
+    match credentials {
+        Some(credentials) => {
+            let login = STANDARD
+                .decode(credentials.trim())
+                .ok()
+                .and_then(|credentials| String::from_utf8(credentials).ok())
+                .and_then(|credentials| {
+                    let (username, password) = credentials.split_once(':')?;
+                    Some(Login {
+                        username: username.to_string(),
+                        password: password.to_string(),
+                    })
+                });
+
+            match login {
+                Some(login) => {
+                    let users = verify_password(&login, db).await?;
+                    Ok(match users.as_slice() {
+                        [user] => Some(user.id),
+                        _ => None,
+                    })
+                }
+                None => Ok(None),
+            }
+        }
+        None => Ok(validate_token(authorization).ok()),
+    }
+}
+
+fn depth(req: &HttpRequest) -> u8 {
+    match req
+        .headers()
+        .get("Depth")
+        .and_then(|depth| depth.to_str().ok())
+    {
+        Some("0") => 0,
+        _ => 1,
+    }
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.descendants()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// The properties asked for in the `prop` element of a request, None for `allprop` or an empty body.
+fn requested_props(document: Option<&Document>) -> Option<Vec<(String, String)>> {
+    let root = document?.root_element();
+    let prop = root
+        .children()
+        .find(|child| child.is_element() && child.tag_name().name() == "prop")?;
+
+    Some(
+        prop.children()
+            .filter(|child| child.is_element())
+            .map(|child| {
+                (
+                    child.tag_name().namespace().unwrap_or("").to_string(),
+                    child.tag_name().name().to_string(),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn parse_body(body: &str) -> Result<Option<Document>, HttpResponse> {
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+
+    Document::parse(body)
+        .map(Some)
+        .map_err(|_| HttpResponse::BadRequest().body("The request body isn't valid XML"))
+}
+
+/// It checks If-Match and If-None-Match against the current ETag of an object, if it exists.
+fn preconditions_hold(req: &HttpRequest, current: Option<&str>) -> bool {
+    let matches = |value: &HeaderValue| match value.to_str() {
+        Ok(value) => value.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || Some(tag) == current
+        }),
+        Err(_) => false,
+    };
+
+    if let Some(value) = req.headers().get(header::IF_MATCH) {
+        if current.is_none() || !matches(value) {
+            return false;
+        }
+    }
+    if let Some(value) = req.headers().get(header::IF_NONE_MATCH) {
+        if current.is_some() && matches(value) {
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn dav(req: HttpRequest, body: Bytes, db: Data<Db>, hub: Data<dyn EventHub>) -> HttpResponse {
+    if req.method().as_str() == "OPTIONS" {
+        return HttpResponse::Ok()
+            .insert_header(("DAV", "1, 3, calendar-access"))
+            .insert_header((header::ALLOW, "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT"))
+            .finish();
+    }
+
+    let user_id = match authenticate(&req, &db).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return unauthorized(),
+        Err(error) => return ApiError::from(error).error_response(),
+    };
+
+    let path = match DavPath::parse(req.path()) {
+        Some(path) => path,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    if path.owner().map_or(false, |owner| owner != user_id) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let body = match String::from_utf8(body.to_vec()) {
+        Ok(body) => body,
+        Err(_) => return HttpResponse::BadRequest().body("The request body isn't valid UTF-8"),
+    };
+
+    let account = match Account::load(user_id, &db).await {
+        Ok(account) => account,
+        Err(error) => return ApiError::from(error).error_response(),
+    };
+
+    match req.method().as_str() {
+        "PROPFIND" => propfind(&req, &account, path, &body),
+        "REPORT" => report(&account, path, &body, &db).await,
+        "GET" | "HEAD" => get_object(&account, path),
+        "PUT" => put_object(&req, &account, path, &body, &db, &hub).await,
+        "DELETE" => delete_object(&req, &account, path, &db, &hub).await,
+        _ => HttpResponse::MethodNotAllowed().finish(),
+    }
+}
+
+fn propfind(req: &HttpRequest, account: &Account, path: DavPath, body: &str) -> HttpResponse {
+    let document = match parse_body(body) {
+        Ok(document) => document,
+        Err(response) => return response,
+    };
+    let requested = requested_props(document.as_ref());
+    let depth = depth(req);
+    let user_id = account.user_id;
+
+    let mut responses = String::new();
+    match path {
+        DavPath::Root => {
+            responses.push_str(&prop_response(
+                account,
+                "/dav/",
+                &Resource::Root,
+                &requested,
+            ));
+        }
+        DavPath::Principal(_) => {
+            responses.push_str(&prop_response(
+                account,
+                &principal_href(user_id),
+                &Resource::Principal,
+                &requested,
+            ));
+        }
+        DavPath::Home(_) => {
+            responses.push_str(&prop_response(
+                account,
+                &home_href(user_id),
+                &Resource::Home,
+                &requested,
+            ));
+            if depth > 0 {
+                for collection in &account.collections {
+                    responses.push_str(&prop_response(
+                        account,
+                        &collection_href(user_id, collection),
+                        &Resource::Collection(collection),
+                        &requested,
+                    ));
+                }
+            }
+        }
+        DavPath::Collection(_, slug) => {
+            let collection = match account.collection(&slug) {
+                Some(collection) => collection,
+                None => return HttpResponse::NotFound().finish(),
+            };
+            responses.push_str(&prop_response(
+                account,
+                &collection_href(user_id, collection),
+                &Resource::Collection(collection),
+                &requested,
+            ));
+            if depth > 0 {
+                for task in account.objects(collection) {
+                    responses.push_str(&prop_response(
+                        account,
+                        &object_href(user_id, task),
+                        &Resource::Object(task),
+                        &requested,
+                    ));
+                }
+            }
+        }
+        DavPath::Object(_, slug, name) => match find_object(account, &slug, &name) {
+            Some(task) => responses.push_str(&prop_response(
+                account,
+                &object_href(user_id, task),
+                &Resource::Object(task),
+                &requested,
+            )),
+            None => return HttpResponse::NotFound().finish(),
+        },
+    }
+
+    multistatus(responses)
+}
+
+fn find_object<'a>(account: &'a Account, slug: &str, name: &str) -> Option<&'a ResponseTask> {
+    let collection = account.collection(slug)?;
+    let uid = object_uid(name);
+
+    account.objects(collection).find(|task| task.uid == uid)
+}
+
+/// Whether a task passes the filter of a calendar-query. Only VTODOs are stored, and a time-range
+/// on them is checked against the due date (RFC 4791, section 9.9).
+fn matches_filter(document: &Document, task: &ResponseTask) -> bool {
+    let root = document.root_element();
+
+    for filter in children(root, "comp-filter") {
+        match filter.attribute("name") {
+            Some("VCALENDAR") | Some("VTODO") | None => {}
+            Some(_) => return false,
+        }
+
+        if filter.attribute("name") != Some("VTODO") {
+            continue;
+        }
+        let time_range = filter
+            .children()
+            .find(|child| child.is_element() && child.tag_name().name() == "time-range");
+        if let (Some(time_range), Some(due_at)) = (time_range, task.due_at) {
+            let parse = |attribute| {
+                time_range
+                    .attribute(attribute)
+                    .and_then(|value: &str| value.strip_suffix('Z'))
+                    .and_then(|value| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok())
+                    .map(|date_time| Utc.from_utc_datetime(&date_time).timestamp())
+            };
+            if parse("start").map_or(false, |start| start >= due_at)
+                || parse("end").map_or(false, |end| end < due_at)
+            {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+async fn report(account: &Account, path: DavPath, body: &str, db: &Data<Db>) -> HttpResponse {
+    let collection = match &path {
+        DavPath::Collection(_, slug) => match account.collection(slug) {
+            Some(collection) => collection,
+            None => return HttpResponse::NotFound().finish(),
+        },
+        _ => return precondition_failed(StatusCode::FORBIDDEN, DAV, "supported-report"),
+    };
+
+    let document = match parse_body(body) {
+        Ok(Some(document)) => document,
+        Ok(None) => return HttpResponse::BadRequest().body("A REPORT needs a body"),
+        Err(response) => return response,
+    };
+    let requested = requested_props(Some(&document));
+    let user_id = account.user_id;
+
+    let mut responses = String::new();
+    match document.root_element().tag_name().name() {
+        "calendar-query" => {
+            for task in account
+                .objects(collection)
+                .filter(|task| matches_filter(&document, task))
+            {
+                responses.push_str(&prop_response(
+                    account,
+                    &object_href(user_id, task),
+                    &Resource::Object(task),
+                    &requested,
+                ));
+            }
+        }
+        "calendar-multiget" => {
+            for node in children(document.root_element(), "href") {
+                let object = node.text().unwrap_or("").trim();
+                let task = match DavPath::parse(&percent_decode_str(object).decode_utf8_lossy()) {
+                    Some(DavPath::Object(owner, slug, name)) if owner == user_id => {
+                        if slug == collection.slug {
+                            find_object(account, &slug, &name)
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+
+                match task {
+                    Some(task) => responses.push_str(&prop_response(
+                        account,
+                        object,
+                        &Resource::Object(task),
+                        &requested,
+                    )),
+                    None => responses.push_str(&not_found_response(object)),
+                }
+            }
+        }
+        "sync-collection" => {
+            let token = children(document.root_element(), "sync-token")
+                .next()
+                .and_then(|node| node.text())
+                .unwrap_or("")
+                .trim();
+
+            if token.is_empty() {
+                for task in account.objects(collection) {
+                    responses.push_str(&prop_response(
+                        account,
+                        &object_href(user_id, task),
+                        &Resource::Object(task),
+                        &requested,
+                    ));
+                }
+            } else {
+                let since = match token
+                    .strip_prefix(SYNC_TOKEN_PREFIX)
+                    .and_then(|token| token.parse::<i64>().ok())
+                {
+                    Some(since) if since <= account.sync_token => since,
+                    _ => {
+                        return precondition_failed(StatusCode::FORBIDDEN, DAV, "valid-sync-token")
+                    }
+                };
+
+                let changed = match task_uids_changed_since(user_id, since, db).await {
+                    Ok(changed) => changed,
+                    Err(error) => return ApiError::from(error).error_response(),
+                };
+
+                // Tasks deleted, or moved to another list, no longer show up in this collection.
+                for uid in changed {
+                    match account.objects(collection).find(|task| task.uid == uid) {
+                        Some(task) => responses.push_str(&prop_response(
+                            account,
+                            &object_href(user_id, task),
+                            &Resource::Object(task),
+                            &requested,
+                        )),
+                        None => responses.push_str(&not_found_response(&format!(
+                            "{}{}.ics",
+                            collection_href(user_id, collection),
+                            utf8_percent_encode(&uid, SEGMENT)
+                        ))),
+                    }
+                }
+            }
+
+            responses.push_str(&element(DAV, "sync-token", &sync_token(account.sync_token)));
+        }
+        _ => return precondition_failed(StatusCode::FORBIDDEN, DAV, "supported-report"),
+    }
+
+    multistatus(responses)
+}
+
+fn get_object(account: &Account, path: DavPath) -> HttpResponse {
+    let task = match &path {
+        DavPath::Object(_, slug, name) => find_object(account, slug, name),
+        _ => return HttpResponse::MethodNotAllowed().finish(),
+    };
+
+    match task {
+        Some(task) => {
+            let calendar_data = render_todo_object(task);
+            HttpResponse::Ok()
+                .content_type("text/calendar; charset=utf-8")
+                .insert_header((header::ETAG, etag(&calendar_data)))
+                .body(calendar_data)
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn put_object(
+    req: &HttpRequest,
+    account: &Account,
+    path: DavPath,
+    body: &str,
+    db: &Data<Db>,
+    hub: &Data<dyn EventHub>,
+) -> HttpResponse {
+    let (collection, name) = match &path {
+        DavPath::Object(_, slug, name) => match account.collection(slug) {
+            Some(collection) => (collection, name),
+            None => return HttpResponse::Conflict().body("There is no task list at that path"),
+        },
+        _ => return HttpResponse::MethodNotAllowed().finish(),
+    };
+    let uid = object_uid(name);
+
+    // Uids are unique per user, so the task may be in another list. It is moved here then.
+    let existing = account.tasks.iter().find(|task| task.uid == uid);
+    let current_etag = existing.map(|task| etag(&render_todo_object(task)));
+    if !preconditions_hold(req, current_etag.as_deref()) {
+        return HttpResponse::PreconditionFailed().finish();
+    }
+
+    let clock = match get_user_settings(account.user_id, db).await {
+        Ok(settings) => UserClock::from(&settings),
+        Err(error) => return ApiError::from(error).error_response(),
+    };
+    let todo: ParsedTodo = match parse_todo(body, &clock) {
+        Ok(todo) => todo,
+        Err(IcalError::Malformed) => {
+            return precondition_failed(StatusCode::FORBIDDEN, CALDAV, "valid-calendar-data")
+        }
+        Err(IcalError::NotATodo) => {
+            return precondition_failed(
+                StatusCode::FORBIDDEN,
+                CALDAV,
+                "supported-calendar-component",
+            )
+        }
+    };
+
+    let result = match existing {
+        Some(task) => {
+            let update = TaskUpdate {
+                name: Some(todo.summary),
+                description: Some(todo.description),
+                estimate: None,
+                tags: Some(todo.categories),
+                project_id: Some(collection.project_id),
+                billable: None,
+                hourly_rate: None,
+                due_at: Some(todo.due_at),
+                priority: Some(todo.priority),
+                recurrence: Some(todo.recurrence),
+            };
+            let mut result = update_task(task.id, account.user_id, update, db)
+                .await
+                .map(|_| StatusCode::NO_CONTENT);
+            if result.is_ok() {
+                hub.publish(account.user_id, TaskEventKind::TaskUpdated, Some(task.id));
+            }
+
+            // Completing a task in a calendar app stops its timer.
+            if result.is_ok() && todo.completed && task.status == TaskStatus::InProgress {
+                result = finish_task_and_save_time(task.id, account.user_id, db)
+                    .await
+                    .map(|_| StatusCode::NO_CONTENT);
+                if result.is_ok() {
+                    hub.publish(account.user_id, TaskEventKind::TimerStopped, Some(task.id));
+                }
+            }
+            result
+        }
+        None => {
+            match is_a_hidden_task_uid(account.user_id, uid, db).await {
+                Ok(true) => {
+                    return HttpResponse::Conflict()
+                        .body("The task with that uid is in the trash or archived")
+                }
+                Ok(false) => {}
+                Err(error) => return ApiError::from(error).error_response(),
+            }
+
+            let task = NewTask {
+                uid: Some(uid.to_string()),
+                name: todo.summary,
+                description: todo.description,
+                estimate: None,
+                tags: todo.categories,
+                project_id: collection.project_id,
+                billable: false,
+                hourly_rate: None,
+                due_at: todo.due_at,
+                priority: todo.priority,
+                recurrence: todo.recurrence,
+            };
+            add_task(account.user_id, task, db).await.map(|task_id| {
+                hub.publish(account.user_id, TaskEventKind::TaskCreated, Some(task_id));
+                StatusCode::CREATED
+            })
+        }
+    };
+
+    // No ETag is sent back: the stored object isn't byte for byte what the client sent.
+    match result {
+        Ok(status) => HttpResponse::build(status).finish(),
+        Err(error) => ApiError::from(error).error_response(),
+    }
+}
+
+async fn delete_object(
+    req: &HttpRequest,
+    account: &Account,
+    path: DavPath,
+    db: &Data<Db>,
+    hub: &Data<dyn EventHub>,
+) -> HttpResponse {
+    let task = match &path {
+        DavPath::Object(_, slug, name) => match find_object(account, slug, name) {
+            Some(task) => task,
+            None => return HttpResponse::NotFound().finish(),
+        },
+        _ => return HttpResponse::Forbidden().body("Task lists can't be deleted over CalDAV"),
+    };
+
+    if !preconditions_hold(req, Some(&etag(&render_todo_object(task)))) {
+        return HttpResponse::PreconditionFailed().finish();
+    }
+
+    match delete_task(task.id, account.user_id, db).await {
+        Ok(_) => {
+            hub.publish(account.user_id, TaskEventKind::TaskDeleted, Some(task.id));
+            HttpResponse::NoContent().finish()
+        }
+        Err(error) => ApiError::from(error).error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{archive_task, connect, insert_new_user};
+    use crate::events::MemoryHub;
+    use crate::model::User;
+
+    use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+    use actix_web::http::Method;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    use dotenv::dotenv;
+
+    use rand::distributions::{Alphanumeric, DistString};
+
+    use std::sync::Arc;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    /* A user of their own for each test, so they can run at the same time against one database */
+    async fn new_user(db: &Data<Db>) -> (i32, String) {
+        let username = format!(
+            "caldav-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 12)
+        );
+        let user = User {
+            username: username.clone(),
+            email: format!("{}@example.com", username),
+            password: PASSWORD.to_string(),
+        };
+        let result = insert_new_user(user, db)
+            .await
+            .expect("the user should be added");
+        let credentials = STANDARD.encode(format!("{}:{}", username, PASSWORD));
+
+        (
+            result.last_insert_id() as i32,
+            format!("Basic {}", credentials),
+        )
+    }
+
+    fn app(
+        db: &Data<Db>,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        let hub: Data<dyn EventHub> = Data::from(Arc::new(MemoryHub::new()) as Arc<dyn EventHub>);
+
+        App::new()
+            .app_data(db.clone())
+            .app_data(hub)
+            .configure(configure)
+    }
+
+    async fn setup() -> (Data<Db>, i32, String) {
+        dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")
+            .expect("DATABASE_URL must be set as a environment variable");
+        let pool = connect(&database_url)
+            .await
+            .expect("Could not connect to database");
+        let db = Data::new(Db { pool });
+        let (user_id, authorization) = new_user(&db).await;
+
+        (db, user_id, authorization)
+    }
+
+    fn todo(uid: &str, summary: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Tests//EN\r\nBEGIN:VTODO\r\nUID:{}\r\nDTSTAMP:20261019T120000Z\r\nSUMMARY:{}\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+            uid, summary
+        )
+    }
+
+    fn dav_request(method: &str, uri: &str, authorization: &str) -> TestRequest {
+        TestRequest::default()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(uri)
+            .insert_header((header::AUTHORIZATION, authorization))
+    }
+
+    fn etag_of(response: &ServiceResponse) -> String {
+        response
+            .headers()
+            .get(header::ETAG)
+            .expect("objects should have an ETag")
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    async fn body_of(response: ServiceResponse) -> String {
+        String::from_utf8(read_body(response).await.to_vec()).unwrap()
+    }
+
+    /* The value of the first `sync-token` element of a multistatus */
+    fn sync_token_in(body: &str) -> String {
+        let document = Document::parse(body).expect("the body should be XML");
+        let token = children(document.root_element(), "sync-token")
+            .last()
+            .and_then(|node| node.text())
+            .expect("the response should carry a sync token");
+        token.to_string()
+    }
+
+    #[actix_web::test]
+    async fn rejects_requests_without_credentials() {
+        let (db, user_id, _) = setup().await;
+        let app = init_service(app(&db)).await;
+
+        let req = TestRequest::default()
+            .method(Method::from_bytes(b"PROPFIND").unwrap())
+            .uri(&home_href(user_id))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+    }
+
+    #[actix_web::test]
+    async fn forbids_the_calendars_of_other_users() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+
+        let req = dav_request("PROPFIND", &home_href(user_id + 1), &authorization).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn puts_gets_and_deletes_objects() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+        let object = format!("{}inbox/first-todo.ics", home_href(user_id));
+
+        let req = dav_request("PUT", &object, &authorization)
+            .insert_header((header::IF_NONE_MATCH, "*"))
+            .set_payload(todo("first-todo", "Write the report"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        let req = dav_request("GET", &object, &authorization).to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let first_etag = etag_of(&response);
+        let body = body_of(response).await;
+        assert!(body.contains("UID:first-todo"));
+        assert!(body.contains("SUMMARY:Write the report"));
+
+        /* The object exists now, so creating it again fails */
+        let req = dav_request("PUT", &object, &authorization)
+            .insert_header((header::IF_NONE_MATCH, "*"))
+            .set_payload(todo("first-todo", "Write another report"))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::PRECONDITION_FAILED
+        );
+
+        let req = dav_request("PUT", &object, &authorization)
+            .insert_header((header::IF_MATCH, "\"stale\""))
+            .set_payload(todo("first-todo", "Write another report"))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::PRECONDITION_FAILED
+        );
+
+        let req = dav_request("PUT", &object, &authorization)
+            .insert_header((header::IF_MATCH, first_etag.as_str()))
+            .set_payload(todo("first-todo", "Write the final report"))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+
+        let req = dav_request("GET", &object, &authorization).to_request();
+        let response = call_service(&app, req).await;
+        let second_etag = etag_of(&response);
+        assert_ne!(first_etag, second_etag);
+        assert!(body_of(response)
+            .await
+            .contains("SUMMARY:Write the final report"));
+
+        let req = dav_request("DELETE", &object, &authorization)
+            .insert_header((header::IF_MATCH, first_etag.as_str()))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::PRECONDITION_FAILED
+        );
+
+        let req = dav_request("DELETE", &object, &authorization)
+            .insert_header((header::IF_MATCH, second_etag.as_str()))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+
+        let req = dav_request("GET", &object, &authorization).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let req = dav_request("DELETE", &object, &authorization).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_objects_that_are_not_todos() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+        let object = format!("{}inbox/event.ics", home_href(user_id));
+
+        let event = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:event\r\nSUMMARY:Lunch\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        let req = dav_request("PUT", &object, &authorization)
+            .set_payload(event)
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(body_of(response)
+            .await
+            .contains("supported-calendar-component"));
+
+        let req = dav_request("PUT", &object, &authorization)
+            .set_payload("not a calendar")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn conflicts_with_trashed_and_archived_uids() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+        let inbox = format!("{}inbox/", home_href(user_id));
+
+        for uid in ["trashed-todo", "archived-todo"] {
+            let req = dav_request("PUT", &format!("{}{}.ics", inbox, uid), &authorization)
+                .set_payload(todo(uid, "Old"))
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+        }
+
+        let req = dav_request(
+            "DELETE",
+            &format!("{}trashed-todo.ics", inbox),
+            &authorization,
+        )
+        .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+
+        let tasks = get_tasks_by_user(user_id, &db).await.unwrap();
+        let archived = tasks
+            .iter()
+            .find(|task| task.uid == "archived-todo")
+            .unwrap();
+        assert!(archive_task(archived.id, user_id, &db).await.is_ok());
+
+        for uid in ["trashed-todo", "archived-todo"] {
+            let req = dav_request("PUT", &format!("{}{}.ics", inbox, uid), &authorization)
+                .set_payload(todo(uid, "New"))
+                .to_request();
+            assert_eq!(
+                call_service(&app, req).await.status(),
+                StatusCode::CONFLICT,
+                "{}",
+                uid
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn finds_collections_and_objects_with_propfind() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+        let object = format!("{}inbox/listed-todo.ics", home_href(user_id));
+
+        let req = dav_request("PUT", &object, &authorization)
+            .set_payload(todo("listed-todo", "Listed"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        let req = dav_request("PROPFIND", "/dav/", &authorization)
+            .insert_header(("Depth", "0"))
+            .set_payload(
+                r#"<d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:prop><d:current-user-principal/><c:calendar-home-set/></d:prop></d:propfind>"#,
+            )
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = body_of(response).await;
+        assert!(body.contains(&principal_href(user_id)));
+        assert!(body.contains(&home_href(user_id)));
+
+        let req = dav_request("PROPFIND", &home_href(user_id), &authorization)
+            .insert_header(("Depth", "1"))
+            .set_payload(
+                r#"<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:displayname/><d:unknown/></d:prop></d:propfind>"#,
+            )
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = body_of(response).await;
+        assert!(body.contains(&format!("{}inbox/", home_href(user_id))));
+        assert!(body.contains("<c:calendar/>"));
+        assert!(body.contains("<d:displayname>Inbox</d:displayname>"));
+        assert!(body.contains("HTTP/1.1 404 Not Found"));
+
+        let req = dav_request(
+            "PROPFIND",
+            &format!("{}inbox/", home_href(user_id)),
+            &authorization,
+        )
+        .insert_header(("Depth", "1"))
+        .set_payload(r#"<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/></d:prop></d:propfind>"#)
+        .to_request();
+        let body = body_of(call_service(&app, req).await).await;
+        assert!(body.contains(&object));
+        assert!(body.contains("<d:getetag>"));
+
+        let req = dav_request(
+            "PROPFIND",
+            &format!("{}missing/", home_href(user_id)),
+            &authorization,
+        )
+        .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn answers_calendar_queries_and_multigets() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+        let inbox = format!("{}inbox/", home_href(user_id));
+
+        let req = dav_request(
+            "PUT",
+            &format!("{}reported-todo.ics", inbox),
+            &authorization,
+        )
+        .set_payload(todo("reported-todo", "Reported"))
+        .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(
+                r#"<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:prop><d:getetag/><c:calendar-data/></d:prop><c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VTODO"/></c:comp-filter></c:filter></c:calendar-query>"#,
+            )
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = body_of(response).await;
+        assert!(body.contains("reported-todo.ics"));
+        assert!(body.contains("SUMMARY:Reported"));
+
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(
+                r#"<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:prop><d:getetag/></d:prop><c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VEVENT"/></c:comp-filter></c:filter></c:calendar-query>"#,
+            )
+            .to_request();
+        let body = body_of(call_service(&app, req).await).await;
+        assert!(!body.contains("reported-todo.ics"));
+
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(format!(
+                r#"<c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:prop><d:getetag/></d:prop><d:href>{0}reported-todo.ics</d:href><d:href>{0}missing-todo.ics</d:href></c:calendar-multiget>"#,
+                inbox
+            ))
+            .to_request();
+        let body = body_of(call_service(&app, req).await).await;
+        let document = Document::parse(&body).unwrap();
+        let responses: Vec<Node> = children(document.root_element(), "response").collect();
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0]
+            .descendants()
+            .any(|node| node.tag_name().name() == "getetag"));
+        assert_eq!(
+            children(responses[1], "status").next().unwrap().text(),
+            Some("HTTP/1.1 404 Not Found")
+        );
+
+        let req = dav_request("REPORT", &home_href(user_id), &authorization)
+            .set_payload(r#"<c:calendar-query xmlns:c="urn:ietf:params:xml:ns:caldav"/>"#)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn syncs_changes_after_a_token() {
+        let (db, user_id, authorization) = setup().await;
+        let app = init_service(app(&db)).await;
+        let inbox = format!("{}inbox/", home_href(user_id));
+        let sync = |token: &str| {
+            format!(
+                r#"<d:sync-collection xmlns:d="DAV:"><d:sync-token>{}</d:sync-token><d:sync-level>1</d:sync-level><d:prop><d:getetag/></d:prop></d:sync-collection>"#,
+                token
+            )
+        };
+
+        let req = dav_request("PUT", &format!("{}kept-todo.ics", inbox), &authorization)
+            .set_payload(todo("kept-todo", "Kept"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        /* A first sync, without a token, lists everything */
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(sync(""))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = body_of(response).await;
+        assert!(body.contains("kept-todo.ics"));
+        let first_token = sync_token_in(&body);
+        assert!(first_token.starts_with(SYNC_TOKEN_PREFIX));
+
+        let req = dav_request("PUT", &format!("{}added-todo.ics", inbox), &authorization)
+            .set_payload(todo("added-todo", "Added"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(sync(&first_token))
+            .to_request();
+        let body = body_of(call_service(&app, req).await).await;
+        assert!(body.contains("added-todo.ics"));
+        assert!(!body.contains("kept-todo.ics"));
+        let second_token = sync_token_in(&body);
+        assert_ne!(first_token, second_token);
+
+        let req = dav_request(
+            "DELETE",
+            &format!("{}added-todo.ics", inbox),
+            &authorization,
+        )
+        .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+
+        /* Deleted objects come back as a response with a 404 status */
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(sync(&second_token))
+            .to_request();
+        let body = body_of(call_service(&app, req).await).await;
+        let document = Document::parse(&body).unwrap();
+        let responses: Vec<Node> = children(document.root_element(), "response").collect();
+        assert_eq!(responses.len(), 1);
+        assert!(children(responses[0], "href")
+            .next()
+            .and_then(|node| node.text())
+            .unwrap()
+            .ends_with("added-todo.ics"));
+        assert_eq!(
+            children(responses[0], "status").next().unwrap().text(),
+            Some("HTTP/1.1 404 Not Found")
+        );
+
+        /* Nothing changed since the last token */
+        let last_token = sync_token_in(&body);
+        let req = dav_request("REPORT", &inbox, &authorization)
+            .set_payload(sync(&last_token))
+            .to_request();
+        let body = body_of(call_service(&app, req).await).await;
+        assert_eq!(
+            children(Document::parse(&body).unwrap().root_element(), "response").count(),
+            0
+        );
+
+        for token in [
+            String::from("http://example.com/other/1"),
+            format!("{}{}", SYNC_TOKEN_PREFIX, i64::MAX),
+        ] {
+            let req = dav_request("REPORT", &inbox, &authorization)
+                .set_payload(sync(&token))
+                .to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert!(body_of(response).await.contains("valid-sync-token"));
+        }
+    }
+}
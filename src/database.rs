@@ -1,14 +1,38 @@
-use crate::hashing::hash;
+use crate::hashing::{generate_secret, hash, hash_secret};
 
+use crate::invoice::{invoice_lines, invoice_total};
 use crate::model::{
-    DBUser, Db, History, Login, NewTask, ResponseTask, Task, TaskError, TaskHistory, User,
+    Activity, ArchiveFilter, Attachment, AttachmentError, BillableEntry, BulkOperation,
+    BulkOutcome, Comment, DBActivity, DBAttachment, DBChange, DBComment, DBFocusSession, DBInvoice,
+    DBSyncClock, DBTaskSession, DBTimeEntry, DBUser, DBUserSettings, DBWebhook, DBWebhookDelivery,
+    DBWebhookEvent, Db, DueDelivery, FieldChange, FocusTransition, History, Invoice, InvoiceError,
+    InvoiceLine, Login, NewComment, NewProject, NewSmartList, NewTask, NewWebhook, PomodoroStats,
+    Priority, Project, Recurrence, RejectedChange, ResponseTask, RoundingMode, SmartList,
+    SmartListUpdate, SyncEntity, TagUpdate, Task, TaskChange, TaskError, TaskEventKind,
+    TaskHistory, TaskPomodoros, TaskStatus, TaskTag, TaskUpdate, TimeEntryChange, User,
+    UserSettings, Webhook, WebhookEvent, WebhookUpdate,
 };
 
+use crate::filter::{Clause, Comparison, Condition, DueFilter, DueValue, Filter, StatusFilter};
+use crate::rank::{self, MAX_RANK_LEN};
+use crate::reports::pomodoros_by_day;
+use crate::sync::{self, rejected, task_update_fields, winning_task_update, wins, Hlc};
+use crate::timezone::UserClock;
+use crate::utils::{generate_task_uid, normalize_tags, task_status, tracked_seconds};
+use crate::validation::validate_task_update;
+
+use actix_web::cookie::time::OffsetDateTime;
+
 use actix_web::web::Data;
 
-use sqlx::mysql::{MySqlPool, MySqlQueryResult};
-use sqlx::Error;
+use sqlx::mysql::{MySql, MySqlConnection, MySqlPool, MySqlQueryResult};
+use sqlx::{Connection, Error, Executor, QueryBuilder, Transaction};
+
+use chrono::Duration;
+
+use serde_json::{json, Value};
 
+use std::collections::BTreeMap;
 use std::result::Result;
 
 /// `connect` takes a URL as a string and returns a `MySqlPool` or an `Error`
@@ -49,7 +73,8 @@ pub async fn insert_new_user(user: User, db: &Data<Db>) -> Result<MySqlQueryResu
     .await
 }
 
-/// It deletes a task from the database
+/// It moves a task to the trash, stopping its timer and focus session. Its tracked time stays until
+/// it is purged, and it can be restored until then.
 ///
 /// Arguments:
 ///
@@ -59,248 +84,5487 @@ pub async fn insert_new_user(user: User, db: &Data<Db>) -> Result<MySqlQueryResu
 ///
 /// Returns:
 ///
-/// A Result<MySqlQueryResult, Error>
-pub async fn delete_task(id: i32, user_id: i32, db: &Data<Db>) -> Result<MySqlQueryResult, Error> {
+/// A Result<MySqlQueryResult, Error>, with no rows affected if the task was trashed already.
+pub async fn delete_task(
+    id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<MySqlQueryResult, TaskError> {
+    let mut tx = db.pool.begin().await?;
+    if has_billed_time(id, &mut tx).await? {
+        return Err(TaskError::Locked);
+    }
+    let result = trash_task(id, user_id, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(result)
+}
+
+/* Clients that sync are told the task and its time entries are deleted, and that they are back
+if it is restored */
+async fn trash_task(
+    task_id: i32,
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<MySqlQueryResult, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+		UPDATE tasks
+		SET deleted_at = UTC_TIMESTAMP()
+		WHERE id = ? AND user_id = ? AND deleted_at IS NULL
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(result);
+    }
+
     sqlx::query!(
         r#"
-		DELETE FROM tasks
-			WHERE id = ? AND user_id = ?
+		UPDATE task_history
+		SET finish_time = UTC_TIMESTAMP()
+		WHERE task_id = ? AND user_id = ? AND finish_time IS NULL
 			"#,
-        id,
+        task_id,
         user_id,
     )
-    .execute(&db.pool)
-    .await
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		UPDATE focus_sessions
+		SET ended_at = UTC_TIMESTAMP()
+		WHERE task_id = ? AND user_id = ? AND ended_at IS NULL
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    record_entry_changes(task_id, user_id, true, &mut *tx).await?;
+    record_task_change(task_id, user_id, true, &mut *tx).await?;
+    record_activity(
+        task_id,
+        user_id,
+        TaskEventKind::TaskDeleted,
+        &[],
+        None,
+        &mut *tx,
+    )
+    .await?;
+
+    Ok(result)
 }
 
-/// It checks if a task id is invalid
+/// It takes a task out of the trash
 ///
 /// Arguments:
 ///
-/// * `task_id`: The id of the task to be checked
-/// * `user_id`: The user id of the user who is trying to access the task.
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
 /// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
 ///
 /// Returns:
 ///
-/// A boolean value.
-pub async fn is_an_invalid_task_id(
-    task_id: i32,
-    user_id: i32,
-    db: &Data<Db>,
-) -> Result<bool, sqlx::Error> {
-    let task = sqlx::query!(
+/// A boolean value, false if the user has no task with that id in the trash.
+pub async fn restore_task(task_id: i32, user_id: i32, db: &Data<Db>) -> Result<bool, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let restored = sqlx::query!(
         r#"
-		SELECT * 
-		FROM tasks 
-		WHERE id = ? AND user_id = ?
+		UPDATE tasks
+		SET deleted_at = NULL
+		WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL
 			"#,
         task_id,
         user_id,
     )
-    .fetch_all(&db.pool)
-    .await?;
+    .execute(&mut tx)
+    .await?
+    .rows_affected()
+        == 1;
 
-    Ok(task.len() == 0)
+    if restored {
+        record_entry_changes(task_id, user_id, false, &mut tx).await?;
+        record_task_change(task_id, user_id, false, &mut tx).await?;
+        record_activity(
+            task_id,
+            user_id,
+            TaskEventKind::TaskRestored,
+            &[],
+            None,
+            &mut tx,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(restored)
 }
 
-/// It starts a task and saves the time
+/// It deletes a task in the trash for good, with its tracked time
 ///
 /// Arguments:
 ///
-/// * `user_id`: The user id of the user who is starting the task.
-/// * `task_id`: The id of the task to start
-/// * `db`: &Data<Db> - this is the database connection pool that we created in the main.rs file.
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
 ///
 /// Returns:
 ///
-/// The number of rows affected by the query.
-pub async fn start_task_and_save_time(
-    task_id: i32,
-    user_id: i32,
-    db: &Data<Db>,
-) -> Result<bool, TaskError> {
-    if is_an_invalid_task_id(task_id, user_id, db).await? {
-        return Err(TaskError::InvalidId);
+/// A boolean value, false if the user has no task with that id in the trash.
+pub async fn purge_task(task_id: i32, user_id: i32, db: &Data<Db>) -> Result<bool, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let trashed = sqlx::query!(
+        r#"
+		SELECT id FROM tasks WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL FOR UPDATE"#,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    if trashed.is_none() {
+        return Ok(false);
     }
+    remove_task(task_id, user_id, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// It deletes every task in the trash of a user for good
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The ids of the tasks that were purged.
+pub async fn empty_trash(user_id: i32, db: &Data<Db>) -> Result<Vec<i32>, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
 
-    let task_history = sqlx::query!(
+    let task_ids: Vec<i32> = sqlx::query!(
         r#"
-			SELECT start_time, finish_time, task_id
-			FROM task_history
-			WHERE user_id = ? AND task_id = ?
-			"#,
+		SELECT id FROM tasks WHERE user_id = ? AND deleted_at IS NOT NULL FOR UPDATE"#,
         user_id,
-        task_id,
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|task| task.id)
+    .collect();
+
+    for task_id in &task_ids {
+        remove_task(*task_id, user_id, &mut tx).await?;
+    }
+    tx.commit().await?;
+
+    Ok(task_ids)
+}
+
+/// It deletes for good the tasks that have been in the trash for longer than the retention period,
+/// each in its own transaction so a large trash doesn't hold locks for long
+///
+/// Arguments:
+///
+/// * `retention_days`: How many days tasks are kept in the trash.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// How many tasks were purged.
+pub async fn purge_expired_tasks(retention_days: i64, db: &Data<Db>) -> Result<u64, sqlx::Error> {
+    let expired = sqlx::query!(
+        r#"
+		SELECT id, user_id
+		FROM tasks
+		WHERE deleted_at < UTC_TIMESTAMP() - INTERVAL ? DAY"#,
+        retention_days,
     )
     .fetch_all(&db.pool)
     .await?;
 
-    if task_history.len() == 0 || task_history[task_history.len() - 1].finish_time.is_some() {
-        let has_started_task = sqlx::query!(
-            r#"
-			INSERT INTO task_history ( user_id, task_id, start_time )
-		VALUES ( ?, ?, NOW() )
-			"#,
-            user_id,
-            task_id,
-        )
-        .execute(&db.pool)
-        .await?
-        .rows_affected()
-            == 1;
+    let mut purged = 0;
+    for task in expired {
+        let mut tx = db.pool.begin().await?;
+        remove_task(task.id, task.user_id, &mut tx).await?;
+        tx.commit().await?;
+        purged += 1;
+    }
 
-        Ok(has_started_task)
-    } else {
-        Err(TaskError::IsPending)
+    Ok(purged)
+}
+
+/// It archives a task, so it is left out of the task lists. Its tracked time still counts in the
+/// reports.
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A boolean value, false if the task was archived already.
+pub async fn archive_task(task_id: i32, user_id: i32, db: &Data<Db>) -> Result<bool, TaskError> {
+    if is_an_invalid_task_id(task_id, user_id, db).await? {
+        return Err(TaskError::InvalidId);
+    }
+
+    let mut tx = db.pool.begin().await?;
+    let archived = archive_unless_running(task_id, user_id, &mut tx).await?;
+    if !archived && is_running(task_id, user_id, &mut tx).await? {
+        return Err(TaskError::IsPending);
     }
+    tx.commit().await?;
+
+    Ok(archived)
 }
 
-/// It updates the status of a task to 3 (finished) and sets the finish time to the current time
+/// It takes a task out of the archive
 ///
 /// Arguments:
 ///
+/// * `task_id`: The id of the task.
 /// * `user_id`: The user id of the user who owns the task.
-/// * `task_id`: The id of the task to be finished.
 /// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
 ///
 /// Returns:
 ///
-/// A boolean value.
-pub async fn finish_task_and_save_time(
-    task_id: i32,
-    user_id: i32,
-    db: &Data<Db>,
-) -> Result<bool, TaskError> {
+/// A boolean value, false if the task wasn't archived.
+pub async fn unarchive_task(task_id: i32, user_id: i32, db: &Data<Db>) -> Result<bool, TaskError> {
     if is_an_invalid_task_id(task_id, user_id, db).await? {
         return Err(TaskError::InvalidId);
     }
-    let is_task_finished = sqlx::query!(
+
+    let mut tx = db.pool.begin().await?;
+    let unarchived = sqlx::query!(
         r#"
-		UPDATE task_history
-		SET finish_time = NOW()
-		WHERE task_id = ? AND user_id = ? AND start_time IS NOT NULL AND finish_time IS NULL
+		UPDATE tasks
+		SET archived_at = NULL
+		WHERE id = ? AND user_id = ? AND archived_at IS NOT NULL
 			"#,
         task_id,
         user_id,
     )
-    .execute(&db.pool)
+    .execute(&mut tx)
     .await?
     .rows_affected()
         == 1;
 
-    Ok(is_task_finished)
+    if unarchived {
+        record_task_change(task_id, user_id, false, &mut tx).await?;
+        record_activity(
+            task_id,
+            user_id,
+            TaskEventKind::TaskUnarchived,
+            &[],
+            None,
+            &mut tx,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(unarchived)
 }
 
-/// It takes a user_id, a NewTask struct, and a database connection, and returns a Result containing
-/// either a MySqlQueryResult or a sqlx::Error
+/// It archives the finished tasks of a user whose last session ended before a date
 ///
 /// Arguments:
 ///
-/// * `user_id`: The user id of the user who created the task.
-/// * `task`: NewTask - This is the struct that we defined earlier.
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `finished_before`: OffsetDateTime - Tasks that were worked on after this date are kept.
 /// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
 ///
 /// Returns:
 ///
-/// A Result<MySqlQueryResult, sqlx::Error>
-pub async fn add_task(
+/// The ids of the tasks that were archived.
+pub async fn archive_finished_tasks(
     user_id: i32,
-    task: NewTask,
+    finished_before: OffsetDateTime,
     db: &Data<Db>,
-) -> Result<MySqlQueryResult, sqlx::Error> {
-    sqlx::query!(
+) -> Result<Vec<i32>, sqlx::Error> {
+    let finished = sqlx::query!(
         r#"
-		INSERT INTO tasks ( user_id, name, description )
-			VALUES ( ?, ?, ? )
-			"#,
+		SELECT tasks.id
+		FROM tasks
+		JOIN task_history ON task_history.task_id = tasks.id
+		WHERE tasks.user_id = ? AND tasks.archived_at IS NULL AND tasks.deleted_at IS NULL
+		GROUP BY tasks.id
+		HAVING COUNT(*) = COUNT(task_history.finish_time) AND MAX(task_history.finish_time) < ?
+		ORDER BY tasks.id"#,
         user_id,
-        task.name,
-        task.description
+        finished_before,
     )
-    .execute(&db.pool)
-    .await
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut tx = db.pool.begin().await?;
+    let mut archived = Vec::new();
+    for task in finished {
+        if archive_unless_running(task.id, user_id, &mut tx).await? {
+            archived.push(task.id);
+        }
+    }
+    tx.commit().await?;
+
+    Ok(archived)
 }
 
-/// It's getting all the tasks for a user, and then getting all the history for those tasks, and then
-/// returning a list of tasks with their history
+/// It archives the finished tasks of the users who turned on auto-archiving, once the days they
+/// chose have passed since the tasks were last worked on
 ///
 /// Arguments:
 ///
-/// * `user_id`: i32 - The user id that we're going to use to get the tasks.
-/// * `db`: &Data<Db> - This is the database connection pool.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
 ///
 /// Returns:
 ///
-/// A vector of ResponseTask structs.
-pub async fn get_tasks_by_user(
-    user_id: i32,
-    db: &Data<Db>,
-) -> Result<Vec<ResponseTask>, sqlx::Error> {
-    let tasks = sqlx::query_as!(
-        Task,
+/// The user id and task id of the tasks that were archived.
+pub async fn auto_archive_tasks(db: &Data<Db>) -> Result<Vec<(i32, i32)>, sqlx::Error> {
+    let finished = sqlx::query!(
         r#"
-		SELECT id, name, description FROM tasks WHERE user_id = ?"#,
-        user_id,
+		SELECT tasks.id, tasks.user_id
+		FROM tasks
+		JOIN user_settings ON user_settings.user_id = tasks.user_id
+		JOIN task_history ON task_history.task_id = tasks.id
+		WHERE user_settings.auto_archive_days IS NOT NULL
+			AND tasks.archived_at IS NULL AND tasks.deleted_at IS NULL
+		GROUP BY tasks.id, tasks.user_id, user_settings.auto_archive_days
+		HAVING COUNT(*) = COUNT(task_history.finish_time)
+			AND MAX(task_history.finish_time) < UTC_TIMESTAMP() - INTERVAL user_settings.auto_archive_days DAY"#,
     )
     .fetch_all(&db.pool)
     .await?;
 
-    let tasks_history = sqlx::query_as!(
-        History,
+    let mut archived = Vec::new();
+    for task in finished {
+        let mut tx = db.pool.begin().await?;
+        if archive_unless_running(task.id, task.user_id, &mut tx).await? {
+            archived.push((task.user_id, task.id));
+        }
+        tx.commit().await?;
+    }
+
+    Ok(archived)
+}
+
+/* A task that was started again since it was picked stays where it is */
+async fn archive_unless_running(
+    task_id: i32,
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<bool, sqlx::Error> {
+    let archived = sqlx::query!(
         r#"
-		SELECT task_id, start_time, finish_time FROM task_history WHERE user_id = ?"#,
+		UPDATE tasks
+		SET archived_at = UTC_TIMESTAMP()
+		WHERE id = ? AND user_id = ? AND archived_at IS NULL AND deleted_at IS NULL
+			AND NOT EXISTS (
+				SELECT 1 FROM task_history
+				WHERE task_history.task_id = tasks.id AND task_history.finish_time IS NULL
+			)
+			"#,
+        task_id,
         user_id,
     )
-    .fetch_all(&db.pool)
-    .await?;
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        == 1;
 
-    let tasks: Vec<ResponseTask> = tasks
-        .iter()
-        .map(|task| {
-            // It's creating a new ResponseTask struct and returning it.
-            ResponseTask {
-                id: task.id,
-                name: task.name.clone(),
-                description: task.description.clone(),
-                history: tasks_history
-                    .iter()
-                    .filter(|history| history.task_id == task.id)
-                    .map(|history| TaskHistory {
-                        start_time: history.start_time.unix_timestamp(),
-                        finish_time: match history.finish_time {
-                            Some(finish_time) => Some(finish_time.unix_timestamp()),
-                            None => None,
-                        },
-                    })
-                    .collect(),
-            }
-        })
-        .collect();
+    if archived {
+        record_task_change(task_id, user_id, false, &mut *tx).await?;
+        record_activity(
+            task_id,
+            user_id,
+            TaskEventKind::TaskArchived,
+            &[],
+            None,
+            &mut *tx,
+        )
+        .await?;
+    }
 
-    Ok(tasks)
+    Ok(archived)
 }
 
-/// It takes a `Login` struct and a `Data<Db>` struct, and returns a `Result<Vec<DBUser>, sqlx::Error>`
+async fn is_running(
+    task_id: i32,
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<bool, sqlx::Error> {
+    let running = sqlx::query!(
+        r#"
+		SELECT id FROM task_history
+		WHERE task_id = ? AND user_id = ? AND finish_time IS NULL
+		LIMIT 1"#,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(running.is_some())
+}
+
+/// It applies the operations of a bulk request in one transaction. Each operation runs in a
+/// savepoint, so one that fails leaves nothing half done. When `best_effort` is false the first
+/// failure rolls everything back and the operations after it aren't tried.
 ///
 /// Arguments:
 ///
-/// * `user`: &Login - This is the struct that we created earlier.
-/// * `db`: &Data<Db>
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `operations`: The operations, applied in order.
+/// * `best_effort`: Whether the operations that can be applied are kept when others fail.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
 ///
 /// Returns:
 ///
-/// A vector of DBUser structs
-pub async fn verify_password(user: &Login, db: &Data<Db>) -> Result<Vec<DBUser>, sqlx::Error> {
-    let users = sqlx::query_as!(
-        DBUser,
-        r#"
-		SELECT id, username FROM users WHERE username = ? AND password = ?"#,
-        user.username,
-        hash(&user.password)
-    )
-    .fetch_all(&db.pool)
-    .await?;
+/// Whether the transaction was committed, and what happened to each operation, in order.
+pub async fn apply_bulk_operations(
+    user_id: i32,
+    operations: Vec<BulkOperation>,
+    best_effort: bool,
+    db: &Data<Db>,
+) -> Result<(bool, Vec<BulkOutcome>), sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+    let mut outcomes = Vec::with_capacity(operations.len());
+    let mut failed = false;
 
-    Ok(users)
+    for operation in operations {
+        if failed && !best_effort {
+            outcomes.push(BulkOutcome::Skipped);
+            continue;
+        }
+
+        let mut savepoint = tx.begin().await?;
+        match apply_bulk_operation(user_id, operation, &mut savepoint).await {
+            Ok(event) => {
+                savepoint.commit().await?;
+                outcomes.push(BulkOutcome::Applied(event));
+            }
+            /* Only the errors of the operation itself are reported one by one */
+            Err(TaskError::DbError(error)) => return Err(error),
+            Err(error) => {
+                savepoint.rollback().await?;
+                failed = true;
+                outcomes.push(BulkOutcome::Failed(error));
+            }
+        }
+    }
+
+    let committed = best_effort || !failed;
+    if committed {
+        tx.commit().await?;
+    }
+
+    Ok((committed, outcomes))
+}
+
+/* It returns the event to publish once the transaction is committed, if the task changed */
+async fn apply_bulk_operation(
+    user_id: i32,
+    operation: BulkOperation,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Option<TaskEventKind>, TaskError> {
+    let task_id = operation.task_id();
+    let task = sqlx::query!(
+        r#"
+		SELECT id FROM tasks WHERE id = ? AND user_id = ? AND deleted_at IS NULL FOR UPDATE"#,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if task.is_none() {
+        return Err(TaskError::InvalidId);
+    }
+
+    match operation {
+        BulkOperation::Update { changes, .. } => {
+            apply_task_update(task_id, user_id, changes, &sync::now(), tx).await?;
+            Ok(Some(TaskEventKind::TaskUpdated))
+        }
+        BulkOperation::Move { project_id, .. } => {
+            let update = TaskUpdate {
+                project_id: Some(project_id),
+                ..TaskUpdate::default()
+            };
+            apply_task_update(task_id, user_id, update, &sync::now(), tx).await?;
+            Ok(Some(TaskEventKind::TaskUpdated))
+        }
+        BulkOperation::AddTags { tags, .. } => {
+            let mut current = get_task_tag_names(task_id, user_id, tx).await?;
+            current.extend(tags);
+            let update = TaskUpdate {
+                tags: Some(current),
+                ..TaskUpdate::default()
+            };
+            apply_task_update(task_id, user_id, update, &sync::now(), tx).await?;
+            Ok(Some(TaskEventKind::TaskUpdated))
+        }
+        BulkOperation::RemoveTags { tags, .. } => {
+            let removed = normalize_tags(&tags);
+            let mut current = get_task_tag_names(task_id, user_id, tx).await?;
+            current.retain(|tag| !removed.contains(tag));
+            let update = TaskUpdate {
+                tags: Some(current),
+                ..TaskUpdate::default()
+            };
+            apply_task_update(task_id, user_id, update, &sync::now(), tx).await?;
+            Ok(Some(TaskEventKind::TaskUpdated))
+        }
+        BulkOperation::Archive { .. } => {
+            if archive_unless_running(task_id, user_id, tx).await? {
+                Ok(Some(TaskEventKind::TaskArchived))
+            } else if is_running(task_id, user_id, tx).await? {
+                Err(TaskError::IsPending)
+            } else {
+                Ok(None)
+            }
+        }
+        BulkOperation::Delete { .. } => {
+            if has_billed_time(task_id, &mut *tx).await? {
+                return Err(TaskError::Locked);
+            }
+            trash_task(task_id, user_id, tx).await?;
+            Ok(Some(TaskEventKind::TaskDeleted))
+        }
+        BulkOperation::Start { .. } => {
+            /* A timer running already fails the operation with TaskError::IsPending */
+            start_timer(task_id, user_id, None, tx).await?;
+            Ok(Some(TaskEventKind::TimerStarted))
+        }
+        BulkOperation::Finish { .. } => {
+            if finish_timer(task_id, user_id, None, tx).await? {
+                Ok(Some(TaskEventKind::TimerStopped))
+            } else {
+                Err(TaskError::NotRunning)
+            }
+        }
+    }
+}
+
+async fn get_task_tag_names(
+    task_id: i32,
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let tags = sqlx::query!(
+        r#"
+		SELECT tags.name
+		FROM task_tags
+		JOIN tags ON tags.id = task_tags.tag_id
+		WHERE task_tags.task_id = ? AND tags.user_id = ?
+		ORDER BY tags.name"#,
+        task_id,
+        user_id,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok(tags.into_iter().map(|tag| tag.name).collect())
+}
+
+/* It deletes a task with everything that hangs from it: tags, time entries, focus sessions and
+their pomodoros, and the clocks of the clients that sync. The deletion is logged for them too. */
+async fn remove_task(
+    task_id: i32,
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<MySqlQueryResult, sqlx::Error> {
+    record_entry_changes(task_id, user_id, true, &mut *tx).await?;
+    record_task_change(task_id, user_id, true, &mut *tx).await?;
+
+    sqlx::query!(
+        r#"
+		DELETE sync_clocks
+		FROM sync_clocks
+		JOIN task_history ON task_history.user_id = sync_clocks.user_id AND task_history.uid = sync_clocks.uid
+		WHERE sync_clocks.entity = 'time_entry' AND task_history.task_id = ? AND task_history.user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE sync_clocks
+		FROM sync_clocks
+		JOIN tasks ON tasks.user_id = sync_clocks.user_id AND tasks.uid = sync_clocks.uid
+		WHERE sync_clocks.entity = 'task' AND tasks.id = ? AND tasks.user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM task_history
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM pomodoros
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    /* Replies point at other comments of the task, which may go first */
+    sqlx::query!(
+        r#"
+		UPDATE task_comments
+		SET parent_id = NULL
+		WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM task_comments
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM task_activity
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    /* Their files go once they have been unused for a while, see take_unused_blobs */
+    sqlx::query!(
+        r#"
+		DELETE FROM task_attachments
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM focus_sessions
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE task_tags
+		FROM task_tags
+		JOIN tasks ON tasks.id = task_tags.task_id
+		WHERE tasks.id = ? AND tasks.user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		DELETE FROM tasks
+			WHERE id = ? AND user_id = ?
+			"#,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await
+}
+
+/// It checks if any of the time tracked on a task has been invoiced already. In a transaction the
+/// time entries of the task stay locked until it ends, so they can't be invoiced meanwhile.
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task to be checked
+/// * `executor`: The pool, or a transaction the check has to be part of.
+///
+/// Returns:
+///
+/// A boolean value.
+pub async fn has_billed_time<'e, E>(task_id: i32, executor: E) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    let billed = sqlx::query!(
+        r#"
+		SELECT task_id
+		FROM task_history
+		WHERE task_id = ? AND invoice_id IS NOT NULL
+		LIMIT 1
+		FOR UPDATE
+			"#,
+        task_id,
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(billed.is_some())
+}
+
+/// It checks if a project id is invalid
+///
+/// Arguments:
+///
+/// * `project_id`: The id of the project to be checked
+/// * `user_id`: The user id of the user who is trying to use the project.
+/// * `executor`: The pool, or a transaction the check has to be part of.
+///
+/// Returns:
+///
+/// A boolean value.
+pub async fn is_an_invalid_project_id<'e, E>(
+    project_id: i32,
+    user_id: i32,
+    executor: E,
+) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    let project = sqlx::query!(
+        r#"
+		SELECT id
+		FROM projects
+		WHERE id = ? AND user_id = ?
+			"#,
+        project_id,
+        user_id,
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(project.is_none())
+}
+
+/// It checks if a task id is invalid
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task to be checked
+/// * `user_id`: The user id of the user who is trying to access the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A boolean value.
+pub async fn is_an_invalid_task_id(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let task = sqlx::query!(
+        r#"
+		SELECT * 
+		FROM tasks 
+		WHERE id = ? AND user_id = ? AND deleted_at IS NULL
+			"#,
+        task_id,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(task.len() == 0)
+}
+
+/// It starts a task and saves the time
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who is starting the task.
+/// * `task_id`: The id of the task to start
+/// * `db`: &Data<Db> - this is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The number of rows affected by the query.
+pub async fn start_task_and_save_time(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, TaskError> {
+    if is_an_invalid_task_id(task_id, user_id, db).await? {
+        return Err(TaskError::InvalidId);
+    }
+
+    let mut tx = db.pool.begin().await?;
+    let has_started_task = start_timer(task_id, user_id, None, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(has_started_task)
+}
+
+/* Whether the task has a session running. The task stays locked until the transaction ends, so
+no other session can be opened meanwhile, even when none is running now */
+async fn has_running_entry(
+    task_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		SELECT id FROM tasks WHERE id = ? FOR UPDATE"#,
+        task_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let running = sqlx::query!(
+        r#"
+		SELECT id FROM task_history
+		WHERE task_id = ? AND finish_time IS NULL
+		LIMIT 1
+		FOR UPDATE"#,
+        task_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(running.is_some())
+}
+
+/* It opens a new session of the task at `at`, or now, unless it has one running already */
+async fn start_timer(
+    task_id: i32,
+    user_id: i32,
+    at: Option<OffsetDateTime>,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<bool, TaskError> {
+    if !has_running_entry(task_id, tx).await? {
+        let result = sqlx::query!(
+            r#"
+			INSERT INTO task_history ( user_id, task_id, start_time )
+		VALUES ( ?, ?, COALESCE(?, UTC_TIMESTAMP()) )
+			"#,
+            user_id,
+            task_id,
+            at,
+        )
+        .execute(&mut *tx)
+        .await?;
+        let has_started_task = result.rows_affected() == 1;
+
+        if has_started_task {
+            /* Working on an archived task brings it back to the lists */
+            sqlx::query!(
+                r#"
+			UPDATE tasks SET archived_at = NULL WHERE id = ? AND user_id = ?
+			"#,
+                task_id,
+                user_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+            record_entry_change(result.last_insert_id() as i32, user_id, false, &mut *tx).await?;
+            record_task_change(task_id, user_id, false, &mut *tx).await?;
+            record_webhook_event(task_id, user_id, TaskEventKind::TimerStarted, tx).await?;
+        }
+
+        Ok(has_started_task)
+    } else {
+        Err(TaskError::IsPending)
+    }
+}
+
+/// It updates the status of a task to 3 (finished) and sets the finish time to the current time
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the task.
+/// * `task_id`: The id of the task to be finished.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A boolean value.
+pub async fn finish_task_and_save_time(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, TaskError> {
+    if is_an_invalid_task_id(task_id, user_id, db).await? {
+        return Err(TaskError::InvalidId);
+    }
+
+    let mut tx = db.pool.begin().await?;
+    let is_task_finished = finish_timer(task_id, user_id, None, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(is_task_finished)
+}
+
+/* It closes the running session of the task at `at`, or now, if it has one */
+async fn finish_timer(
+    task_id: i32,
+    user_id: i32,
+    at: Option<OffsetDateTime>,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<bool, sqlx::Error> {
+    let running = sqlx::query!(
+        r#"
+		SELECT id, uid
+		FROM task_history
+		WHERE task_id = ? AND user_id = ? AND start_time IS NOT NULL AND finish_time IS NULL
+		FOR UPDATE
+			"#,
+        task_id,
+        user_id,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let is_task_finished = sqlx::query!(
+        r#"
+		UPDATE task_history
+		SET finish_time = COALESCE(?, UTC_TIMESTAMP())
+		WHERE task_id = ? AND user_id = ? AND start_time IS NOT NULL AND finish_time IS NULL
+			"#,
+        at,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        >= 1;
+
+    if is_task_finished {
+        let hlc = sync::now();
+        for entry in running {
+            stamp_clocks(
+                user_id,
+                SyncEntity::TimeEntry,
+                &entry.uid,
+                &["finish_time"],
+                &hlc,
+                &mut *tx,
+            )
+            .await?;
+            record_entry_change(entry.id, user_id, false, &mut *tx).await?;
+        }
+        record_task_change(task_id, user_id, false, &mut *tx).await?;
+        record_webhook_event(task_id, user_id, TaskEventKind::TimerStopped, tx).await?;
+    }
+
+    Ok(is_task_finished)
+}
+
+/// It gets every tracked session of a task, oldest first
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DBTaskSession structs, or TaskError::InvalidId if the user has no task with that id.
+pub async fn get_task_sessions(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<DBTaskSession>, TaskError> {
+    if is_an_invalid_task_id(task_id, user_id, db).await? {
+        return Err(TaskError::InvalidId);
+    }
+
+    let sessions = sqlx::query_as!(
+        DBTaskSession,
+        r#"
+		SELECT id, task_id, start_time, finish_time
+		FROM task_history
+		WHERE task_id = ? AND user_id = ?
+		ORDER BY start_time, id"#,
+        task_id,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// It gets one tracked session of a task
+///
+/// Arguments:
+///
+/// * `session_id`: The id of the session.
+/// * `task_id`: The id of the task the session belongs to.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// An Option<DBTaskSession>
+pub async fn get_task_session(
+    session_id: i32,
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<DBTaskSession>, sqlx::Error> {
+    sqlx::query_as!(
+        DBTaskSession,
+        r#"
+		SELECT task_history.id, task_history.task_id, task_history.start_time, task_history.finish_time
+		FROM task_history
+		JOIN tasks ON tasks.id = task_history.task_id
+		WHERE task_history.id = ? AND task_history.task_id = ? AND task_history.user_id = ?
+			AND tasks.deleted_at IS NULL"#,
+        session_id,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/// It gets the session of a task whose timer is running, if there is one
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// An Option<DBTaskSession>
+pub async fn get_running_task_session(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<DBTaskSession>, sqlx::Error> {
+    sqlx::query_as!(
+        DBTaskSession,
+        r#"
+		SELECT id, task_id, start_time, finish_time
+		FROM task_history
+		WHERE task_id = ? AND user_id = ? AND finish_time IS NULL
+		ORDER BY start_time DESC, id DESC
+		LIMIT 1"#,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/// It gets the tracked sessions of several tasks in one query
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `task_ids`: The ids of the tasks. The ones that belong to someone else are left out.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DBTaskSession structs, in the order they were started.
+pub async fn get_sessions_by_task_ids(
+    user_id: i32,
+    task_ids: &[i32],
+    db: &Data<Db>,
+) -> Result<Vec<DBTaskSession>, sqlx::Error> {
+    if task_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let task_ids = task_ids
+        .iter()
+        .map(i32::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    sqlx::query_as!(
+        DBTaskSession,
+        r#"
+		SELECT id, task_id, start_time, finish_time
+		FROM task_history
+		WHERE user_id = ? AND FIND_IN_SET(task_id, ?)
+		ORDER BY start_time, id"#,
+        user_id,
+        task_ids,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It inserts a task with its tags as part of a transaction, so several can be added at once
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who created the task.
+/// * `task`: NewTask - The task to insert.
+/// * `tx`: The transaction the task is inserted in.
+///
+/// Returns:
+///
+/// The id of the new task.
+async fn insert_task(
+    user_id: i32,
+    task: NewTask,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<i32, TaskError> {
+    if let Some(project_id) = task.project_id {
+        if is_an_invalid_project_id(project_id, user_id, &mut *tx).await? {
+            return Err(TaskError::InvalidProjectId);
+        }
+    }
+
+    let position = end_of_list(user_id, task.project_id, tx).await?;
+
+    let task_id = sqlx::query!(
+        r#"
+		INSERT INTO tasks ( user_id, uid, name, description, estimate_seconds, project_id, billable, hourly_rate, due_at, priority, recurrence, position )
+			VALUES ( ?, ?, ?, ?, ?, ?, ?, ?, FROM_UNIXTIME(?), ?, ?, ? )
+			"#,
+        user_id,
+        task.uid.unwrap_or_else(generate_task_uid),
+        task.name,
+        task.description,
+        task.estimate,
+        task.project_id,
+        task.billable,
+        task.hourly_rate,
+        task.due_at,
+        task.priority.map(|priority| priority.to_db()),
+        task.recurrence.as_ref().map(Recurrence::to_rrule),
+        position,
+    )
+    .execute(&mut *tx)
+    .await?
+    .last_insert_id() as i32;
+
+    if !task.tags.is_empty() {
+        replace_task_tags(task_id, user_id, &task.tags, tx).await?;
+    }
+    record_task_change(task_id, user_id, false, &mut *tx).await?;
+    record_activity(
+        task_id,
+        user_id,
+        TaskEventKind::TaskCreated,
+        &[],
+        None,
+        &mut *tx,
+    )
+    .await?;
+
+    Ok(task_id)
+}
+
+/// It takes a user_id, a NewTask struct, and a database connection, and adds the task with its
+/// tags
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who created the task.
+/// * `task`: NewTask - This is the struct that we defined earlier.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The id of the new task.
+pub async fn add_task(user_id: i32, task: NewTask, db: &Data<Db>) -> Result<i32, TaskError> {
+    let mut tx = db.pool.begin().await?;
+
+    let task_id = insert_task(user_id, task, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(task_id)
+}
+
+/// It adds several tasks in one transaction: either all of them are added or none is
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who created the tasks.
+/// * `tasks`: The tasks to add.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The ids of the new tasks, in the same order.
+pub async fn add_tasks(
+    user_id: i32,
+    tasks: Vec<NewTask>,
+    db: &Data<Db>,
+) -> Result<Vec<i32>, TaskError> {
+    let mut tx = db.pool.begin().await?;
+    let mut task_ids = Vec::with_capacity(tasks.len());
+
+    /* Returning early drops the transaction, which rolls it back */
+    for task in tasks {
+        task_ids.push(insert_task(user_id, task, &mut tx).await?);
+    }
+
+    tx.commit().await?;
+
+    Ok(task_ids)
+}
+
+/// It updates the fields of a task that are present in the `TaskUpdate`, leaving the rest as they
+/// are
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task to update.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `update`: TaskUpdate - The fields to change.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Nothing, or TaskError::InvalidId if the user has no task with that id.
+pub async fn update_task(
+    task_id: i32,
+    user_id: i32,
+    update: TaskUpdate,
+    db: &Data<Db>,
+) -> Result<(), TaskError> {
+    if is_an_invalid_task_id(task_id, user_id, db).await? {
+        return Err(TaskError::InvalidId);
+    }
+
+    let mut tx = db.pool.begin().await?;
+    apply_task_update(task_id, user_id, update, &sync::now(), &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/* It writes the fields set in an update, stamping them with `hlc` for the clients that sync */
+async fn apply_task_update(
+    task_id: i32,
+    user_id: i32,
+    update: TaskUpdate,
+    hlc: &Hlc,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<(), TaskError> {
+    if let Some(Some(project_id)) = update.project_id {
+        if is_an_invalid_project_id(project_id, user_id, &mut *tx).await? {
+            return Err(TaskError::InvalidProjectId);
+        }
+    }
+    let fields = task_update_fields(&update);
+    let before = task_snapshot(task_id, user_id, tx).await?;
+
+    let task = sqlx::query!(
+        r#"
+		SELECT project_id, billable AS `billable: bool`, hourly_rate
+		FROM tasks WHERE id = ? AND user_id = ?"#,
+        task_id,
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let moves = matches!(update.project_id, Some(project_id) if project_id != task.project_id);
+
+    /* What has been billed has to keep adding up to the invoices, so once some time of a task is
+    billed, what decides its price can't change */
+    let reprices = moves
+        || matches!(update.billable, Some(billable) if billable != task.billable)
+        || matches!(update.hourly_rate, Some(rate) if rate != task.hourly_rate);
+    if reprices && has_billed_time(task_id, &mut *tx).await? {
+        return Err(TaskError::Locked);
+    }
+
+    /* A task that changes project goes to the end of the list of the new one */
+    let position = match update.project_id {
+        Some(project_id) if moves => Some(end_of_list(user_id, project_id, tx).await?),
+        _ => None,
+    };
+
+    sqlx::query!(
+        r#"
+		UPDATE tasks
+		SET name = COALESCE(?, name),
+			description = COALESCE(?, description),
+			estimate_seconds = IF(?, ?, estimate_seconds),
+			project_id = IF(?, ?, project_id),
+			billable = COALESCE(?, billable),
+			hourly_rate = IF(?, ?, hourly_rate),
+			due_at = IF(?, FROM_UNIXTIME(?), due_at),
+			priority = IF(?, ?, priority),
+			recurrence = IF(?, ?, recurrence),
+			position = COALESCE(?, position)
+		WHERE id = ? AND user_id = ?
+			"#,
+        update.name,
+        update.description,
+        update.estimate.is_some(),
+        update.estimate.flatten(),
+        update.project_id.is_some(),
+        update.project_id.flatten(),
+        update.billable,
+        update.hourly_rate.is_some(),
+        update.hourly_rate.flatten(),
+        update.due_at.is_some(),
+        update.due_at.flatten(),
+        update.priority.is_some(),
+        update.priority.flatten().map(|priority| priority.to_db()),
+        update.recurrence.is_some(),
+        update
+            .recurrence
+            .as_ref()
+            .and_then(Option::as_ref)
+            .map(Recurrence::to_rrule),
+        position,
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(tags) = update.tags {
+        replace_task_tags(task_id, user_id, &tags, tx).await?;
+    }
+
+    let task = sqlx::query!(
+        r#"
+		SELECT uid FROM tasks WHERE id = ? AND user_id = ?"#,
+        task_id,
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    stamp_clocks(user_id, SyncEntity::Task, &task.uid, &fields, hlc, tx).await?;
+    record_task_change(task_id, user_id, false, &mut *tx).await?;
+
+    let after = task_snapshot(task_id, user_id, tx).await?;
+    let changes: Vec<FieldChange> = fields
+        .iter()
+        .filter_map(|field| {
+            let (from, to) = (before.get(field)?, after.get(field)?);
+            (from != to).then(|| FieldChange {
+                field: field.to_string(),
+                from: from.clone(),
+                to: to.clone(),
+            })
+        })
+        .collect();
+    if !changes.is_empty() {
+        record_activity(
+            task_id,
+            user_id,
+            TaskEventKind::TaskUpdated,
+            &changes,
+            None,
+            &mut *tx,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/* The fields of a task the way the activity feed shows them, to tell what an edit changed */
+async fn task_snapshot(
+    task_id: i32,
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<BTreeMap<&'static str, Value>, sqlx::Error> {
+    let task = sqlx::query!(
+        r#"
+		SELECT name, description, estimate_seconds, project_id, billable AS `billable: bool`,
+			hourly_rate, due_at, priority, recurrence
+		FROM tasks
+		WHERE id = ? AND user_id = ?"#,
+        task_id,
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let tags = get_task_tag_names(task_id, user_id, tx).await?;
+
+    Ok(BTreeMap::from([
+        ("name", json!(task.name)),
+        ("description", json!(task.description)),
+        ("estimate", json!(task.estimate_seconds)),
+        ("tags", json!(tags)),
+        ("project_id", json!(task.project_id)),
+        ("billable", json!(task.billable)),
+        ("hourly_rate", json!(task.hourly_rate)),
+        (
+            "due_at",
+            json!(task.due_at.map(|due_at| due_at.unix_timestamp())),
+        ),
+        ("priority", json!(task.priority.map(Priority::from_db))),
+        (
+            "recurrence",
+            json!(task.recurrence.as_deref().and_then(Recurrence::from_rrule)),
+        ),
+    ]))
+}
+
+/// It moves a task right before or right after another task of the same list. Only the moved task
+/// gets a new position, unless the list has run out of short positions there and is rebalanced.
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task to move.
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `reference_id`: The id of the task it goes next to.
+/// * `after`: Whether it goes after the reference task instead of before it.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Nothing, or TaskError::DifferentList if the tasks aren't in the same project.
+pub async fn move_task(
+    task_id: i32,
+    user_id: i32,
+    reference_id: i32,
+    after: bool,
+    db: &Data<Db>,
+) -> Result<(), TaskError> {
+    let mut tx = db.pool.begin().await?;
+
+    let tasks = sqlx::query!(
+        r#"
+		SELECT id, project_id
+		FROM tasks
+		WHERE user_id = ? AND deleted_at IS NULL AND id IN ( ?, ? )"#,
+        user_id,
+        task_id,
+        reference_id,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let project_of = |id: i32| {
+        tasks
+            .iter()
+            .find(|task| task.id == id)
+            .map(|task| task.project_id)
+    };
+    let project_id = match (project_of(task_id), project_of(reference_id)) {
+        (Some(project_id), Some(reference_project_id)) if project_id == reference_project_id => {
+            project_id
+        }
+        (Some(_), Some(_)) => return Err(TaskError::DifferentList),
+        _ => return Err(TaskError::InvalidId),
+    };
+
+    let mut list = lock_list(user_id, project_id, &mut tx).await?;
+    list.retain(|(id, _)| *id != task_id);
+    let index = list
+        .iter()
+        .position(|(id, _)| *id == reference_id)
+        .ok_or(TaskError::InvalidId)?
+        + after as usize;
+    let previous = index.checked_sub(1).map(|index| list[index].1.as_str());
+    let next = list.get(index).map(|(_, position)| position.as_str());
+
+    match rank::between(previous, next).filter(|position| position.len() <= MAX_RANK_LEN) {
+        Some(position) => {
+            sqlx::query!(
+                r#"
+			UPDATE tasks SET position = ? WHERE id = ? AND user_id = ?
+			"#,
+                position,
+                task_id,
+                user_id,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        None => {
+            list.insert(index, (task_id, String::new()));
+            rebalance_list(user_id, &list, &mut tx).await?;
+        }
+    }
+    record_task_change(task_id, user_id, false, &mut tx).await?;
+    record_webhook_event(task_id, user_id, TaskEventKind::TaskUpdated, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/* The ids and positions of the tasks of a list, in order. Locking it makes moves and additions
+from two devices take turns, so each one is placed against what the other left. */
+async fn lock_list(
+    user_id: i32,
+    project_id: Option<i32>,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Vec<(i32, String)>, sqlx::Error> {
+    let tasks = sqlx::query!(
+        r#"
+		SELECT id, position
+		FROM tasks
+		WHERE user_id = ? AND project_id <=> ? AND deleted_at IS NULL
+		ORDER BY position, id
+		FOR UPDATE"#,
+        user_id,
+        project_id,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok(tasks
+        .into_iter()
+        .map(|task| (task.id, task.position))
+        .collect())
+}
+
+/* The position for a task added at the end of a list */
+async fn end_of_list(
+    user_id: i32,
+    project_id: Option<i32>,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<String, sqlx::Error> {
+    let list = lock_list(user_id, project_id, tx).await?;
+    let position = rank::after(list.last().map(|(_, position)| position.as_str()));
+    if position.len() <= MAX_RANK_LEN {
+        return Ok(position);
+    }
+
+    let positions = rebalance_list(user_id, &list, tx).await?;
+    Ok(rank::after(positions.last().map(String::as_str)))
+}
+
+/* It gives the tasks of a list evenly spaced positions, in the order they come */
+async fn rebalance_list(
+    user_id: i32,
+    list: &[(i32, String)],
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let positions = rank::spread(list.len());
+    for ((task_id, current), position) in list.iter().zip(&positions) {
+        if current != position {
+            sqlx::query!(
+                r#"
+			UPDATE tasks SET position = ? WHERE id = ? AND user_id = ?
+			"#,
+                position,
+                task_id,
+                user_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    Ok(positions)
+}
+
+/// It replaces the tags of a task, creating the ones the user didn't have yet
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task to tag.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `tags`: The tag names, normalized before being stored.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A Result<(), sqlx::Error>
+pub async fn set_task_tags(
+    task_id: i32,
+    user_id: i32,
+    tags: &[String],
+    db: &Data<Db>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    replace_task_tags(task_id, user_id, tags, &mut tx).await?;
+
+    tx.commit().await
+}
+
+async fn replace_task_tags(
+    task_id: i32,
+    user_id: i32,
+    tags: &[String],
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+		DELETE FROM task_tags
+			WHERE task_id = ?
+			"#,
+        task_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let tags = normalize_tags(tags);
+
+    /* The copy of the names that the full-text index of the tasks covers */
+    sqlx::query!(
+        r#"
+		UPDATE tasks SET tag_text = ? WHERE id = ? AND user_id = ?
+			"#,
+        tags.join(" "),
+        task_id,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for tag in tags {
+        sqlx::query!(
+            r#"
+			INSERT IGNORE INTO tags ( user_id, name )
+				VALUES ( ?, ? )
+			"#,
+            user_id,
+            tag,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+			INSERT INTO task_tags ( task_id, tag_id )
+				SELECT ?, id FROM tags WHERE user_id = ? AND name = ?
+			"#,
+            task_id,
+            user_id,
+            tag,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// It's getting all the tasks for a user, and then getting all the history for those tasks, and then
+/// returning a list of tasks with their history, in the order of their positions
+///
+/// Arguments:
+///
+/// * `user_id`: i32 - The user id that we're going to use to get the tasks.
+/// * `db`: &Data<Db> - This is the database connection pool.
+///
+/// Returns:
+///
+/// A vector of ResponseTask structs.
+pub async fn get_tasks_by_user(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<ResponseTask>, sqlx::Error> {
+    let mut tasks = load_tasks(user_id, None, false, db).await?;
+    sort_by_position(&mut tasks);
+
+    Ok(tasks)
+}
+
+fn sort_by_position(tasks: &mut [ResponseTask]) {
+    tasks.sort_by(|a, b| a.position.cmp(&b.position).then(a.id.cmp(&b.id)));
+}
+
+/// It gets the tasks of a user that match a filter, in the order of their positions. The filter
+/// must not name saved lists any more, they are replaced by their terms before.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `filter`: &Filter - The terms every task has to match.
+/// * `clock`: &UserClock - Tells when the days the filter names start for the user.
+/// * `db`: &Data<Db> - This is the database connection pool.
+///
+/// Returns:
+///
+/// A vector of ResponseTask structs.
+pub async fn get_filtered_tasks(
+    user_id: i32,
+    filter: &Filter,
+    clock: &UserClock,
+    db: &Data<Db>,
+) -> Result<Vec<ResponseTask>, sqlx::Error> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    let mut query = filter_query(user_id, filter, clock, now);
+    let task_ids: Vec<(i32,)> = query.build_query_as().fetch_all(&db.pool).await?;
+    let task_ids: Vec<i32> = task_ids.into_iter().map(|(task_id,)| task_id).collect();
+
+    let mut tasks = get_tasks_by_ids(user_id, &task_ids, db).await?;
+    sort_by_position(&mut tasks);
+
+    Ok(tasks)
+}
+
+/* The ids of the tasks that match a filter. What the user wrote only ever goes in as bound values */
+fn filter_query(
+    user_id: i32,
+    filter: &Filter,
+    clock: &UserClock,
+    now: i64,
+) -> QueryBuilder<'static, MySql> {
+    let mut query = QueryBuilder::<MySql>::new(
+        "SELECT tasks.id FROM tasks WHERE tasks.deleted_at IS NULL AND tasks.user_id = ",
+    );
+    query.push_bind(user_id);
+    for clause in &filter.clauses {
+        query.push(" AND ");
+        push_clause(&mut query, user_id, clause, clock, now);
+    }
+
+    query
+}
+
+/* A condition that is NULL for a task, like a comparison with no due date, doesn't match it, and
+neither does its negation unless it says so */
+fn push_clause(
+    query: &mut QueryBuilder<MySql>,
+    user_id: i32,
+    clause: &Clause,
+    clock: &UserClock,
+    now: i64,
+) {
+    if clause.negated {
+        query.push("NOT ");
+    }
+    query.push("COALESCE((");
+    push_condition(query, user_id, &clause.condition, clock, now);
+    query.push("), FALSE)");
+}
+
+fn push_condition(
+    query: &mut QueryBuilder<MySql>,
+    user_id: i32,
+    condition: &Condition,
+    clock: &UserClock,
+    now: i64,
+) {
+    const HAS_HISTORY: &str =
+        "EXISTS (SELECT 1 FROM task_history WHERE task_history.task_id = tasks.id)";
+    const IS_RUNNING: &str = "EXISTS (SELECT 1 FROM task_history \
+        WHERE task_history.task_id = tasks.id AND task_history.finish_time IS NULL)";
+
+    match condition {
+        Condition::Status(StatusFilter::Open) => {
+            query.push(format!("NOT {} OR {}", HAS_HISTORY, IS_RUNNING));
+        }
+        Condition::Status(StatusFilter::Pending) => {
+            query.push(format!("NOT {}", HAS_HISTORY));
+        }
+        Condition::Status(StatusFilter::InProgress) => {
+            query.push(IS_RUNNING);
+        }
+        Condition::Status(StatusFilter::Finished) => {
+            query.push(format!("{} AND NOT {}", HAS_HISTORY, IS_RUNNING));
+        }
+        Condition::Due(DueFilter::None) => {
+            query.push("tasks.due_at IS NULL");
+        }
+        Condition::Due(DueFilter::Any) => {
+            query.push("tasks.due_at IS NOT NULL");
+        }
+        Condition::Due(DueFilter::Overdue) => {
+            query.push("tasks.due_at < FROM_UNIXTIME(");
+            query.push_bind(now);
+            query.push(format!(") AND ( NOT {} OR {} )", HAS_HISTORY, IS_RUNNING));
+        }
+        Condition::Due(DueFilter::Compare(comparison, value)) => {
+            let (start, end) = due_range(*comparison, *value, clock, now);
+            query.push("TRUE");
+            if let Some(start) = start {
+                query.push(" AND tasks.due_at >= FROM_UNIXTIME(");
+                query.push_bind(start);
+                query.push(")");
+            }
+            if let Some(end) = end {
+                query.push(" AND tasks.due_at < FROM_UNIXTIME(");
+                query.push_bind(end);
+                query.push(")");
+            }
+        }
+        Condition::Tag(tag) => {
+            query.push(
+                "EXISTS (SELECT 1 FROM task_tags JOIN tags ON tags.id = task_tags.tag_id \
+                WHERE task_tags.task_id = tasks.id AND tags.name = ",
+            );
+            query.push_bind(tag.clone());
+            query.push(")");
+        }
+        Condition::Project(None) => {
+            query.push("tasks.project_id IS NULL");
+        }
+        Condition::Project(Some(name)) => {
+            query.push("tasks.project_id IN (SELECT id FROM projects WHERE user_id = ");
+            query.push_bind(user_id);
+            query.push(" AND name = ");
+            query.push_bind(name.clone());
+            query.push(")");
+        }
+        Condition::Priority(None) => {
+            query.push("tasks.priority IS NULL");
+        }
+        Condition::Priority(Some(priority)) => {
+            query.push("tasks.priority = ");
+            query.push_bind(priority.to_db());
+        }
+        Condition::Billable(billable) => {
+            query.push("tasks.billable = ");
+            query.push_bind(*billable);
+        }
+        Condition::Archived(archived) => {
+            query.push("( tasks.archived_at IS NOT NULL ) = ");
+            query.push_bind(*archived);
+        }
+        Condition::Text(text) => {
+            let pattern = format!(
+                "%{}%",
+                text.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            query.push("tasks.name LIKE ");
+            query.push_bind(pattern.clone());
+            query.push(" OR tasks.description LIKE ");
+            query.push_bind(pattern);
+        }
+        Condition::All(clauses) => {
+            query.push("TRUE");
+            for clause in clauses {
+                query.push(" AND ");
+                push_clause(query, user_id, clause, clock, now);
+            }
+        }
+        /* Lists are expanded before, an unknown one matches nothing */
+        Condition::List(_) => {
+            query.push("FALSE");
+        }
+    }
+}
+
+/* The due dates a comparison accepts, from a start (inclusive) to an end (exclusive). A day covers
+all of it, so <=tomorrow takes until the end of tomorrow and >tomorrow starts after it. */
+fn due_range(
+    comparison: Comparison,
+    value: DueValue,
+    clock: &UserClock,
+    now: i64,
+) -> (Option<i64>, Option<i64>) {
+    let (start, end) = match value {
+        DueValue::Day(day) => (
+            clock.start_of_day(day),
+            clock.start_of_day(day + Duration::days(1)),
+        ),
+        DueValue::DaysFromToday(days) => {
+            let day = clock.today() + Duration::days(days);
+            (
+                clock.start_of_day(day),
+                clock.start_of_day(day + Duration::days(1)),
+            )
+        }
+        /* Without a comparison, a time from now means until then, or since then for the past */
+        DueValue::SecondsFromNow(seconds) if comparison == Comparison::Within => {
+            return if seconds < 0 {
+                (Some(now + seconds), Some(now))
+            } else {
+                (Some(now), Some(now + seconds))
+            };
+        }
+        DueValue::SecondsFromNow(seconds) => (now + seconds, now + seconds + 1),
+    };
+
+    match comparison {
+        Comparison::Before => (None, Some(start)),
+        Comparison::AtOrBefore => (None, Some(end)),
+        Comparison::After => (Some(end), None),
+        Comparison::AtOrAfter => (Some(start), None),
+        Comparison::Within => (Some(start), Some(end)),
+    }
+}
+
+/// It gets some tasks of a user with their tags and history, in the order of their ids
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `task_ids`: The ids of the tasks. The ones that belong to someone else are left out.
+/// * `db`: &Data<Db> - This is the database connection pool.
+///
+/// Returns:
+///
+/// A vector of ResponseTask structs.
+pub async fn get_tasks_by_ids(
+    user_id: i32,
+    task_ids: &[i32],
+    db: &Data<Db>,
+) -> Result<Vec<ResponseTask>, sqlx::Error> {
+    if task_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    /* FIND_IN_SET takes the ids as one comma separated string, so the query stays static */
+    let task_ids = task_ids
+        .iter()
+        .map(i32::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    load_tasks(user_id, Some(task_ids), false, db).await
+}
+
+/// It finds the tasks of a user that match a full-text query in boolean mode, best matches first.
+/// Matches in the name count more than matches in the description or the tags.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `query`: The query for MATCH ... AGAINST, in boolean mode. It is matched against the fields
+/// of the tasks and against their comments.
+/// * `project_id`: Only the tasks of this project, if it is set.
+/// * `updated_after`: Only the tasks changed at or after this date, if it is set.
+/// * `updated_before`: Only the tasks changed before this date, if it is set.
+/// * `status`: Only the tasks with this status, if it is set.
+/// * `archived`: Whether archived tasks are left out, found too or the only ones found.
+/// * `limit`: The most ids to return.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The ids of the tasks with their relevance.
+pub async fn search_task_ids(
+    user_id: i32,
+    query: &str,
+    project_id: Option<i32>,
+    updated_after: Option<OffsetDateTime>,
+    updated_before: Option<OffsetDateTime>,
+    status: Option<TaskStatus>,
+    archived: ArchiveFilter,
+    limit: i64,
+    db: &Data<Db>,
+) -> Result<Vec<(i32, f64)>, sqlx::Error> {
+    let status = status.map(|status| match status {
+        TaskStatus::Pending => "pending",
+        TaskStatus::InProgress => "in_progress",
+        TaskStatus::Finished => "finished",
+    });
+    let archived = match archived {
+        ArchiveFilter::Exclude => Some(false),
+        ArchiveFilter::Include => None,
+        ArchiveFilter::Only => Some(true),
+    };
+
+    let matches = sqlx::query!(
+        r#"
+		SELECT id,
+			MATCH(name) AGAINST(? IN BOOLEAN MODE) * 3
+				+ MATCH(name, description, tag_text) AGAINST(? IN BOOLEAN MODE)
+				+ COALESCE((
+					SELECT MAX(MATCH(body) AGAINST(? IN BOOLEAN MODE))
+					FROM task_comments
+					WHERE task_id = tasks.id AND deleted_at IS NULL
+				), 0) AS `score!: f64`
+		FROM tasks
+		WHERE user_id = ? AND deleted_at IS NULL
+			AND ( MATCH(name, description, tag_text) AGAINST(? IN BOOLEAN MODE)
+				OR EXISTS (
+					SELECT 1 FROM task_comments
+					WHERE task_id = tasks.id AND deleted_at IS NULL
+						AND MATCH(body) AGAINST(? IN BOOLEAN MODE)
+				) )
+			AND ( ? IS NULL OR project_id = ? )
+			AND ( ? IS NULL OR updated_at >= ? )
+			AND ( ? IS NULL OR updated_at < ? )
+			AND ( ? IS NULL OR ( archived_at IS NOT NULL ) = ? )
+			AND ( ? IS NULL OR ? = CASE
+				WHEN EXISTS (
+					SELECT 1 FROM task_history
+					WHERE task_id = tasks.id AND finish_time IS NULL
+				) THEN 'in_progress'
+				WHEN EXISTS ( SELECT 1 FROM task_history WHERE task_id = tasks.id ) THEN 'finished'
+				ELSE 'pending'
+			END )
+		ORDER BY `score!: f64` DESC, id DESC
+		LIMIT ?"#,
+        query,
+        query,
+        query,
+        user_id,
+        query,
+        query,
+        project_id,
+        project_id,
+        updated_after,
+        updated_after,
+        updated_before,
+        updated_before,
+        archived,
+        archived,
+        status,
+        status,
+        limit,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(matches
+        .into_iter()
+        .map(|task| (task.id, task.score))
+        .collect())
+}
+
+/// It gets one task of a user with its tags and history
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool.
+///
+/// Returns:
+///
+/// The task, or sqlx::Error::RowNotFound if the user has no task with that id.
+pub async fn get_task_by_user(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<ResponseTask, sqlx::Error> {
+    get_tasks_by_ids(user_id, &[task_id], db)
+        .await?
+        .pop()
+        .ok_or(sqlx::Error::RowNotFound)
+}
+
+/// It gets the tasks in the trash of a user, with when they were deleted, most recent first
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `db`: &Data<Db> - This is the database connection pool.
+///
+/// Returns:
+///
+/// A vector of the tasks with the date they were deleted.
+pub async fn get_trashed_tasks(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<(ResponseTask, OffsetDateTime)>, sqlx::Error> {
+    let trashed = sqlx::query!(
+        r#"
+		SELECT id, deleted_at AS `deleted_at!`
+		FROM tasks
+		WHERE user_id = ? AND deleted_at IS NOT NULL
+		ORDER BY deleted_at DESC, id DESC"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    if trashed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let task_ids = trashed
+        .iter()
+        .map(|task| task.id.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut tasks = load_tasks(user_id, Some(task_ids), true, db).await?;
+
+    Ok(trashed
+        .into_iter()
+        .filter_map(|trashed| {
+            let index = tasks.iter().position(|task| task.id == trashed.id)?;
+            Some((tasks.swap_remove(index), trashed.deleted_at))
+        })
+        .collect())
+}
+
+/* Tasks in the trash are only loaded for the trash itself */
+async fn load_tasks(
+    user_id: i32,
+    task_ids: Option<String>,
+    trashed: bool,
+    db: &Data<Db>,
+) -> Result<Vec<ResponseTask>, sqlx::Error> {
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+		SELECT id, uid, name, description, estimate_seconds, project_id, billable as `billable: bool`,
+			hourly_rate, due_at, priority, recurrence, updated_at, archived_at, position
+		FROM tasks
+		WHERE user_id = ? AND ( deleted_at IS NOT NULL ) = ? AND ( ? IS NULL OR FIND_IN_SET(id, ?) )
+		ORDER BY id"#,
+        user_id,
+        trashed,
+        task_ids,
+        task_ids,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let tags = sqlx::query_as!(
+        TaskTag,
+        r#"
+		SELECT task_tags.task_id, tags.name
+		FROM task_tags
+		JOIN tags ON tags.id = task_tags.tag_id
+		WHERE tags.user_id = ? AND ( ? IS NULL OR FIND_IN_SET(task_tags.task_id, ?) )
+		ORDER BY tags.name"#,
+        user_id,
+        task_ids,
+        task_ids,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let tasks_history = sqlx::query_as!(
+        History,
+        r#"
+		SELECT task_id, start_time, finish_time FROM task_history
+		WHERE user_id = ? AND ( ? IS NULL OR FIND_IN_SET(task_id, ?) )"#,
+        user_id,
+        task_ids,
+        task_ids,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    let tasks: Vec<ResponseTask> = tasks
+        .iter()
+        .map(|task| {
+            let history: Vec<TaskHistory> = tasks_history
+                .iter()
+                .filter(|history| history.task_id == task.id)
+                .map(|history| TaskHistory {
+                    start_time: history.start_time.unix_timestamp(),
+                    finish_time: match history.finish_time {
+                        Some(finish_time) => Some(finish_time.unix_timestamp()),
+                        None => None,
+                    },
+                })
+                .collect();
+            let estimate = task.estimate_seconds.map(i64::from);
+            let tracked = tracked_seconds(&history, now);
+
+            // It's creating a new ResponseTask struct and returning it.
+            ResponseTask {
+                id: task.id,
+                uid: task.uid.clone(),
+                name: task.name.clone(),
+                description: task.description.clone(),
+                tags: tags
+                    .iter()
+                    .filter(|tag| tag.task_id == task.id)
+                    .map(|tag| tag.name.clone())
+                    .collect(),
+                estimate,
+                tracked,
+                variance: estimate.map(|estimate| tracked - estimate),
+                project_id: task.project_id,
+                billable: task.billable,
+                hourly_rate: task.hourly_rate,
+                due_at: task.due_at.map(|due_at| due_at.unix_timestamp()),
+                priority: task.priority.map(Priority::from_db),
+                recurrence: task.recurrence.as_deref().and_then(Recurrence::from_rrule),
+                status: task_status(&history),
+                updated_at: task.updated_at.unix_timestamp(),
+                archived_at: task
+                    .archived_at
+                    .map(|archived_at| archived_at.unix_timestamp()),
+                position: task.position.clone(),
+                history,
+            }
+        })
+        .collect();
+
+    Ok(tasks)
+}
+
+/// It takes a `Login` struct and a `Data<Db>` struct, and returns a `Result<Vec<DBUser>, sqlx::Error>`
+///
+/// Arguments:
+///
+/// * `user`: &Login - This is the struct that we created earlier.
+/// * `db`: &Data<Db>
+///
+/// Returns:
+///
+/// A vector of DBUser structs
+pub async fn verify_password(user: &Login, db: &Data<Db>) -> Result<Vec<DBUser>, sqlx::Error> {
+    let users = sqlx::query_as!(
+        DBUser,
+        r#"
+		SELECT id, username FROM users WHERE username = ? AND password = ?"#,
+        user.username,
+        hash(&user.password)
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(users)
+}
+
+/// It gets the id and username of a user
+///
+/// Arguments:
+///
+/// * `user_id`: The id of the user.
+/// * `db`: &Data<Db>
+///
+/// Returns:
+///
+/// A DBUser struct, or sqlx::Error::RowNotFound if the user doesn't exist anymore.
+pub async fn get_user(user_id: i32, db: &Data<Db>) -> Result<DBUser, sqlx::Error> {
+    sqlx::query_as!(
+        DBUser,
+        r#"
+		SELECT id, username FROM users WHERE id = ?"#,
+        user_id,
+    )
+    .fetch_one(&db.pool)
+    .await
+}
+
+/// It gets the focus session the user is currently running, if there is one
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the focus session.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// An Option<DBFocusSession>
+pub async fn get_active_focus_session(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<DBFocusSession>, sqlx::Error> {
+    sqlx::query_as!(
+        DBFocusSession,
+        r#"
+		SELECT id, user_id, task_id, phase, work_seconds, break_seconds, phase_started_at, completed_pomodoros
+		FROM focus_sessions
+		WHERE user_id = ? AND ended_at IS NULL"#,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/* The focus session the user is running, if there is one. The user stays locked until the
+transaction ends, so no other request can start or stop a session meanwhile, even when none is
+running now */
+async fn lock_active_focus_session(
+    user_id: i32,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Option<DBFocusSession>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		SELECT id FROM users WHERE id = ? FOR UPDATE"#,
+        user_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    sqlx::query_as!(
+        DBFocusSession,
+        r#"
+		SELECT id, user_id, task_id, phase, work_seconds, break_seconds, phase_started_at, completed_pomodoros
+		FROM focus_sessions
+		WHERE user_id = ? AND ended_at IS NULL
+		FOR UPDATE"#,
+        user_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+}
+
+/// It starts a task and opens a focus session on it, beginning with a work interval
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task to focus on.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `work_seconds`: The length of every work interval, at most a day.
+/// * `break_seconds`: The length of every break interval, at most a day.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The focus session that has been started.
+pub async fn start_focus_session(
+    task_id: i32,
+    user_id: i32,
+    work_seconds: i64,
+    break_seconds: i64,
+    db: &Data<Db>,
+) -> Result<DBFocusSession, TaskError> {
+    if is_an_invalid_task_id(task_id, user_id, db).await? {
+        return Err(TaskError::InvalidId);
+    }
+
+    let mut tx = db.pool.begin().await?;
+
+    if lock_active_focus_session(user_id, &mut tx).await?.is_some() {
+        return Err(TaskError::FocusInProgress);
+    }
+
+    if !start_timer(task_id, user_id, None, &mut tx).await? {
+        return Err(TaskError::IsPending);
+    }
+
+    let id = sqlx::query!(
+        r#"
+		INSERT INTO focus_sessions ( user_id, task_id, phase, work_seconds, break_seconds, phase_started_at )
+			VALUES ( ?, ?, 'work', ?, ?, UTC_TIMESTAMP() )
+			"#,
+        user_id,
+        task_id,
+        work_seconds,
+        break_seconds,
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_id();
+
+    let session = sqlx::query_as!(
+        DBFocusSession,
+        r#"
+		SELECT id, user_id, task_id, phase, work_seconds, break_seconds, phase_started_at, completed_pomodoros
+		FROM focus_sessions
+		WHERE id = ?"#,
+        id,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(session)
+}
+
+/// It stops the running focus session of a user, closing the work interval if it is still open
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the focus session.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The focus session that has been stopped, or None if there wasn't one running.
+pub async fn stop_focus_session(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<DBFocusSession>, TaskError> {
+    let mut tx = db.pool.begin().await?;
+
+    let session = match lock_active_focus_session(user_id, &mut tx).await? {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    if session.phase == "work" {
+        finish_timer(session.task_id, user_id, None, &mut tx).await?;
+    }
+
+    sqlx::query!(
+        r#"
+		UPDATE focus_sessions
+		SET ended_at = UTC_TIMESTAMP()
+		WHERE id = ?
+			"#,
+        session.id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(session))
+}
+
+/// It moves every focus session whose current interval has run out on to the next one. A work
+/// interval that ends stops the timer of the task and counts a pomodoro, a break that ends starts
+/// it again, both when the interval ended and the same way the timer endpoints do.
+///
+/// Arguments:
+///
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The transitions that have been made.
+pub async fn advance_focus_sessions(db: &Data<Db>) -> Result<Vec<FocusTransition>, sqlx::Error> {
+    let sessions = sqlx::query_as!(
+        DBFocusSession,
+        r#"
+		SELECT id, user_id, task_id, phase, work_seconds, break_seconds, phase_started_at, completed_pomodoros
+		FROM focus_sessions
+		WHERE ended_at IS NULL
+			AND phase_started_at + INTERVAL IF(phase = 'work', work_seconds, break_seconds) SECOND <= UTC_TIMESTAMP()"#,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut transitions = Vec::new();
+
+    for session in sessions {
+        if let Some(transition) = advance_focus_session(&session, db).await? {
+            transitions.push(transition);
+        }
+    }
+
+    Ok(transitions)
+}
+
+async fn advance_focus_session(
+    session: &DBFocusSession,
+    db: &Data<Db>,
+) -> Result<Option<FocusTransition>, sqlx::Error> {
+    let phase_end = session.phase_ends_at();
+    let mut tx = db.pool.begin().await?;
+
+    let next_phase = if session.phase == "work" {
+        // The task may have been finished by hand in the meantime, in which case the session is over.
+        let closed_segment =
+            finish_timer(session.task_id, session.user_id, Some(phase_end), &mut tx).await?;
+
+        if closed_segment {
+            sqlx::query!(
+                r#"
+				INSERT INTO pomodoros ( user_id, task_id, focus_session_id, completed_at )
+					VALUES ( ?, ?, ?, ? )
+				"#,
+                session.user_id,
+                session.task_id,
+                session.id,
+                phase_end,
+            )
+            .execute(&mut tx)
+            .await?;
+
+            "break"
+        } else {
+            "stopped"
+        }
+    } else {
+        // A timer started by hand during the break keeps running.
+        match start_timer(session.task_id, session.user_id, Some(phase_end), &mut tx).await {
+            Err(TaskError::DbError(error)) => return Err(error),
+            Ok(_) | Err(_) => {}
+        }
+
+        "work"
+    };
+
+    let completed_pomodoros = if next_phase == "break" {
+        session.completed_pomodoros + 1
+    } else {
+        session.completed_pomodoros
+    };
+
+    // Guarding on the phase start keeps two servers from advancing the same interval twice, and
+    // on the end a session stopped meanwhile from coming back.
+    let advanced = sqlx::query!(
+        r#"
+		UPDATE focus_sessions
+		SET phase = ?, phase_started_at = ?, completed_pomodoros = ?,
+			ended_at = IF(? = 'stopped', ?, NULL)
+		WHERE id = ? AND phase = ? AND phase_started_at = ? AND ended_at IS NULL
+			"#,
+        next_phase,
+        phase_end,
+        completed_pomodoros,
+        next_phase,
+        phase_end,
+        session.id,
+        session.phase,
+        session.phase_started_at,
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected()
+        == 1;
+
+    if !advanced {
+        tx.rollback().await?;
+        return Ok(None);
+    }
+
+    tx.commit().await?;
+
+    Ok(Some(FocusTransition {
+        user_id: session.user_id,
+        focus_session_id: session.id,
+        task_id: session.task_id,
+        phase: next_phase.to_string(),
+        completed_pomodoros,
+        at: phase_end.unix_timestamp(),
+    }))
+}
+
+/// It counts the pomodoros a user has completed, per task and per day
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who completed the pomodoros.
+/// * `clock`: &UserClock - Tells which day every pomodoro belongs to for the user.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A PomodoroStats struct.
+pub async fn get_pomodoro_stats(
+    user_id: i32,
+    clock: &UserClock,
+    db: &Data<Db>,
+) -> Result<PomodoroStats, sqlx::Error> {
+    let by_task = sqlx::query_as!(
+        TaskPomodoros,
+        r#"
+		SELECT pomodoros.task_id, tasks.name, COUNT(*) AS pomodoros
+		FROM pomodoros
+		JOIN tasks ON tasks.id = pomodoros.task_id
+		WHERE pomodoros.user_id = ? AND tasks.deleted_at IS NULL
+		GROUP BY pomodoros.task_id, tasks.name"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let completed_at: Vec<i64> = sqlx::query!(
+        r#"
+		SELECT pomodoros.completed_at
+		FROM pomodoros
+		JOIN tasks ON tasks.id = pomodoros.task_id
+		WHERE pomodoros.user_id = ? AND tasks.deleted_at IS NULL"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?
+    .iter()
+    .map(|pomodoro| pomodoro.completed_at.unix_timestamp())
+    .collect();
+
+    Ok(PomodoroStats {
+        by_task,
+        by_day: pomodoros_by_day(&completed_at, clock),
+    })
+}
+
+/// It creates a project for a user
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the project.
+/// * `project`: NewProject - The project to create.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A Result<MySqlQueryResult, sqlx::Error>
+pub async fn add_project(
+    user_id: i32,
+    project: NewProject,
+    db: &Data<Db>,
+) -> Result<MySqlQueryResult, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		INSERT INTO projects ( user_id, name, client, hourly_rate, rounding_minutes, rounding_mode )
+			VALUES ( ?, ?, ?, ?, ?, ? )
+			"#,
+        user_id,
+        project.name,
+        project.client,
+        project.hourly_rate,
+        project.rounding_minutes,
+        project.rounding_mode.unwrap_or(RoundingMode::Up).as_str(),
+    )
+    .execute(&db.pool)
+    .await
+}
+
+/// It gets one project of a user
+///
+/// Arguments:
+///
+/// * `project_id`: The id of the project.
+/// * `user_id`: The user id of the user who owns the project.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The project, or sqlx::Error::RowNotFound if the user has no project with that id.
+pub async fn get_project_by_user(
+    project_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Project, sqlx::Error> {
+    sqlx::query_as!(
+        Project,
+        r#"
+		SELECT id, name, client, hourly_rate, rounding_minutes, rounding_mode
+		FROM projects
+		WHERE id = ? AND user_id = ?"#,
+        project_id,
+        user_id,
+    )
+    .fetch_one(&db.pool)
+    .await
+}
+
+/// It gets all the projects of a user
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the projects.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of Project structs.
+pub async fn get_projects_by_user(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<Project>, sqlx::Error> {
+    sqlx::query_as!(
+        Project,
+        r#"
+		SELECT id, name, client, hourly_rate, rounding_minutes, rounding_mode
+		FROM projects
+		WHERE user_id = ?"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It sets the hourly rate of one of the user's tags
+///
+/// Arguments:
+///
+/// * `name`: The name of the tag.
+/// * `user_id`: The user id of the user who owns the tag.
+/// * `update`: TagUpdate - The fields to change.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A boolean value, false if the user has no tag with that name.
+pub async fn update_tag(
+    name: &str,
+    user_id: i32,
+    update: TagUpdate,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let tag = sqlx::query!(
+        r#"
+		SELECT id FROM tags WHERE user_id = ? AND name = ?"#,
+        user_id,
+        name,
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    let tag = match tag {
+        Some(tag) => tag,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+        r#"
+		UPDATE tags
+		SET hourly_rate = IF(?, ?, hourly_rate)
+		WHERE id = ?
+			"#,
+        update.hourly_rate.is_some(),
+        update.hourly_rate.flatten(),
+        tag.id,
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(true)
+}
+
+/// It gets the finished, not yet invoiced time entries of the billable tasks in the projects of a
+/// client, locking them when it runs inside a transaction
+async fn billable_entries<'e, E>(
+    user_id: i32,
+    client: &str,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+    executor: E,
+) -> Result<Vec<BillableEntry>, sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    sqlx::query_as!(
+        BillableEntry,
+        r#"
+		SELECT task_history.task_id, tasks.name, task_history.start_time, task_history.finish_time,
+			tasks.hourly_rate AS task_rate,
+			(
+				SELECT CAST(MAX(tags.hourly_rate) AS SIGNED)
+				FROM task_tags
+				JOIN tags ON tags.id = task_tags.tag_id
+				WHERE task_tags.task_id = tasks.id
+			) AS tag_rate,
+			projects.hourly_rate AS project_rate, projects.rounding_minutes, projects.rounding_mode
+		FROM task_history
+		JOIN tasks ON tasks.id = task_history.task_id
+		JOIN projects ON projects.id = tasks.project_id
+		WHERE task_history.user_id = ? AND tasks.billable AND tasks.deleted_at IS NULL
+			AND projects.client = ?
+			AND task_history.finish_time IS NOT NULL AND task_history.invoice_id IS NULL
+			AND task_history.start_time >= ? AND task_history.start_time < ?
+		ORDER BY task_history.task_id, task_history.start_time
+		FOR UPDATE"#,
+        user_id,
+        client,
+        from,
+        to,
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// It works out what would be invoiced to a client for a period, without locking anything
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who tracked the time.
+/// * `client`: The client of the projects to invoice.
+/// * `from`: Start of the period, inclusive.
+/// * `to`: End of the period, exclusive.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// An Invoice struct without an id, or InvoiceError::TooLarge if its amounts don't fit.
+pub async fn preview_invoice(
+    user_id: i32,
+    client: &str,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+    db: &Data<Db>,
+) -> Result<Invoice, InvoiceError> {
+    let entries = billable_entries(user_id, client, from, to, &db.pool).await?;
+    let lines = invoice_lines(&entries).ok_or(InvoiceError::TooLarge)?;
+
+    Ok(Invoice {
+        id: None,
+        client: client.to_string(),
+        period_start: from.unix_timestamp(),
+        period_end: to.unix_timestamp(),
+        total: invoice_total(&lines).ok_or(InvoiceError::TooLarge)?,
+        lines,
+    })
+}
+
+/// It invoices a client for a period, storing the invoice and locking the time entries it bills
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who tracked the time.
+/// * `client`: The client of the projects to invoice.
+/// * `from`: Start of the period, inclusive.
+/// * `to`: End of the period, exclusive.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The stored Invoice, None if there was nothing to bill, or InvoiceError::TooLarge if its amounts
+/// don't fit.
+pub async fn issue_invoice(
+    user_id: i32,
+    client: &str,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+    db: &Data<Db>,
+) -> Result<Option<Invoice>, InvoiceError> {
+    let mut tx = db.pool.begin().await?;
+
+    let entries = billable_entries(user_id, client, from, to, &mut tx).await?;
+    if entries.is_empty() {
+        tx.rollback().await?;
+        return Ok(None);
+    }
+    let (lines, total) = match invoice_lines(&entries)
+        .and_then(|lines| invoice_total(&lines).map(|total| (lines, total)))
+    {
+        Some(invoice) => invoice,
+        None => {
+            tx.rollback().await?;
+            return Err(InvoiceError::TooLarge);
+        }
+    };
+
+    let invoice_id = sqlx::query!(
+        r#"
+		INSERT INTO invoices ( user_id, client, period_start, period_end )
+			VALUES ( ?, ?, ?, ? )
+			"#,
+        user_id,
+        client,
+        from,
+        to,
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_id() as i32;
+
+    for line in &lines {
+        sqlx::query!(
+            r#"
+			INSERT INTO invoice_lines ( invoice_id, task_id, description, entries, seconds, hourly_rate, amount )
+				VALUES ( ?, ?, ?, ?, ?, ?, ? )
+			"#,
+            invoice_id,
+            line.task_id,
+            line.description,
+            line.entries,
+            line.seconds,
+            line.hourly_rate,
+            line.amount,
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    sqlx::query!(
+        r#"
+		UPDATE task_history
+		JOIN tasks ON tasks.id = task_history.task_id
+		JOIN projects ON projects.id = tasks.project_id
+		SET task_history.invoice_id = ?
+		WHERE task_history.user_id = ? AND tasks.billable AND tasks.deleted_at IS NULL
+			AND projects.client = ?
+			AND task_history.finish_time IS NOT NULL AND task_history.invoice_id IS NULL
+			AND task_history.start_time >= ? AND task_history.start_time < ?
+			"#,
+        invoice_id,
+        user_id,
+        client,
+        from,
+        to,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(Invoice {
+        id: Some(invoice_id),
+        client: client.to_string(),
+        period_start: from.unix_timestamp(),
+        period_end: to.unix_timestamp(),
+        total,
+        lines,
+    }))
+}
+
+/// It gets an invoice that has been issued, as it was when it was issued
+///
+/// Arguments:
+///
+/// * `invoice_id`: The id of the invoice.
+/// * `user_id`: The user id of the user who issued the invoice.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// An Option<Invoice>
+pub async fn get_invoice(
+    invoice_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<Invoice>, InvoiceError> {
+    let invoice = sqlx::query_as!(
+        DBInvoice,
+        r#"
+		SELECT id, client, period_start, period_end
+		FROM invoices
+		WHERE id = ? AND user_id = ?"#,
+        invoice_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    let invoice = match invoice {
+        Some(invoice) => invoice,
+        None => return Ok(None),
+    };
+
+    let lines: Vec<InvoiceLine> = sqlx::query_as!(
+        InvoiceLine,
+        r#"
+		SELECT task_id, description, entries, seconds, hourly_rate, amount
+		FROM invoice_lines
+		WHERE invoice_id = ?
+		ORDER BY id"#,
+        invoice.id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(Some(Invoice {
+        id: Some(invoice.id),
+        client: invoice.client,
+        period_start: invoice.period_start.unix_timestamp(),
+        period_end: invoice.period_end.unix_timestamp(),
+        total: invoice_total(&lines).ok_or(InvoiceError::TooLarge)?,
+        lines,
+    }))
+}
+
+/// It gets the settings of a user, falling back to the defaults for the ones never saved
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user whose settings we want.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A UserSettings struct.
+pub async fn get_user_settings(user_id: i32, db: &Data<Db>) -> Result<UserSettings, sqlx::Error> {
+    let settings = sqlx::query_as!(
+        DBUserSettings,
+        r#"
+		SELECT time_zone, locale, week_start, date_format, auto_archive_days
+		FROM user_settings
+		WHERE user_id = ?"#,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(settings.map(UserSettings::from).unwrap_or_default())
+}
+
+/// It stores the settings of a user
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the settings.
+/// * `settings`: &UserSettings - The complete settings to store.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A Result<MySqlQueryResult, sqlx::Error>
+pub async fn save_user_settings(
+    user_id: i32,
+    settings: &UserSettings,
+    db: &Data<Db>,
+) -> Result<MySqlQueryResult, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		INSERT INTO user_settings ( user_id, time_zone, locale, week_start, date_format, auto_archive_days )
+			VALUES ( ?, ?, ?, ?, ?, ? )
+		ON DUPLICATE KEY UPDATE
+			time_zone = VALUES(time_zone),
+			locale = VALUES(locale),
+			week_start = VALUES(week_start),
+			date_format = VALUES(date_format),
+			auto_archive_days = VALUES(auto_archive_days)
+			"#,
+        user_id,
+        settings.time_zone,
+        settings.locale,
+        settings.week_start.iso(),
+        settings.date_format.pattern(),
+        settings.auto_archive_days,
+    )
+    .execute(&db.pool)
+    .await
+}
+
+/// It creates a new secret token for the user's calendar feed, replacing the one they had. Only a
+/// hash of the token is stored.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the feed.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The new token.
+pub async fn rotate_calendar_feed(user_id: i32, db: &Data<Db>) -> Result<String, sqlx::Error> {
+    let token = generate_secret();
+
+    sqlx::query!(
+        r#"
+		INSERT INTO calendar_feeds ( user_id, token_hash )
+			VALUES ( ?, ? )
+		ON DUPLICATE KEY UPDATE token_hash = VALUES(token_hash), created_at = UTC_TIMESTAMP()
+			"#,
+        user_id,
+        hash_secret(&token),
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// It revokes the user's calendar feed, so its URL stops working
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the feed.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A boolean value, false if the user had no feed.
+pub async fn revoke_calendar_feed(user_id: i32, db: &Data<Db>) -> Result<bool, sqlx::Error> {
+    let revoked = sqlx::query!(
+        r#"
+		DELETE FROM calendar_feeds
+			WHERE user_id = ?
+			"#,
+        user_id,
+    )
+    .execute(&db.pool)
+    .await?
+    .rows_affected()
+        == 1;
+
+    Ok(revoked)
+}
+
+/// It finds the user a calendar feed token belongs to
+///
+/// Arguments:
+///
+/// * `token`: The secret token from the feed URL.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The user id, or None if the token is unknown or has been revoked.
+pub async fn get_calendar_feed_user(
+    token: &str,
+    db: &Data<Db>,
+) -> Result<Option<i32>, sqlx::Error> {
+    let feed = sqlx::query!(
+        r#"
+		SELECT user_id FROM calendar_feeds WHERE token_hash = ?"#,
+        hash_secret(token),
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(feed.map(|feed| feed.user_id))
+}
+
+/// It logs that a task changed, or is about to be deleted, so clients that sync can find out. It has
+/// to run before the task is deleted, while its uid can still be read.
+async fn record_task_change<'e, E>(
+    task_id: i32,
+    user_id: i32,
+    deleted: bool,
+    executor: E,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    sqlx::query!(
+        r#"
+		INSERT INTO task_changes ( user_id, task_id, uid, deleted )
+			SELECT user_id, id, uid, ?
+			FROM tasks
+			WHERE id = ? AND user_id = ?
+			"#,
+        deleted,
+        task_id,
+        user_id,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// It adds an entry to the activity feed of a task, and queues the webhook event it stands for.
+/// Timers aren't logged, the feed reads them from task_history.
+async fn record_activity(
+    task_id: i32,
+    user_id: i32,
+    kind: TaskEventKind,
+    changes: &[FieldChange],
+    comment_id: Option<i32>,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<(), sqlx::Error> {
+    let changes = if changes.is_empty() {
+        None
+    } else {
+        serde_json::to_string(changes).ok()
+    };
+
+    sqlx::query!(
+        r#"
+		INSERT INTO task_activity ( user_id, task_id, kind, changes, comment_id )
+			VALUES ( ?, ?, ?, ?, ? )
+			"#,
+        user_id,
+        task_id,
+        kind.as_str(),
+        changes,
+        comment_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    record_webhook_event(task_id, user_id, kind, tx).await
+}
+
+/// It queues the webhook event a change of a task stands for, in the transaction of the change so
+/// that the event is saved if and only if the change is. Nothing is queued for users without a
+/// webhook subscribed to it.
+async fn record_webhook_event(
+    task_id: i32,
+    user_id: i32,
+    kind: TaskEventKind,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<(), sqlx::Error> {
+    let event = match WebhookEvent::from_task_event(kind) {
+        Some(event) => event.as_str(),
+        None => return Ok(()),
+    };
+
+    sqlx::query!(
+        r#"
+		INSERT INTO webhook_events ( user_id, event, task_id )
+			SELECT ?, ?, ?
+			FROM DUAL
+			WHERE EXISTS (
+				SELECT id FROM webhooks WHERE user_id = ? AND enabled AND FIND_IN_SET(?, events)
+			)
+			"#,
+        user_id,
+        event,
+        task_id,
+        user_id,
+        event,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// It logs that a time entry changed, or is about to be deleted, in the same log as the tasks.
+async fn record_entry_change<'e, E>(
+    entry_id: i32,
+    user_id: i32,
+    deleted: bool,
+    executor: E,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    sqlx::query!(
+        r#"
+		INSERT INTO task_changes ( user_id, entity, task_id, uid, deleted )
+			SELECT user_id, 'time_entry', task_id, uid, ?
+			FROM task_history
+			WHERE id = ? AND user_id = ?
+			"#,
+        deleted,
+        entry_id,
+        user_id,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// It logs a change to every time entry of a task, when the task is trashed, restored or deleted.
+async fn record_entry_changes<'e, E>(
+    task_id: i32,
+    user_id: i32,
+    deleted: bool,
+    executor: E,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    sqlx::query!(
+        r#"
+		INSERT INTO task_changes ( user_id, entity, task_id, uid, deleted )
+			SELECT user_id, 'time_entry', task_id, uid, ?
+			FROM task_history
+			WHERE task_id = ? AND user_id = ?
+			"#,
+        deleted,
+        task_id,
+        user_id,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// It keeps the clock of a write to some fields of a record, for later syncs to merge against.
+async fn stamp_clocks(
+    user_id: i32,
+    entity: SyncEntity,
+    uid: &str,
+    fields: &[&str],
+    hlc: &Hlc,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<(), sqlx::Error> {
+    for field in fields {
+        sqlx::query!(
+            r#"
+			INSERT INTO sync_clocks ( user_id, entity, uid, field, hlc )
+				VALUES ( ?, ?, ?, ?, ? )
+				ON DUPLICATE KEY UPDATE hlc = VALUES(hlc)
+			"#,
+            user_id,
+            entity.as_str(),
+            uid,
+            field,
+            hlc.to_string(),
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn get_clocks(
+    user_id: i32,
+    entity: SyncEntity,
+    uid: &str,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let clocks = sqlx::query!(
+        r#"
+		SELECT field, hlc
+		FROM sync_clocks
+		WHERE user_id = ? AND entity = ? AND uid = ?"#,
+        user_id,
+        entity.as_str(),
+        uid,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok(clocks
+        .into_iter()
+        .map(|clock| (clock.field, clock.hlc))
+        .collect())
+}
+
+async fn was_deleted(
+    user_id: i32,
+    entity: SyncEntity,
+    uid: &str,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<bool, sqlx::Error> {
+    let change = sqlx::query!(
+        r#"
+		SELECT id
+		FROM task_changes
+		WHERE user_id = ? AND entity = ? AND uid = ? AND deleted
+		LIMIT 1"#,
+        user_id,
+        entity.as_str(),
+        uid,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(change.is_some())
+}
+
+/// It applies the changes a client made offline, all in one transaction. Every field is merged on
+/// its own: a change to it is written when its clock is later than the one of the last write to
+/// the field. Deleting is final, later changes to a deleted record are refused.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the records.
+/// * `tasks`: The changes to tasks, applied before the ones to time entries.
+/// * `time_entries`: The changes to time entries.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The changes that couldn't be applied, and why.
+pub async fn apply_sync_changes(
+    user_id: i32,
+    tasks: Vec<TaskChange>,
+    time_entries: Vec<TimeEntryChange>,
+    db: &Data<Db>,
+) -> Result<Vec<RejectedChange>, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+    let mut rejections = Vec::new();
+
+    for change in tasks {
+        if let Some(rejection) = apply_task_change(user_id, change, &mut tx).await? {
+            rejections.push(rejection);
+        }
+    }
+    for change in time_entries {
+        if let Some(rejection) = apply_time_entry_change(user_id, change, &mut tx).await? {
+            rejections.push(rejection);
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(rejections)
+}
+
+fn parse_change_hlc(entity: SyncEntity, uid: &str, hlc: &str) -> Result<Hlc, RejectedChange> {
+    match Hlc::parse(hlc) {
+        Some(hlc) if hlc.is_too_far_ahead() => Err(rejected(
+            entity,
+            uid,
+            "clock_skew",
+            "The clock of the change is too far ahead of the server",
+        )),
+        Some(hlc) => {
+            sync::observe(&hlc);
+            Ok(hlc)
+        }
+        None => Err(rejected(
+            entity,
+            uid,
+            "invalid_clock",
+            "The clock of the change can't be read",
+        )),
+    }
+}
+
+async fn apply_task_change(
+    user_id: i32,
+    change: TaskChange,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Option<RejectedChange>, sqlx::Error> {
+    let uid = change.uid;
+    let hlc = match parse_change_hlc(SyncEntity::Task, &uid, &change.hlc) {
+        Ok(hlc) => hlc,
+        Err(rejection) => return Ok(Some(rejection)),
+    };
+    if let Some(error) = validate_task_update(&change.fields).into_iter().next() {
+        return Ok(Some(rejected(
+            SyncEntity::Task,
+            &uid,
+            "invalid_field",
+            error.message,
+        )));
+    }
+
+    let task = sqlx::query!(
+        r#"
+		SELECT id FROM tasks WHERE user_id = ? AND uid = ? AND deleted_at IS NULL FOR UPDATE"#,
+        user_id,
+        uid,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    // Tasks in the trash count as deleted; they can only come back through the trash.
+    let result = match task {
+        None if change.deleted => return Ok(None),
+        None if was_deleted(user_id, SyncEntity::Task, &uid, tx).await? => {
+            return Ok(Some(rejected(
+                SyncEntity::Task,
+                &uid,
+                "task_deleted",
+                "The task was deleted",
+            )))
+        }
+        None => {
+            let fields = change.fields;
+            let name = match fields.name.clone() {
+                Some(name) => name,
+                None => {
+                    return Ok(Some(rejected(
+                        SyncEntity::Task,
+                        &uid,
+                        "missing_name",
+                        "A new task needs a name",
+                    )))
+                }
+            };
+            let stamped = task_update_fields(&fields);
+            let task = NewTask {
+                uid: Some(uid.clone()),
+                name,
+                description: fields.description.unwrap_or_default(),
+                estimate: fields.estimate.flatten(),
+                tags: fields.tags.unwrap_or_default(),
+                project_id: fields.project_id.flatten(),
+                billable: fields.billable.unwrap_or(false),
+                hourly_rate: fields.hourly_rate.flatten(),
+                due_at: fields.due_at.flatten(),
+                priority: fields.priority.flatten(),
+                recurrence: fields.recurrence.flatten(),
+            };
+
+            match insert_task(user_id, task, tx).await {
+                Ok(_) => {
+                    stamp_clocks(user_id, SyncEntity::Task, &uid, &stamped, &hlc, tx).await?;
+                    Ok(())
+                }
+                Err(error) => Err(error),
+            }
+        }
+        Some(task) if change.deleted => {
+            if has_billed_time(task.id, &mut *tx).await? {
+                Err(TaskError::Locked)
+            } else {
+                trash_task(task.id, user_id, tx).await?;
+                Ok(())
+            }
+        }
+        Some(task) => {
+            let clocks = get_clocks(user_id, SyncEntity::Task, &uid, tx).await?;
+            let update = winning_task_update(change.fields, &hlc, &clocks);
+            apply_task_update(task.id, user_id, update, &hlc, tx).await
+        }
+    };
+
+    match result {
+        Ok(()) => Ok(None),
+        Err(TaskError::DbError(error)) => Err(error),
+        Err(TaskError::InvalidProjectId) => Ok(Some(rejected(
+            SyncEntity::Task,
+            &uid,
+            "project_not_found",
+            "There is no project with the provided id",
+        ))),
+        Err(TaskError::Locked) => Ok(Some(rejected(
+            SyncEntity::Task,
+            &uid,
+            "task_locked",
+            "The task has time that has been billed and can't be changed",
+        ))),
+        Err(_) => Ok(Some(rejected(
+            SyncEntity::Task,
+            &uid,
+            "invalid_change",
+            "The change couldn't be applied",
+        ))),
+    }
+}
+
+async fn apply_time_entry_change(
+    user_id: i32,
+    change: TimeEntryChange,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Option<RejectedChange>, sqlx::Error> {
+    let uid = change.uid;
+    let reject =
+        |code: &'static str, message: &str| -> Result<Option<RejectedChange>, sqlx::Error> {
+            Ok(Some(rejected(SyncEntity::TimeEntry, &uid, code, message)))
+        };
+    let hlc = match parse_change_hlc(SyncEntity::TimeEntry, &uid, &change.hlc) {
+        Ok(hlc) => hlc,
+        Err(rejection) => return Ok(Some(rejection)),
+    };
+
+    let entry = sqlx::query!(
+        r#"
+		SELECT task_history.id, task_history.task_id, task_history.start_time, task_history.finish_time,
+			task_history.invoice_id IS NOT NULL AS `billed: bool`
+		FROM task_history
+		JOIN tasks ON tasks.id = task_history.task_id
+		WHERE task_history.user_id = ? AND task_history.uid = ? AND tasks.deleted_at IS NULL
+		FOR UPDATE"#,
+        user_id,
+        uid,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let entry = match entry {
+        Some(entry) if entry.billed => {
+            return reject("time_entry_locked", "The time entry has been billed")
+        }
+        Some(entry) => entry,
+        None if change.deleted => return Ok(None),
+        None if was_deleted(user_id, SyncEntity::TimeEntry, &uid, tx).await? => {
+            return reject("time_entry_deleted", "The time entry was deleted")
+        }
+        None => {
+            let task = sqlx::query!(
+                r#"
+				SELECT id FROM tasks WHERE user_id = ? AND uid = ? AND deleted_at IS NULL"#,
+                user_id,
+                change.task_uid,
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let task_id = match task {
+                Some(task) => task.id,
+                None => return reject("task_not_found", "There is no task with the provided uid"),
+            };
+            let start_time = match change.start_time {
+                Some(start_time) => start_time,
+                None => return reject("missing_start_time", "A new time entry needs a start time"),
+            };
+            let finish_time = change.finish_time.flatten();
+            if finish_time.map_or(false, |finish_time| finish_time < start_time) {
+                return reject("invalid_time_range", "The entry finishes before it starts");
+            }
+            if finish_time.is_none() && has_running_entry(task_id, tx).await? {
+                return reject("timer_running", "The task has a time entry running already");
+            }
+
+            let entry_id = sqlx::query!(
+                r#"
+				INSERT INTO task_history ( user_id, task_id, uid, start_time, finish_time )
+					VALUES ( ?, ?, ?, FROM_UNIXTIME(?), FROM_UNIXTIME(?) )
+				"#,
+                user_id,
+                task_id,
+                uid,
+                start_time,
+                finish_time,
+            )
+            .execute(&mut *tx)
+            .await?
+            .last_insert_id() as i32;
+
+            let mut stamped = vec!["start_time"];
+            if change.finish_time.is_some() {
+                stamped.push("finish_time");
+            }
+            stamp_clocks(user_id, SyncEntity::TimeEntry, &uid, &stamped, &hlc, tx).await?;
+            record_entry_change(entry_id, user_id, false, &mut *tx).await?;
+            record_task_change(task_id, user_id, false, &mut *tx).await?;
+
+            return Ok(None);
+        }
+    };
+
+    if change.deleted {
+        record_entry_change(entry.id, user_id, true, &mut *tx).await?;
+        sqlx::query!(
+            r#"
+			DELETE FROM sync_clocks WHERE user_id = ? AND entity = 'time_entry' AND uid = ?"#,
+            user_id,
+            uid,
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+			DELETE FROM task_history WHERE id = ?"#,
+            entry.id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_task_change(entry.task_id, user_id, false, &mut *tx).await?;
+
+        return Ok(None);
+    }
+
+    let clocks = get_clocks(user_id, SyncEntity::TimeEntry, &uid, tx).await?;
+    let start_time = change
+        .start_time
+        .filter(|_| wins(&hlc, "start_time", &clocks));
+    let finish_time = change
+        .finish_time
+        .filter(|_| wins(&hlc, "finish_time", &clocks));
+
+    let merged_start = start_time.unwrap_or(entry.start_time.unix_timestamp());
+    let merged_finish = match finish_time {
+        Some(finish_time) => finish_time,
+        None => entry
+            .finish_time
+            .map(|finish_time| finish_time.unix_timestamp()),
+    };
+    if merged_finish.map_or(false, |finish_time| finish_time < merged_start) {
+        return reject(
+            "invalid_time_range",
+            "The entry would finish before it starts",
+        );
+    }
+    /* Reopening an entry would leave the task with two running */
+    if merged_finish.is_none()
+        && entry.finish_time.is_some()
+        && has_running_entry(entry.task_id, tx).await?
+    {
+        return reject("timer_running", "The task has a time entry running already");
+    }
+
+    let mut stamped = Vec::new();
+    if start_time.is_some() {
+        stamped.push("start_time");
+    }
+    if finish_time.is_some() {
+        stamped.push("finish_time");
+    }
+    if stamped.is_empty() {
+        return Ok(None);
+    }
+
+    sqlx::query!(
+        r#"
+		UPDATE task_history
+		SET start_time = IF(?, FROM_UNIXTIME(?), start_time),
+			finish_time = IF(?, FROM_UNIXTIME(?), finish_time)
+		WHERE id = ?
+			"#,
+        start_time.is_some(),
+        start_time,
+        finish_time.is_some(),
+        finish_time.flatten(),
+        entry.id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    stamp_clocks(user_id, SyncEntity::TimeEntry, &uid, &stamped, &hlc, tx).await?;
+    record_entry_change(entry.id, user_id, false, &mut *tx).await?;
+    record_task_change(entry.task_id, user_id, false, &mut *tx).await?;
+
+    Ok(None)
+}
+
+/// It gets the last change to every record that changed after a cursor
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the records.
+/// * `cursor`: The id of the last change the client has seen.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The changes, oldest first.
+pub async fn get_changes_since(
+    user_id: i32,
+    cursor: i64,
+    db: &Data<Db>,
+) -> Result<Vec<DBChange>, sqlx::Error> {
+    sqlx::query_as!(
+        DBChange,
+        r#"
+		SELECT entity AS `entity!`, uid, deleted AS `deleted: bool`
+		FROM task_changes
+		WHERE user_id = ? AND id > ?
+		ORDER BY id"#,
+        user_id,
+        cursor,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It gets every time entry of a user with the uid of its task
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the entries.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DBTimeEntry structs.
+pub async fn get_time_entries_by_user(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<DBTimeEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        DBTimeEntry,
+        r#"
+		SELECT task_history.uid, tasks.uid AS task_uid, task_history.start_time,
+			task_history.finish_time, task_history.invoice_id IS NOT NULL AS `billed: bool`
+		FROM task_history
+		JOIN tasks ON tasks.id = task_history.task_id
+		WHERE task_history.user_id = ? AND tasks.deleted_at IS NULL
+		ORDER BY task_history.id"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It gets the clocks of the last writes to every field of the records of a user
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the records.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DBSyncClock structs.
+pub async fn get_sync_clocks_by_user(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<DBSyncClock>, sqlx::Error> {
+    sqlx::query_as!(
+        DBSyncClock,
+        r#"
+		SELECT entity AS `entity!`, uid, field, hlc
+		FROM sync_clocks
+		WHERE user_id = ?"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It gets the id of the last change to the user's tasks, which works as a sync token
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The id of the last change, 0 if there was none.
+pub async fn current_sync_token(user_id: i32, db: &Data<Db>) -> Result<i64, sqlx::Error> {
+    let change = sqlx::query!(
+        r#"
+		SELECT CAST(COALESCE(MAX(id), 0) AS SIGNED) AS `token!`
+		FROM task_changes
+		WHERE user_id = ?"#,
+        user_id,
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(change.token)
+}
+
+/// It gets the uids of the tasks that changed, or were deleted, after a sync token
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `token`: The sync token the client got last time.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of task uids.
+pub async fn task_uids_changed_since(
+    user_id: i32,
+    token: i64,
+    db: &Data<Db>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let changes = sqlx::query!(
+        r#"
+		SELECT DISTINCT uid
+		FROM task_changes
+		WHERE user_id = ? AND entity = 'task' AND id > ?"#,
+        user_id,
+        token,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(changes.into_iter().map(|change| change.uid).collect())
+}
+
+/// It checks if a uid belongs to a task of the user that is in the trash or archived
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `uid`: The uid a client wants to use.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A boolean value. Such a uid can't be given to a new task, as uids are unique per user.
+pub async fn is_a_hidden_task_uid(
+    user_id: i32,
+    uid: &str,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let task = sqlx::query!(
+        r#"
+		SELECT id
+		FROM tasks
+		WHERE user_id = ? AND uid = ? AND (deleted_at IS NOT NULL OR archived_at IS NOT NULL)"#,
+        user_id,
+        uid,
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(task.is_some())
+}
+
+/// It saves a filter under a name
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the list.
+/// * `list`: NewSmartList - The name and the filter, which has been validated.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The list. A name the user already uses violates the key smart_lists_name.
+pub async fn add_smart_list(
+    user_id: i32,
+    list: NewSmartList,
+    db: &Data<Db>,
+) -> Result<SmartList, sqlx::Error> {
+    let list_id = sqlx::query!(
+        r#"
+		INSERT INTO smart_lists ( user_id, name, filter )
+			VALUES ( ?, ?, ? )
+			"#,
+        user_id,
+        list.name,
+        list.filter,
+    )
+    .execute(&db.pool)
+    .await?
+    .last_insert_id() as i32;
+
+    Ok(SmartList {
+        id: list_id,
+        name: list.name,
+        filter: list.filter,
+    })
+}
+
+/// It gets every saved list of a user, by name
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the lists.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of SmartList structs.
+pub async fn get_smart_lists_by_user(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<SmartList>, sqlx::Error> {
+    sqlx::query_as!(
+        SmartList,
+        r#"
+		SELECT id, name, filter
+		FROM smart_lists
+		WHERE user_id = ?
+		ORDER BY name"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It gets a saved list of a user by its name, the way filters refer to it
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the list.
+/// * `name`: The name of the list.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The list, or None if the user has no list with that name.
+pub async fn get_smart_list_by_name(
+    user_id: i32,
+    name: &str,
+    db: &Data<Db>,
+) -> Result<Option<SmartList>, sqlx::Error> {
+    sqlx::query_as!(
+        SmartList,
+        r#"
+		SELECT id, name, filter
+		FROM smart_lists
+		WHERE user_id = ? AND name = ?"#,
+        user_id,
+        name,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/// It changes the name or the filter of a saved list
+///
+/// Arguments:
+///
+/// * `list_id`: The id of the list.
+/// * `user_id`: The user id of the user who owns the list.
+/// * `update`: SmartListUpdate - The fields to change, the filter already validated.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The list, or None if the user has no list with that id.
+pub async fn update_smart_list(
+    list_id: i32,
+    user_id: i32,
+    update: SmartListUpdate,
+    db: &Data<Db>,
+) -> Result<Option<SmartList>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		UPDATE smart_lists
+		SET name = COALESCE(?, name),
+			filter = COALESCE(?, filter)
+		WHERE id = ? AND user_id = ?
+			"#,
+        update.name,
+        update.filter,
+        list_id,
+        user_id,
+    )
+    .execute(&db.pool)
+    .await?;
+
+    sqlx::query_as!(
+        SmartList,
+        r#"
+		SELECT id, name, filter
+		FROM smart_lists
+		WHERE id = ? AND user_id = ?"#,
+        list_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/// It deletes a saved list. The tasks in it are left as they are.
+///
+/// Arguments:
+///
+/// * `list_id`: The id of the list.
+/// * `user_id`: The user id of the user who owns the list.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether there was such a list.
+pub async fn delete_smart_list(
+    list_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"
+		DELETE FROM smart_lists
+			WHERE id = ? AND user_id = ?
+			"#,
+        list_id,
+        user_id,
+    )
+    .execute(&db.pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+/// It gets the comments of a task, oldest first, deleted ones included while they have replies
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of Comment structs.
+pub async fn get_comments(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<Comment>, sqlx::Error> {
+    let comments = sqlx::query_as!(
+        DBComment,
+        r#"
+		SELECT id, task_id, parent_id, body, created_at, edited_at, deleted_at
+		FROM task_comments
+		WHERE task_id = ? AND user_id = ?
+		ORDER BY created_at, id"#,
+        task_id,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(comments.into_iter().map(Comment::from).collect())
+}
+
+/// It gets one comment of a task
+///
+/// Arguments:
+///
+/// * `comment_id`: The id of the comment.
+/// * `task_id`: The id of the task it is on.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The comment, or None if the task has no comment with that id.
+pub async fn get_comment(
+    comment_id: i32,
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<Comment>, sqlx::Error> {
+    let comment = sqlx::query_as!(
+        DBComment,
+        r#"
+		SELECT id, task_id, parent_id, body, created_at, edited_at, deleted_at
+		FROM task_comments
+		WHERE id = ? AND task_id = ? AND user_id = ?"#,
+        comment_id,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(comment.map(Comment::from))
+}
+
+/// It adds a comment to a task, and to the activity feed of the task
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `comment`: NewComment - The body and the comment it answers, both already checked.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The id of the new comment.
+pub async fn add_comment(
+    task_id: i32,
+    user_id: i32,
+    comment: &NewComment,
+    db: &Data<Db>,
+) -> Result<i32, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let comment_id = sqlx::query!(
+        r#"
+		INSERT INTO task_comments ( user_id, task_id, parent_id, body )
+			VALUES ( ?, ?, ?, ? )
+			"#,
+        user_id,
+        task_id,
+        comment.parent_id,
+        comment.body,
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_id() as i32;
+    record_activity(
+        task_id,
+        user_id,
+        TaskEventKind::CommentAdded,
+        &[],
+        Some(comment_id),
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(comment_id)
+}
+
+/// It changes the body of a comment and marks it as edited. Deleted comments can't be edited.
+///
+/// Arguments:
+///
+/// * `comment_id`: The id of the comment.
+/// * `task_id`: The id of the task it is on.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `body`: The new body, already checked.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether there was such a comment to edit.
+pub async fn update_comment(
+    comment_id: i32,
+    task_id: i32,
+    user_id: i32,
+    body: &str,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let updated = sqlx::query!(
+        r#"
+		UPDATE task_comments
+		SET body = ?, edited_at = CURRENT_TIMESTAMP
+		WHERE id = ? AND task_id = ? AND user_id = ? AND deleted_at IS NULL
+			"#,
+        body,
+        comment_id,
+        task_id,
+        user_id,
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if updated {
+        record_activity(
+            task_id,
+            user_id,
+            TaskEventKind::CommentEdited,
+            &[],
+            Some(comment_id),
+            &mut tx,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(updated)
+}
+
+/// It deletes a comment. A comment with replies keeps its place in the thread without its body.
+///
+/// Arguments:
+///
+/// * `comment_id`: The id of the comment.
+/// * `task_id`: The id of the task it is on.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether there was such a comment to delete.
+pub async fn delete_comment(
+    comment_id: i32,
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let replies = sqlx::query!(
+        r#"
+		SELECT COUNT(*) AS `count!: i64`
+		FROM task_comments
+		WHERE parent_id = ?"#,
+        comment_id,
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .count;
+
+    let deleted = if replies > 0 {
+        sqlx::query!(
+            r#"
+		UPDATE task_comments
+		SET body = '', deleted_at = CURRENT_TIMESTAMP
+		WHERE id = ? AND task_id = ? AND user_id = ? AND deleted_at IS NULL
+			"#,
+            comment_id,
+            task_id,
+            user_id,
+        )
+        .execute(&mut tx)
+        .await?
+    } else {
+        sqlx::query!(
+            r#"
+		DELETE FROM task_comments
+			WHERE id = ? AND task_id = ? AND user_id = ? AND deleted_at IS NULL
+			"#,
+            comment_id,
+            task_id,
+            user_id,
+        )
+        .execute(&mut tx)
+        .await?
+    }
+    .rows_affected()
+        > 0;
+    if deleted {
+        record_activity(
+            task_id,
+            user_id,
+            TaskEventKind::CommentDeleted,
+            &[],
+            Some(comment_id),
+            &mut tx,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(deleted)
+}
+
+/// It gets the bodies of the comments on some tasks, to show where a search matched them
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `task_ids`: The ids of the tasks.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The ids of the tasks with the bodies of their comments, oldest first.
+pub async fn get_comment_bodies(
+    user_id: i32,
+    task_ids: &[i32],
+    db: &Data<Db>,
+) -> Result<Vec<(i32, String)>, sqlx::Error> {
+    if task_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query = QueryBuilder::<MySql>::new(
+        "SELECT task_id, body FROM task_comments WHERE deleted_at IS NULL AND user_id = ",
+    );
+    query.push_bind(user_id).push(" AND task_id IN (");
+    let mut ids = query.separated(", ");
+    for task_id in task_ids {
+        ids.push_bind(*task_id);
+    }
+    query.push(") ORDER BY created_at, id");
+
+    query
+        .build_query_as::<(i32, String)>()
+        .fetch_all(&db.pool)
+        .await
+}
+
+/// It gets what happened to the tasks of a user, or to one of them, newest first
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the tasks.
+/// * `task_id`: Only what happened to this task, if it is set.
+/// * `before`: Only what happened before this date, if it is set.
+/// * `limit`: The most entries to return.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of Activity structs, with the timers of the tasks among the rest.
+pub async fn get_activity(
+    user_id: i32,
+    task_id: Option<i32>,
+    before: Option<OffsetDateTime>,
+    limit: i64,
+    db: &Data<Db>,
+) -> Result<Vec<Activity>, sqlx::Error> {
+    let logged = sqlx::query_as!(
+        DBActivity,
+        r#"
+		SELECT task_id, kind, changes, comment_id, created_at
+		FROM task_activity
+		WHERE user_id = ? AND ( ? IS NULL OR task_id = ? ) AND ( ? IS NULL OR created_at < ? )
+		ORDER BY created_at DESC, id DESC
+		LIMIT ?"#,
+        user_id,
+        task_id,
+        task_id,
+        before,
+        before,
+        limit,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let starts = sqlx::query!(
+        r#"
+		SELECT id, task_id, start_time
+		FROM task_history
+		WHERE user_id = ? AND ( ? IS NULL OR task_id = ? ) AND ( ? IS NULL OR start_time < ? )
+		ORDER BY start_time DESC, id DESC
+		LIMIT ?"#,
+        user_id,
+        task_id,
+        task_id,
+        before,
+        before,
+        limit,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let finishes = sqlx::query!(
+        r#"
+		SELECT id, task_id, finish_time AS `finish_time!: OffsetDateTime`
+		FROM task_history
+		WHERE user_id = ? AND ( ? IS NULL OR task_id = ? ) AND finish_time IS NOT NULL
+			AND ( ? IS NULL OR finish_time < ? )
+		ORDER BY finish_time DESC, id DESC
+		LIMIT ?"#,
+        user_id,
+        task_id,
+        task_id,
+        before,
+        before,
+        limit,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut activity: Vec<Activity> = logged
+        .into_iter()
+        .filter_map(|entry| {
+            Some(Activity {
+                task_id: entry.task_id,
+                kind: TaskEventKind::from_db(&entry.kind)?,
+                at: entry.created_at.unix_timestamp(),
+                changes: entry
+                    .changes
+                    .and_then(|changes: String| serde_json::from_str(&changes).ok())
+                    .unwrap_or_default(),
+                comment_id: entry.comment_id,
+                session_id: None,
+            })
+        })
+        .chain(starts.into_iter().map(|start| Activity {
+            task_id: start.task_id,
+            kind: TaskEventKind::TimerStarted,
+            at: start.start_time.unix_timestamp(),
+            changes: Vec::new(),
+            comment_id: None,
+            session_id: Some(start.id),
+        }))
+        .chain(finishes.into_iter().map(|finish| Activity {
+            task_id: finish.task_id,
+            kind: TaskEventKind::TimerStopped,
+            at: finish.finish_time.unix_timestamp(),
+            changes: Vec::new(),
+            comment_id: None,
+            session_id: Some(finish.id),
+        }))
+        .collect();
+    activity.sort_by(|a, b| b.at.cmp(&a.at));
+    activity.truncate(limit as usize);
+
+    Ok(activity)
+}
+
+/// It gets the attachments of a task, oldest first
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of Attachment structs.
+pub async fn get_attachments(
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<Attachment>, sqlx::Error> {
+    let attachments = sqlx::query_as!(
+        DBAttachment,
+        r#"
+		SELECT task_attachments.id, task_attachments.task_id, task_attachments.filename,
+			attachment_blobs.hash, attachment_blobs.size, attachment_blobs.content_type,
+			attachment_blobs.has_thumbnail AS `has_thumbnail: bool`, task_attachments.created_at
+		FROM task_attachments
+		JOIN attachment_blobs ON attachment_blobs.hash = task_attachments.blob_hash
+		WHERE task_attachments.task_id = ? AND task_attachments.user_id = ?
+		ORDER BY task_attachments.created_at, task_attachments.id"#,
+        task_id,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(attachments
+        .into_iter()
+        .map(|attachment| Attachment::new(attachment, user_id))
+        .collect())
+}
+
+/// It gets one attachment of a task
+///
+/// Arguments:
+///
+/// * `attachment_id`: The id of the attachment.
+/// * `task_id`: The id of the task it is on.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The attachment, or None if the task has no attachment with that id.
+pub async fn get_attachment(
+    attachment_id: i32,
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<Attachment>, sqlx::Error> {
+    let attachment = sqlx::query_as!(
+        DBAttachment,
+        r#"
+		SELECT task_attachments.id, task_attachments.task_id, task_attachments.filename,
+			attachment_blobs.hash, attachment_blobs.size, attachment_blobs.content_type,
+			attachment_blobs.has_thumbnail AS `has_thumbnail: bool`, task_attachments.created_at
+		FROM task_attachments
+		JOIN attachment_blobs ON attachment_blobs.hash = task_attachments.blob_hash
+		WHERE task_attachments.id = ? AND task_attachments.task_id = ?
+			AND task_attachments.user_id = ?"#,
+        attachment_id,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(attachment.map(|attachment| Attachment::new(attachment, user_id)))
+}
+
+/// It adds up the files a user has attached. A file attached several times counts once.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the attachments.
+/// * `executor`: The pool, or a transaction the sum has to be part of.
+///
+/// Returns:
+///
+/// The bytes used.
+pub async fn get_attachment_usage<'e, E>(user_id: i32, executor: E) -> Result<i64, sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    let usage = sqlx::query!(
+        r#"
+		SELECT CAST(COALESCE(SUM(size), 0) AS SIGNED) AS `used!: i64`
+		FROM attachment_blobs
+		WHERE hash IN ( SELECT blob_hash FROM task_attachments WHERE user_id = ? )"#,
+        user_id,
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(usage.used)
+}
+
+/// It checks whether a file fits in what is left of the quota of a user. A file the user has
+/// attached already takes no more space, so it always fits.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who attaches the file.
+/// * `hash`: The SHA-256 of the file, in hex.
+/// * `size`: The size of the file, in bytes.
+/// * `quota`: The bytes each user has for attachments.
+/// * `conn`: A connection of the pool, or the transaction that attaches the file.
+///
+/// Returns:
+///
+/// None if the file fits, or the bytes left of the quota if it doesn't.
+pub async fn exceeds_attachment_quota(
+    user_id: i32,
+    hash: &str,
+    size: i64,
+    quota: i64,
+    conn: &mut MySqlConnection,
+) -> Result<Option<i64>, sqlx::Error> {
+    if has_attached(user_id, hash, &mut *conn).await? {
+        return Ok(None);
+    }
+
+    let used = get_attachment_usage(user_id, &mut *conn).await?;
+    if used + size > quota {
+        Ok(Some((quota - used).max(0)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// It tells whether a user has attached a file already, in which case it takes no more space.
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the attachments.
+/// * `hash`: The SHA-256 of the file, in hex.
+/// * `executor`: The pool, or a transaction the check has to be part of.
+///
+/// Returns:
+///
+/// A boolean value.
+pub async fn has_attached<'e, E>(user_id: i32, hash: &str, executor: E) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'e, Database = MySql>,
+{
+    let attachments = sqlx::query!(
+        r#"
+		SELECT COUNT(*) AS `count!: i64`
+		FROM task_attachments
+		WHERE user_id = ? AND blob_hash = ?"#,
+        user_id,
+        hash,
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(attachments.count > 0)
+}
+
+/// It registers a file by its content, or marks the one with the same content as just used, so it
+/// isn't removed before an attachment uses it.
+///
+/// Arguments:
+///
+/// * `hash`: The SHA-256 of the file, in hex.
+/// * `size`: The size of the file in bytes.
+/// * `content_type`: The type it is served with.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether the file is in storage already. If it isn't, it has to be put there and marked with
+/// mark_blob_stored before it can be attached, even when another upload of it is under way.
+pub async fn claim_blob(
+    hash: &str,
+    size: i64,
+    content_type: &str,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		INSERT INTO attachment_blobs ( hash, size, content_type, stored )
+			VALUES ( ?, ?, ?, FALSE )
+			ON DUPLICATE KEY UPDATE last_used_at = CURRENT_TIMESTAMP
+			"#,
+        hash,
+        size,
+        content_type,
+    )
+    .execute(&db.pool)
+    .await?;
+
+    let blob = sqlx::query!(
+        r#"
+		SELECT stored AS `stored: bool` FROM attachment_blobs WHERE hash = ?"#,
+        hash,
+    )
+    .fetch_one(&db.pool)
+    .await?;
+
+    Ok(blob.stored)
+}
+
+/// It records that a file is in storage, so attachments can use it
+///
+/// Arguments:
+///
+/// * `hash`: The SHA-256 of the file, in hex.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The number of rows affected by the query.
+pub async fn mark_blob_stored(hash: &str, db: &Data<Db>) -> Result<MySqlQueryResult, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		UPDATE attachment_blobs
+		SET stored = TRUE, last_used_at = CURRENT_TIMESTAMP
+		WHERE hash = ?
+			"#,
+        hash,
+    )
+    .execute(&db.pool)
+    .await
+}
+
+/// It records that a file has a thumbnail in storage
+///
+/// Arguments:
+///
+/// * `hash`: The SHA-256 of the file, in hex.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The number of rows affected by the query.
+pub async fn set_blob_thumbnail(
+    hash: &str,
+    db: &Data<Db>,
+) -> Result<MySqlQueryResult, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		UPDATE attachment_blobs
+		SET has_thumbnail = TRUE
+		WHERE hash = ?
+			"#,
+        hash,
+    )
+    .execute(&db.pool)
+    .await
+}
+
+/// It attaches a file, which is in storage already, to a task, if it fits in the quota of the user
+///
+/// Arguments:
+///
+/// * `task_id`: The id of the task.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `hash`: The SHA-256 of the file, in hex.
+/// * `filename`: The name of the file.
+/// * `quota`: The bytes each user has for attachments.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The id of the new attachment, or AttachmentError::QuotaExceeded with the bytes left.
+pub async fn add_attachment(
+    task_id: i32,
+    user_id: i32,
+    hash: &str,
+    filename: &str,
+    quota: i64,
+    db: &Data<Db>,
+) -> Result<i32, AttachmentError> {
+    let mut tx = db.pool.begin().await?;
+
+    /* Attachments of the same user wait for each other, so two can't both take the last space */
+    sqlx::query!(
+        r#"
+		SELECT id FROM users WHERE id = ? FOR UPDATE"#,
+        user_id,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    /* Shared, so the file can't be forgotten until the attachment is saved */
+    let blob = sqlx::query!(
+        r#"
+		SELECT size FROM attachment_blobs WHERE hash = ? AND stored FOR SHARE"#,
+        hash,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if let Some(left) = exceeds_attachment_quota(user_id, hash, blob.size, quota, &mut tx).await? {
+        return Err(AttachmentError::QuotaExceeded(left));
+    }
+
+    let attachment_id = sqlx::query!(
+        r#"
+		INSERT INTO task_attachments ( user_id, task_id, blob_hash, filename )
+			VALUES ( ?, ?, ?, ? )
+			"#,
+        user_id,
+        task_id,
+        hash,
+        filename,
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_id() as i32;
+    let change = FieldChange {
+        field: String::from("attachments"),
+        from: Value::Null,
+        to: json!(filename),
+    };
+    record_activity(
+        task_id,
+        user_id,
+        TaskEventKind::AttachmentAdded,
+        &[change],
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(attachment_id)
+}
+
+/// It takes an attachment off a task. Its file stays in storage until nothing has used it for a
+/// while; see take_unused_blobs.
+///
+/// Arguments:
+///
+/// * `attachment_id`: The id of the attachment.
+/// * `task_id`: The id of the task it is on.
+/// * `user_id`: The user id of the user who owns the task.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether there was such an attachment to delete.
+pub async fn delete_attachment(
+    attachment_id: i32,
+    task_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let filename = sqlx::query!(
+        r#"
+		SELECT filename
+		FROM task_attachments
+		WHERE id = ? AND task_id = ? AND user_id = ?
+		FOR UPDATE"#,
+        attachment_id,
+        task_id,
+        user_id,
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .map(|attachment| attachment.filename);
+    let filename: String = match filename {
+        Some(filename) => filename,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+        r#"
+		DELETE FROM task_attachments
+			WHERE id = ?
+			"#,
+        attachment_id,
+    )
+    .execute(&mut tx)
+    .await?;
+    let change = FieldChange {
+        field: String::from("attachments"),
+        from: json!(filename),
+        to: Value::Null,
+    };
+    record_activity(
+        task_id,
+        user_id,
+        TaskEventKind::AttachmentDeleted,
+        &[change],
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// It forgets the files that no attachment has used for a while, so they can be removed from
+/// storage
+///
+/// Arguments:
+///
+/// * `unused_for`: Seconds a file has to have gone unused.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The hashes of the files, and whether each had a thumbnail.
+pub async fn take_unused_blobs(
+    unused_for: i64,
+    db: &Data<Db>,
+) -> Result<Vec<(String, bool)>, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    let blobs = sqlx::query!(
+        r#"
+		SELECT hash, has_thumbnail AS `has_thumbnail: bool`
+		FROM attachment_blobs
+		WHERE last_used_at < NOW() - INTERVAL ? SECOND
+			AND NOT EXISTS ( SELECT 1 FROM task_attachments WHERE blob_hash = attachment_blobs.hash )
+		FOR UPDATE"#,
+        unused_for,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    for blob in &blobs {
+        sqlx::query!(
+            r#"
+		DELETE FROM attachment_blobs
+			WHERE hash = ?
+			"#,
+            blob.hash,
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(blobs
+        .into_iter()
+        .map(|blob| (blob.hash, blob.has_thumbnail))
+        .collect())
+}
+
+/// It registers a webhook, with a new secret to sign its payloads
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the webhook.
+/// * `webhook`: NewWebhook - The URL and the events it subscribes to.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The webhook, the only time it comes with its secret.
+pub async fn add_webhook(
+    user_id: i32,
+    webhook: NewWebhook,
+    db: &Data<Db>,
+) -> Result<Webhook, sqlx::Error> {
+    let secret = generate_secret();
+
+    let webhook_id = sqlx::query!(
+        r#"
+		INSERT INTO webhooks ( user_id, url, secret, events )
+			VALUES ( ?, ?, ?, ? )
+			"#,
+        user_id,
+        webhook.url,
+        secret,
+        WebhookEvent::to_db(&webhook.events),
+    )
+    .execute(&db.pool)
+    .await?
+    .last_insert_id() as i32;
+
+    let webhook = get_webhook(webhook_id, user_id, db)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+    Ok(Webhook {
+        secret: Some(secret),
+        ..Webhook::from(webhook)
+    })
+}
+
+/// It gets every webhook of a user
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the webhooks.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DBWebhook structs.
+pub async fn get_webhooks_by_user(
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<DBWebhook>, sqlx::Error> {
+    sqlx::query_as!(
+        DBWebhook,
+        r#"
+		SELECT id, url, secret, events, enabled AS `enabled: bool`, consecutive_failures,
+			disabled_at, created_at
+		FROM webhooks
+		WHERE user_id = ?
+		ORDER BY id"#,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It gets a webhook of a user
+///
+/// Arguments:
+///
+/// * `webhook_id`: The id of the webhook.
+/// * `user_id`: The user id of the user who owns the webhook.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The webhook, or None if the user has no webhook with that id.
+pub async fn get_webhook(
+    webhook_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<DBWebhook>, sqlx::Error> {
+    sqlx::query_as!(
+        DBWebhook,
+        r#"
+		SELECT id, url, secret, events, enabled AS `enabled: bool`, consecutive_failures,
+			disabled_at, created_at
+		FROM webhooks
+		WHERE id = ? AND user_id = ?"#,
+        webhook_id,
+        user_id,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/// It changes the fields of a webhook that are present in the update. Enabling it again clears
+/// its failures.
+///
+/// Arguments:
+///
+/// * `webhook_id`: The id of the webhook.
+/// * `user_id`: The user id of the user who owns the webhook.
+/// * `update`: WebhookUpdate - The fields to change.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The webhook, or None if the user has no webhook with that id.
+pub async fn update_webhook(
+    webhook_id: i32,
+    user_id: i32,
+    update: WebhookUpdate,
+    db: &Data<Db>,
+) -> Result<Option<DBWebhook>, sqlx::Error> {
+    let enable = update.enabled == Some(true);
+
+    sqlx::query!(
+        r#"
+		UPDATE webhooks
+		SET url = COALESCE(?, url),
+			events = COALESCE(?, events),
+			enabled = COALESCE(?, enabled),
+			consecutive_failures = IF(?, 0, consecutive_failures),
+			disabled_at = IF(?, NULL, disabled_at)
+		WHERE id = ? AND user_id = ?
+			"#,
+        update.url,
+        update.events.as_deref().map(WebhookEvent::to_db),
+        update.enabled,
+        enable,
+        enable,
+        webhook_id,
+        user_id,
+    )
+    .execute(&db.pool)
+    .await?;
+
+    get_webhook(webhook_id, user_id, db).await
+}
+
+/// It deletes a webhook with its delivery log
+///
+/// Arguments:
+///
+/// * `webhook_id`: The id of the webhook.
+/// * `user_id`: The user id of the user who owns the webhook.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether there was such a webhook.
+pub async fn delete_webhook(
+    webhook_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+		DELETE webhook_deliveries
+		FROM webhook_deliveries
+		JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+		WHERE webhooks.id = ? AND webhooks.user_id = ?
+			"#,
+        webhook_id,
+        user_id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let deleted = sqlx::query!(
+        r#"
+		DELETE FROM webhooks
+			WHERE id = ? AND user_id = ?
+			"#,
+        webhook_id,
+        user_id,
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected()
+        == 1;
+
+    tx.commit().await?;
+
+    Ok(deleted)
+}
+
+/// It takes the oldest task events waiting for their deliveries. They stay locked, and are removed,
+/// with the transaction, so two servers don't queue the same event twice and an event isn't lost
+/// if the transaction fails.
+///
+/// Arguments:
+///
+/// * `limit`: How many to take at most.
+/// * `tx`: The transaction the deliveries of the events are queued in.
+///
+/// Returns:
+///
+/// A vector of DBWebhookEvent structs.
+pub async fn take_webhook_events(
+    limit: i64,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<Vec<DBWebhookEvent>, sqlx::Error> {
+    let events = sqlx::query_as!(
+        DBWebhookEvent,
+        r#"
+		SELECT id, user_id, event, task_id, created_at
+		FROM webhook_events
+		ORDER BY id
+		LIMIT ?
+		FOR UPDATE"#,
+        limit,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if !events.is_empty() {
+        let mut query = QueryBuilder::new("DELETE FROM webhook_events WHERE id IN (");
+        let mut ids = query.separated(", ");
+        for event in &events {
+            ids.push_bind(event.id);
+        }
+        query.push(")");
+        query.build().execute(&mut *tx).await?;
+    }
+
+    Ok(events)
+}
+
+/// It queues a payload for every enabled webhook of a user that subscribes to its event
+///
+/// Arguments:
+///
+/// * `user_id`: The user id of the user who owns the webhooks.
+/// * `event`: The name of the event, such as "task.created".
+/// * `payload`: The JSON body to send.
+/// * `tx`: The transaction the event was taken in.
+///
+/// Returns:
+///
+/// A Result<MySqlQueryResult, sqlx::Error>
+pub async fn enqueue_subscribed_deliveries(
+    user_id: i32,
+    event: &str,
+    payload: &str,
+    tx: &mut Transaction<'_, MySql>,
+) -> Result<MySqlQueryResult, sqlx::Error> {
+    sqlx::query!(
+        r#"
+		INSERT INTO webhook_deliveries ( webhook_id, event, payload, next_attempt_at )
+			SELECT id, ?, ?, UTC_TIMESTAMP()
+			FROM webhooks
+			WHERE user_id = ? AND enabled AND FIND_IN_SET(?, events)
+			"#,
+        event,
+        payload,
+        user_id,
+        event,
+    )
+    .execute(&mut *tx)
+    .await
+}
+
+/// It queues a payload to be sent to a webhook as soon as possible
+///
+/// Arguments:
+///
+/// * `webhook_id`: The id of the webhook.
+/// * `event`: The name of the event.
+/// * `payload`: The JSON body to send.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The id of the delivery.
+pub async fn enqueue_webhook_delivery(
+    webhook_id: i32,
+    event: &str,
+    payload: &str,
+    db: &Data<Db>,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+		INSERT INTO webhook_deliveries ( webhook_id, event, payload, next_attempt_at )
+			VALUES ( ?, ?, ?, UTC_TIMESTAMP() )
+			"#,
+        webhook_id,
+        event,
+        payload,
+    )
+    .execute(&db.pool)
+    .await?;
+
+    Ok(result.last_insert_id() as i64)
+}
+
+/// It gets the latest deliveries of a webhook, newest first
+///
+/// Arguments:
+///
+/// * `webhook_id`: The id of the webhook.
+/// * `user_id`: The user id of the user who owns the webhook.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DBWebhookDelivery structs.
+pub async fn get_webhook_deliveries(
+    webhook_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Vec<DBWebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        DBWebhookDelivery,
+        r#"
+		SELECT webhook_deliveries.id, webhook_deliveries.event, webhook_deliveries.status AS `status!`,
+			webhook_deliveries.attempts, webhook_deliveries.next_attempt_at,
+			webhook_deliveries.last_status_code, webhook_deliveries.last_error,
+			webhook_deliveries.created_at, webhook_deliveries.delivered_at
+		FROM webhook_deliveries
+		JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+		WHERE webhooks.id = ? AND webhooks.user_id = ?
+		ORDER BY webhook_deliveries.id DESC
+		LIMIT 100"#,
+        webhook_id,
+        user_id,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It queues a past delivery again, with the same payload, as a new delivery
+///
+/// Arguments:
+///
+/// * `delivery_id`: The id of the delivery to send again.
+/// * `webhook_id`: The id of the webhook.
+/// * `user_id`: The user id of the user who owns the webhook.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// The new delivery, or None if the webhook has no delivery with that id.
+pub async fn replay_webhook_delivery(
+    delivery_id: i64,
+    webhook_id: i32,
+    user_id: i32,
+    db: &Data<Db>,
+) -> Result<Option<DBWebhookDelivery>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+		INSERT INTO webhook_deliveries ( webhook_id, event, payload, next_attempt_at )
+			SELECT webhook_deliveries.webhook_id, webhook_deliveries.event, webhook_deliveries.payload,
+				UTC_TIMESTAMP()
+			FROM webhook_deliveries
+			JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+			WHERE webhook_deliveries.id = ? AND webhooks.id = ? AND webhooks.user_id = ?
+			"#,
+        delivery_id,
+        webhook_id,
+        user_id,
+    )
+    .execute(&db.pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    sqlx::query_as!(
+        DBWebhookDelivery,
+        r#"
+		SELECT id, event, status AS `status!`, attempts, next_attempt_at, last_status_code,
+			last_error, created_at, delivered_at
+		FROM webhook_deliveries
+		WHERE id = ?"#,
+        result.last_insert_id() as i64,
+    )
+    .fetch_optional(&db.pool)
+    .await
+}
+
+/// It gets the pending deliveries of enabled webhooks whose next attempt is due, oldest first
+///
+/// Arguments:
+///
+/// * `limit`: How many to get at most.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A vector of DueDelivery structs.
+pub async fn get_due_webhook_deliveries(
+    limit: i64,
+    db: &Data<Db>,
+) -> Result<Vec<DueDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        DueDelivery,
+        r#"
+		SELECT webhook_deliveries.id, webhook_deliveries.webhook_id, webhooks.url, webhooks.secret,
+			webhook_deliveries.event, webhook_deliveries.payload, webhook_deliveries.attempts,
+			webhook_deliveries.next_attempt_at
+		FROM webhook_deliveries
+		JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+		WHERE webhook_deliveries.status = 'pending'
+			AND webhook_deliveries.next_attempt_at <= UTC_TIMESTAMP()
+			AND webhooks.enabled
+		ORDER BY webhook_deliveries.next_attempt_at
+		LIMIT ?"#,
+        limit,
+    )
+    .fetch_all(&db.pool)
+    .await
+}
+
+/// It takes a due delivery for this server, pushing its next attempt back while it is sent, so
+/// another server doesn't send it too.
+///
+/// Arguments:
+///
+/// * `delivery`: The delivery, as it was read.
+/// * `lease_seconds`: How long the other servers leave it alone.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// Whether this server got it.
+pub async fn claim_webhook_delivery(
+    delivery: &DueDelivery,
+    lease_seconds: i64,
+    db: &Data<Db>,
+) -> Result<bool, sqlx::Error> {
+    // Guarding on the attempt time keeps two servers from sending the same attempt.
+    let claimed = sqlx::query!(
+        r#"
+		UPDATE webhook_deliveries
+		SET next_attempt_at = DATE_ADD(UTC_TIMESTAMP(), INTERVAL ? SECOND)
+		WHERE id = ? AND status = 'pending' AND next_attempt_at = ?
+			"#,
+        lease_seconds,
+        delivery.id,
+        delivery.next_attempt_at,
+    )
+    .execute(&db.pool)
+    .await?
+    .rows_affected()
+        == 1;
+
+    Ok(claimed)
+}
+
+/// It saves that a delivery was received, clearing the failures of its webhook
+///
+/// Arguments:
+///
+/// * `delivery`: The delivery.
+/// * `status_code`: The status the webhook answered with.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A Result<(), sqlx::Error>
+pub async fn record_webhook_success(
+    delivery: &DueDelivery,
+    status_code: i32,
+    db: &Data<Db>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+		UPDATE webhook_deliveries
+		SET status = 'delivered', attempts = attempts + 1, last_status_code = ?, last_error = NULL,
+			delivered_at = UTC_TIMESTAMP()
+		WHERE id = ?
+			"#,
+        status_code,
+        delivery.id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+		UPDATE webhooks SET consecutive_failures = 0 WHERE id = ?"#,
+        delivery.webhook_id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// It saves a failed attempt, scheduling the next one or giving up, and disables the webhook
+/// once it has failed too many times in a row
+///
+/// Arguments:
+///
+/// * `delivery`: The delivery.
+/// * `status_code`: The status the webhook answered with, if it answered.
+/// * `error`: What went wrong.
+/// * `retry_in`: Seconds until the next attempt, or None to give up.
+/// * `disable_after`: The failures in a row that disable the webhook.
+/// * `db`: &Data<Db> - This is the database connection pool that we created in the main.rs file.
+///
+/// Returns:
+///
+/// A Result<(), sqlx::Error>
+pub async fn record_webhook_failure(
+    delivery: &DueDelivery,
+    status_code: Option<i32>,
+    error: &str,
+    retry_in: Option<i64>,
+    disable_after: i32,
+    db: &Data<Db>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+		UPDATE webhook_deliveries
+		SET status = IF(? IS NULL, 'failed', 'pending'), attempts = attempts + 1,
+			next_attempt_at = DATE_ADD(UTC_TIMESTAMP(), INTERVAL COALESCE(?, 0) SECOND),
+			last_status_code = ?, last_error = LEFT(?, 255)
+		WHERE id = ?
+			"#,
+        retry_in,
+        retry_in,
+        status_code,
+        error,
+        delivery.id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // MySQL assigns from left to right, so the counter is increased last.
+    sqlx::query!(
+        r#"
+		UPDATE webhooks
+		SET disabled_at = IF(enabled AND consecutive_failures + 1 >= ?, UTC_TIMESTAMP(), disabled_at),
+			enabled = enabled AND consecutive_failures + 1 < ?,
+			consecutive_failures = consecutive_failures + 1
+		WHERE id = ?
+			"#,
+        disable_after,
+        disable_after,
+        delivery.webhook_id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_sql(filter: &Filter) -> String {
+        let clock = UserClock::from(&UserSettings::default());
+        filter_query(1, filter, &clock, 1_792_368_000).into_sql()
+    }
+
+    #[test]
+    fn binds_the_values_of_filters() {
+        let filter = Filter::parse(
+            r#"project:"x' OR '1'='1" tag:"a'); DROP TABLE tasks; --" "100%_off" priority:high billable:yes"#,
+        )
+        .unwrap();
+        let sql = filter_sql(&filter);
+
+        for written in ["x'", "'1'='1", "DROP", "100%", "high"] {
+            assert!(!sql.contains(written), "{:?} is in {}", written, sql);
+        }
+        /* The user, then the user and name of the project, the tag, the name and description
+        patterns, the priority and the billable flag */
+        assert_eq!(sql.matches('?').count(), 8, "{}", sql);
+    }
+
+    #[test]
+    fn negates_each_term_and_lists_as_a_whole() {
+        let mut filter = Filter::parse("-tag:waiting -list:Done").unwrap();
+        filter.expand_list("Done", &Filter::parse("status:finished tag:old").unwrap());
+        let sql = filter_sql(&filter);
+
+        assert!(
+            sql.contains(" AND NOT COALESCE((EXISTS (SELECT 1 FROM task_tags"),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.contains(
+                " AND NOT COALESCE((TRUE AND COALESCE((EXISTS (SELECT 1 FROM task_history"
+            ),
+            "{}",
+            sql
+        );
+    }
 }
@@ -0,0 +1,331 @@
+use crate::model::{AttachmentError, BulkItemError, InvoiceError, TaskError, VerificationError};
+use crate::validation::FieldError;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::{JsonConfig, PathConfig, QueryConfig};
+use actix_web::{Error, HttpRequest, HttpResponse, ResponseError};
+
+use async_graphql::ErrorExtensions;
+
+use serde::Serialize;
+
+use utoipa::ToSchema;
+
+use sqlx::mysql::MySqlDatabaseError;
+
+use std::fmt;
+use std::future::Future;
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/* MySQL's ER_DUP_ENTRY */
+const DUPLICATE_ENTRY: u16 = 1062;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Every error the API returns. It is rendered as an RFC 7807 problem, where `code` is what clients
+/// should match on; `detail` is only meant for people.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    errors: Vec<FieldError>,
+}
+
+/// The body of every error response (RFC 7807).
+#[derive(Serialize, ToSchema)]
+pub struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'a str,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    code: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError],
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// A request that could be parsed but has fields with values that aren't allowed.
+    pub fn validation(errors: Vec<FieldError>) -> Self {
+        ApiError {
+            errors,
+            ..ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_failed",
+                "Some fields are invalid",
+            )
+        }
+    }
+
+    /// A unique key was violated. The field is named, so clients can point at it.
+    pub fn duplicate_field(field: &'static str, message: impl Into<String>) -> Self {
+        let message = message.into();
+        ApiError {
+            errors: vec![FieldError::new(field, "taken", message.clone())],
+            ..ApiError::conflict("duplicate_field", message)
+        }
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::CONFLICT, code, message)
+    }
+
+    pub fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::UNAUTHORIZED, code, message)
+    }
+
+    pub fn internal() -> Self {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Couldn't complete operation",
+        )
+    }
+
+    /// The same error for GraphQL, which reports errors next to the data instead of in the status.
+    /// `code` and `status` go in the extensions.
+    pub fn into_graphql(self) -> async_graphql::Error {
+        let ApiError {
+            status,
+            code,
+            message,
+            errors,
+        } = self;
+
+        async_graphql::Error::new(message).extend_with(|_, extensions| {
+            extensions.set("code", code);
+            extensions.set("status", status.as_u16());
+            if !errors.is_empty() {
+                extensions.set(
+                    "errors",
+                    async_graphql::to_value(&errors).unwrap_or_default(),
+                );
+            }
+        })
+    }
+
+    /// The same error for one operation of a bulk request, which is reported in its result.
+    pub fn into_bulk_item(self) -> BulkItemError {
+        BulkItemError {
+            status: self.status.as_u16(),
+            code: self.code,
+            message: self.message,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: self.status.canonical_reason().unwrap_or("Error"),
+            status: self.status.as_u16(),
+            detail: &self.message,
+            code: self.code,
+            request_id: REQUEST_ID.try_with(|request_id| request_id.clone()).ok(),
+            errors: &self.errors,
+        };
+
+        HttpResponse::build(self.status)
+            .content_type("application/problem+json")
+            .json(problem)
+    }
+}
+
+/// The name of the unique key a query violated, if that is why it failed. MySQL only reports it
+/// in the message, as in "Duplicate entry 'x' for key 'users.users_email'".
+pub fn duplicate_key(error: &sqlx::Error) -> Option<String> {
+    let database_error = error.as_database_error()?;
+    let mysql_error = database_error.try_downcast_ref::<MySqlDatabaseError>()?;
+    if mysql_error.number() != DUPLICATE_ENTRY {
+        return None;
+    }
+
+    let key = mysql_error
+        .message()
+        .rsplit_once("for key '")?
+        .1
+        .trim_end_matches('\'');
+    Some(key.rsplit('.').next().unwrap_or(key).to_string())
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => {
+                return ApiError::not_found("not_found", "The resource doesn't exist")
+            }
+            sqlx::Error::Database(_) if duplicate_key(&error).is_some() => {
+                return ApiError::conflict("duplicate", "The resource already exists");
+            }
+            _ => {}
+        }
+
+        println!("{:#?}", error);
+        ApiError::internal()
+    }
+}
+
+impl From<TaskError> for ApiError {
+    fn from(error: TaskError) -> Self {
+        match error {
+            TaskError::InvalidId => {
+                ApiError::not_found("task_not_found", "There is no task with the provided id")
+            }
+            TaskError::InvalidProjectId => ApiError::not_found(
+                "project_not_found",
+                "There is no project with the provided id",
+            ),
+            TaskError::IsPending => {
+                ApiError::conflict("task_running", "The tasks hasn't been completed yet")
+            }
+            TaskError::NotRunning => {
+                ApiError::conflict("task_already_finished", "Task already finished")
+            }
+            TaskError::DifferentList => {
+                ApiError::conflict("different_list", "The tasks are in different projects")
+            }
+            TaskError::FocusInProgress => ApiError::conflict(
+                "focus_in_progress",
+                "There is a focus session running already",
+            ),
+            TaskError::Locked => ApiError::conflict(
+                "task_locked",
+                "The task has time that has been billed and can't be changed",
+            ),
+            TaskError::DbError(error) => ApiError::from(error),
+        }
+    }
+}
+
+impl From<AttachmentError> for ApiError {
+    fn from(error: AttachmentError) -> Self {
+        match error {
+            AttachmentError::QuotaExceeded(left) => ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "quota_exceeded",
+                format!(
+                    "The file doesn't fit in the {} bytes left of the quota",
+                    left
+                ),
+            ),
+            AttachmentError::DbError(error) => ApiError::from(error),
+        }
+    }
+}
+
+impl From<InvoiceError> for ApiError {
+    fn from(error: InvoiceError) -> Self {
+        match error {
+            InvoiceError::TooLarge => ApiError::bad_request(
+                "invoice_too_large",
+                "The time or the amounts of the invoice are too large, try a shorter period",
+            ),
+            InvoiceError::DbError(error) => ApiError::from(error),
+        }
+    }
+}
+
+impl From<VerificationError> for ApiError {
+    fn from(error: VerificationError) -> Self {
+        match error {
+            VerificationError::EmptyToken => {
+                ApiError::unauthorized("missing_token", "Empty validation token")
+            }
+            VerificationError::InvalidToken => {
+                ApiError::unauthorized("invalid_token", "Invalid Token")
+            }
+        }
+    }
+}
+
+/* Bodies, queries and paths that can't be parsed fail with a problem too */
+pub fn json_config() -> JsonConfig {
+    JsonConfig::default().error_handler(|error: JsonPayloadError, _: &HttpRequest| {
+        ApiError::bad_request("invalid_body", error.to_string()).into()
+    })
+}
+
+pub fn query_config() -> QueryConfig {
+    QueryConfig::default().error_handler(|error: QueryPayloadError, _: &HttpRequest| {
+        ApiError::bad_request("invalid_query", error.to_string()).into()
+    })
+}
+
+pub fn path_config() -> PathConfig {
+    PathConfig::default().error_handler(|error: PathError, _: &HttpRequest| {
+        ApiError::not_found("not_found", error.to_string()).into()
+    })
+}
+
+pub async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::not_found(
+        "not_found",
+        "There is nothing at this path",
+    ))
+}
+
+/// Middleware that gives every request an ID, reusing the `X-Request-Id` sent by a proxy if there
+/// is one. It is echoed in a header and in the body of the errors, so reports can be matched with
+/// the logs.
+pub fn with_request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let request_id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 64)
+        .map(String::from)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+
+    let response = REQUEST_ID.scope(request_id.clone(), srv.call(req));
+
+    async move {
+        let mut response = response.await?;
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(X_REQUEST_ID, value);
+        }
+        Ok(response)
+    }
+}
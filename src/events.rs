@@ -0,0 +1,208 @@
+use crate::model::{TaskEvent, TaskEventKind};
+
+use actix_web::cookie::time::OffsetDateTime;
+use actix_web::rt::{spawn, time::interval};
+use actix_web::web::Bytes;
+use actix_web::Error;
+
+use actix_ws::{Message, MessageStream, ProtocolError, Session};
+
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+
+use tokio::sync::broadcast::{channel, error::RecvError, Sender};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many events are kept to resume the streams of clients that reconnect.
+const KEPT_EVENTS: usize = 1024;
+
+/// How often something is sent on idle streams, so proxies don't close them.
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Where task events are published and streamed from. `MemoryHub` only reaches the clients
+/// connected to this process; running several instances needs a hub backed by the database or a
+/// pub/sub service, and that is all it has to implement.
+pub trait EventHub: Send + Sync {
+    fn publish(&self, user_id: i32, kind: TaskEventKind, task_id: Option<i32>);
+
+    /// The events of a user after `last_event_id`, followed by the new ones as they are published.
+    /// When some of the missed events are gone, a resync event is sent instead of them.
+    fn subscribe(&self, user_id: i32, last_event_id: Option<u64>) -> BoxStream<'static, TaskEvent>;
+}
+
+/// Fan-out of task events inside this process, keeping the latest ones for clients that resume.
+pub struct MemoryHub {
+    state: Arc<Mutex<HubState>>,
+}
+
+struct HubState {
+    last_id: u64,
+    recent: VecDeque<TaskEvent>,
+    sender: Sender<TaskEvent>,
+}
+
+impl MemoryHub {
+    pub fn new() -> Self {
+        let (sender, _) = channel(256);
+        MemoryHub {
+            state: Arc::new(Mutex::new(HubState {
+                last_id: 0,
+                recent: VecDeque::with_capacity(KEPT_EVENTS),
+                sender,
+            })),
+        }
+    }
+}
+
+fn resync(user_id: i32, id: u64) -> TaskEvent {
+    TaskEvent {
+        id,
+        user_id,
+        kind: TaskEventKind::Resync,
+        task_id: None,
+        at: OffsetDateTime::now_utc().unix_timestamp(),
+    }
+}
+
+impl EventHub for MemoryHub {
+    fn publish(&self, user_id: i32, kind: TaskEventKind, task_id: Option<i32>) {
+        let mut state = self.state.lock().unwrap();
+        state.last_id += 1;
+
+        let event = TaskEvent {
+            id: state.last_id,
+            user_id,
+            kind,
+            task_id,
+            at: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        if state.recent.len() == KEPT_EVENTS {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(event.clone());
+
+        // Sending only fails when nobody is listening, which is fine.
+        let _ = state.sender.send(event);
+    }
+
+    fn subscribe(&self, user_id: i32, last_event_id: Option<u64>) -> BoxStream<'static, TaskEvent> {
+        // Subscribing under the lock, so nothing is published between the backlog and the stream.
+        let state = self.state.lock().unwrap();
+        let receiver = state.sender.subscribe();
+
+        let mut backlog = Vec::new();
+        if let Some(last_event_id) = last_event_id {
+            let oldest = state
+                .recent
+                .front()
+                .map_or(state.last_id + 1, |event| event.id);
+
+            // Ids start over when the server restarts, so a higher one is as stale as an old one.
+            if last_event_id.saturating_add(1) < oldest || last_event_id > state.last_id {
+                backlog.push(resync(user_id, state.last_id));
+            } else {
+                backlog.extend(
+                    state
+                        .recent
+                        .iter()
+                        .filter(|event| event.user_id == user_id && event.id > last_event_id)
+                        .cloned(),
+                );
+            }
+        }
+        drop(state);
+
+        let hub = self.state.clone();
+        let live = stream::unfold(receiver, move |mut receiver| {
+            let hub = hub.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) if event.user_id == user_id => return Some((event, receiver)),
+                        Ok(_) => continue,
+                        // The client was too slow and can't know what it missed.
+                        Err(RecvError::Lagged(_)) => {
+                            let last_id = hub.lock().unwrap().last_id;
+                            return Some((resync(user_id, last_id), receiver));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+
+        stream::iter(backlog).chain(live).boxed()
+    }
+}
+
+fn heartbeats() -> impl Stream<Item = ()> {
+    stream::unfold(interval(HEARTBEAT), |mut ticker| async move {
+        ticker.tick().await;
+        Some(((), ticker))
+    })
+}
+
+/// Server-Sent Events with the events of a subscription, and a comment now and then so the
+/// connection isn't closed for being idle.
+pub fn sse_stream(
+    events: BoxStream<'static, TaskEvent>,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    let events = events.map(|event| {
+        format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            event.id,
+            event.kind.as_str(),
+            serde_json::to_string(&event).unwrap()
+        )
+    });
+    let heartbeats = heartbeats().map(|_| ": keep-alive\n\n".to_string());
+
+    stream::select(events, heartbeats).map(|chunk| Ok(Bytes::from(chunk)))
+}
+
+enum Input {
+    Event(TaskEvent),
+    Heartbeat,
+    Message(Result<Message, ProtocolError>),
+    Closed,
+}
+
+/// It sends the events of a subscription to a WebSocket, one JSON text message each, until the
+/// client goes away. Messages from the client other than pings and close are ignored.
+pub fn serve_websocket(
+    mut session: Session,
+    messages: MessageStream,
+    events: BoxStream<'static, TaskEvent>,
+) {
+    spawn(async move {
+        let mut inputs = stream::select(
+            stream::select(
+                events.map(Input::Event),
+                heartbeats().map(|_| Input::Heartbeat),
+            ),
+            messages
+                .map(Input::Message)
+                .chain(stream::once(async { Input::Closed })),
+        )
+        .boxed_local();
+
+        while let Some(input) = inputs.next().await {
+            let sent = match input {
+                Input::Event(event) => session.text(serde_json::to_string(&event).unwrap()).await,
+                Input::Heartbeat => session.ping(b"").await,
+                Input::Message(Ok(Message::Ping(bytes))) => session.pong(&bytes).await,
+                Input::Message(Ok(Message::Close(_))) | Input::Message(Err(_)) | Input::Closed => {
+                    break
+                }
+                Input::Message(Ok(_)) => Ok(()),
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+}
@@ -0,0 +1,672 @@
+/* The language of GET /tasks?filter= and of saved lists. A filter is a list of terms separated by
+spaces, and a task has to match every one of them:
+
+    status:open due:<7d tag:work -tag:waiting project:"Client X" invoice
+
+A term is `field:value`, or a word or a quoted phrase to look for in the name and the description.
+A leading `-` negates it. Values with spaces go in double quotes, inside which \" and \\ are
+escapes. Parsing only splits the terms; validating them turns each one into a condition that
+database.rs translates to SQL. */
+
+use crate::model::Priority;
+
+use chrono::NaiveDate;
+
+use std::fmt;
+
+const FIELDS: [&str; 8] = [
+    "status", "due", "tag", "project", "priority", "billable", "archived", "list",
+];
+
+/// Why a filter can't be used, and where: `position` counts characters from 0.
+#[derive(Debug)]
+pub struct FilterError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl FilterError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        FilterError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StatusFilter {
+    Open, /* Pending or in progress */
+    Pending,
+    InProgress,
+    Finished,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Before,
+    AtOrBefore,
+    After,
+    AtOrAfter,
+    Within, /* No operator: on that day, or between now and then */
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DueValue {
+    Day(NaiveDate),
+    DaysFromToday(i64), /* today, tomorrow and yesterday */
+    SecondsFromNow(i64),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DueFilter {
+    None,
+    Any,
+    Overdue, /* Due before now and not finished */
+    Compare(Comparison, DueValue),
+}
+
+#[derive(Clone)]
+pub enum Condition {
+    Status(StatusFilter),
+    Due(DueFilter),
+    Tag(String),
+    Project(Option<String>), /* None is the inbox */
+    Priority(Option<Priority>),
+    Billable(bool),
+    Archived(bool),
+    /* A saved list, until it is replaced by the terms saved in it */
+    List(String),
+    Text(String),
+    All(Vec<Clause>),
+}
+
+#[derive(Clone)]
+pub struct Clause {
+    pub negated: bool,
+    pub position: usize,
+    pub condition: Condition,
+}
+
+#[derive(Clone)]
+pub struct Filter {
+    pub clauses: Vec<Clause>,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Filter, FilterError> {
+        let clauses = tokenize(input)?
+            .into_iter()
+            .map(validate)
+            .collect::<Result<Vec<Clause>, FilterError>>()?;
+
+        Ok(Filter { clauses })
+    }
+
+    /// Whether a term says if the tasks are archived, so the archived ones aren't left out.
+    pub fn mentions_archived(&self) -> bool {
+        fn mentions(clauses: &[Clause]) -> bool {
+            clauses.iter().any(|clause| match &clause.condition {
+                Condition::Archived(_) => true,
+                Condition::All(clauses) => mentions(clauses),
+                _ => false,
+            })
+        }
+        mentions(&self.clauses)
+    }
+
+    /// The saved lists it uses, with the position of the term that names each of them.
+    pub fn lists(&self) -> Vec<(&str, usize)> {
+        self.clauses
+            .iter()
+            .filter_map(|clause| match &clause.condition {
+                Condition::List(name) => Some((name.as_str(), clause.position)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// It puts the terms of a saved list in place of the terms that name it.
+    pub fn expand_list(&mut self, name: &str, saved: &Filter) {
+        for clause in self.clauses.iter_mut() {
+            if matches!(&clause.condition, Condition::List(list) if list == name) {
+                clause.condition = Condition::All(saved.clauses.clone());
+            }
+        }
+    }
+}
+
+/* A term as it was written */
+struct Term {
+    negated: bool,
+    position: usize,
+    field: Option<String>,
+    value: String,
+    value_position: usize,
+    quoted: bool,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Term>, FilterError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut terms = Vec::new();
+    let mut index = 0;
+
+    loop {
+        while index < chars.len() && chars[index].is_whitespace() {
+            index += 1;
+        }
+        if index == chars.len() {
+            return Ok(terms);
+        }
+
+        let position = index;
+        let negated = chars[index] == '-';
+        if negated {
+            index += 1;
+            if index == chars.len() || chars[index].is_whitespace() {
+                return Err(FilterError::new(
+                    position,
+                    "A - has to be followed by the term it negates",
+                ));
+            }
+        }
+
+        if chars[index] == '"' {
+            let value_position = index;
+            let value = read_quoted(&chars, &mut index)?;
+            terms.push(Term {
+                negated,
+                position,
+                field: None,
+                value,
+                value_position,
+                quoted: true,
+            });
+            continue;
+        }
+
+        let word_start = index;
+        let word = read_bare(&chars, &mut index, true);
+        if index < chars.len() && chars[index] == '"' {
+            return Err(FilterError::new(
+                index,
+                "Unexpected quote, put the whole value in quotes",
+            ));
+        }
+        if index == chars.len() || chars[index] != ':' {
+            terms.push(Term {
+                negated,
+                position,
+                field: None,
+                value: word,
+                value_position: word_start,
+                quoted: false,
+            });
+            continue;
+        }
+
+        if word.is_empty() {
+            return Err(FilterError::new(
+                index,
+                "Missing the name of a field before :",
+            ));
+        }
+        index += 1;
+        let value_position = index;
+        let (value, quoted) = if index < chars.len() && chars[index] == '"' {
+            (read_quoted(&chars, &mut index)?, true)
+        } else {
+            let value = read_bare(&chars, &mut index, false);
+            if index < chars.len() && chars[index] == '"' {
+                return Err(FilterError::new(
+                    index,
+                    "Unexpected quote, put the whole value in quotes",
+                ));
+            }
+            (value, false)
+        };
+        if value.is_empty() && !quoted {
+            return Err(FilterError::new(
+                value_position,
+                format!("Missing the value of {}:", word),
+            ));
+        }
+        terms.push(Term {
+            negated,
+            position,
+            field: Some(word),
+            value,
+            value_position,
+            quoted,
+        });
+    }
+}
+
+/* It reads up to a space, a quote or, for field names, a colon */
+fn read_bare(chars: &[char], index: &mut usize, stop_at_colon: bool) -> String {
+    let start = *index;
+    while *index < chars.len()
+        && !chars[*index].is_whitespace()
+        && chars[*index] != '"'
+        && !(stop_at_colon && chars[*index] == ':')
+    {
+        *index += 1;
+    }
+    chars[start..*index].iter().collect()
+}
+
+/* `index` is on the opening quote, and ends after the closing one */
+fn read_quoted(chars: &[char], index: &mut usize) -> Result<String, FilterError> {
+    let opening = *index;
+    let mut value = String::new();
+    *index += 1;
+
+    loop {
+        match chars.get(*index) {
+            None => return Err(FilterError::new(opening, "This quote is never closed")),
+            Some('\\') if *index + 1 < chars.len() => {
+                value.push(chars[*index + 1]);
+                *index += 2;
+            }
+            Some('"') => {
+                *index += 1;
+                break;
+            }
+            Some(c) => {
+                value.push(*c);
+                *index += 1;
+            }
+        }
+    }
+
+    match chars.get(*index) {
+        Some(c) if !c.is_whitespace() => Err(FilterError::new(
+            *index,
+            "Expected a space after the closing quote",
+        )),
+        _ => Ok(value),
+    }
+}
+
+fn validate(term: Term) -> Result<Clause, FilterError> {
+    let field = match &term.field {
+        Some(field) => field.to_lowercase(),
+        None if term.value.trim().is_empty() => {
+            return Err(FilterError::new(
+                term.position,
+                "Empty quotes match nothing",
+            ))
+        }
+        None => {
+            return Ok(Clause {
+                negated: term.negated,
+                position: term.position,
+                condition: Condition::Text(term.value),
+            })
+        }
+    };
+
+    let value = term.value.trim();
+    let lowercase = value.to_lowercase();
+    let position = term.value_position;
+    /* A quoted "none" is a name, not the absence of one */
+    let none = !term.quoted && lowercase == "none";
+
+    let condition = match field.as_str() {
+        "status" => Condition::Status(match lowercase.as_str() {
+            "open" => StatusFilter::Open,
+            "pending" | "todo" => StatusFilter::Pending,
+            "in_progress" | "running" | "started" => StatusFilter::InProgress,
+            "finished" | "done" => StatusFilter::Finished,
+            _ => {
+                return Err(FilterError::new(
+                    position,
+                    format!(
+                        "\"{}\" isn't a status, use open, pending, in_progress or finished",
+                        value
+                    ),
+                ))
+            }
+        }),
+        "due" => Condition::Due(match lowercase.as_str() {
+            "none" => DueFilter::None,
+            "any" => DueFilter::Any,
+            "overdue" => DueFilter::Overdue,
+            _ => parse_due(&lowercase, position)?,
+        }),
+        "tag" => {
+            let tag = lowercase.trim_start_matches('#').to_string();
+            if tag.is_empty() {
+                return Err(FilterError::new(position, "Missing the name of the tag"));
+            }
+            Condition::Tag(tag)
+        }
+        "project" if none => Condition::Project(None),
+        "project" if value.is_empty() => {
+            return Err(FilterError::new(
+                position,
+                "Missing the name of the project",
+            ))
+        }
+        "project" => Condition::Project(Some(value.to_string())),
+        "priority" => Condition::Priority(match lowercase.as_str() {
+            "none" => None,
+            "high" => Some(Priority::High),
+            "medium" => Some(Priority::Medium),
+            "low" => Some(Priority::Low),
+            _ => {
+                return Err(FilterError::new(
+                    position,
+                    format!(
+                        "\"{}\" isn't a priority, use high, medium, low or none",
+                        value
+                    ),
+                ))
+            }
+        }),
+        "billable" => Condition::Billable(parse_yes_no(&lowercase, position)?),
+        "archived" => Condition::Archived(parse_yes_no(&lowercase, position)?),
+        "list" if value.is_empty() => {
+            return Err(FilterError::new(position, "Missing the name of the list"))
+        }
+        "list" => Condition::List(value.to_string()),
+        _ => {
+            let suggestion = FIELDS
+                .iter()
+                .find(|known| distance(known, &field) <= 2)
+                .map(|known| format!(", did you mean {}:?", known))
+                .unwrap_or_else(|| format!(", use one of {}", FIELDS.join(", ")));
+            return Err(FilterError::new(
+                term.position + term.negated as usize,
+                format!("Unknown field {}{}", field, suggestion),
+            ));
+        }
+    };
+
+    Ok(Clause {
+        negated: term.negated,
+        position: term.position,
+        condition,
+    })
+}
+
+fn parse_yes_no(value: &str, position: usize) -> Result<bool, FilterError> {
+    match value {
+        "yes" | "true" => Ok(true),
+        "no" | "false" => Ok(false),
+        _ => Err(FilterError::new(
+            position,
+            format!("\"{}\" isn't yes or no", value),
+        )),
+    }
+}
+
+/* An optional comparison, then a day or a time from now: <7d, >=2026-10-01, tomorrow */
+fn parse_due(value: &str, position: usize) -> Result<DueFilter, FilterError> {
+    let (comparison, rest) = if let Some(rest) = value.strip_prefix("<=") {
+        (Comparison::AtOrBefore, rest)
+    } else if let Some(rest) = value.strip_prefix(">=") {
+        (Comparison::AtOrAfter, rest)
+    } else if let Some(rest) = value.strip_prefix('<') {
+        (Comparison::Before, rest)
+    } else if let Some(rest) = value.strip_prefix('>') {
+        (Comparison::After, rest)
+    } else {
+        (Comparison::Within, value)
+    };
+    let rest_position = position + (value.len() - rest.len());
+
+    let due = match rest {
+        "today" => DueValue::DaysFromToday(0),
+        "tomorrow" => DueValue::DaysFromToday(1),
+        "yesterday" => DueValue::DaysFromToday(-1),
+        _ => {
+            if let Ok(day) = NaiveDate::parse_from_str(rest, "%Y-%m-%d") {
+                DueValue::Day(day)
+            } else if let Some(seconds) = parse_duration(rest) {
+                DueValue::SecondsFromNow(seconds)
+            } else {
+                return Err(FilterError::new(
+                    rest_position,
+                    format!(
+                        "\"{}\" isn't a date, use a day like 2026-10-01, today, tomorrow, or a time from now like 7d, 12h or 2w",
+                        rest
+                    ),
+                ));
+            }
+        }
+    };
+
+    Ok(DueFilter::Compare(comparison, due))
+}
+
+/* 12h, 7d, 2w, or with a - for the past */
+fn parse_duration(value: &str) -> Option<i64> {
+    let unit = match value.chars().last()? {
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let amount: i64 = value[..value.len() - 1].parse().ok()?;
+    amount.checked_mul(unit)
+}
+
+/* Edit distance, to suggest the field that was meant */
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + (a != *b) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<Clause> {
+        match Filter::parse(input) {
+            Ok(filter) => filter.clauses,
+            Err(error) => panic!("{:?} didn't parse: {}", input, error),
+        }
+    }
+
+    fn error(input: &str) -> FilterError {
+        match Filter::parse(input) {
+            Ok(_) => panic!("{:?} shouldn't parse", input),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn parses_fields_and_words() {
+        let clauses = parse("status:open due:<7d tag:#Work project:none priority:high invoice");
+
+        assert_eq!(clauses.len(), 6);
+        assert!(matches!(
+            clauses[0].condition,
+            Condition::Status(StatusFilter::Open)
+        ));
+        assert!(matches!(
+            clauses[1].condition,
+            Condition::Due(DueFilter::Compare(
+                Comparison::Before,
+                DueValue::SecondsFromNow(604800)
+            ))
+        ));
+        assert!(matches!(&clauses[2].condition, Condition::Tag(tag) if tag == "work"));
+        assert!(matches!(clauses[3].condition, Condition::Project(None)));
+        assert!(matches!(
+            clauses[4].condition,
+            Condition::Priority(Some(Priority::High))
+        ));
+        assert!(matches!(&clauses[5].condition, Condition::Text(text) if text == "invoice"));
+        assert_eq!(
+            clauses
+                .iter()
+                .map(|clause| clause.position)
+                .collect::<Vec<_>>(),
+            [0, 12, 20, 30, 43, 57]
+        );
+    }
+
+    #[test]
+    fn reads_due_comparisons() {
+        let due = |input: &str| match parse(input)[0].condition {
+            Condition::Due(due) => due,
+            _ => panic!("{:?} isn't a due date", input),
+        };
+
+        assert!(
+            due("due:<=tomorrow")
+                == DueFilter::Compare(Comparison::AtOrBefore, DueValue::DaysFromToday(1))
+        );
+        assert!(
+            due("due:>=2026-10-01")
+                == DueFilter::Compare(
+                    Comparison::AtOrAfter,
+                    DueValue::Day(NaiveDate::from_ymd_opt(2026, 10, 1).unwrap())
+                )
+        );
+        assert!(
+            due("due:>yesterday")
+                == DueFilter::Compare(Comparison::After, DueValue::DaysFromToday(-1))
+        );
+        assert!(
+            due("due:-2w")
+                == DueFilter::Compare(Comparison::Within, DueValue::SecondsFromNow(-1209600))
+        );
+        assert!(due("due:OVERDUE") == DueFilter::Overdue);
+    }
+
+    /* Every term has to match, and a - only takes the term it is written on */
+    #[test]
+    fn negates_only_the_next_term() {
+        let clauses = parse("-tag:waiting tag:work -\"on hold\" tag:follow-up");
+
+        assert_eq!(clauses.len(), 4);
+        assert!(clauses[0].negated);
+        assert!(!clauses[1].negated);
+        assert!(clauses[2].negated);
+        assert!(matches!(&clauses[2].condition, Condition::Text(text) if text == "on hold"));
+        assert!(!clauses[3].negated);
+        assert!(matches!(&clauses[3].condition, Condition::Tag(tag) if tag == "follow-up"));
+    }
+
+    /* A negated list negates all of its terms together, not each one */
+    #[test]
+    fn negates_a_saved_list_as_a_whole() {
+        let mut filter = Filter::parse("-list:Waiting tag:work").unwrap();
+        let saved = Filter::parse("tag:waiting -status:finished").unwrap();
+
+        assert_eq!(filter.lists(), [("Waiting", 0)]);
+        filter.expand_list("Waiting", &saved);
+
+        assert!(filter.clauses[0].negated);
+        match &filter.clauses[0].condition {
+            Condition::All(clauses) => {
+                assert_eq!(clauses.len(), 2);
+                assert!(!clauses[0].negated);
+                assert!(clauses[1].negated);
+            }
+            _ => panic!("the list wasn't expanded"),
+        }
+        assert!(!filter.clauses[1].negated);
+        assert!(filter.lists().is_empty());
+    }
+
+    #[test]
+    fn mentions_archived_inside_lists() {
+        let mut filter = Filter::parse("list:old").unwrap();
+        assert!(!filter.mentions_archived());
+
+        filter.expand_list("old", &Filter::parse("archived:yes").unwrap());
+        assert!(filter.mentions_archived());
+    }
+
+    #[test]
+    fn reads_quoted_values() {
+        let clauses =
+            parse(r#"project:"Client X" "say \"hi\" \\ now" project:"none" tag:"Long Tag""#);
+
+        assert!(matches!(
+            &clauses[0].condition,
+            Condition::Project(Some(name)) if name == "Client X"
+        ));
+        assert!(matches!(
+            &clauses[1].condition,
+            Condition::Text(text) if text == r#"say "hi" \ now"#
+        ));
+        /* Quoted, none is the name of a project and not the inbox */
+        assert!(matches!(
+            &clauses[2].condition,
+            Condition::Project(Some(name)) if name == "none"
+        ));
+        assert!(matches!(&clauses[3].condition, Condition::Tag(tag) if tag == "long tag"));
+    }
+
+    #[test]
+    fn rejects_broken_quotes() {
+        let unclosed = error(r#"tag:work "never closed"#);
+        assert_eq!(unclosed.position, 9);
+        assert_eq!(unclosed.message, "This quote is never closed");
+
+        let inside = error(r#"project:Client"X""#);
+        assert_eq!(inside.position, 14);
+
+        let after = error(r#""a"b"#);
+        assert_eq!(after.position, 3);
+        assert_eq!(after.message, "Expected a space after the closing quote");
+
+        let empty = error(r#"tag:work """#);
+        assert_eq!(empty.position, 9);
+        assert_eq!(empty.message, "Empty quotes match nothing");
+    }
+
+    #[test]
+    fn suggests_the_field_that_was_meant() {
+        let typo = error("tag:work -stauts:open");
+        assert_eq!(typo.position, 10);
+        assert_eq!(typo.message, "Unknown field stauts, did you mean status:?");
+
+        let unknown = error("colour:red");
+        assert_eq!(unknown.position, 0);
+        assert!(unknown
+            .message
+            .starts_with("Unknown field colour, use one of status, due"));
+    }
+
+    #[test]
+    fn rejects_invalid_values_and_operators() {
+        let cases = [
+            ("status:closed", 7),
+            ("priority:urgent", 9),
+            ("billable:maybe", 9),
+            ("due:=>7d", 4),
+            ("due:<<7d", 5),
+            ("due:<7x", 5),
+            ("due:2026-13-01", 4),
+            ("tag:#", 4),
+            ("tag:", 4),
+            (":open", 0),
+            ("- tag:work", 0),
+        ];
+
+        for (input, position) in cases {
+            assert_eq!(error(input).position, position, "position of {:?}", input);
+        }
+    }
+}
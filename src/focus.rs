@@ -0,0 +1,84 @@
+use crate::database::advance_focus_sessions;
+use crate::events::EventHub;
+use crate::model::{Db, FocusTransition, TaskEventKind};
+
+use actix_web::rt::{spawn, time::interval};
+use actix_web::web::{Bytes, Data};
+use actix_web::Error;
+
+use futures_util::stream::{unfold, Stream};
+
+use tokio::sync::broadcast::{channel, error::RecvError, Sender};
+
+use std::time::Duration;
+
+/// How often the running focus sessions are checked for intervals that have run out.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Fan-out of focus session transitions to the clients that are listening for them.
+pub struct FocusEvents {
+    sender: Sender<FocusTransition>,
+}
+
+impl FocusEvents {
+    pub fn new() -> Self {
+        let (sender, _) = channel(256);
+        FocusEvents { sender }
+    }
+
+    pub fn publish(&self, transition: FocusTransition) {
+        // Sending only fails when nobody is listening, which is fine.
+        let _ = self.sender.send(transition);
+    }
+
+    /// Server-Sent Events stream with the transitions of a single user.
+    pub fn stream(&self, user_id: i32) -> impl Stream<Item = Result<Bytes, Error>> {
+        unfold(self.sender.subscribe(), move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(transition) if transition.user_id == user_id => {
+                        let event = format!(
+                            "event: {}\ndata: {}\n\n",
+                            transition.phase,
+                            serde_json::to_string(&transition).unwrap()
+                        );
+                        return Some((Ok(Bytes::from(event)), receiver));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+/* The task event of the timer change a transition made, if it made one */
+fn timer_event(transition: &FocusTransition) -> Option<TaskEventKind> {
+    match transition.phase.as_str() {
+        "work" => Some(TaskEventKind::TimerStarted),
+        "break" => Some(TaskEventKind::TimerStopped),
+        _ => None,
+    }
+}
+
+/// It spawns the background task that moves focus sessions from work to break and back when their
+/// intervals end, publishing every transition, and the timer change it made to the task events.
+pub fn spawn_focus_ticker(db: Data<Db>, events: Data<FocusEvents>, hub: Data<dyn EventHub>) {
+    spawn(async move {
+        let mut ticker = interval(TICK);
+        loop {
+            ticker.tick().await;
+            match advance_focus_sessions(&db).await {
+                Ok(transitions) => {
+                    for transition in transitions {
+                        if let Some(kind) = timer_event(&transition) {
+                            hub.publish(transition.user_id, kind, Some(transition.task_id));
+                        }
+                        events.publish(transition);
+                    }
+                }
+                Err(err) => println!("{:#?}", err),
+            }
+        }
+    });
+}
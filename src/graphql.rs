@@ -0,0 +1,562 @@
+use crate::database::{
+    add_task, delete_task, finish_task_and_save_time, get_pomodoro_stats, get_projects_by_user,
+    get_sessions_by_task_ids, get_task_by_user, get_task_sessions, get_tasks_by_ids,
+    get_tasks_by_user, get_user, get_user_settings, start_task_and_save_time,
+};
+use crate::error::ApiError;
+use crate::events::EventHub;
+use crate::model::{
+    ArchiveFilter, DBUser, Db, EstimateReport, NewTask, PomodoroStats, Priority, Project,
+    ReportPeriod, ResponseTask, TaskError, TaskEvent, TaskEventKind, TaskSession, TaskStatus,
+};
+use crate::reports::estimate_report;
+use crate::timezone::UserClock;
+use crate::utils::validate_token;
+use crate::validation::validate_task;
+
+use actix_web::http::header::HeaderValue;
+use actix_web::rt::spawn;
+use actix_web::web::Data;
+
+use actix_ws::{CloseReason, Message, MessageStream, Session};
+
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{
+    ComplexObject, Context, InputObject, Object, Result, Schema, SimpleObject, Subscription,
+};
+
+use futures_util::future::ready;
+use futures_util::stream::{Stream, StreamExt};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Deeper or costlier queries are refused, since fields like `Session.task` let a query nest as
+/// much as it wants.
+const MAX_DEPTH: usize = 12;
+const MAX_COMPLEXITY: usize = 2000;
+
+/// Requests in a batch. Each one is limited as above, so without this a batch could still cost
+/// as much as a client wants.
+pub const MAX_BATCH: usize = 20;
+
+/// Tasks in a page when `first` is missing, and the most a page can have.
+const DEFAULT_PAGE: usize = 50;
+const MAX_PAGE: usize = 200;
+
+pub type TaskSchema = Schema<Query, Mutation, Subscription>;
+
+pub fn build_schema(db: Data<Db>, hub: Data<dyn EventHub>) -> TaskSchema {
+    Schema::build(Query, Mutation, Subscription)
+        .data(db)
+        .data(hub)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Who a request comes from, with the loaders that batch what its fields look up: the sessions of
+/// a page of tasks are one query instead of one per task. It lasts as long as a request or a
+/// WebSocket connection, and the loaders don't cache, so nothing is kept between them.
+pub struct Viewer {
+    user_id: i32,
+    tasks: DataLoader<TaskLoader>,
+    sessions: DataLoader<SessionLoader>,
+    projects: DataLoader<ProjectLoader>,
+}
+
+impl Viewer {
+    pub fn new(user_id: i32, db: &Data<Db>) -> Arc<Viewer> {
+        Arc::new(Viewer {
+            user_id,
+            tasks: DataLoader::new(
+                TaskLoader {
+                    user_id,
+                    db: db.clone(),
+                },
+                spawn,
+            ),
+            sessions: DataLoader::new(
+                SessionLoader {
+                    user_id,
+                    db: db.clone(),
+                },
+                spawn,
+            ),
+            projects: DataLoader::new(
+                ProjectLoader {
+                    user_id,
+                    db: db.clone(),
+                },
+                spawn,
+            ),
+        })
+    }
+}
+
+fn graphql_error(error: impl Into<ApiError>) -> async_graphql::Error {
+    error.into().into_graphql()
+}
+
+fn viewer<'a>(ctx: &Context<'a>) -> Result<&'a Viewer> {
+    ctx.data_opt::<Arc<Viewer>>()
+        .map(|viewer| viewer.as_ref())
+        .ok_or_else(|| {
+            graphql_error(ApiError::unauthorized(
+                "missing_token",
+                "Empty validation token",
+            ))
+        })
+}
+
+pub struct TaskLoader {
+    user_id: i32,
+    db: Data<Db>,
+}
+
+impl Loader<i32> for TaskLoader {
+    type Value = ResponseTask;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, task_ids: &[i32]) -> Result<HashMap<i32, ResponseTask>> {
+        let tasks = get_tasks_by_ids(self.user_id, task_ids, &self.db)
+            .await
+            .map_err(graphql_error)?;
+
+        Ok(tasks.into_iter().map(|task| (task.id, task)).collect())
+    }
+}
+
+pub struct SessionLoader {
+    user_id: i32,
+    db: Data<Db>,
+}
+
+impl Loader<i32> for SessionLoader {
+    type Value = Vec<TaskSession>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, task_ids: &[i32]) -> Result<HashMap<i32, Vec<TaskSession>>> {
+        let sessions = get_sessions_by_task_ids(self.user_id, task_ids, &self.db)
+            .await
+            .map_err(graphql_error)?;
+
+        let mut by_task: HashMap<i32, Vec<TaskSession>> = HashMap::new();
+        for session in sessions {
+            by_task
+                .entry(session.task_id)
+                .or_default()
+                .push(TaskSession::from(session));
+        }
+        Ok(by_task)
+    }
+}
+
+/* Users have a handful of projects, so loading all of them is still one small query */
+pub struct ProjectLoader {
+    user_id: i32,
+    db: Data<Db>,
+}
+
+impl Loader<i32> for ProjectLoader {
+    type Value = Project;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, project_ids: &[i32]) -> Result<HashMap<i32, Project>> {
+        let projects = get_projects_by_user(self.user_id, &self.db)
+            .await
+            .map_err(graphql_error)?;
+
+        Ok(projects
+            .into_iter()
+            .filter(|project| project_ids.contains(&project.id))
+            .map(|project| (project.id, project))
+            .collect())
+    }
+}
+
+#[ComplexObject]
+impl ResponseTask {
+    /// The tracked sessions of the task, with their ids.
+    async fn sessions(&self, ctx: &Context<'_>) -> Result<Vec<TaskSession>> {
+        let sessions = viewer(ctx)?.sessions.load_one(self.id).await?;
+        Ok(sessions.unwrap_or_default())
+    }
+
+    async fn project(&self, ctx: &Context<'_>) -> Result<Option<Project>> {
+        match self.project_id {
+            Some(project_id) => viewer(ctx)?.projects.load_one(project_id).await,
+            None => Ok(None),
+        }
+    }
+}
+
+#[ComplexObject]
+impl TaskSession {
+    async fn task(&self, ctx: &Context<'_>) -> Result<Option<ResponseTask>> {
+        viewer(ctx)?.tasks.load_one(self.task_id).await
+    }
+}
+
+/// Which tasks to list. Every field that is set has to match.
+#[derive(InputObject, Default)]
+pub struct TaskFilter {
+    status: Option<TaskStatus>,
+    tag: Option<String>,
+    project_id: Option<i32>,
+    priority: Option<Priority>,
+    billable: Option<bool>,
+    due_after: Option<i64>,  /* Date expressed in seconds */
+    due_before: Option<i64>, /* Date expressed in seconds */
+    /// Part of the name or the description, ignoring case.
+    search: Option<String>,
+    /// Archived tasks are left out unless this is INCLUDE or ONLY.
+    archived: Option<ArchiveFilter>,
+}
+
+impl TaskFilter {
+    fn matches(&self, task: &ResponseTask) -> bool {
+        let tag = self
+            .tag
+            .as_ref()
+            .map(|tag| tag.trim().trim_start_matches('#').to_lowercase());
+        let search = self.search.as_ref().map(|search| search.to_lowercase());
+
+        self.archived.unwrap_or_default().matches(task)
+            && self.status.map_or(true, |status| task.status == status)
+            && tag.map_or(true, |tag| task.tags.contains(&tag))
+            && self
+                .project_id
+                .map_or(true, |project_id| task.project_id == Some(project_id))
+            && self
+                .priority
+                .map_or(true, |priority| task.priority == Some(priority))
+            && self
+                .billable
+                .map_or(true, |billable| task.billable == billable)
+            && self.due_after.map_or(true, |due_after| {
+                task.due_at.map_or(false, |due_at| due_at >= due_after)
+            })
+            && self.due_before.map_or(true, |due_before| {
+                task.due_at.map_or(false, |due_at| due_at < due_before)
+            })
+            && search.map_or(true, |search| {
+                task.name.to_lowercase().contains(&search)
+                    || task.description.to_lowercase().contains(&search)
+            })
+    }
+}
+
+/// Aggregates over every task that matches the filter, not only the ones in the page.
+#[derive(SimpleObject)]
+pub struct TaskTotals {
+    total_count: usize,
+    estimated: i64, /* Duration expressed in seconds */
+    tracked: i64,   /* Duration expressed in seconds */
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The user the token belongs to.
+    async fn viewer(&self, ctx: &Context<'_>) -> Result<DBUser> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        get_user(viewer(ctx)?.user_id, db)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn task(&self, ctx: &Context<'_>, id: i32) -> Result<Option<ResponseTask>> {
+        viewer(ctx)?.tasks.load_one(id).await
+    }
+
+    /// The tasks in the order they were created, a page at a time. `after` is the cursor of the
+    /// last edge of the previous page.
+    async fn tasks(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TaskFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<i32, ResponseTask, TaskTotals>> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let filter = filter.unwrap_or_default();
+        let first = first.map_or(DEFAULT_PAGE, |first| (first.max(0) as usize).min(MAX_PAGE));
+        let after = match after {
+            Some(after) => Some(after.parse::<i32>().map_err(|_| {
+                graphql_error(ApiError::bad_request(
+                    "invalid_cursor",
+                    "The cursor is invalid",
+                ))
+            })?),
+            None => None,
+        };
+
+        let mut tasks: Vec<ResponseTask> = get_tasks_by_user(viewer(ctx)?.user_id, db)
+            .await
+            .map_err(graphql_error)?
+            .into_iter()
+            .filter(|task| filter.matches(task))
+            .collect();
+        /* The cursors are ids, so the pages go in the order of the ids */
+        tasks.sort_by_key(|task| task.id);
+
+        let totals = TaskTotals {
+            total_count: tasks.len(),
+            estimated: tasks.iter().filter_map(|task| task.estimate).sum(),
+            tracked: tasks.iter().map(|task| task.tracked).sum(),
+        };
+        let start = after.map_or(0, |after| tasks.partition_point(|task| task.id <= after));
+        let end = (start + first).min(tasks.len());
+
+        let mut connection =
+            Connection::with_additional_fields(start > 0, end < tasks.len(), totals);
+        connection
+            .edges
+            .extend(tasks.drain(start..end).map(|task| Edge::new(task.id, task)));
+
+        Ok(connection)
+    }
+
+    /// Every tracked session of a task.
+    async fn sessions(&self, ctx: &Context<'_>, task_id: i32) -> Result<Vec<TaskSession>> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let sessions = get_task_sessions(task_id, viewer(ctx)?.user_id, db)
+            .await
+            .map_err(graphql_error)?;
+
+        Ok(sessions.into_iter().map(TaskSession::from).collect())
+    }
+
+    async fn projects(&self, ctx: &Context<'_>) -> Result<Vec<Project>> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        get_projects_by_user(viewer(ctx)?.user_id, db)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// Estimation accuracy overall, per period and per tag, as in /reports/estimates.
+    async fn estimate_report(
+        &self,
+        ctx: &Context<'_>,
+        period: Option<ReportPeriod>,
+    ) -> Result<EstimateReport> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let user_id = viewer(ctx)?.user_id;
+        let tasks = get_tasks_by_user(user_id, db)
+            .await
+            .map_err(graphql_error)?;
+        let settings = get_user_settings(user_id, db)
+            .await
+            .map_err(graphql_error)?;
+
+        Ok(estimate_report(
+            &tasks,
+            period.unwrap_or(ReportPeriod::Week),
+            &UserClock::from(&settings),
+        ))
+    }
+
+    /// Completed pomodoros per task and per day, as in /focus/stats.
+    async fn pomodoro_stats(&self, ctx: &Context<'_>) -> Result<PomodoroStats> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let user_id = viewer(ctx)?.user_id;
+        let settings = get_user_settings(user_id, db)
+            .await
+            .map_err(graphql_error)?;
+
+        get_pomodoro_stats(user_id, &UserClock::from(&settings), db)
+            .await
+            .map_err(graphql_error)
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn add_task(&self, ctx: &Context<'_>, task: NewTask) -> Result<ResponseTask> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let hub = ctx.data_unchecked::<Data<dyn EventHub>>();
+        let user_id = viewer(ctx)?.user_id;
+
+        let errors = validate_task(&task);
+        if !errors.is_empty() {
+            return Err(graphql_error(ApiError::validation(errors)));
+        }
+        let task_id = add_task(user_id, task, db).await.map_err(graphql_error)?;
+        hub.publish(user_id, TaskEventKind::TaskCreated, Some(task_id));
+
+        get_task_by_user(task_id, user_id, db)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// Returns the id of the deleted task.
+    async fn delete_task(&self, ctx: &Context<'_>, id: i32) -> Result<i32> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let hub = ctx.data_unchecked::<Data<dyn EventHub>>();
+        let user_id = viewer(ctx)?.user_id;
+
+        if delete_task(id, user_id, db)
+            .await
+            .map_err(graphql_error)?
+            .rows_affected()
+            == 1
+        {
+            hub.publish(user_id, TaskEventKind::TaskDeleted, Some(id));
+            Ok(id)
+        } else {
+            Err(graphql_error(TaskError::InvalidId))
+        }
+    }
+
+    async fn start_task(&self, ctx: &Context<'_>, id: i32) -> Result<ResponseTask> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let hub = ctx.data_unchecked::<Data<dyn EventHub>>();
+        let user_id = viewer(ctx)?.user_id;
+
+        if !start_task_and_save_time(id, user_id, db)
+            .await
+            .map_err(graphql_error)?
+        {
+            return Err(graphql_error(ApiError::conflict(
+                "task_not_started",
+                "Couldn't be started",
+            )));
+        }
+        hub.publish(user_id, TaskEventKind::TimerStarted, Some(id));
+
+        get_task_by_user(id, user_id, db)
+            .await
+            .map_err(graphql_error)
+    }
+
+    async fn finish_task(&self, ctx: &Context<'_>, id: i32) -> Result<ResponseTask> {
+        let db = ctx.data_unchecked::<Data<Db>>();
+        let hub = ctx.data_unchecked::<Data<dyn EventHub>>();
+        let user_id = viewer(ctx)?.user_id;
+
+        if !finish_task_and_save_time(id, user_id, db)
+            .await
+            .map_err(graphql_error)?
+        {
+            return Err(graphql_error(ApiError::conflict(
+                "task_already_finished",
+                "Task already finished",
+            )));
+        }
+        hub.publish(user_id, TaskEventKind::TimerStopped, Some(id));
+
+        get_task_by_user(id, user_id, db)
+            .await
+            .map_err(graphql_error)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Changes to the tasks and timers of the viewer, the same events as /events.
+    async fn task_events(
+        &self,
+        ctx: &Context<'_>,
+        last_event_id: Option<u64>,
+    ) -> Result<impl Stream<Item = TaskEvent>> {
+        let hub = ctx.data_unchecked::<Data<dyn EventHub>>();
+        Ok(hub.subscribe(viewer(ctx)?.user_id, last_event_id))
+    }
+}
+
+/// The GraphQL WebSocket protocol the client asked for in `Sec-WebSocket-Protocol`, if it is one
+/// that is supported.
+pub fn websocket_protocol(protocols: Option<&HeaderValue>) -> Option<WebSocketProtocols> {
+    protocols?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find_map(|protocol| protocol.trim().parse().ok())
+}
+
+/// It runs the subscriptions of a GraphQL WebSocket connection until either side closes it. The
+/// user comes from the handshake or, when it had no token, from the `token` or `Authorization`
+/// of the connection_init payload, as GraphQL clients can't set headers on WebSockets either.
+pub fn serve_graphql_websocket(
+    schema: TaskSchema,
+    protocol: WebSocketProtocols,
+    mut session: Session,
+    messages: MessageStream,
+    user_id: Option<i32>,
+    db: Data<Db>,
+) {
+    let pong = session.clone();
+    let incoming = messages
+        .take_while(|message| {
+            ready(matches!(message, Ok(message) if !matches!(message, Message::Close(_))))
+        })
+        .filter_map(move |message| {
+            let mut pong = pong.clone();
+            async move {
+                match message {
+                    Ok(Message::Text(text)) => Some(text.into_bytes()),
+                    Ok(Message::Binary(bytes)) => Some(bytes),
+                    Ok(Message::Ping(bytes)) => {
+                        let _ = pong.pong(&bytes).await;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+        });
+
+    let mut connection = async_graphql::Data::default();
+    if let Some(user_id) = user_id {
+        connection.insert(Viewer::new(user_id, &db));
+    }
+
+    let mut outgoing = WebSocket::new(schema, incoming, protocol)
+        .connection_data(connection)
+        .on_connection_init(move |payload| async move {
+            let mut data = async_graphql::Data::default();
+            if user_id.is_some() {
+                return Ok(data);
+            }
+
+            let token = ["token", "Authorization"]
+                .iter()
+                .find_map(|key| payload.get(key)?.as_str())
+                .and_then(|token| HeaderValue::from_str(token).ok());
+            let user_id = validate_token(token.as_ref()).map_err(graphql_error)?;
+            data.insert(Viewer::new(user_id, &db));
+
+            Ok(data)
+        })
+        .boxed_local();
+
+    spawn(async move {
+        while let Some(message) = outgoing.next().await {
+            match message {
+                WsMessage::Text(text) => {
+                    if session.text(text).await.is_err() {
+                        return;
+                    }
+                }
+                WsMessage::Close(code, reason) => {
+                    let _ = session
+                        .close(Some(CloseReason {
+                            code: code.into(),
+                            description: Some(reason),
+                        }))
+                        .await;
+                    return;
+                }
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+}
@@ -1,5 +1,10 @@
 use argon2::Config;
 
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use sha2::{Digest, Sha256};
+
 use std::env::var;
 
 pub fn hash(password: &str) -> String {
@@ -8,3 +13,49 @@ pub fn hash(password: &str) -> String {
     let hash = argon2::hash_encoded(password.as_bytes(), &salt, &config).unwrap();
     return hash;
 }
+
+/// Random token for URLs that work without a login, like calendar feeds.
+pub fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Secrets are long and random, so a plain SHA-256 is enough to avoid storing them as they are.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// HMAC-SHA256 (RFC 2104) of a message, in hex. It signs the payloads sent to webhooks.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    hex::encode(hmac_sha256_bytes(key, message))
+}
+
+/// HMAC-SHA256 of a message as raw bytes, for signatures whose keys are themselves HMACs, like
+/// the ones of S3 requests.
+pub fn hmac_sha256_bytes(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner_pad: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    let outer_pad: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+
+    let inner = Sha256::new()
+        .chain_update(&inner_pad)
+        .chain_update(message)
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(&outer_pad)
+        .chain_update(inner)
+        .finalize();
+
+    outer.to_vec()
+}
@@ -0,0 +1,346 @@
+use crate::model::{Priority, Recurrence, ResponseTask, TaskStatus};
+use crate::timezone::UserClock;
+
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+const PRODID: &str = "-//surphury//todo-list-backend//EN";
+
+/// Escapes a TEXT value (RFC 5545, section 3.3.11).
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn date_time(timestamp: i64) -> String {
+    match Utc.timestamp_opt(timestamp, 0).single() {
+        Some(date_time) => date_time.format("%Y%m%dT%H%M%SZ").to_string(),
+        None => String::from("19700101T000000Z"),
+    }
+}
+
+/// Appends a content line, folding it so no line is longer than 75 octets.
+fn push_line(calendar: &mut String, line: &str) {
+    let mut octets = 0;
+    for character in line.chars() {
+        if octets + character.len_utf8() > 75 {
+            calendar.push_str("\r\n ");
+            octets = 1;
+        }
+        calendar.push(character);
+        octets += character.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+fn todo(calendar: &mut String, task: &ResponseTask, dtstamp: i64) {
+    push_line(calendar, "BEGIN:VTODO");
+    push_line(calendar, &format!("UID:{}", task.uid));
+    push_line(calendar, &format!("DTSTAMP:{}", date_time(dtstamp)));
+    push_line(
+        calendar,
+        &format!("LAST-MODIFIED:{}", date_time(task.updated_at)),
+    );
+    push_line(calendar, &format!("SUMMARY:{}", escape_text(&task.name)));
+    if !task.description.is_empty() {
+        push_line(
+            calendar,
+            &format!("DESCRIPTION:{}", escape_text(&task.description)),
+        );
+    }
+    if let Some(due_at) = task.due_at {
+        push_line(calendar, &format!("DUE:{}", date_time(due_at)));
+        /* A rule needs a start to count from, the due date is the first occurrence */
+        if let Some(recurrence) = &task.recurrence {
+            push_line(calendar, &format!("DTSTART:{}", date_time(due_at)));
+            push_line(calendar, &format!("RRULE:{}", recurrence.to_rrule()));
+        }
+    }
+    if let Some(priority) = task.priority {
+        // iCalendar goes from 1 (highest) to 9 (lowest).
+        let priority = match priority {
+            Priority::High => 1,
+            Priority::Medium => 5,
+            Priority::Low => 9,
+        };
+        push_line(calendar, &format!("PRIORITY:{}", priority));
+    }
+    let status = match task.status {
+        TaskStatus::Pending => "NEEDS-ACTION",
+        TaskStatus::InProgress => "IN-PROCESS",
+        TaskStatus::Finished => "COMPLETED",
+    };
+    push_line(calendar, &format!("STATUS:{}", status));
+    if task.status == TaskStatus::Finished {
+        if let Some(completed) = task
+            .history
+            .iter()
+            .filter_map(|segment| segment.finish_time)
+            .max()
+        {
+            push_line(calendar, &format!("COMPLETED:{}", date_time(completed)));
+        }
+    }
+    if !task.tags.is_empty() {
+        let tags: Vec<String> = task.tags.iter().map(|tag| escape_text(tag)).collect();
+        push_line(calendar, &format!("CATEGORIES:{}", tags.join(",")));
+    }
+    push_line(calendar, "END:VTODO");
+}
+
+/// Every tracked segment of a task becomes an event. A segment that is still running ends now.
+fn events(calendar: &mut String, task: &ResponseTask, now: i64) {
+    for segment in &task.history {
+        push_line(calendar, "BEGIN:VEVENT");
+        push_line(
+            calendar,
+            &format!("UID:session-{}-{}@surphury", task.id, segment.start_time),
+        );
+        push_line(calendar, &format!("DTSTAMP:{}", date_time(now)));
+        push_line(
+            calendar,
+            &format!("DTSTART:{}", date_time(segment.start_time)),
+        );
+        push_line(
+            calendar,
+            &format!("DTEND:{}", date_time(segment.finish_time.unwrap_or(now))),
+        );
+        push_line(calendar, &format!("SUMMARY:{}", escape_text(&task.name)));
+        push_line(calendar, &format!("RELATED-TO:{}", task.uid));
+        push_line(calendar, "TRANSP:OPAQUE");
+        push_line(calendar, "END:VEVENT");
+    }
+}
+
+fn begin_calendar(calendar: &mut String) {
+    push_line(calendar, "BEGIN:VCALENDAR");
+    push_line(calendar, "VERSION:2.0");
+    push_line(calendar, &format!("PRODID:{}", PRODID));
+    push_line(calendar, "CALSCALE:GREGORIAN");
+}
+
+/// It renders a single task as a calendar object, the way CalDAV serves it. It only changes when the
+/// task does, so it can be hashed into an ETag.
+pub fn render_todo_object(task: &ResponseTask) -> String {
+    let mut calendar = String::new();
+
+    begin_calendar(&mut calendar);
+    todo(&mut calendar, task, task.updated_at);
+    push_line(&mut calendar, "END:VCALENDAR");
+
+    calendar
+}
+
+/// It renders the tasks of a user as VTODOs and their tracked time as VEVENTs.
+pub fn render_calendar(tasks: &[ResponseTask], now: i64) -> String {
+    let mut calendar = String::new();
+
+    begin_calendar(&mut calendar);
+    push_line(&mut calendar, "X-WR-CALNAME:Surphury tasks");
+
+    for task in tasks {
+        todo(&mut calendar, task, now);
+        events(&mut calendar, task, now);
+    }
+
+    push_line(&mut calendar, "END:VCALENDAR");
+
+    calendar
+}
+
+/// The parts of a VTODO that map onto a task.
+pub struct ParsedTodo {
+    pub summary: String,
+    pub description: String,
+    pub due_at: Option<i64>, /* Date expressed in seconds */
+    pub priority: Option<Priority>,
+    pub categories: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+    pub completed: bool,
+}
+
+pub enum IcalError {
+    Malformed,
+    NotATodo,
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut characters = text.chars();
+
+    while let Some(character) = characters.next() {
+        if character == '\\' {
+            match characters.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(escaped) => unescaped.push(escaped),
+                None => {}
+            }
+        } else {
+            unescaped.push(character);
+        }
+    }
+
+    unescaped
+}
+
+/// Splits a list value on the commas that are not escaped.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut escaped = false;
+
+    for character in value.chars() {
+        if escaped {
+            item.push('\\');
+            item.push(character);
+            escaped = false;
+        } else if character == '\\' {
+            escaped = true;
+        } else if character == ',' {
+            items.push(unescape_text(&item));
+            item.clear();
+        } else {
+            item.push(character);
+        }
+    }
+    items.push(unescape_text(&item));
+
+    items
+}
+
+/// Splits a content line into its name, parameters and value. The value starts at the first colon
+/// that is not inside a quoted parameter value.
+fn split_content_line(line: &str) -> Option<(String, Vec<(String, String)>, &str)> {
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(index, character)| {
+        match character {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => return Some(index),
+            _ => {}
+        }
+        None
+    })?;
+
+    let mut parts = line[..colon].split(';');
+    let name = parts.next()?.to_ascii_uppercase();
+    let params = parts
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((
+                key.to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+
+    Some((name, params, &line[colon + 1..]))
+}
+
+/// Reads a DATE or DATE-TIME value. Dates and floating times are taken in the user's time zone.
+fn parse_date_time(value: &str, params: &[(String, String)], clock: &UserClock) -> Option<i64> {
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some(clock.start_of_day(date));
+    }
+
+    if let Some(utc) = value.strip_suffix('Z') {
+        let date_time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(Utc.from_utc_datetime(&date_time).timestamp());
+    }
+
+    let date_time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let time_zone = params
+        .iter()
+        .find(|(key, _)| key == "TZID")
+        .and_then(|(_, tzid)| tzid.parse::<Tz>().ok())
+        .unwrap_or(clock.time_zone);
+
+    time_zone
+        .from_local_datetime(&date_time)
+        .earliest()
+        .map(|date_time| date_time.timestamp())
+}
+
+/// It reads the first VTODO of a calendar object, ignoring the alarms inside it.
+pub fn parse_todo(data: &str, clock: &UserClock) -> Result<ParsedTodo, IcalError> {
+    let unfolded = data
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut todo: Option<ParsedTodo> = None;
+    let mut in_todo = false;
+    let mut nested_components = 0;
+    let mut saw_calendar = false;
+
+    for line in unfolded.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.is_empty() {
+            continue;
+        }
+        let (name, params, value) = split_content_line(line).ok_or(IcalError::Malformed)?;
+
+        match (name.as_str(), value.to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VCALENDAR") => saw_calendar = true,
+            ("BEGIN", "VTODO") if todo.is_none() => {
+                in_todo = true;
+                todo = Some(ParsedTodo {
+                    summary: String::new(),
+                    description: String::new(),
+                    due_at: None,
+                    priority: None,
+                    categories: Vec::new(),
+                    recurrence: None,
+                    completed: false,
+                });
+            }
+            ("END", "VTODO") if in_todo => in_todo = false,
+            ("BEGIN", _) if in_todo => nested_components += 1,
+            ("END", _) if in_todo => nested_components -= 1,
+            _ => {}
+        }
+
+        if !in_todo || nested_components > 0 {
+            continue;
+        }
+        let todo = match todo.as_mut() {
+            Some(todo) => todo,
+            None => continue,
+        };
+
+        match name.as_str() {
+            "SUMMARY" => todo.summary = unescape_text(value),
+            "DESCRIPTION" => todo.description = unescape_text(value),
+            "DUE" => {
+                todo.due_at =
+                    Some(parse_date_time(value, &params, clock).ok_or(IcalError::Malformed)?)
+            }
+            "PRIORITY" => {
+                todo.priority = match value.trim().parse::<u8>() {
+                    Ok(1..=4) => Some(Priority::High),
+                    Ok(5) => Some(Priority::Medium),
+                    Ok(6..=9) => Some(Priority::Low),
+                    Ok(_) => None,
+                    Err(_) => return Err(IcalError::Malformed),
+                }
+            }
+            "CATEGORIES" => todo.categories.extend(
+                split_list(value)
+                    .into_iter()
+                    .filter(|category| !category.trim().is_empty()),
+            ),
+            "RRULE" => todo.recurrence = Recurrence::from_rrule(value),
+            "STATUS" => todo.completed = value.eq_ignore_ascii_case("COMPLETED"),
+            _ => {}
+        }
+    }
+
+    if !saw_calendar {
+        return Err(IcalError::Malformed);
+    }
+
+    todo.ok_or(IcalError::NotATodo)
+}
@@ -0,0 +1,175 @@
+use crate::model::{BillableEntry, Invoice, InvoiceLine};
+
+use crate::timezone::UserClock;
+
+/// It rounds the length of a time entry to a multiple of `rounding_minutes`, the way the project
+/// asks for. Without a rounding step the exact length is billed.
+pub fn round_seconds(seconds: i64, rounding_minutes: Option<i32>, rounding_mode: &str) -> i64 {
+    let step = match rounding_minutes {
+        Some(minutes) if minutes > 0 => i64::from(minutes) * 60,
+        _ => return seconds,
+    };
+
+    match rounding_mode {
+        "down" => seconds / step * step,
+        "nearest" => (seconds + step / 2) / step * step,
+        _ => (seconds + step - 1) / step * step,
+    }
+}
+
+/// It groups billable entries into one line per task. Every entry is rounded on its own, and the
+/// hourly rate of a task comes from its own override, then its tags, then its project.
+///
+/// Seconds, rates and amounts are stored as INT, so it returns None when one of them doesn't fit.
+pub fn invoice_lines(entries: &[BillableEntry]) -> Option<Vec<InvoiceLine>> {
+    let mut lines: Vec<InvoiceLine> = Vec::new();
+
+    for entry in entries {
+        let finish_time = entry.finish_time.unwrap_or(entry.start_time);
+        let seconds = i32::try_from(round_seconds(
+            (finish_time - entry.start_time).whole_seconds(),
+            entry.rounding_minutes,
+            &entry.rounding_mode,
+        ))
+        .ok()?;
+
+        match lines.iter_mut().find(|line| line.task_id == entry.task_id) {
+            Some(line) => {
+                line.entries = line.entries.checked_add(1)?;
+                line.seconds = line.seconds.checked_add(seconds)?;
+            }
+            None => lines.push(InvoiceLine {
+                task_id: entry.task_id,
+                description: entry.name.clone(),
+                entries: 1,
+                seconds,
+                hourly_rate: match entry.task_rate {
+                    Some(rate) => Some(rate),
+                    None => match entry.tag_rate {
+                        Some(rate) => Some(i32::try_from(rate).ok()?),
+                        None => entry.project_rate,
+                    },
+                },
+                amount: 0,
+            }),
+        }
+    }
+
+    for line in lines.iter_mut() {
+        line.amount = match line.hourly_rate {
+            Some(rate) => {
+                i32::try_from((i64::from(line.seconds) * i64::from(rate) + 1800) / 3600).ok()?
+            }
+            None => 0,
+        };
+    }
+
+    Some(lines)
+}
+
+/// The sum of the amounts of the lines, or None when it doesn't fit in an INT.
+pub fn invoice_total(lines: &[InvoiceLine]) -> Option<i32> {
+    lines
+        .iter()
+        .try_fold(0i32, |total, line| total.checked_add(line.amount))
+}
+
+fn money(cents: i32) -> String {
+    format!("{}.{:02}", cents / 100, (cents % 100).abs())
+}
+
+fn hours(seconds: i32) -> String {
+    format!("{:.2}", f64::from(seconds) / 3600.0)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub fn render_csv(invoice: &Invoice) -> String {
+    let mut csv = String::from("task_id,description,entries,hours,hourly_rate,amount\r\n");
+
+    for line in &invoice.lines {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\r\n",
+            line.task_id,
+            csv_field(&line.description),
+            line.entries,
+            hours(line.seconds),
+            line.hourly_rate.map(money).unwrap_or_default(),
+            money(line.amount),
+        ));
+    }
+    csv.push_str(&format!(",Total,,,,{}\r\n", money(invoice.total)));
+
+    csv
+}
+
+/// Printable invoice. The period is shown as the first and last day it covers, in the user's time
+/// zone and date format.
+pub fn render_html(invoice: &Invoice, clock: &UserClock) -> String {
+    let title = match invoice.id {
+        Some(id) => format!("Invoice #{}", id),
+        None => String::from("Invoice preview"),
+    };
+
+    let rows: String = invoice
+        .lines
+        .iter()
+        .map(|line| {
+            format!(
+                "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>\n",
+                html_escape(&line.description),
+                hours(line.seconds),
+                line.hourly_rate.map(money).unwrap_or_default(),
+                money(line.amount),
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border-bottom: 1px solid #ccc; padding: 0.4em; text-align: left; }}
+.number {{ text-align: right; }}
+@media print {{ body {{ margin: 0; }} }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>Client: {client}<br>Period: {start} &ndash; {end}</p>
+<table>
+<thead><tr><th>Description</th><th class="number">Hours</th><th class="number">Rate</th><th class="number">Amount</th></tr></thead>
+<tbody>
+{rows}</tbody>
+<tfoot><tr><th colspan="3">Total</th><th class="number">{total}</th></tr></tfoot>
+</table>
+</body>
+</html>
+"#,
+        title = title,
+        client = html_escape(&invoice.client),
+        start = clock.format_date(clock.local_date(invoice.period_start)),
+        end = clock.format_date(clock.local_date(invoice.period_end - 1)),
+        rows = rows,
+        total = money(invoice.total),
+    )
+}
@@ -1,4 +1,6 @@
-use super::model::{Claims, DBUser, Token};
+use super::model::{Claims, DBUser, DownloadClaims, Token};
+
+use actix_web::cookie::time::OffsetDateTime;
 
 use jsonwebtoken::errors::Error;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
@@ -36,3 +38,38 @@ pub fn verify_token(recieved_token: &str) -> Result<TokenData<Claims>, Error> {
 
     decoded_token
 }
+
+/* Download tokens expire at the end of the next window, so the links to an attachment stay the
+same for a while and browsers can cache what they fetch */
+const DOWNLOAD_WINDOW_SECONDS: i64 = 15 * 60;
+
+/* Download tokens are signed with a key of their own, so they are no good as login tokens and
+login tokens are no good as download tokens */
+fn download_key() -> String {
+    let key = var("SECRET_KEY").expect("SECRET_KEY must be set");
+
+    format!("{}/downloads", key)
+}
+
+pub fn generate_download_token(user_id: i32, attachment_id: i32) -> Result<String, Error> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let claims = DownloadClaims {
+        id_user: user_id,
+        attachment_id,
+        exp: (now / DOWNLOAD_WINDOW_SECONDS + 2) * DOWNLOAD_WINDOW_SECONDS,
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(download_key().as_bytes()),
+    )
+}
+
+pub fn verify_download_token(recieved_token: &str) -> Result<TokenData<DownloadClaims>, Error> {
+    decode::<DownloadClaims>(
+        recieved_token,
+        &DecodingKey::from_secret(download_key().as_bytes()),
+        &Validation::default(),
+    )
+}
@@ -1,14 +1,37 @@
+mod archive;
+mod attachments;
+mod caldav;
 mod database;
+mod error;
+mod events;
+mod filter;
+mod focus;
+mod graphql;
 mod hashing;
+mod ical;
+mod invoice;
 mod jwt;
+mod markdown;
 mod model;
+mod openapi;
+mod quick_add;
+mod rank;
+mod reports;
 mod routes;
+mod search;
+mod storage;
+mod sync;
+mod timezone;
+mod trash;
 mod utils;
+mod validation;
+mod versions;
+mod webhooks;
 
 /* #[cfg(test)]
 mod test; */
 
-use actix_web::web::Data;
+use actix_web::web::{self, Data};
 use actix_web::{http, App, HttpServer};
 
 use sqlx::mysql::MySqlPool;
@@ -16,16 +39,37 @@ use sqlx::mysql::MySqlPool;
 use std::env::var;
 use std::io;
 use std::result::Result;
+use std::sync::Arc;
 
 use actix_cors::Cors;
 
+use archive::spawn_auto_archiver;
+
+use attachments::spawn_blob_sweeper;
+
 use dotenv::dotenv;
 
 use database::connect;
 
+use error::{json_config, not_found, path_config, query_config, with_request_id};
+
+use events::{EventHub, MemoryHub};
+
+use focus::{spawn_focus_ticker, FocusEvents};
+
+use graphql::build_schema;
+
 use model::Db;
 
-use routes::{delete_tasks, finish_task, get_tasks, login, post_task, register_user, start_task};
+use openapi::{docs, openapi_json};
+
+use storage::{storage_from_env, Storage};
+
+use trash::spawn_trash_purger;
+
+use versions::{legacy_alias, V1};
+
+use webhooks::{spawn_webhook_dispatcher, spawn_webhook_worker};
 
 #[actix_web::main]
 async fn main() -> Result<(), io::Error> {
@@ -42,6 +86,19 @@ async fn main() -> Result<(), io::Error> {
         .await
         .expect("Could not connect to database");
 
+    let db = Data::new(Db { pool });
+    let events = Data::new(FocusEvents::new());
+    let hub: Data<dyn EventHub> = Data::from(Arc::new(MemoryHub::new()) as Arc<dyn EventHub>);
+    let storage: Data<dyn Storage> = Data::from(storage_from_env());
+    let schema = Data::new(build_schema(db.clone(), hub.clone()));
+
+    spawn_focus_ticker(db.clone(), events.clone(), hub.clone());
+    spawn_webhook_dispatcher(db.clone());
+    spawn_webhook_worker(db.clone());
+    spawn_trash_purger(db.clone());
+    spawn_auto_archiver(db.clone(), hub.clone());
+    spawn_blob_sweeper(db.clone(), storage.clone());
+
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:5173")
@@ -51,15 +108,26 @@ async fn main() -> Result<(), io::Error> {
             .max_age(3600);
 
         App::new()
-            .app_data(Data::new(Db { pool: pool.clone() }))
+            .app_data(db.clone())
+            .app_data(events.clone())
+            .app_data(hub.clone())
+            .app_data(storage.clone())
+            .app_data(schema.clone())
+            .app_data(json_config())
+            .app_data(query_config())
+            .app_data(path_config())
             .wrap(cors)
-            .service(login)
-            .service(get_tasks)
-            .service(post_task)
-            .service(register_user)
-            .service(delete_tasks)
-            .service(start_task)
-            .service(finish_task)
+            .wrap_fn(with_request_id)
+            .configure(caldav::configure)
+            .service(web::scope(V1).configure(routes::configure))
+            .service(openapi_json)
+            .service(docs)
+            .service(
+                web::scope("")
+                    .wrap_fn(legacy_alias)
+                    .configure(routes::configure_legacy),
+            )
+            .default_service(web::to(not_found))
     })
     .bind(("127.0.0.1", port))?
     .run()
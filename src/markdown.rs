@@ -0,0 +1,112 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Schemes a link or an image in a comment may point to. Relative URLs are allowed too.
+const ALLOWED_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// It renders the Markdown of a comment as HTML that can be shown as it is: raw HTML is escaped
+/// instead of passed through, and links that aren't web or mail links lose their target.
+pub fn render(markdown: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(raw) => Event::Text(raw),
+        Event::Start(Tag::Link(kind, url, title)) => {
+            Event::Start(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) => {
+            Event::Start(Tag::Image(kind, safe_url(url), title))
+        }
+        event => event,
+    });
+
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut output, events);
+    output
+}
+
+fn safe_url(url: CowStr) -> CowStr {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+
+    match scheme {
+        Some(scheme) if !ALLOWED_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) => {
+            CowStr::Borrowed("")
+        }
+        _ => url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_raw_html() {
+        assert_eq!(
+            render("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(
+            render("Hi <img src=x onerror=alert(1)> there"),
+            "<p>Hi &lt;img src=x onerror=alert(1)&gt; there</p>\n"
+        );
+        assert_eq!(
+            render("<a href=\"javascript:alert(1)\">x</a>"),
+            "<p>&lt;a href=&quot;javascript:alert(1)&quot;&gt;x&lt;/a&gt;</p>\n"
+        );
+    }
+
+    #[test]
+    fn drops_the_target_of_script_links() {
+        for markdown in [
+            "[x](javascript:alert(1))",
+            "[x](JavaScript:alert(1))",
+            "[x](&#106;avascript:alert(1))",
+            "[x](vbscript:msgbox)",
+            "[x]: javascript:alert(1)\n\n[x][]",
+        ] {
+            assert_eq!(
+                render(markdown),
+                "<p><a href=\"\">x</a></p>\n",
+                "{:?}",
+                markdown
+            );
+        }
+        assert_eq!(
+            render("<javascript:alert(1)>"),
+            "<p><a href=\"\">javascript:alert(1)</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn drops_the_source_of_script_and_data_images() {
+        for markdown in [
+            "![a](javascript:alert(1))",
+            "![a](data:image/svg+xml;base64,PHN2Zz4=)",
+        ] {
+            assert_eq!(
+                render(markdown),
+                "<p><img src=\"\" alt=\"a\" /></p>\n",
+                "{:?}",
+                markdown
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_web_mail_and_relative_urls() {
+        assert_eq!(
+            render("[a](/tasks/1) [b](mailto:a@b.c) [c](https://x.y/?q=a:b)"),
+            "<p><a href=\"/tasks/1\">a</a> <a href=\"mailto:a@b.c\">b</a> <a href=\"https://x.y/?q=a:b\">c</a></p>\n"
+        );
+        assert_eq!(
+            render("![a](https://example.com/a.png)"),
+            "<p><img src=\"https://example.com/a.png\" alt=\"a\" /></p>\n"
+        );
+    }
+}
@@ -1,23 +1,35 @@
-use actix_web::{cookie::time::OffsetDateTime, HttpResponse};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+
+use async_graphql::{Enum, InputObject, SimpleObject};
 
 use serde::{Deserialize, Serialize};
 
+use utoipa::{IntoParams, ToSchema};
+
+use crate::jwt::generate_download_token;
+use crate::markdown;
+use crate::utils::double_option;
+use crate::versions::V1;
+
 use sqlx::mysql::MySqlPool;
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
 pub struct User {
     pub username: String,
     pub email: String,
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, SimpleObject)]
+#[graphql(name = "Viewer")]
 pub struct DBUser {
     pub username: String,
     pub id: i32,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct Login {
     pub username: String,
     pub password: String,
@@ -27,34 +39,171 @@ pub struct Db {
     pub pool: MySqlPool,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema, InputObject)]
+#[graphql(name = "NewTaskInput")]
 pub struct NewTask {
+    #[serde(default)]
+    pub uid: Option<String>, /* Generated when missing */
     pub name: String,
     pub description: String,
+    #[serde(default)]
+    pub estimate: Option<i64>, /* Duration expressed in seconds */
+    #[serde(default)]
+    #[graphql(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub project_id: Option<i32>,
+    #[serde(default)]
+    #[graphql(default)]
+    pub billable: bool,
+    #[serde(default)]
+    pub hourly_rate: Option<i32>, /* Cents per hour, overrides the tag and project rates */
+    #[serde(default)]
+    pub due_at: Option<i64>, /* Date expressed in seconds */
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+}
+
+#[derive(Deserialize, Default, ToSchema)]
+pub struct TaskUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /* Missing leaves the estimate as it is, null removes it */
+    #[serde(default, deserialize_with = "double_option")]
+    pub estimate: Option<Option<i64>>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub project_id: Option<Option<i32>>,
+    pub billable: Option<bool>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub hourly_rate: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub due_at: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub priority: Option<Option<Priority>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub recurrence: Option<Option<Recurrence>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl Priority {
+    pub fn from_db(priority: i8) -> Self {
+        match priority {
+            1 => Priority::High,
+            2 => Priority::Medium,
+            _ => Priority::Low,
+        }
+    }
+
+    pub fn to_db(&self) -> i8 {
+        match self {
+            Priority::High => 1,
+            Priority::Medium => 2,
+            Priority::Low => 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Pending,    /* Never started */
+    InProgress, /* Has a running segment */
+    Finished,   /* Started, and every segment has been closed */
 }
 
-#[derive(Deserialize, Serialize)]
 pub struct Task {
     pub id: i32,
+    pub uid: String,
     pub name: String,
     pub description: String,
+    pub estimate_seconds: Option<i32>,
+    pub project_id: Option<i32>,
+    pub billable: bool,
+    pub hourly_rate: Option<i32>,
+    pub due_at: Option<OffsetDateTime>,
+    pub priority: Option<i8>,
+    pub recurrence: Option<String>, /* An iCalendar RRULE */
+    pub updated_at: OffsetDateTime,
+    pub archived_at: Option<OffsetDateTime>,
+    pub position: String,
+}
+
+pub struct TaskTag {
+    pub task_id: i32,
+    pub name: String,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize, ToSchema, SimpleObject)]
 pub struct TaskHistory {
     pub start_time: i64,          /* Date expressed in seconds */
     pub finish_time: Option<i64>, /* Date expressed in seconds */
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize, ToSchema, SimpleObject)]
+#[graphql(complex, name = "Task")]
 pub struct ResponseTask {
     pub id: i32,
+    pub uid: String,
     pub name: String,
     pub description: String,
+    pub tags: Vec<String>,
+    pub estimate: Option<i64>, /* Duration expressed in seconds */
+    pub tracked: i64,          /* Duration expressed in seconds */
+    pub variance: Option<i64>, /* Tracked minus estimated, in seconds */
+    pub project_id: Option<i32>,
+    pub billable: bool,
+    pub hourly_rate: Option<i32>, /* Cents per hour */
+    pub due_at: Option<i64>,      /* Date expressed in seconds */
+    pub priority: Option<Priority>,
+    pub recurrence: Option<Recurrence>,
+    pub status: TaskStatus,
+    pub updated_at: i64,          /* Date expressed in seconds */
+    pub archived_at: Option<i64>, /* Date expressed in seconds, missing unless archived */
+    pub position: String,         /* Tasks sort by it in their project, or in the inbox */
     pub history: Vec<TaskHistory>,
 }
 
-#[derive(Serialize)]
+pub struct DBTaskSession {
+    pub id: i32,
+    pub task_id: i32,
+    pub start_time: OffsetDateTime,
+    pub finish_time: Option<OffsetDateTime>,
+}
+
+/// A tracked segment of a task, from starting its timer to finishing it.
+#[derive(Clone, Serialize, ToSchema, SimpleObject)]
+#[graphql(complex, name = "Session")]
+pub struct TaskSession {
+    pub id: i32,
+    pub task_id: i32,
+    pub start_time: i64,          /* Date expressed in seconds */
+    pub finish_time: Option<i64>, /* Date expressed in seconds, missing while it runs */
+}
+
+impl From<DBTaskSession> for TaskSession {
+    fn from(session: DBTaskSession) -> Self {
+        TaskSession {
+            id: session.id,
+            task_id: session.task_id,
+            start_time: session.start_time.unix_timestamp(),
+            finish_time: session
+                .finish_time
+                .map(|finish_time| finish_time.unix_timestamp()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct Token {
     pub token: String,
 }
@@ -65,11 +214,398 @@ pub struct Claims {
     pub exp: i32,
 }
 
-#[derive(Deserialize /* Serialize */)]
+/* What a download token lets its holder do: read one attachment of a user, until it expires */
+#[derive(Deserialize, Debug, Serialize)]
+pub struct DownloadClaims {
+    pub id_user: i32,
+    pub attachment_id: i32,
+    pub exp: i64,
+}
+
+#[derive(Deserialize, ToSchema /* Serialize */)]
 pub struct TaskId {
     pub id: i32,
 }
 
+/// Which tasks a list shows depending on whether they are archived.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFilter {
+    #[default]
+    Exclude,
+    Include,
+    Only,
+}
+
+impl ArchiveFilter {
+    pub fn matches(&self, task: &ResponseTask) -> bool {
+        match self {
+            ArchiveFilter::Exclude => task.archived_at.is_none(),
+            ArchiveFilter::Include => true,
+            ArchiveFilter::Only => task.archived_at.is_some(),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TaskListQuery {
+    /// Archived tasks are left out unless this is `include` or `only`, or the filter says which
+    /// ones it wants with `archived:`.
+    pub archived: Option<ArchiveFilter>,
+    /// Only the tasks that match this filter, such as
+    /// `status:open due:<7d tag:work -tag:waiting project:"Client X"`. Saved lists are used with
+    /// `list:name`.
+    pub filter: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Words to look for in names, descriptions and tags. Each one has to match a word or the start
+    /// of one, and at least one has to be 3 characters long.
+    pub q: String,
+    pub project_id: Option<i32>,
+    pub status: Option<TaskStatus>,
+    /// Only tasks changed at or after this date, in seconds.
+    pub updated_after: Option<i64>,
+    /// Only tasks changed before this date, in seconds.
+    pub updated_before: Option<i64>,
+    /// Archived tasks are found too, unless this is `exclude`.
+    pub archived: Option<ArchiveFilter>,
+    /// At most this many results: 20 by default, 100 at most.
+    pub limit: Option<usize>,
+}
+
+pub struct DBComment {
+    pub id: i32,
+    pub task_id: i32,
+    pub parent_id: Option<i32>,
+    pub body: String,
+    pub created_at: OffsetDateTime,
+    pub edited_at: Option<OffsetDateTime>,
+    pub deleted_at: Option<OffsetDateTime>,
+}
+
+/// A comment on a task. Replies have the id of the comment they answer in `parent_id`; comments
+/// come oldest first, so a thread is built by putting each one under its parent.
+#[derive(Serialize, ToSchema)]
+pub struct Comment {
+    pub id: i32,
+    pub task_id: i32,
+    pub parent_id: Option<i32>,
+    pub body: String,           /* Markdown, empty once deleted */
+    pub html: String,           /* The body rendered, safe to show as it is */
+    pub created_at: i64,        /* Date expressed in seconds */
+    pub edited_at: Option<i64>, /* Date expressed in seconds, missing unless edited */
+    pub deleted: bool,          /* Kept while it has replies */
+}
+
+impl From<DBComment> for Comment {
+    fn from(comment: DBComment) -> Self {
+        Comment {
+            id: comment.id,
+            task_id: comment.task_id,
+            parent_id: comment.parent_id,
+            html: markdown::render(&comment.body),
+            body: comment.body,
+            created_at: comment.created_at.unix_timestamp(),
+            edited_at: comment
+                .edited_at
+                .map(|edited_at| edited_at.unix_timestamp()),
+            deleted: comment.deleted_at.is_some(),
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewComment {
+    pub body: String,
+    #[serde(default)]
+    pub parent_id: Option<i32>, /* The comment it answers */
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CommentUpdate {
+    pub body: String,
+}
+
+pub struct DBAttachment {
+    pub id: i32,
+    pub task_id: i32,
+    pub filename: String,
+    pub hash: String,
+    pub size: i64,
+    pub content_type: String,
+    pub has_thumbnail: bool,
+    pub created_at: OffsetDateTime,
+}
+
+/// A file attached to a task. Its content is at .../content, and images have a smaller preview at
+/// .../thumbnail.
+#[derive(Serialize, ToSchema)]
+pub struct Attachment {
+    pub id: i32,
+    pub task_id: i32,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,      /* In bytes */
+    pub sha256: String, /* Hex digest of the content */
+    pub has_thumbnail: bool,
+    pub created_at: i64, /* Date expressed in seconds */
+    /// The content, for img and a elements, which can't send the Authorization header. The link
+    /// works without it for 15 to 30 minutes, then the attachment has to be fetched again.
+    pub content_url: String,
+    /// The thumbnail the same way, if the attachment has one.
+    pub thumbnail_url: Option<String>,
+}
+
+impl Attachment {
+    /* The links carry a download token of the user for this attachment only */
+    pub fn new(attachment: DBAttachment, user_id: i32) -> Self {
+        let url = format!(
+            "{}/tasks/{}/attachments/{}",
+            V1, attachment.task_id, attachment.id
+        );
+        let token = generate_download_token(user_id, attachment.id)
+            .expect("Signing a download token with a secret key can't fail");
+
+        Attachment {
+            id: attachment.id,
+            task_id: attachment.task_id,
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size: attachment.size,
+            sha256: attachment.hash,
+            has_thumbnail: attachment.has_thumbnail,
+            created_at: attachment.created_at.unix_timestamp(),
+            content_url: format!("{}/content?token={}", url, token),
+            thumbnail_url: attachment
+                .has_thumbnail
+                .then(|| format!("{}/thumbnail?token={}", url, token)),
+        }
+    }
+}
+
+/// How much of their quota a user's attachments use. A file attached several times counts once.
+#[derive(Serialize, ToSchema)]
+pub struct AttachmentUsage {
+    pub used: i64,          /* In bytes */
+    pub quota: i64,         /* In bytes */
+    pub max_file_size: i64, /* In bytes */
+}
+
+/// A field an edit changed, with its value before and after.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct FieldChange {
+    pub field: String,
+    #[schema(value_type = Object)]
+    pub from: serde_json::Value,
+    #[schema(value_type = Object)]
+    pub to: serde_json::Value,
+}
+
+pub struct DBActivity {
+    pub task_id: i32,
+    pub kind: String,
+    pub changes: Option<String>,
+    pub comment_id: Option<i32>,
+    pub created_at: OffsetDateTime,
+}
+
+/// Something that happened to a task: it was created, edited, archived, deleted or restored, its
+/// timer was started or stopped, or it got a comment.
+#[derive(Serialize, ToSchema)]
+pub struct Activity {
+    pub task_id: i32,
+    pub kind: TaskEventKind,
+    pub at: i64, /* Date expressed in seconds */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<FieldChange>, /* What an edit changed */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<i32>, /* The session a timer started or stopped */
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ActivityQuery {
+    /// Only what happened before this date, in seconds, to get the page after the last one.
+    pub before: Option<i64>,
+    /// At most this many entries, 50 by default and 200 at most.
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchHighlight {
+    pub field: &'static str, /* "name", "description", "tags" or "comments" */
+    pub snippet: String,     /* HTML escaped, with the matching words in <mark> */
+}
+
+/// A task that matches a search, best matches first.
+#[derive(Serialize, ToSchema)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub task: ResponseTask,
+    pub score: f64,
+    pub highlights: Vec<SearchHighlight>,
+}
+
+/// Where to put a task in its list: right before one task or right after another, but not both.
+#[derive(Deserialize, ToSchema)]
+pub struct TaskMove {
+    pub before: Option<i32>, /* The id of the task it goes before */
+    pub after: Option<i32>,  /* The id of the task it goes after */
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ArchiveFinished {
+    pub finished_before: i64, /* Date expressed in seconds */
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArchivedTasks {
+    pub task_ids: Vec<i32>,
+}
+
+/// Something to do to one task, as part of POST /tasks/bulk.
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BulkOperation {
+    /// Changes the fields that are present, like PATCH /tasks/{task_id}.
+    Update {
+        task_id: i32,
+        changes: TaskUpdate,
+    },
+    /// Moves the task to a project, or out of it with null.
+    Move {
+        task_id: i32,
+        project_id: Option<i32>,
+    },
+    AddTags {
+        task_id: i32,
+        tags: Vec<String>,
+    },
+    RemoveTags {
+        task_id: i32,
+        tags: Vec<String>,
+    },
+    Archive {
+        task_id: i32,
+    },
+    /// Moves the task to the trash.
+    Delete {
+        task_id: i32,
+    },
+    /// Starts the timer of the task. It fails with `task_running` if the timer is running already.
+    Start {
+        task_id: i32,
+    },
+    /// Stops the timer of the task. It fails with `task_already_finished` if it isn't running.
+    Finish {
+        task_id: i32,
+    },
+}
+
+impl BulkOperation {
+    pub fn task_id(&self) -> i32 {
+        match self {
+            BulkOperation::Update { task_id, .. }
+            | BulkOperation::Move { task_id, .. }
+            | BulkOperation::AddTags { task_id, .. }
+            | BulkOperation::RemoveTags { task_id, .. }
+            | BulkOperation::Archive { task_id }
+            | BulkOperation::Delete { task_id }
+            | BulkOperation::Start { task_id }
+            | BulkOperation::Finish { task_id } => *task_id,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+    /// Nothing is changed if an operation fails.
+    #[default]
+    AllOrNothing,
+    /// The operations that can be applied are, the ones that fail are reported.
+    BestEffort,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BulkRequest {
+    #[serde(default)]
+    pub mode: BulkMode,
+    pub operations: Vec<BulkOperation>,
+}
+
+/// What happened to one operation of a bulk request, in the database.
+pub enum BulkOutcome {
+    Applied(Option<TaskEventKind>), /* The event to publish, if something changed */
+    Failed(TaskError),
+    Skipped,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkItemStatus {
+    Applied,
+    Failed,
+    /// It could be applied, but another operation failed and the request was all-or-nothing.
+    RolledBack,
+    /// It wasn't tried because an operation before it failed.
+    Skipped,
+}
+
+/// Why an operation failed, with the status and code a request for it alone would have got.
+#[derive(Serialize, ToSchema)]
+pub struct BulkItemError {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkItemResult {
+    pub index: usize, /* Position of the operation in the request */
+    pub task_id: i32,
+    pub status: BulkItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BulkItemError>,
+}
+
+/// One line that describes a task, like `Email Ana tomorrow 9am #work !high every monday ~30m`.
+#[derive(Deserialize, ToSchema)]
+pub struct QuickAddRequest {
+    pub text: String,
+    #[serde(default)]
+    pub project_id: Option<i32>,
+}
+
+/// What quick add understood from the line, for clients to show.
+#[derive(Serialize, ToSchema)]
+pub struct ParsedQuickAdd {
+    pub name: String,
+    pub due_at: Option<i64>, /* Date expressed in seconds */
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<Recurrence>,
+    pub estimate: Option<i64>, /* Duration expressed in seconds */
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QuickAddResponse {
+    pub task: ResponseTask,
+    pub parsed: ParsedQuickAdd,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkResponse {
+    pub committed: bool, /* False when an all-or-nothing request was rolled back */
+    pub results: Vec<BulkItemResult>,
+}
+
 /* #[derive(Clone)] */
 pub struct History {
     pub task_id: i32,
@@ -77,57 +613,973 @@ pub struct History {
     pub finish_time: Option<OffsetDateTime>,
 }
 
-pub enum TaskError {
-    InvalidId,
-    IsPending,
-    DbError(sqlx::Error),
+#[derive(Deserialize, ToSchema)]
+pub struct NewFocusSession {
+    pub work_minutes: Option<i32>,
+    pub break_minutes: Option<i32>,
 }
 
-impl From<sqlx::Error> for TaskError {
-    fn from(error: sqlx::Error) -> Self {
-        TaskError::DbError(error)
+pub struct DBFocusSession {
+    pub id: i32,
+    pub user_id: i32,
+    pub task_id: i32,
+    pub phase: String,
+    pub work_seconds: i32,
+    pub break_seconds: i32,
+    pub phase_started_at: OffsetDateTime,
+    pub completed_pomodoros: i32,
+}
+
+impl DBFocusSession {
+    pub fn phase_ends_at(&self) -> OffsetDateTime {
+        let phase_seconds = if self.phase == "work" {
+            self.work_seconds
+        } else {
+            self.break_seconds
+        };
+        self.phase_started_at + Duration::seconds(phase_seconds.into())
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FocusSession {
+    pub id: i32,
+    pub task_id: i32,
+    pub phase: String, /* "work" or "break" */
+    pub work_seconds: i32,
+    pub break_seconds: i32,
+    pub phase_started_at: i64, /* Date expressed in seconds */
+    pub phase_ends_at: i64,    /* Date expressed in seconds */
+    pub completed_pomodoros: i32,
+}
+
+impl From<DBFocusSession> for FocusSession {
+    fn from(session: DBFocusSession) -> Self {
+        FocusSession {
+            id: session.id,
+            task_id: session.task_id,
+            phase_ends_at: session.phase_ends_at().unix_timestamp(),
+            phase_started_at: session.phase_started_at.unix_timestamp(),
+            phase: session.phase,
+            work_seconds: session.work_seconds,
+            break_seconds: session.break_seconds,
+            completed_pomodoros: session.completed_pomodoros,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct FocusTransition {
+    #[serde(skip)]
+    pub user_id: i32,
+    pub focus_session_id: i32,
+    pub task_id: i32,
+    pub phase: String, /* "work", "break" or "stopped" */
+    pub completed_pomodoros: i32,
+    pub at: i64, /* Date expressed in seconds */
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskEventKind {
+    TaskCreated,
+    TaskUpdated,
+    TaskDeleted,
+    TaskRestored, /* Taken out of the trash */
+    TaskArchived,
+    TaskUnarchived,
+    TimerStarted,
+    TimerStopped,
+    CommentAdded,
+    CommentEdited,
+    CommentDeleted,
+    AttachmentAdded,
+    AttachmentDeleted,
+    /* Events were missed, the client has to fetch the tasks again */
+    Resync,
+}
+
+impl TaskEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskEventKind::TaskCreated => "task_created",
+            TaskEventKind::TaskUpdated => "task_updated",
+            TaskEventKind::TaskDeleted => "task_deleted",
+            TaskEventKind::TaskRestored => "task_restored",
+            TaskEventKind::TaskArchived => "task_archived",
+            TaskEventKind::TaskUnarchived => "task_unarchived",
+            TaskEventKind::TimerStarted => "timer_started",
+            TaskEventKind::TimerStopped => "timer_stopped",
+            TaskEventKind::CommentAdded => "comment_added",
+            TaskEventKind::CommentEdited => "comment_edited",
+            TaskEventKind::CommentDeleted => "comment_deleted",
+            TaskEventKind::AttachmentAdded => "attachment_added",
+            TaskEventKind::AttachmentDeleted => "attachment_deleted",
+            TaskEventKind::Resync => "resync",
+        }
     }
+
+    /* The activity feed stores the kinds by name */
+    pub fn from_db(kind: &str) -> Option<Self> {
+        [
+            TaskEventKind::TaskCreated,
+            TaskEventKind::TaskUpdated,
+            TaskEventKind::TaskDeleted,
+            TaskEventKind::TaskRestored,
+            TaskEventKind::TaskArchived,
+            TaskEventKind::TaskUnarchived,
+            TaskEventKind::TimerStarted,
+            TaskEventKind::TimerStopped,
+            TaskEventKind::CommentAdded,
+            TaskEventKind::CommentEdited,
+            TaskEventKind::CommentDeleted,
+            TaskEventKind::AttachmentAdded,
+            TaskEventKind::AttachmentDeleted,
+        ]
+        .into_iter()
+        .find(|known| known.as_str() == kind)
+    }
+}
+
+/// Something that changed in the tasks of a user. The ids grow with every event, so a client that
+/// reconnects can ask for the ones after the last it saw.
+#[derive(Clone, Debug, Serialize, ToSchema, SimpleObject)]
+pub struct TaskEvent {
+    pub id: u64,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub user_id: i32,
+    pub kind: TaskEventKind,
+    pub task_id: Option<i32>, /* Missing for resync */
+    pub at: i64,              /* Date expressed in seconds */
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AttachmentQuery {
+    /// The download token in the `content_url` and `thumbnail_url` of the attachment, for browsers,
+    /// which can't set headers on the requests of img and a elements. The token from /login is only
+    /// accepted in the Authorization header.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventStreamQuery {
+    /// Resume after this event. Server-Sent Events clients can send the Last-Event-ID header instead.
+    pub last_event_id: Option<u64>,
+    /// The token from /login, for browsers, which can't set headers on EventSource and WebSocket
+    /// connections.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GraphQLSocketQuery {
+    /// The token from /login. It can also go in the connection_init payload, as `token`.
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct TaskPomodoros {
+    pub task_id: i32,
+    pub name: String,
+    pub pomodoros: i64,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct DayPomodoros {
+    pub day: String, /* YYYY-MM-DD */
+    pub pomodoros: i64,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct PomodoroStats {
+    pub by_task: Vec<TaskPomodoros>,
+    pub by_day: Vec<DayPomodoros>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    Week,
+    Month,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EstimateReportQuery {
+    pub period: Option<ReportPeriod>,
+}
+
+#[derive(Serialize, Default, ToSchema, SimpleObject)]
+pub struct EstimateAccuracy {
+    pub tasks: i64,
+    pub estimated: i64,     /* Duration expressed in seconds */
+    pub tracked: i64,       /* Duration expressed in seconds */
+    pub variance: i64,      /* Tracked minus estimated, in seconds */
+    pub ratio: Option<f64>, /* Tracked divided by estimated */
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct PeriodAccuracy {
+    pub period: String, /* First day of the period, YYYY-MM-DD */
+    pub accuracy: EstimateAccuracy,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct TagAccuracy {
+    pub tag: String,
+    pub overall: EstimateAccuracy,
+    pub periods: Vec<PeriodAccuracy>,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct EstimateReport {
+    pub overall: EstimateAccuracy,
+    pub periods: Vec<PeriodAccuracy>,
+    pub tags: Vec<TagAccuracy>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewProject {
+    pub name: String,
+    pub client: Option<String>,
+    pub hourly_rate: Option<i32>, /* Cents per hour */
+    pub rounding_minutes: Option<i32>,
+    pub rounding_mode: Option<RoundingMode>,
+}
+
+#[derive(Clone, Serialize, ToSchema, SimpleObject)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+    pub client: Option<String>,
+    pub hourly_rate: Option<i32>, /* Cents per hour */
+    pub rounding_minutes: Option<i32>,
+    pub rounding_mode: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    Up,
+    Down,
+    Nearest,
 }
 
-impl TaskError {
-    pub fn message(self) -> HttpResponse {
+impl RoundingMode {
+    pub fn as_str(&self) -> &'static str {
         match self {
-            TaskError::InvalidId => {
-                HttpResponse::NotFound().body("There is no task with the provided id")
+            RoundingMode::Up => "up",
+            RoundingMode::Down => "down",
+            RoundingMode::Nearest => "nearest",
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TagUpdate {
+    #[serde(default, deserialize_with = "double_option")]
+    pub hourly_rate: Option<Option<i32>>, /* Cents per hour */
+}
+
+pub struct BillableEntry {
+    pub task_id: i32,
+    pub name: String,
+    pub start_time: OffsetDateTime,
+    pub finish_time: Option<OffsetDateTime>,
+    pub task_rate: Option<i32>,
+    pub tag_rate: Option<i64>,
+    pub project_rate: Option<i32>,
+    pub rounding_minutes: Option<i32>,
+    pub rounding_mode: String,
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct InvoiceQuery {
+    pub client: String,
+    pub from: Option<i64>, /* Date expressed in seconds */
+    pub to: Option<i64>,   /* Date expressed in seconds */
+    /* Instead of from and to, a period in the user's time zone */
+    pub period: Option<NamedPeriod>,
+    pub format: Option<InvoiceFormat>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct InvoiceFormatQuery {
+    pub format: Option<InvoiceFormat>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InvoiceLine {
+    pub task_id: i32,
+    pub description: String,
+    pub entries: i32,
+    pub seconds: i32,             /* Rounded billable time */
+    pub hourly_rate: Option<i32>, /* Cents per hour */
+    pub amount: i32,              /* Cents */
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Invoice {
+    pub id: Option<i32>, /* Missing while the invoice is only a preview */
+    pub client: String,
+    pub period_start: i64, /* Date expressed in seconds */
+    pub period_end: i64,   /* Date expressed in seconds */
+    pub lines: Vec<InvoiceLine>,
+    pub total: i32, /* Cents */
+}
+
+pub struct DBInvoice {
+    pub id: i32,
+    pub client: String,
+    pub period_start: OffsetDateTime,
+    pub period_end: OffsetDateTime,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    /* ISO day numbers, Monday is 1 */
+    pub fn from_iso(day: i8) -> Self {
+        match day {
+            2 => Weekday::Tuesday,
+            3 => Weekday::Wednesday,
+            4 => Weekday::Thursday,
+            5 => Weekday::Friday,
+            6 => Weekday::Saturday,
+            7 => Weekday::Sunday,
+            _ => Weekday::Monday,
+        }
+    }
+
+    pub fn iso(&self) -> i8 {
+        *self as i8 + 1
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// How often a task comes back: every `interval` days, weeks, months or years, and for weekly
+/// ones on which days. It is stored as the RRULE iCalendar uses.
+#[derive(Deserialize, Serialize, Clone, PartialEq, ToSchema, SimpleObject, InputObject)]
+#[graphql(input_name = "RecurrenceInput")]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    #[serde(default = "Recurrence::default_interval")]
+    #[graphql(default = 1)]
+    pub interval: u32,
+    #[serde(default)]
+    #[graphql(default)]
+    pub weekdays: Vec<Weekday>, /* Only for weekly ones, empty means the day it started */
+}
+
+const RRULE_DAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+impl Recurrence {
+    fn default_interval() -> u32 {
+        1
+    }
+
+    /// It reads a RRULE such as `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH`. The parts it can't keep,
+    /// like COUNT or UNTIL, are dropped.
+    pub fn from_rrule(rule: &str) -> Option<Self> {
+        let mut recurrence = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            weekdays: Vec::new(),
+        };
+        let mut has_frequency = false;
+
+        for part in rule.trim().trim_start_matches("RRULE:").split(';') {
+            let (key, value) = part.split_once('=')?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    has_frequency = true;
+                    recurrence.frequency = match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return None,
+                    };
+                }
+                "INTERVAL" => recurrence.interval = value.parse().ok().filter(|n| *n > 0)?,
+                "BYDAY" => {
+                    recurrence.weekdays = value
+                        .split(',')
+                        .filter_map(|day| {
+                            /* Ordinals like 2MO only make sense monthly, the day is kept */
+                            let code = day.trim_start_matches(|c: char| !c.is_ascii_alphabetic());
+                            RRULE_DAYS
+                                .iter()
+                                .position(|known| code.eq_ignore_ascii_case(known))
+                                .map(|index| Weekday::from_iso(index as i8 + 1))
+                        })
+                        .collect()
+                }
+                _ => {}
             }
-            TaskError::IsPending => {
-                HttpResponse::Conflict().body("The tasks hasn't been completed yet")
+        }
+
+        if recurrence.frequency != Frequency::Weekly {
+            recurrence.weekdays.clear();
+        }
+        has_frequency.then_some(recurrence)
+    }
+
+    pub fn to_rrule(&self) -> String {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        let mut rule = format!("FREQ={}", frequency);
+        if self.interval > 1 {
+            rule.push_str(&format!(";INTERVAL={}", self.interval));
+        }
+        if self.frequency == Frequency::Weekly && !self.weekdays.is_empty() {
+            let mut days: Vec<i8> = self.weekdays.iter().map(Weekday::iso).collect();
+            days.sort_unstable();
+            days.dedup();
+            let days: Vec<&str> = days
+                .into_iter()
+                .map(|day| RRULE_DAYS[day as usize - 1])
+                .collect();
+            rule.push_str(&format!(";BYDAY={}", days.join(",")));
+        }
+        rule
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+pub enum DateFormat {
+    #[serde(rename = "YYYY-MM-DD")]
+    Iso,
+    #[serde(rename = "DD/MM/YYYY")]
+    DayMonthYear,
+    #[serde(rename = "MM/DD/YYYY")]
+    MonthDayYear,
+    #[serde(rename = "DD.MM.YYYY")]
+    DottedDayMonthYear,
+}
+
+impl DateFormat {
+    pub fn from_pattern(pattern: &str) -> Self {
+        match pattern {
+            "DD/MM/YYYY" => DateFormat::DayMonthYear,
+            "MM/DD/YYYY" => DateFormat::MonthDayYear,
+            "DD.MM.YYYY" => DateFormat::DottedDayMonthYear,
+            _ => DateFormat::Iso,
+        }
+    }
+
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            DateFormat::Iso => "YYYY-MM-DD",
+            DateFormat::DayMonthYear => "DD/MM/YYYY",
+            DateFormat::MonthDayYear => "MM/DD/YYYY",
+            DateFormat::DottedDayMonthYear => "DD.MM.YYYY",
+        }
+    }
+}
+
+pub struct DBUserSettings {
+    pub time_zone: String,
+    pub locale: String,
+    pub week_start: i8,
+    pub date_format: String,
+    pub auto_archive_days: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserSettings {
+    pub time_zone: String, /* IANA name, like Europe/Madrid */
+    pub locale: String,    /* BCP 47 tag, like es-MX */
+    pub week_start: Weekday,
+    pub date_format: DateFormat,
+    pub auto_archive_days: Option<u32>, /* Finished tasks are archived after these days */
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        UserSettings {
+            time_zone: String::from("UTC"),
+            locale: String::from("en-US"),
+            week_start: Weekday::Monday,
+            date_format: DateFormat::Iso,
+            auto_archive_days: None,
+        }
+    }
+}
+
+impl From<DBUserSettings> for UserSettings {
+    fn from(settings: DBUserSettings) -> Self {
+        UserSettings {
+            time_zone: settings.time_zone,
+            locale: settings.locale,
+            week_start: Weekday::from_iso(settings.week_start),
+            date_format: DateFormat::from_pattern(&settings.date_format),
+            auto_archive_days: settings.auto_archive_days.map(|days| days as u32),
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SettingsUpdate {
+    pub time_zone: Option<String>,
+    pub locale: Option<String>,
+    pub week_start: Option<Weekday>,
+    pub date_format: Option<DateFormat>,
+    pub auto_archive_days: Option<u32>, /* 0 turns it off */
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NamedPeriod {
+    Today,
+    Yesterday,
+    ThisWeek,
+    LastWeek,
+    ThisMonth,
+    LastMonth,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CalendarFeed {
+    pub url: String,
+}
+
+/// The events a webhook can subscribe to. Stopping a timer is finishing the task, as in
+/// /finish_task.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "task.created")]
+    TaskCreated,
+    #[serde(rename = "task.updated")]
+    TaskUpdated,
+    #[serde(rename = "task.deleted")]
+    TaskDeleted,
+    #[serde(rename = "task.finished")]
+    TaskFinished,
+    #[serde(rename = "session.started")]
+    SessionStarted,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::TaskCreated,
+        WebhookEvent::TaskUpdated,
+        WebhookEvent::TaskDeleted,
+        WebhookEvent::TaskFinished,
+        WebhookEvent::SessionStarted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::TaskCreated => "task.created",
+            WebhookEvent::TaskUpdated => "task.updated",
+            WebhookEvent::TaskDeleted => "task.deleted",
+            WebhookEvent::TaskFinished => "task.finished",
+            WebhookEvent::SessionStarted => "session.started",
+        }
+    }
+
+    pub fn from_task_event(kind: TaskEventKind) -> Option<Self> {
+        match kind {
+            TaskEventKind::TaskCreated => Some(WebhookEvent::TaskCreated),
+            TaskEventKind::TaskUpdated => Some(WebhookEvent::TaskUpdated),
+            TaskEventKind::TaskDeleted => Some(WebhookEvent::TaskDeleted),
+            /* For other services a restored task is one that shows up again */
+            TaskEventKind::TaskRestored => Some(WebhookEvent::TaskCreated),
+            TaskEventKind::TaskArchived | TaskEventKind::TaskUnarchived => {
+                Some(WebhookEvent::TaskUpdated)
             }
-            TaskError::DbError(err) => {
-                println!("{:#?}", err);
-                HttpResponse::InternalServerError().body("Couldn't complete operation")
+            TaskEventKind::TimerStarted => Some(WebhookEvent::SessionStarted),
+            TaskEventKind::TimerStopped => Some(WebhookEvent::TaskFinished),
+            TaskEventKind::CommentAdded
+            | TaskEventKind::CommentEdited
+            | TaskEventKind::CommentDeleted
+            | TaskEventKind::AttachmentAdded
+            | TaskEventKind::AttachmentDeleted
+            | TaskEventKind::Resync => None,
+        }
+    }
+
+    /* They are stored comma separated, so FIND_IN_SET can look for one */
+    pub fn to_db(events: &[WebhookEvent]) -> String {
+        let mut names: Vec<&str> = Vec::new();
+        for event in events {
+            if !names.contains(&event.as_str()) {
+                names.push(event.as_str());
             }
         }
+        names.join(",")
+    }
+
+    pub fn from_db(events: &str) -> Vec<WebhookEvent> {
+        WebhookEvent::ALL
+            .into_iter()
+            .filter(|event| events.split(',').any(|name| name == event.as_str()))
+            .collect()
     }
 }
 
-pub enum VerificationError {
-    InvalidToken,
-    EmptyToken,
-    /* ServerFailedVerifyingToken */
+/// A filter saved under a name, to be used as a list.
+#[derive(Serialize, ToSchema)]
+pub struct SmartList {
+    pub id: i32,
+    pub name: String,
+    pub filter: String,
 }
 
-/* impl From<VerificationError> for HttpResponse {
-    fn from(error: VerificationError) -> Self {
-        error.message()
+#[derive(Deserialize, ToSchema)]
+pub struct NewSmartList {
+    pub name: String,
+    pub filter: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SmartListUpdate {
+    pub name: Option<String>,
+    pub filter: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebhookUpdate {
+    pub url: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+    pub enabled: Option<bool>, /* Enabling it again clears the failures */
+}
+
+pub struct DBWebhook {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: String,
+    pub enabled: bool,
+    pub consecutive_failures: i32,
+    pub disabled_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub enabled: bool,
+    pub consecutive_failures: i32,
+    pub disabled_at: Option<i64>, /* Date expressed in seconds, set when failures disabled it */
+    pub created_at: i64,          /* Date expressed in seconds */
+    /* Only sent when the webhook is created */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl From<DBWebhook> for Webhook {
+    fn from(webhook: DBWebhook) -> Self {
+        Webhook {
+            id: webhook.id,
+            url: webhook.url,
+            events: WebhookEvent::from_db(&webhook.events),
+            enabled: webhook.enabled,
+            consecutive_failures: webhook.consecutive_failures,
+            disabled_at: webhook
+                .disabled_at
+                .map(|disabled_at| disabled_at.unix_timestamp()),
+            created_at: webhook.created_at.unix_timestamp(),
+            secret: None,
+        }
     }
-} */
+}
+
+pub struct DBWebhookDelivery {
+    pub id: i64,
+    pub event: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: OffsetDateTime,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub delivered_at: Option<OffsetDateTime>,
+}
 
-impl VerificationError {
-    pub fn message(self) -> HttpResponse {
+#[derive(Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub event: String,
+    pub status: String, /* "pending", "delivered" or "failed" */
+    pub attempts: i32,
+    pub next_attempt_at: Option<i64>, /* Date expressed in seconds, while it is pending */
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: i64,           /* Date expressed in seconds */
+    pub delivered_at: Option<i64>, /* Date expressed in seconds */
+}
+
+impl From<DBWebhookDelivery> for WebhookDelivery {
+    fn from(delivery: DBWebhookDelivery) -> Self {
+        WebhookDelivery {
+            id: delivery.id,
+            event: delivery.event,
+            next_attempt_at: (delivery.status == "pending")
+                .then(|| delivery.next_attempt_at.unix_timestamp()),
+            status: delivery.status,
+            attempts: delivery.attempts,
+            last_status_code: delivery.last_status_code,
+            last_error: delivery.last_error,
+            created_at: delivery.created_at.unix_timestamp(),
+            delivered_at: delivery
+                .delivered_at
+                .map(|delivered_at| delivered_at.unix_timestamp()),
+        }
+    }
+}
+
+/// A task event waiting for the dispatcher to queue its deliveries.
+pub struct DBWebhookEvent {
+    pub id: i64,
+    pub user_id: i32,
+    pub event: String,
+    pub task_id: Option<i32>,
+    pub created_at: OffsetDateTime,
+}
+
+/// A delivery whose time has come, with what is needed to send it.
+pub struct DueDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub next_attempt_at: OffsetDateTime,
+}
+
+/// The body of every webhook request. `id` is the same when a delivery is retried or replayed, so
+/// receivers can tell they got it already.
+#[derive(Serialize, ToSchema)]
+pub struct WebhookPayload {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event: String,
+    pub created_at: i64, /* Date expressed in seconds */
+    pub task_id: Option<i32>,
+    pub task: Option<ResponseTask>, /* As it was when the event happened, missing once deleted */
+}
+
+/// What a sync record is. Tasks and their time entries are merged separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncEntity {
+    Task,
+    TimeEntry,
+}
+
+impl SyncEntity {
+    pub fn from_db(entity: &str) -> Self {
+        match entity {
+            "time_entry" => SyncEntity::TimeEntry,
+            _ => SyncEntity::Task,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
         match self {
-            VerificationError::EmptyToken => {
-                HttpResponse::Unauthorized().body("Empty validation token")
-            }
-            VerificationError::InvalidToken => HttpResponse::Unauthorized().body("Invalid Token"),
-            /* VerificationError::ServerFailedVerifyingToken => {
-                HttpResponse::ServiceUnavailable().body("Not able to verify at the moment")
-            } */
+            SyncEntity::Task => "task",
+            SyncEntity::TimeEntry => "time_entry",
         }
     }
 }
+
+#[derive(Deserialize, ToSchema)]
+pub struct SyncRequest {
+    /* The cursor the last sync returned, missing on the first one */
+    #[serde(default)]
+    pub cursor: Option<i64>,
+    #[serde(default)]
+    pub tasks: Vec<TaskChange>,
+    #[serde(default)]
+    pub time_entries: Vec<TimeEntryChange>,
+}
+
+/// A task created, changed or deleted on a client. Only the fields that are present are written.
+#[derive(Deserialize, ToSchema)]
+pub struct TaskChange {
+    pub uid: String,
+    pub hlc: String, /* Hybrid logical clock of the change */
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(flatten)]
+    pub fields: TaskUpdate,
+}
+
+/// A time entry created, changed or deleted on a client. Entries can't move to another task.
+#[derive(Deserialize, ToSchema)]
+pub struct TimeEntryChange {
+    pub uid: String,
+    pub task_uid: String,
+    pub hlc: String, /* Hybrid logical clock of the change */
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub start_time: Option<i64>, /* Date expressed in seconds */
+    #[serde(default, deserialize_with = "double_option")]
+    pub finish_time: Option<Option<i64>>, /* Date expressed in seconds, null while it runs */
+}
+
+pub struct DBTimeEntry {
+    pub uid: String,
+    pub task_uid: String,
+    pub start_time: OffsetDateTime,
+    pub finish_time: Option<OffsetDateTime>,
+    pub billed: bool,
+}
+
+pub struct DBSyncClock {
+    pub entity: String,
+    pub uid: String,
+    pub field: String,
+    pub hlc: String,
+}
+
+pub struct DBChange {
+    pub entity: String,
+    pub uid: String,
+    pub deleted: bool,
+}
+
+/// A task in the trash. It is purged for good at `purge_at`.
+#[derive(Serialize, ToSchema)]
+pub struct TrashedTask {
+    #[serde(flatten)]
+    pub task: ResponseTask,
+    pub deleted_at: i64, /* Date expressed in seconds */
+    pub purge_at: i64,   /* Date expressed in seconds */
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncTask {
+    #[serde(flatten)]
+    pub task: ResponseTask,
+    pub clocks: BTreeMap<String, String>, /* Clock of the last write to each field */
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncTimeEntry {
+    pub uid: String,
+    pub task_uid: String,
+    pub start_time: i64,                  /* Date expressed in seconds */
+    pub finish_time: Option<i64>,         /* Date expressed in seconds */
+    pub billed: bool,                     /* Billed entries can't be changed */
+    pub clocks: BTreeMap<String, String>, /* Clock of the last write to each field */
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Tombstone {
+    pub entity: SyncEntity,
+    pub uid: String,
+}
+
+/// A change from the client that wasn't applied. Clients should drop it, the next sync brings the
+/// state of the server.
+#[derive(Serialize, ToSchema)]
+pub struct RejectedChange {
+    pub entity: SyncEntity,
+    pub uid: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncResponse {
+    pub cursor: i64,
+    pub hlc: String, /* The clock of the server, clients move theirs past it */
+    pub tasks: Vec<SyncTask>,
+    pub time_entries: Vec<SyncTimeEntry>,
+    pub tombstones: Vec<Tombstone>,
+    pub rejected: Vec<RejectedChange>,
+}
+
+pub enum TaskError {
+    InvalidId,
+    InvalidProjectId,
+    IsPending,
+    NotRunning,
+    DifferentList,
+    FocusInProgress,
+    Locked,
+    DbError(sqlx::Error),
+}
+
+impl From<sqlx::Error> for TaskError {
+    fn from(error: sqlx::Error) -> Self {
+        TaskError::DbError(error)
+    }
+}
+
+pub enum AttachmentError {
+    QuotaExceeded(i64), /* With the bytes left */
+    DbError(sqlx::Error),
+}
+
+impl From<sqlx::Error> for AttachmentError {
+    fn from(error: sqlx::Error) -> Self {
+        AttachmentError::DbError(error)
+    }
+}
+
+pub enum InvoiceError {
+    TooLarge, /* Some time or amount doesn't fit in an INT */
+    DbError(sqlx::Error),
+}
+
+impl From<sqlx::Error> for InvoiceError {
+    fn from(error: sqlx::Error) -> Self {
+        InvoiceError::DbError(error)
+    }
+}
+
+pub enum VerificationError {
+    InvalidToken,
+    EmptyToken,
+    /* ServerFailedVerifyingToken */
+}
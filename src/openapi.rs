@@ -0,0 +1,242 @@
+use crate::routes;
+
+use actix_web::{get, HttpResponse};
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// The OpenAPI document of the JSON API. The operations come from the `utoipa::path` attributes in
+/// routes.rs and the schemas from the models, so a handler missing here is missing from the
+/// generated clients.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Surphury",
+        description = "Tasks, time tracking, focus sessions and invoices."
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        routes::register_user,
+        routes::login,
+        routes::get_tasks,
+        routes::post_task,
+        routes::post_task_quick,
+        routes::post_tasks_batch,
+        routes::post_tasks_bulk,
+        routes::get_task,
+        routes::delete_tasks,
+        routes::patch_task,
+        routes::start_task,
+        routes::finish_task,
+        routes::delete_task_by_id,
+        routes::move_task_by_id,
+        routes::archive_task_by_id,
+        routes::unarchive_task_by_id,
+        routes::archive_finished,
+        routes::get_trash,
+        routes::restore_trashed_task,
+        routes::purge_trashed_task,
+        routes::delete_trash,
+        routes::get_sessions,
+        routes::get_session,
+        routes::post_session,
+        routes::patch_current_session,
+        routes::start_focus,
+        routes::get_focus,
+        routes::stop_focus,
+        routes::focus_events,
+        routes::focus_stats,
+        routes::task_events,
+        routes::task_events_ws,
+        routes::post_sync,
+        routes::search,
+        routes::estimates_report,
+        routes::post_project,
+        routes::get_projects,
+        routes::get_project,
+        routes::patch_tag,
+        routes::invoice_preview,
+        routes::post_invoice,
+        routes::get_invoice_by_id,
+        routes::get_settings,
+        routes::patch_settings,
+        routes::get_calendar,
+        routes::post_calendar_feed,
+        routes::delete_calendar_feed,
+        routes::get_calendar_feed,
+        routes::post_smart_list,
+        routes::get_smart_lists,
+        routes::patch_smart_list,
+        routes::delete_smart_list_by_id,
+        routes::get_task_comments,
+        routes::post_task_comment,
+        routes::patch_task_comment,
+        routes::delete_task_comment,
+        routes::get_task_activity,
+        routes::get_user_activity,
+        routes::get_attachment_usage_by_user,
+        routes::get_task_attachments,
+        routes::post_task_attachment,
+        routes::get_attachment_content,
+        routes::get_attachment_thumbnail,
+        routes::delete_task_attachment,
+        routes::post_webhook,
+        routes::get_webhooks,
+        routes::patch_webhook,
+        routes::delete_webhook_by_id,
+        routes::get_deliveries,
+        routes::replay_delivery,
+        routes::ping_webhook,
+        routes::post_graphql,
+        routes::get_graphql_schema,
+        routes::graphql_ws,
+    ),
+    modifiers(&TokenSecurity),
+    tags(
+        (name = "users", description = "Registration and login"),
+        (name = "tasks", description = "Tasks and their timers"),
+        (name = "trash", description = "Deleted tasks, until they are restored or purged"),
+        (name = "focus", description = "Pomodoro focus sessions"),
+        (name = "events", description = "Real-time changes to tasks and timers"),
+        (name = "search", description = "Full-text search over tasks"),
+        (name = "lists", description = "Saved filters"),
+        (name = "comments", description = "Threaded Markdown comments on tasks"),
+        (name = "activity", description = "What happened to tasks, newest first"),
+        (name = "attachments", description = "Files attached to tasks"),
+        (name = "sync", description = "Offline changes and what changed on the server"),
+        (name = "reports", description = "Estimation accuracy"),
+        (name = "billing", description = "Projects, rates and invoices"),
+        (name = "settings", description = "Time zone, locale and formats"),
+        (name = "calendar", description = "iCalendar export and feeds"),
+        (name = "webhooks", description = "Signed task events sent to other services"),
+        (name = "graphql", description = "Tasks, sessions and reports in one round trip"),
+    )
+)]
+pub struct ApiDoc;
+
+/* The token from /login goes as it is in the Authorization header, without "Bearer" */
+struct TokenSecurity;
+
+impl Modify for TokenSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("Authorization"))),
+        );
+    }
+}
+
+#[get("/openapi.json")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/* Redoc renders the document; it is loaded from its CDN so nothing has to be vendored */
+#[get("/docs")]
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            r#"<!DOCTYPE html>
+<html>
+	<head>
+		<meta charset="utf-8">
+		<meta name="viewport" content="width=device-width, initial-scale=1">
+		<title>Surphury API</title>
+	</head>
+	<body>
+		<redoc spec-url="/openapi.json"></redoc>
+		<script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+	</body>
+</html>
+"#,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::http::Method;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    use std::collections::BTreeSet;
+
+    /* The method and path of every operation in the document */
+    fn documented() -> BTreeSet<(String, String)> {
+        let mut operations = BTreeSet::new();
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            let methods = [
+                ("GET", item.get.is_some()),
+                ("POST", item.post.is_some()),
+                ("PUT", item.put.is_some()),
+                ("PATCH", item.patch.is_some()),
+                ("DELETE", item.delete.is_some()),
+            ];
+            for (method, _) in methods.into_iter().filter(|(_, documented)| *documented) {
+                operations.insert((method.to_string(), path.clone()));
+            }
+        }
+        operations
+    }
+
+    /* The method and path of every handler, from the route attributes in routes.rs */
+    fn handlers() -> BTreeSet<(String, String)> {
+        include_str!("routes.rs")
+            .lines()
+            .filter_map(|line| {
+                let (method, rest) = line.strip_prefix("#[")?.split_once("(\"")?;
+                let path = rest.strip_suffix("\")]")?;
+                matches!(method, "get" | "post" | "put" | "patch" | "delete")
+                    .then(|| (method.to_uppercase(), path.to_string()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn documents_every_handler() {
+        let documented = documented();
+        let handlers = handlers();
+
+        let undocumented: Vec<_> = handlers.difference(&documented).collect();
+        assert!(undocumented.is_empty(), "Not in ApiDoc: {:?}", undocumented);
+        let missing: Vec<_> = documented.difference(&handlers).collect();
+        assert!(missing.is_empty(), "No handler for: {:?}", missing);
+    }
+
+    /* Every documented operation reaches a route of routes::configure with the same pattern. The
+    handlers fail without a database or a token, but they are only matched here. */
+    #[actix_web::test]
+    async fn serves_every_documented_operation() {
+        let app = init_service(App::new().configure(routes::configure)).await;
+
+        for (method, path) in documented() {
+            let uri: String = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "1"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let request = TestRequest::default()
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .uri(&uri)
+                .to_request();
+
+            let response = call_service(&app, request).await;
+            assert_eq!(
+                response.request().match_pattern().as_deref(),
+                Some(path.as_str()),
+                "{} {} isn't served by routes::configure",
+                method,
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,871 @@
+/* Quick add turns one line into a task:
+
+    Email Ana tomorrow 9am #work !high every monday ~30m
+
+Tags go after #, the priority after ! and the estimate after ~. Dates, times and recurrences are
+phrases, in English or in Spanish ("mañana a las 9", "cada lunes y jueves"). The words that aren't
+part of any of them are the name, in the order they were written. A word that starts with a
+backslash is always part of the name, without the backslash, so "\tomorrow" stays a word. */
+
+use crate::model::{DateFormat, Frequency, Priority, Recurrence, Weekday};
+
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// What a line says about the task it describes.
+pub struct QuickAdd {
+    pub name: String,
+    /// Wall clock time of the user. A day without a time is due at the end of it.
+    pub due: Option<NaiveDateTime>,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<Recurrence>,
+    pub estimate: Option<i64>, /* Duration expressed in seconds */
+}
+
+#[derive(Clone, Copy)]
+enum Unit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/* The words of the line, and the same words lowercased, without accents and without the
+punctuation around them, which is what the phrases are matched against */
+struct Words<'a> {
+    original: Vec<&'a str>,
+    normalized: Vec<String>,
+}
+
+impl Words<'_> {
+    fn get(&self, index: usize) -> &str {
+        self.normalized.get(index).map_or("", String::as_str)
+    }
+
+    /* Whether the words from `index` on are `phrase` */
+    fn phrase(&self, index: usize, phrase: &[&str]) -> bool {
+        phrase
+            .iter()
+            .enumerate()
+            .all(|(offset, word)| self.get(index + offset) == *word)
+    }
+}
+
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| matches!(c, ',' | ';' | '(' | ')' | '¿' | '?' | '¡'))
+        .trim_end_matches('.')
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' => 'a',
+            'é' | 'è' => 'e',
+            'í' => 'i',
+            'ó' | 'ò' => 'o',
+            'ú' | 'ü' => 'u',
+            'ñ' => 'n',
+            c => c,
+        })
+        .collect()
+}
+
+/// It reads a line. `now` is the wall clock time of the user, which relative dates count from, and
+/// `date_format` tells if 03/04 is the 3rd of April or March the 4th.
+pub fn parse(text: &str, now: NaiveDateTime, date_format: DateFormat) -> QuickAdd {
+    let original: Vec<&str> = text.split_whitespace().collect();
+    let words = Words {
+        normalized: original.iter().map(|word| normalize(word)).collect(),
+        original,
+    };
+    let today = now.date();
+
+    let mut name: Vec<&str> = Vec::new();
+    let mut tags: Vec<String> = Vec::new();
+    let mut priority = None;
+    let mut estimate = None;
+    let mut recurrence = None;
+    let mut date: Option<NaiveDate> = None;
+    let mut time: Option<NaiveTime> = None;
+
+    let mut index = 0;
+    while index < words.original.len() {
+        let word = words.original[index];
+
+        if let Some(literal) = word.strip_prefix('\\').filter(|rest| !rest.is_empty()) {
+            name.push(literal);
+            index += 1;
+            continue;
+        }
+        if let Some(tag) = parse_tag(word) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+            index += 1;
+            continue;
+        }
+        if let Some(found) = word.strip_prefix('!').and_then(parse_priority) {
+            priority = Some(found);
+            index += 1;
+            continue;
+        }
+        if let Some(found) = word.strip_prefix('~').and_then(parse_estimate) {
+            estimate = Some(found);
+            index += 1;
+            continue;
+        }
+        if recurrence.is_none() {
+            if let Some((length, found)) = parse_recurrence(&words, index) {
+                recurrence = Some(found);
+                index += length;
+                continue;
+            }
+        }
+        if time.is_none() {
+            if let Some((length, found)) = parse_time(&words, index) {
+                time = Some(found);
+                index += length;
+                continue;
+            }
+        }
+        if date.is_none() {
+            if let Some((length, found, at)) = parse_date(&words, index, today, date_format) {
+                date = Some(found);
+                time = time.or(at);
+                index += length;
+                continue;
+            }
+        }
+
+        name.push(word);
+        index += 1;
+    }
+
+    /* A recurring task without a date is first due on its next occurrence */
+    let date = date.or_else(|| {
+        let first = recurrence
+            .as_ref()
+            .map(|recurrence| first_occurrence(recurrence, today))?;
+        match time {
+            Some(time) if first == today && first.and_time(time) <= now => recurrence
+                .as_ref()
+                .map(|recurrence| first_occurrence(recurrence, today + Duration::days(1))),
+            _ => Some(first),
+        }
+    });
+    let due = match (date, time) {
+        (Some(date), Some(time)) => Some(date.and_time(time)),
+        (Some(date), None) => date.and_hms_opt(23, 59, 59),
+        /* A time alone is the next time the clock shows it */
+        (None, Some(time)) if today.and_time(time) > now => Some(today.and_time(time)),
+        (None, Some(time)) => Some((today + Duration::days(1)).and_time(time)),
+        (None, None) => None,
+    };
+
+    QuickAdd {
+        name: name.join(" "),
+        due,
+        tags,
+        priority,
+        recurrence,
+        estimate,
+    }
+}
+
+/* #work. A # followed only by digits, like #12, is more likely a reference than a tag */
+fn parse_tag(word: &str) -> Option<String> {
+    let tag = word.strip_prefix('#')?.trim_end_matches([',', '.', ';']);
+    if tag.chars().any(char::is_alphabetic) {
+        Some(tag.to_lowercase())
+    } else {
+        None
+    }
+}
+
+fn parse_priority(value: &str) -> Option<Priority> {
+    match normalize(value).as_str() {
+        "high" | "h" | "1" | "alta" | "a" => Some(Priority::High),
+        "medium" | "med" | "m" | "2" | "media" => Some(Priority::Medium),
+        "low" | "l" | "3" | "baja" | "b" => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+/* 30m, 1h, 1h30m, 1.5h, or minutes without a unit. Estimates are stored as INT, so longer ones
+aren't taken */
+fn parse_estimate(value: &str) -> Option<i64> {
+    let value = normalize(value);
+    let mut rest = value.as_str();
+    let mut seconds = 0.0;
+
+    while !rest.is_empty() {
+        let number_length = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let amount: f64 = rest[..number_length].parse().ok()?;
+        rest = &rest[number_length..];
+        let unit_length = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_length] {
+            "h" | "hr" | "hrs" | "hour" | "hours" | "hora" | "horas" => 3600.0,
+            "" | "m" | "min" | "mins" | "minute" | "minutes" | "minuto" | "minutos" => 60.0,
+            _ => return None,
+        };
+        rest = &rest[unit_length..];
+        seconds += amount * unit;
+    }
+
+    let seconds = seconds.round();
+    if seconds > 0.0 && seconds <= i32::MAX as f64 {
+        Some(seconds as i64)
+    } else {
+        None
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "monday" | "mondays" | "lunes" => Some(Weekday::Monday),
+        "tuesday" | "tuesdays" | "martes" => Some(Weekday::Tuesday),
+        "wednesday" | "wednesdays" | "miercoles" => Some(Weekday::Wednesday),
+        "thursday" | "thursdays" | "jueves" => Some(Weekday::Thursday),
+        "friday" | "fridays" | "viernes" => Some(Weekday::Friday),
+        "saturday" | "saturdays" | "sabado" | "sabados" => Some(Weekday::Saturday),
+        "sunday" | "sundays" | "domingo" | "domingos" => Some(Weekday::Sunday),
+        _ => None,
+    }
+}
+
+/* Month names are only taken next to a day, so "may" alone is still a word */
+fn parse_month(word: &str) -> Option<u32> {
+    match word {
+        "january" | "jan" | "enero" | "ene" => Some(1),
+        "february" | "feb" | "febrero" => Some(2),
+        "march" | "mar" | "marzo" => Some(3),
+        "april" | "apr" | "abril" | "abr" => Some(4),
+        "may" | "mayo" => Some(5),
+        "june" | "jun" | "junio" => Some(6),
+        "july" | "jul" | "julio" => Some(7),
+        "august" | "aug" | "agosto" | "ago" => Some(8),
+        "september" | "sep" | "sept" | "septiembre" | "setiembre" => Some(9),
+        "october" | "oct" | "octubre" => Some(10),
+        "november" | "nov" | "noviembre" => Some(11),
+        "december" | "dec" | "diciembre" | "dic" => Some(12),
+        _ => None,
+    }
+}
+
+fn parse_number(word: &str) -> Option<u32> {
+    match word {
+        "a" | "an" | "one" | "un" | "una" | "uno" => Some(1),
+        "two" | "dos" => Some(2),
+        "three" | "tres" => Some(3),
+        "four" | "cuatro" => Some(4),
+        "five" | "cinco" => Some(5),
+        "six" | "seis" => Some(6),
+        "seven" | "siete" => Some(7),
+        "eight" | "ocho" => Some(8),
+        "nine" | "nueve" => Some(9),
+        "ten" | "diez" => Some(10),
+        _ => word.parse().ok().filter(|number| *number > 0),
+    }
+}
+
+fn parse_unit(word: &str) -> Option<Unit> {
+    match word {
+        "day" | "days" | "dia" | "dias" => Some(Unit::Day),
+        "week" | "weeks" | "semana" | "semanas" => Some(Unit::Week),
+        "month" | "months" | "mes" | "meses" => Some(Unit::Month),
+        "year" | "years" | "ano" | "anos" => Some(Unit::Year),
+        _ => None,
+    }
+}
+
+fn add(date: NaiveDate, amount: u32, unit: Unit) -> NaiveDate {
+    match unit {
+        Unit::Day => date + Duration::days(amount.into()),
+        Unit::Week => date + Duration::weeks(amount.into()),
+        Unit::Month => date + Months::new(amount),
+        Unit::Year => date + Months::new(amount * 12),
+    }
+}
+
+/* The first `weekday` after `date`, or on it if `inclusive` */
+fn next_weekday(date: NaiveDate, weekday: Weekday, inclusive: bool) -> NaiveDate {
+    let today = date.weekday().number_from_monday() as i64;
+    let mut days = (weekday.iso() as i64 - today).rem_euclid(7);
+    if days == 0 && !inclusive {
+        days = 7;
+    }
+    date + Duration::days(days)
+}
+
+fn first_occurrence(recurrence: &Recurrence, from: NaiveDate) -> NaiveDate {
+    recurrence
+        .weekdays
+        .iter()
+        .map(|weekday| next_weekday(from, *weekday, true))
+        .min()
+        .unwrap_or(from)
+}
+
+/* every day, every 2 weeks, every other month, every monday and thursday, every weekday, daily,
+cada día, cada 2 semanas, todos los lunes, cada lunes y jueves, los días laborables */
+fn parse_recurrence(words: &Words, index: usize) -> Option<(usize, Recurrence)> {
+    let single = |frequency| Recurrence {
+        frequency,
+        interval: 1,
+        weekdays: Vec::new(),
+    };
+    match words.get(index) {
+        "daily" | "everyday" | "diario" | "diariamente" => {
+            return Some((1, single(Frequency::Daily)))
+        }
+        "weekly" | "semanal" | "semanalmente" => return Some((1, single(Frequency::Weekly))),
+        "monthly" | "mensual" | "mensualmente" => return Some((1, single(Frequency::Monthly))),
+        "yearly" | "annually" | "anual" | "anualmente" => {
+            return Some((1, single(Frequency::Yearly)))
+        }
+        _ => {}
+    }
+
+    let start = if words.phrase(index, &["every"]) || words.phrase(index, &["cada"]) {
+        index + 1
+    } else if words.phrase(index, &["todos", "los"]) || words.phrase(index, &["todas", "las"]) {
+        index + 2
+    } else {
+        return None;
+    };
+
+    let (interval, unit_index) = if words.get(start) == "other" {
+        (2, start + 1)
+    } else {
+        match parse_number(words.get(start)) {
+            Some(number) if parse_unit(words.get(start + 1)).is_some() => (number, start + 1),
+            _ => (1, start),
+        }
+    };
+    if let Some(unit) = parse_unit(words.get(unit_index)) {
+        /* días laborables, días hábiles */
+        if matches!(unit, Unit::Day)
+            && matches!(
+                words.get(unit_index + 1),
+                "laborables" | "laborable" | "habiles"
+            )
+        {
+            return Some((unit_index + 2 - index, workdays()));
+        }
+        let frequency = match unit {
+            Unit::Day => Frequency::Daily,
+            Unit::Week => Frequency::Weekly,
+            Unit::Month => Frequency::Monthly,
+            Unit::Year => Frequency::Yearly,
+        };
+        return Some((
+            unit_index + 1 - index,
+            Recurrence {
+                frequency,
+                interval,
+                weekdays: Vec::new(),
+            },
+        ));
+    }
+
+    if matches!(
+        words.get(start),
+        "weekday" | "weekdays" | "workday" | "workdays"
+    ) {
+        return Some((start + 1 - index, workdays()));
+    }
+
+    /* A list of days: monday, thursday and friday */
+    let mut weekdays = Vec::new();
+    let mut end = start;
+    while let Some(weekday) = parse_weekday(words.get(end)) {
+        weekdays.push(weekday);
+        end += 1;
+        let joined = matches!(words.get(end), "and" | "y" | "&")
+            && parse_weekday(words.get(end + 1)).is_some();
+        if joined {
+            end += 1;
+        }
+    }
+    if weekdays.is_empty() {
+        return None;
+    }
+    Some((
+        end - index,
+        Recurrence {
+            frequency: Frequency::Weekly,
+            interval: 1,
+            weekdays,
+        },
+    ))
+}
+
+fn workdays() -> Recurrence {
+    Recurrence {
+        frequency: Frequency::Weekly,
+        interval: 1,
+        weekdays: vec![
+            Weekday::Monday,
+            Weekday::Tuesday,
+            Weekday::Wednesday,
+            Weekday::Thursday,
+            Weekday::Friday,
+        ],
+    }
+}
+
+/* 9am, 9:30pm, 21:00, at 9, a las 9, 9 de la noche, noon, al mediodía */
+fn parse_time(words: &Words, index: usize) -> Option<(usize, NaiveTime)> {
+    let prefix = if words.phrase(index, &["a", "las"]) {
+        2
+    } else if matches!(words.get(index), "at" | "@" | "al") || words.phrase(index, &["a", "la"]) {
+        if words.get(index) == "a" {
+            2
+        } else {
+            1
+        }
+    } else {
+        0
+    };
+    let at = index + prefix;
+
+    if matches!(words.get(at), "noon" | "midday" | "mediodia") {
+        return Some((prefix + 1, NaiveTime::from_hms_opt(12, 0, 0)?));
+    }
+
+    let word = words.get(at);
+    let (clock, mut meridiem) = match word.strip_suffix("am") {
+        Some(clock) => (clock, Some(false)),
+        None => match word.strip_suffix("pm") {
+            Some(clock) => (clock, Some(true)),
+            None => (word, None),
+        },
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => {
+            (hour.parse::<u32>().ok()?, minute.parse().ok()?)
+        }
+        Some(_) => return None,
+        None => (clock.parse::<u32>().ok()?, 0),
+    };
+    let mut length = prefix + 1;
+
+    if meridiem.is_none() {
+        meridiem = match words.get(at + 1) {
+            "am" | "a.m" => Some(false),
+            "pm" | "p.m" => Some(true),
+            _ => None,
+        };
+        if meridiem.is_some() {
+            length += 1;
+        }
+    }
+    /* 9 de la mañana, 7 de la tarde, 9 in the evening */
+    let mut part_of_day = false;
+    if meridiem.is_none() {
+        let period = if words.phrase(at + 1, &["de", "la"]) || words.phrase(at + 1, &["in", "the"])
+        {
+            words.get(at + 3)
+        } else {
+            ""
+        };
+        meridiem = match period {
+            "manana" | "madrugada" | "morning" => Some(false),
+            "tarde" | "noche" | "afternoon" | "evening" | "night" => Some(true),
+            _ => None,
+        };
+        if meridiem.is_some() {
+            part_of_day = true;
+            length += 3;
+        }
+    }
+
+    /* A bare number is only a time with something that says so */
+    if prefix == 0 && meridiem.is_none() && !clock.contains(':') {
+        return None;
+    }
+    let hour = match meridiem {
+        Some(_) if hour == 0 || hour > 12 => {
+            if part_of_day && hour <= 23 {
+                hour
+            } else {
+                return None;
+            }
+        }
+        Some(false) if hour == 12 => 0,
+        Some(true) if hour < 12 => hour + 12,
+        _ => hour,
+    };
+
+    NaiveTime::from_hms_opt(hour, minute, 0).map(|time| (length, time))
+}
+
+/* A date, maybe after on, by, el or para: today, mañana, pasado mañana, friday, next week, el
+lunes que viene, in 3 days, en dos semanas, 2026-10-25, 25/10, oct 25, 25 de octubre de 2026. Some
+phrases also say the time, like tonight. */
+fn parse_date(
+    words: &Words,
+    index: usize,
+    today: NaiveDate,
+    date_format: DateFormat,
+) -> Option<(usize, NaiveDate, Option<NaiveTime>)> {
+    let mut prefix = 0;
+    while prefix < 2
+        && matches!(
+            words.get(index + prefix),
+            "on" | "by" | "el" | "la" | "para"
+        )
+    {
+        prefix += 1;
+    }
+    let at = index + prefix;
+
+    let (length, date, time) = match words.get(at) {
+        "today" | "hoy" => (1, today, None),
+        "tonight" => (1, today, NaiveTime::from_hms_opt(20, 0, 0)),
+        "tomorrow" | "manana" => (1, today + Duration::days(1), None),
+        "pasado" if words.get(at + 1) == "manana" => (2, today + Duration::days(2), None),
+        "day" if words.phrase(at + 1, &["after", "tomorrow"]) => {
+            (3, today + Duration::days(2), None)
+        }
+        "the" if words.phrase(at + 1, &["day", "after", "tomorrow"]) => {
+            (4, today + Duration::days(2), None)
+        }
+        "esta" if words.get(at + 1) == "noche" => (2, today, NaiveTime::from_hms_opt(20, 0, 0)),
+        "next" | "proximo" | "proxima" => {
+            let next = words.get(at + 1);
+            if let Some(weekday) = parse_weekday(next) {
+                (2, next_weekday(today, weekday, false), None)
+            } else {
+                (2, add(today, 1, parse_unit(next)?), None)
+            }
+        }
+        "this" | "este" | "esta" => {
+            let weekday = parse_weekday(words.get(at + 1))?;
+            (2, next_weekday(today, weekday, true), None)
+        }
+        "in" | "en" | "dentro" => {
+            let start = if words.phrase(at, &["dentro", "de"]) {
+                at + 2
+            } else {
+                at + 1
+            };
+            let amount = parse_number(words.get(start))?;
+            let unit = parse_unit(words.get(start + 1))?;
+            (start + 2 - at, add(today, amount, unit), None)
+        }
+        word => {
+            if let Some(weekday) = parse_weekday(word) {
+                (1, next_weekday(today, weekday, false), None)
+            } else if let Some(unit) = parse_unit(word).filter(|_| {
+                /* la semana que viene, el mes que viene */
+                words.phrase(at + 1, &["que", "viene"])
+            }) {
+                (3, add(today, 1, unit), None)
+            } else {
+                let (length, date) = parse_calendar_date(words, at, today, date_format)?;
+                (length, date, None)
+            }
+        }
+    };
+
+    /* el lunes que viene */
+    let length =
+        if parse_weekday(words.get(at)).is_some() && words.phrase(at + 1, &["que", "viene"]) {
+            length + 2
+        } else {
+            length
+        };
+
+    Some((prefix + length, date, time))
+}
+
+/* A day of the calendar, with or without its year. Without it, it is the next time that day comes. */
+fn parse_calendar_date(
+    words: &Words,
+    index: usize,
+    today: NaiveDate,
+    date_format: DateFormat,
+) -> Option<(usize, NaiveDate)> {
+    let word = words.get(index);
+
+    if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+        return Some((1, date));
+    }
+
+    let parts: Vec<&str> = word.split('/').collect();
+    if (2..=3).contains(&parts.len()) {
+        let first: u32 = parts[0].parse().ok()?;
+        let second: u32 = parts[1].parse().ok()?;
+        let (day, month) = match date_format {
+            DateFormat::MonthDayYear => (second, first),
+            _ => (first, second),
+        };
+        let year = match parts.get(2) {
+            Some(year) if year.len() == 2 => Some(2000 + year.parse::<i32>().ok()?),
+            Some(year) => Some(year.parse().ok()?),
+            None => None,
+        };
+        return Some((1, calendar_date(year, month, day, today)?));
+    }
+
+    let day_of = |word: &str| -> Option<u32> {
+        word.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+            .parse()
+            .ok()
+            .filter(|day| (1..=31).contains(day))
+            .filter(|_| {
+                /* 25, 25th, 1st, 2nd, 3rd */
+                let suffix = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                matches!(suffix, "" | "st" | "nd" | "rd" | "th")
+            })
+    };
+
+    /* 25 oct, 25 de octubre, or oct 25, october 25th */
+    let (mut length, day, month) = if let Some(day) = day_of(word) {
+        if let Some(month) = parse_month(words.get(index + 1)) {
+            (2, day, month)
+        } else if words.get(index + 1) == "de" {
+            (3, day, parse_month(words.get(index + 2))?)
+        } else {
+            return None;
+        }
+    } else {
+        let month = parse_month(word)?;
+        (2, day_of(words.get(index + 1))?, month)
+    };
+
+    /* de 2026, or 2026 */
+    let year_index = if words.get(index + length) == "de" {
+        index + length + 1
+    } else {
+        index + length
+    };
+    let year = words
+        .get(year_index)
+        .parse::<i32>()
+        .ok()
+        .filter(|year| (1970..=9999).contains(year));
+    if year.is_some() {
+        length = year_index + 1 - index;
+    }
+
+    Some((length, calendar_date(year, month, day, today)?))
+}
+
+fn calendar_date(year: Option<i32>, month: u32, day: u32, today: NaiveDate) -> Option<NaiveDate> {
+    match year {
+        Some(year) => NaiveDate::from_ymd_opt(year, month, day),
+        None => {
+            let this_year = NaiveDate::from_ymd_opt(today.year(), month, day);
+            match this_year {
+                Some(date) if date >= today => Some(date),
+                _ => NaiveDate::from_ymd_opt(today.year() + 1, month, day),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Case {
+        text: &'static str,
+        now: &'static str,
+        date_format: DateFormat,
+        name: &'static str,
+        tags: &'static [&'static str],
+        priority: Option<Priority>,
+        due: Option<&'static str>,
+        recurrence: Option<&'static str>,
+        estimate: Option<i64>,
+    }
+
+    /* 2026-10-19 is a Monday */
+    const MONDAY: &str = "2026-10-19 10:00:00";
+
+    const fn case(text: &'static str, name: &'static str, due: Option<&'static str>) -> Case {
+        Case {
+            text,
+            now: MONDAY,
+            date_format: DateFormat::DayMonthYear,
+            name,
+            tags: &[],
+            priority: None,
+            due,
+            recurrence: None,
+            estimate: None,
+        }
+    }
+
+    fn cases() -> Vec<Case> {
+        vec![
+            Case {
+                tags: &["work"],
+                priority: Some(Priority::High),
+                recurrence: Some("FREQ=WEEKLY;BYDAY=MO"),
+                estimate: Some(1800),
+                ..case(
+                    "Email Ana tomorrow 9am #work !high every monday ~30m",
+                    "Email Ana",
+                    Some("2026-10-20 09:00:00"),
+                )
+            },
+            Case {
+                tags: &["casa"],
+                priority: Some(Priority::High),
+                ..case(
+                    "Llamar a Juan mañana a las 9 #casa !alta",
+                    "Llamar a Juan",
+                    Some("2026-10-20 09:00:00"),
+                )
+            },
+            Case {
+                estimate: Some(5400),
+                ..case(
+                    "Revisar informe pasado mañana a las 17:30 ~1h30m",
+                    "Revisar informe",
+                    Some("2026-10-21 17:30:00"),
+                )
+            },
+            case(
+                "Entregar pasado mañana",
+                "Entregar",
+                Some("2026-10-21 23:59:59"),
+            ),
+            Case {
+                recurrence: Some("FREQ=WEEKLY;BYDAY=MO,TH"),
+                ..case(
+                    "Gimnasio cada lunes y jueves a las 7 de la tarde",
+                    "Gimnasio",
+                    Some("2026-10-19 19:00:00"),
+                )
+            },
+            Case {
+                recurrence: Some("FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR"),
+                ..case(
+                    "Standup every weekday at 9:15",
+                    "Standup",
+                    Some("2026-10-20 09:15:00"),
+                )
+            },
+            Case {
+                recurrence: Some("FREQ=DAILY;INTERVAL=2"),
+                ..case(
+                    "Water plants every other day",
+                    "Water plants",
+                    Some("2026-10-19 23:59:59"),
+                )
+            },
+            case("Demo next friday 5pm", "Demo", Some("2026-10-23 17:00:00")),
+            case(
+                "Reunión el lunes que viene a las 10",
+                "Reunión",
+                Some("2026-10-26 10:00:00"),
+            ),
+            case("Pay at 9", "Pay", Some("2026-10-20 09:00:00")),
+            case("Fix #12 tonight", "Fix #12", Some("2026-10-19 20:00:00")),
+            case("Cena esta noche", "Cena", Some("2026-10-19 20:00:00")),
+            case("Call \\tomorrow", "Call tomorrow", None),
+            case("Comprar la leche", "Comprar la leche", None),
+            case(
+                "Viaje 25 de diciembre",
+                "Viaje",
+                Some("2026-12-25 23:59:59"),
+            ),
+            case(
+                "Dentist oct 25 at 3pm",
+                "Dentist",
+                Some("2026-10-25 15:00:00"),
+            ),
+            /* Day and month follow the format of the user; past days are next year */
+            case("Report 03/04", "Report", Some("2027-04-03 23:59:59")),
+            Case {
+                date_format: DateFormat::MonthDayYear,
+                ..case("Report 03/04", "Report", Some("2027-03-04 23:59:59"))
+            },
+            case("Report 25/10/27", "Report", Some("2027-10-25 23:59:59")),
+            case("Check 30/02", "Check 30/02", None),
+            /* Months that are shorter end on their last day */
+            Case {
+                now: "2026-01-31 10:00:00",
+                ..case(
+                    "Pay rent in 1 month",
+                    "Pay rent",
+                    Some("2026-02-28 23:59:59"),
+                )
+            },
+            Case {
+                now: "2024-01-31 10:00:00",
+                ..case(
+                    "Pay rent in 1 month",
+                    "Pay rent",
+                    Some("2024-02-29 23:59:59"),
+                )
+            },
+            Case {
+                now: "2026-12-31 10:00:00",
+                ..case("Party 01/01", "Party", Some("2027-01-01 23:59:59"))
+            },
+            Case {
+                now: "2026-12-31 10:00:00",
+                ..case("Party 31/12", "Party", Some("2026-12-31 23:59:59"))
+            },
+            Case {
+                priority: Some(Priority::Low),
+                ..case(
+                    "Renovar pasaporte en dos meses !baja",
+                    "Renovar pasaporte",
+                    Some("2026-12-19 23:59:59"),
+                )
+            },
+            /* Too long to be stored, so it stays in the name */
+            case("Long task ~99999999999h", "Long task ~99999999999h", None),
+        ]
+    }
+
+    #[test]
+    fn parses_lines() {
+        for case in cases() {
+            let now = NaiveDateTime::parse_from_str(case.now, "%Y-%m-%d %H:%M:%S").unwrap();
+            let parsed = parse(case.text, now, case.date_format);
+            let due = parsed
+                .due
+                .map(|due| due.format("%Y-%m-%d %H:%M:%S").to_string());
+            let tags: Vec<&str> = parsed.tags.iter().map(String::as_str).collect();
+
+            assert_eq!(parsed.name, case.name, "name of {:?}", case.text);
+            assert_eq!(tags, case.tags, "tags of {:?}", case.text);
+            assert!(
+                parsed.priority == case.priority,
+                "priority of {:?}",
+                case.text
+            );
+            assert_eq!(due.as_deref(), case.due, "due date of {:?}", case.text);
+            assert_eq!(
+                parsed.recurrence.map(|recurrence| recurrence.to_rrule()),
+                case.recurrence.map(String::from),
+                "recurrence of {:?}",
+                case.text
+            );
+            assert_eq!(
+                parsed.estimate, case.estimate,
+                "estimate of {:?}",
+                case.text
+            );
+        }
+    }
+
+    #[test]
+    fn bounds_estimates() {
+        assert_eq!(parse_estimate("1.5h"), Some(5400));
+        assert_eq!(parse_estimate("45"), Some(2700));
+        assert_eq!(parse_estimate("0m"), None);
+        assert_eq!(parse_estimate("596523h"), Some(596523 * 3600));
+        assert_eq!(parse_estimate("596524h"), None);
+        assert_eq!(parse_estimate("99999999999h"), None);
+    }
+}
@@ -0,0 +1,98 @@
+/* Positions of tasks in a list are strings that sort in the order of the list, so moving a task
+only has to write its own position: there is always a string between two others. They use the
+digits 0-9 and a-z and never end in 0, which leaves room before any of them. */
+
+const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+const BASE: usize = 36;
+
+/// Longest position that is handed out. A list whose positions would get longer is rebalanced.
+pub const MAX_RANK_LEN: usize = 32;
+
+fn digit(character: u8) -> usize {
+    DIGITS
+        .iter()
+        .position(|digit| *digit == character)
+        .unwrap_or(0)
+}
+
+/// A position that sorts after `before` and before `after`. Missing ends mean the start and the
+/// end of the list.
+///
+/// Returns None when there is nothing between them, which only happens if `after` isn't greater
+/// than `before`, as with two tasks that got the same position.
+pub fn between(before: Option<&str>, after: Option<&str>) -> Option<String> {
+    let before = before.unwrap_or("").trim_end_matches('0');
+    match after {
+        Some(after) if after <= before => None,
+        Some(after) => Some(midpoint(before.as_bytes(), Some(after.as_bytes()))),
+        None => Some(increment(before.as_bytes())),
+    }
+}
+
+/// A position at the end of a list whose last task is at `last`.
+pub fn after(last: Option<&str>) -> String {
+    increment(last.unwrap_or("").trim_end_matches('0').as_bytes())
+}
+
+/* Tasks are mostly added at the end, so that keeps positions short for longer than a midpoint */
+fn increment(before: &[u8]) -> String {
+    match before.first() {
+        Some(first) if digit(*first) < BASE - 1 => (DIGITS[digit(*first) + 1] as char).to_string(),
+        Some(first) => (*first as char).to_string() + &increment(&before[1..]),
+        None => String::from("1"),
+    }
+}
+
+fn midpoint(before: &[u8], after: Option<&[u8]>) -> String {
+    if let Some(after) = after {
+        /* A shorter `before` is padded with zeros, which is the same value */
+        let common = after
+            .iter()
+            .enumerate()
+            .take_while(|(index, digit)| before.get(*index).copied().unwrap_or(b'0') == **digit)
+            .count();
+        if common > 0 {
+            let prefix = String::from_utf8_lossy(&after[..common]).into_owned();
+            let before = before.get(common..).unwrap_or(&[]);
+            return prefix + &midpoint(before, Some(&after[common..]));
+        }
+    }
+
+    let low = before.first().map_or(0, |digit| self::digit(*digit));
+    let high = after
+        .and_then(|after| after.first())
+        .map_or(BASE, |digit| self::digit(*digit));
+
+    if high - low > 1 {
+        (DIGITS[(low + high) / 2] as char).to_string()
+    } else if let Some(after) = after.filter(|after| after.len() > 1) {
+        /* The first digit of `after` alone sorts before it and after `before` */
+        (after[0] as char).to_string()
+    } else {
+        (DIGITS[low] as char).to_string() + &midpoint(before.get(1..).unwrap_or(&[]), None)
+    }
+}
+
+/// Evenly spaced positions for a list of `count` tasks, in order, with room between each of them.
+pub fn spread(count: usize) -> Vec<String> {
+    let mut width = 1;
+    while BASE.pow(width as u32) < (count + 1) * BASE {
+        width += 1;
+    }
+    let space = (BASE as u128).pow(width as u32);
+
+    (1..=count)
+        .map(|index| {
+            let mut value = space * index as u128 / (count as u128 + 1);
+            let mut rank = vec![b'0'; width];
+            for digit in rank.iter_mut().rev() {
+                *digit = DIGITS[(value % BASE as u128) as usize];
+                value /= BASE as u128;
+            }
+            String::from_utf8_lossy(&rank)
+                .trim_end_matches('0')
+                .to_string()
+        })
+        .collect()
+}
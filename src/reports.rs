@@ -0,0 +1,123 @@
+use crate::model::{
+    DayPomodoros, EstimateAccuracy, EstimateReport, PeriodAccuracy, ReportPeriod, ResponseTask,
+    TagAccuracy,
+};
+
+use crate::timezone::UserClock;
+
+use chrono::NaiveDate;
+
+use std::collections::BTreeMap;
+
+impl EstimateAccuracy {
+    fn add(&mut self, estimate: i64, tracked: i64) {
+        self.tasks += 1;
+        self.estimated += estimate;
+        self.tracked += tracked;
+        self.variance = self.tracked - self.estimated;
+        self.ratio = if self.estimated > 0 {
+            Some(self.tracked as f64 / self.estimated as f64)
+        } else {
+            None
+        };
+    }
+}
+
+/// When the last segment of a task was closed, or None while it is running or was never started.
+fn completed_at(task: &ResponseTask) -> Option<i64> {
+    if task
+        .history
+        .iter()
+        .any(|segment| segment.finish_time.is_none())
+    {
+        return None;
+    }
+    task.history
+        .iter()
+        .filter_map(|segment| segment.finish_time)
+        .max()
+}
+
+/// First day of the user's week or month that contains the timestamp.
+fn period_start(timestamp: i64, period: ReportPeriod, clock: &UserClock) -> NaiveDate {
+    let date = clock.local_date(timestamp);
+
+    match period {
+        ReportPeriod::Week => clock.start_of_week(date),
+        ReportPeriod::Month => clock.start_of_month(date),
+    }
+}
+
+fn into_periods(periods: BTreeMap<NaiveDate, EstimateAccuracy>) -> Vec<PeriodAccuracy> {
+    periods
+        .into_iter()
+        .map(|(start, accuracy)| PeriodAccuracy {
+            period: start.to_string(),
+            accuracy,
+        })
+        .collect()
+}
+
+/// It compares estimated and tracked time of the tasks that have an estimate and are not running,
+/// overall, per period (by the day the task was last stopped, in the user's time zone) and per tag.
+pub fn estimate_report(
+    tasks: &[ResponseTask],
+    period: ReportPeriod,
+    clock: &UserClock,
+) -> EstimateReport {
+    let mut overall = EstimateAccuracy::default();
+    let mut periods: BTreeMap<NaiveDate, EstimateAccuracy> = BTreeMap::new();
+    let mut tags: BTreeMap<String, (EstimateAccuracy, BTreeMap<NaiveDate, EstimateAccuracy>)> =
+        BTreeMap::new();
+
+    for task in tasks {
+        let (estimate, completed_at) = match (task.estimate, completed_at(task)) {
+            (Some(estimate), Some(completed_at)) => (estimate, completed_at),
+            _ => continue,
+        };
+        let start = period_start(completed_at, period, clock);
+
+        overall.add(estimate, task.tracked);
+        periods
+            .entry(start)
+            .or_default()
+            .add(estimate, task.tracked);
+
+        for tag in &task.tags {
+            let (tag_overall, tag_periods) = tags.entry(tag.clone()).or_default();
+            tag_overall.add(estimate, task.tracked);
+            tag_periods
+                .entry(start)
+                .or_default()
+                .add(estimate, task.tracked);
+        }
+    }
+
+    EstimateReport {
+        overall,
+        periods: into_periods(periods),
+        tags: tags
+            .into_iter()
+            .map(|(tag, (overall, periods))| TagAccuracy {
+                tag,
+                overall,
+                periods: into_periods(periods),
+            })
+            .collect(),
+    }
+}
+
+/// It counts pomodoros per calendar day in the user's time zone.
+pub fn pomodoros_by_day(completed_at: &[i64], clock: &UserClock) -> Vec<DayPomodoros> {
+    let mut days: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for completed_at in completed_at {
+        *days.entry(clock.local_date(*completed_at)).or_insert(0) += 1;
+    }
+
+    days.into_iter()
+        .map(|(day, pomodoros)| DayPomodoros {
+            day: day.to_string(),
+            pomodoros,
+        })
+        .collect()
+}
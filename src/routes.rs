@@ -1,140 +1,3134 @@
-use crate::database::{finish_task_and_save_time, start_task_and_save_time,add_task, delete_task, get_tasks_by_user, insert_new_user, verify_password};
+use crate::database::{
+    add_attachment, add_comment, add_project, add_smart_list, add_task, add_tasks, add_webhook,
+    apply_bulk_operations, apply_sync_changes, archive_finished_tasks, archive_task, claim_blob,
+    current_sync_token, delete_attachment, delete_comment, delete_smart_list, delete_task,
+    delete_webhook, empty_trash, exceeds_attachment_quota, finish_task_and_save_time,
+    get_active_focus_session, get_activity, get_attachment, get_attachment_usage, get_attachments,
+    get_calendar_feed_user, get_changes_since, get_comment, get_comment_bodies, get_comments,
+    get_filtered_tasks, get_invoice, get_pomodoro_stats, get_project_by_user, get_projects_by_user,
+    get_running_task_session, get_smart_list_by_name, get_smart_lists_by_user,
+    get_sync_clocks_by_user, get_task_by_user, get_task_session, get_task_sessions,
+    get_tasks_by_ids, get_tasks_by_user, get_time_entries_by_user, get_trashed_tasks,
+    get_user_settings, get_webhook, get_webhook_deliveries, get_webhooks_by_user, insert_new_user,
+    is_an_invalid_task_id, issue_invoice, mark_blob_stored, move_task, preview_invoice, purge_task,
+    replay_webhook_delivery, restore_task, revoke_calendar_feed, rotate_calendar_feed,
+    save_user_settings, search_task_ids, set_blob_thumbnail, start_focus_session,
+    start_task_and_save_time, stop_focus_session, unarchive_task, update_comment,
+    update_smart_list, update_tag, update_task, update_webhook, verify_password,
+};
 
-use crate::jwt::generate_token;
+use crate::attachments::{
+    blob_key, byte_range, clean_filename, content_type as attachment_type, max_file_size, quota,
+    thumbnail, thumbnail_key, ByteRange,
+};
+
+use crate::error::{duplicate_key, ApiError, Problem};
+
+use crate::filter::{Filter, FilterError};
+
+use crate::events::{serve_websocket, sse_stream, EventHub};
+
+use crate::focus::FocusEvents;
+
+use crate::graphql::{serve_graphql_websocket, websocket_protocol, TaskSchema, Viewer, MAX_BATCH};
+
+use crate::sync::sync_response;
+
+use crate::jwt::{generate_token, verify_download_token};
 
 use crate::utils::validate_token;
 
-use crate::model::{Db, Login, NewTask, TaskId, User};
+use crate::validation::{
+    validate_comment, validate_focus_settings, validate_smart_list, validate_task,
+    validate_task_update, validate_user, validate_webhook, FieldError,
+};
+
+use crate::webhooks::{check_destination, enqueue_ping, private_networks_allowed};
+
+use crate::versions::V1;
+
+use crate::model::{
+    Activity, ActivityQuery, ArchiveFilter, ArchiveFinished, ArchivedTasks, Attachment,
+    AttachmentError, AttachmentQuery, AttachmentUsage, BulkItemResult, BulkItemStatus, BulkMode,
+    BulkOperation, BulkOutcome, BulkRequest, BulkResponse, CalendarFeed, Comment, CommentUpdate,
+    Db, EstimateReport, EstimateReportQuery, EventStreamQuery, FocusSession, FocusTransition,
+    GraphQLSocketQuery, Invoice, InvoiceFormat, InvoiceFormatQuery, InvoiceQuery, Login,
+    NewComment, NewFocusSession, NewProject, NewSmartList, NewTask, NewWebhook, ParsedQuickAdd,
+    PomodoroStats, Project, QuickAddRequest, QuickAddResponse, ReportPeriod, ResponseTask,
+    SearchQuery, SearchResult, SettingsUpdate, SmartList, SmartListUpdate, SyncEntity, SyncRequest,
+    SyncResponse, TagUpdate, TaskError, TaskEvent, TaskEventKind, TaskId, TaskListQuery, TaskMove,
+    TaskSession, TaskUpdate, Token, TrashedTask, User, UserSettings, VerificationError, Webhook,
+    WebhookDelivery, WebhookUpdate,
+};
+
+use crate::ical::render_calendar;
+
+use crate::invoice::{render_csv, render_html};
+
+use crate::quick_add;
+
+use crate::reports::estimate_report;
+
+use crate::search::{boolean_query, highlights, search_terms, MIN_TERM_LEN};
+
+use crate::storage::Storage;
 
-use actix_web::web::{Data, Json, Path};
-use actix_web::{delete, get, patch, post, HttpRequest, HttpResponse, Responder};
+use crate::trash::retention_days;
 
+use crate::timezone::{is_valid_locale, is_valid_time_zone, UserClock};
+
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use actix_web::http::header::{
+    self, ContentDisposition, DispositionParam, DispositionType, HeaderValue,
+};
+use actix_web::http::StatusCode;
+use actix_web::web::{block, BytesMut, Data, Json, Path, Payload, Query, ServiceConfig};
+use actix_web::{delete, get, patch, post, HttpRequest, HttpResponse};
+
+use actix_multipart::{Multipart, MultipartError};
+
+use async_graphql::BatchRequest;
+
+use futures_util::TryStreamExt;
+
+use sha2::{Digest, Sha256};
+
+use std::io;
+
+/// The routes of the JSON API, mounted under `/api/v1`.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(login)
+        .service(get_tasks)
+        .service(post_task)
+        .service(post_task_quick)
+        .service(post_tasks_batch)
+        .service(post_tasks_bulk)
+        .service(get_task)
+        .service(register_user)
+        .service(delete_tasks)
+        .service(patch_task)
+        .service(start_task)
+        .service(finish_task)
+        .service(delete_task_by_id)
+        .service(move_task_by_id)
+        .service(archive_finished)
+        .service(archive_task_by_id)
+        .service(unarchive_task_by_id)
+        .service(get_trash)
+        .service(restore_trashed_task)
+        .service(purge_trashed_task)
+        .service(delete_trash)
+        .service(get_sessions)
+        .service(get_session)
+        .service(post_session)
+        .service(patch_current_session)
+        .service(task_events)
+        .service(task_events_ws)
+        .service(post_sync)
+        .service(search)
+        .service(focus_stats)
+        .service(focus_events)
+        .service(get_focus)
+        .service(start_focus)
+        .service(stop_focus)
+        .service(estimates_report)
+        .service(get_projects)
+        .service(get_project)
+        .service(post_project)
+        .service(patch_tag)
+        .service(invoice_preview)
+        .service(post_invoice)
+        .service(get_invoice_by_id)
+        .service(get_settings)
+        .service(patch_settings)
+        .service(get_calendar)
+        .service(post_calendar_feed)
+        .service(delete_calendar_feed)
+        .service(get_calendar_feed)
+        .service(post_smart_list)
+        .service(get_smart_lists)
+        .service(patch_smart_list)
+        .service(delete_smart_list_by_id)
+        .service(get_task_comments)
+        .service(post_task_comment)
+        .service(patch_task_comment)
+        .service(delete_task_comment)
+        .service(get_task_activity)
+        .service(get_user_activity)
+        .service(get_attachment_usage_by_user)
+        .service(get_task_attachments)
+        .service(post_task_attachment)
+        .service(get_attachment_content)
+        .service(get_attachment_thumbnail)
+        .service(delete_task_attachment)
+        .service(post_webhook)
+        .service(get_webhooks)
+        .service(patch_webhook)
+        .service(delete_webhook_by_id)
+        .service(get_deliveries)
+        .service(replay_delivery)
+        .service(ping_webhook)
+        .service(post_graphql)
+        .service(get_graphql_schema)
+        .service(graphql_ws);
+}
+
+/// The routes that existed before the API was versioned, still mounted at the root as deprecated
+/// aliases. Routes added since then are only published under `/api/v1`, so don't add to this list.
+pub fn configure_legacy(cfg: &mut ServiceConfig) {
+    cfg.service(login)
+        .service(get_tasks)
+        .service(post_task_legacy)
+        .service(register_user)
+        .service(delete_tasks)
+        .service(patch_task)
+        .service(start_task)
+        .service(finish_task)
+        .service(focus_stats)
+        .service(focus_events)
+        .service(get_focus)
+        .service(start_focus)
+        .service(stop_focus)
+        .service(estimates_report)
+        .service(get_projects)
+        .service(post_project_legacy)
+        .service(patch_tag)
+        .service(invoice_preview)
+        .service(post_invoice)
+        .service(get_invoice_by_id)
+        .service(get_settings)
+        .service(patch_settings)
+        .service(get_calendar)
+        .service(post_calendar_feed)
+        .service(delete_calendar_feed)
+        .service(get_calendar_feed);
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = User,
+    responses(
+        (status = 200, description = "User added", body = String, content_type = "text/plain"),
+        (status = 409, description = "The username or email is taken", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[post("/register_user")]
-pub async fn register_user(new_user: Json<User>, db: Data<Db>) -> impl Responder {
+pub async fn register_user(new_user: Json<User>, db: Data<Db>) -> Result<HttpResponse, ApiError> {
     let new_user = User {
         password: new_user.password.clone(),
         username: new_user.username.clone(),
         email: new_user.email.clone(),
     };
 
+    let errors = validate_user(&new_user);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
     match insert_new_user(new_user, &db).await {
-        Ok(_) => HttpResponse::Ok().body("User added"),
-        Err(_) => HttpResponse::InternalServerError().body("Error adding user"),
+        Ok(_) => Ok(HttpResponse::Ok().body("User added")),
+        Err(error) => Err(match duplicate_key(&error).as_deref() {
+            Some("users_username") => {
+                ApiError::duplicate_field("username", "The username is already taken")
+            }
+            Some("users_email") => {
+                ApiError::duplicate_field("email", "There is already an account with that email")
+            }
+            _ => ApiError::from(error),
+        }),
     }
 }
 
+#[utoipa::path(
+    tag = "users",
+    request_body = Login,
+    responses(
+        (status = 200, description = "Token for the other endpoints", body = Token),
+        (status = 401, description = "Wrong username or password", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[post("/login")]
-pub async fn login(user: Json<Login>, db: Data<Db>) -> impl Responder {
+pub async fn login(user: Json<Login>, db: Data<Db>) -> Result<HttpResponse, ApiError> {
     let user = Login {
         password: user.password.clone(),
         username: user.username.clone(),
     };
 
-    let users = verify_password(&user, &db).await;
-
-    match users {
-        Ok(users) => {
-            if users.len() == 1 {
-                let token = generate_token(&users[0]);
+    let users = verify_password(&user, &db).await?;
 
-                match token {
-                    Ok(token) => HttpResponse::Ok().json(token),
-                    Err(_) => HttpResponse::InternalServerError().body("Error generating token"),
-                }
-            } else {
-                HttpResponse::Unauthorized().body("Verification failed")
-            }
+    if users.len() == 1 {
+        match generate_token(&users[0]) {
+            Ok(token) => Ok(HttpResponse::Ok().json(token)),
+            Err(_) => Err(ApiError::internal()),
         }
-        Err(_) => HttpResponse::InternalServerError().body("Error verifying user"),
+    } else {
+        Err(ApiError::unauthorized(
+            "verification_failed",
+            "Verification failed",
+        ))
     }
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    params(TaskListQuery),
+    responses(
+        (status = 200, description = "The tasks of the user that match the filter in the order of their positions, without the archived ones unless they are asked for", body = Vec<ResponseTask>),
+        (status = 400, description = "The filter can't be parsed or uses a list that doesn't exist; the message says where", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
 #[get("/tasks")]
-pub async fn get_tasks(req: HttpRequest, db: Data<Db>) -> impl Responder {
+pub async fn get_tasks(
+    query: Query<TaskListQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
     let authorization = req.headers().get("Authorization");
 
-    match validate_token(authorization) {
-        Ok(user_id) => {
-            let tasks = get_tasks_by_user(user_id, &db).await;
-            match tasks {
-                Ok(tasks) => HttpResponse::Ok().json(tasks),
-                Err(_) => HttpResponse::InternalServerError().body("Error getting tasks"),
-            }
+    let user_id = validate_token(authorization)?;
+
+    let (tasks, archived) = match query.filter.as_deref() {
+        Some(filter) => {
+            let filter = resolve_filter(user_id, filter, &db).await?;
+            let settings = get_user_settings(user_id, &db).await?;
+            let tasks =
+                get_filtered_tasks(user_id, &filter, &UserClock::from(&settings), &db).await?;
+            /* A filter that says which archived tasks it wants gets them */
+            let archived = match query.archived {
+                Some(archived) => archived,
+                None if filter.mentions_archived() => ArchiveFilter::Include,
+                None => ArchiveFilter::default(),
+            };
+            (tasks, archived)
         }
-        Err(error) => error.message(),
+        None => (
+            get_tasks_by_user(user_id, &db).await?,
+            query.archived.unwrap_or_default(),
+        ),
+    };
+    let tasks: Vec<ResponseTask> = tasks
+        .into_iter()
+        .filter(|task| archived.matches(task))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(tasks))
+}
+
+/* It parses a filter and puts the terms of the saved lists it names in their place */
+async fn resolve_filter(user_id: i32, input: &str, db: &Data<Db>) -> Result<Filter, ApiError> {
+    let invalid = |error: FilterError| ApiError::bad_request("invalid_filter", error.to_string());
+
+    let mut filter = Filter::parse(input).map_err(invalid)?;
+
+    let lists: Vec<(String, usize)> = filter
+        .lists()
+        .into_iter()
+        .map(|(name, position)| (name.to_string(), position))
+        .collect();
+    for (name, position) in lists {
+        let list = get_smart_list_by_name(user_id, &name, db)
+            .await?
+            .ok_or_else(|| {
+                invalid(FilterError {
+                    position,
+                    message: format!("There is no list named \"{}\"", name),
+                })
+            })?;
+        /* It was valid when it was saved */
+        let saved = Filter::parse(&list.filter).map_err(invalid)?;
+        filter.expand_list(&name, &saved);
     }
+
+    Ok(filter)
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    request_body = TaskId,
+    responses(
+        (status = 200, description = "Task moved to the trash", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task has billed time", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
 #[delete("/tasks")]
-pub async fn delete_tasks(req: HttpRequest, task: Json<TaskId>, db: Data<Db>) -> impl Responder {
+pub async fn delete_tasks(
+    req: HttpRequest,
+    task: Json<TaskId>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
     let authorization = req.headers().get("Authorization");
 
-    match validate_token(authorization) {
-        Ok(user_id) => match delete_task(task.id, user_id, &db).await {
-            Ok(_) => HttpResponse::Ok().body("Task deleted"),
-            Err(_) => HttpResponse::InternalServerError().body("Error deleting task"),
-        },
-        Err(error) => error.message(),
+    let user_id = validate_token(authorization)?;
+    if delete_task(task.id, user_id, &db).await?.rows_affected() == 1 {
+        hub.publish(user_id, TaskEventKind::TaskDeleted, Some(task.id));
+    }
+
+    Ok(HttpResponse::Ok().body("Task deleted"))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = QuickAddRequest,
+    responses(
+        (status = 201, description = "The created task, with what was understood from the text", body = QuickAddResponse, headers(("Location" = String, description = "The URL of the task"))),
+        (status = 400, description = "Nothing in the text is left for the name", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no project with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/quick")]
+pub async fn post_task_quick(
+    req: HttpRequest,
+    body: Json<QuickAddRequest>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let settings = get_user_settings(user_id, &db).await?;
+    let clock = UserClock::from(&settings);
+
+    let parsed = quick_add::parse(&body.text, clock.now(), clock.date_format);
+    if parsed.name.is_empty() {
+        return Err(ApiError::bad_request(
+            "missing_name",
+            "The text has to have some words for the name of the task",
+        ));
+    }
+    let parsed = ParsedQuickAdd {
+        name: parsed.name,
+        due_at: parsed.due.map(|due| clock.timestamp(due)),
+        tags: parsed.tags,
+        priority: parsed.priority,
+        recurrence: parsed.recurrence,
+        estimate: parsed.estimate,
+    };
+
+    let task = NewTask {
+        uid: None,
+        name: parsed.name.clone(),
+        description: String::new(),
+        estimate: parsed.estimate,
+        tags: parsed.tags.clone(),
+        project_id: body.project_id,
+        billable: false,
+        hourly_rate: None,
+        due_at: parsed.due_at,
+        priority: parsed.priority,
+        recurrence: parsed.recurrence.clone(),
+    };
+    let errors = validate_task(&task);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
     }
+    let task_id = add_task(user_id, task, &db).await?;
+    hub.publish(user_id, TaskEventKind::TaskCreated, Some(task_id));
+    let task = get_task_by_user(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("{}/tasks/{}", V1, task_id)))
+        .json(QuickAddResponse { task, parsed }))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = NewTask,
+    responses(
+        (status = 201, description = "The created task", body = ResponseTask, headers(("Location" = String, description = "The URL of the task"))),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no project with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks")]
+pub async fn post_task(
+    req: HttpRequest,
+    task: Json<NewTask>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, task_id) = create_task(&req, task, &db, &hub).await?;
+    let task = get_task_by_user(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("{}/tasks/{}", V1, task_id)))
+        .json(task))
 }
 
+/// POST /tasks as it was before the API was versioned, answering with every task of the user.
+/// Only mounted at the root, for the clients that still use it.
 #[post("/tasks")]
-pub async fn post_task(req: HttpRequest, task: Json<NewTask>, db: Data<Db>) -> impl Responder {
+pub async fn post_task_legacy(
+    req: HttpRequest,
+    task: Json<NewTask>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, _) = create_task(&req, task, &db, &hub).await?;
+    let tasks = get_tasks_by_user(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(tasks))
+}
+
+/* It checks and adds a task, returning the user it belongs to and its id */
+async fn create_task(
+    req: &HttpRequest,
+    task: Json<NewTask>,
+    db: &Data<Db>,
+    hub: &Data<dyn EventHub>,
+) -> Result<(i32, i32), ApiError> {
     let authorization = req.headers().get("Authorization");
 
     let task = NewTask {
+        uid: task.uid.clone(),
         name: task.name.clone(),
         description: task.description.clone(),
+        estimate: task.estimate,
+        tags: task.tags.clone(),
+        project_id: task.project_id,
+        billable: task.billable,
+        hourly_rate: task.hourly_rate,
+        due_at: task.due_at,
+        priority: task.priority,
+        recurrence: task.recurrence.clone(),
     };
 
-    match validate_token(authorization) {
-        Ok(user_id) => match add_task(user_id, task, &db).await {
-            Ok(_) => match get_tasks_by_user(user_id, &db).await {
-                Ok(tasks) => HttpResponse::Ok().json(tasks),
-                Err(_) => HttpResponse::InternalServerError().body("Error getting tasks"),
-            },
-            Err(_) => HttpResponse::InternalServerError().body("Error posting tasks"),
-        },
-        Err(error) => error.message(),
+    let errors = validate_task(&task);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
     }
+
+    let user_id = validate_token(authorization)?;
+    let task_id = add_task(user_id, task, db).await?;
+    hub.publish(user_id, TaskEventKind::TaskCreated, Some(task_id));
+
+    Ok((user_id, task_id))
 }
 
-#[patch("/start_task/{task_id}")]
-pub async fn start_task(task_id: Path<i32>, req: HttpRequest, db: Data<Db>) -> impl Responder {
-    let task_id = task_id.into_inner();
+/* The problems of the items of a batch, each message saying which item it is about */
+fn indexed_errors(errors: impl Iterator<Item = Vec<FieldError>>, item: &str) -> Vec<FieldError> {
+    errors
+        .enumerate()
+        .flat_map(|(index, errors)| {
+            errors.into_iter().map(move |error| FieldError {
+                message: format!("{} {}: {}", item, index, error.message),
+                ..error
+            })
+        })
+        .collect()
+}
+
+/* Large enough for imports, small enough to keep the transaction short */
+const MAX_BATCH_TASKS: usize = 100;
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = Vec<NewTask>,
+    responses(
+        (status = 201, description = "The created tasks, in the order they were sent", body = Vec<ResponseTask>),
+        (status = 400, description = "No tasks, or more than 100", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no project with one of the provided ids; no task was created", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields in some of the tasks; no task was created", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/batch")]
+pub async fn post_tasks_batch(
+    req: HttpRequest,
+    tasks: Json<Vec<NewTask>>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    if tasks.is_empty() || tasks.len() > MAX_BATCH_TASKS {
+        return Err(ApiError::bad_request(
+            "invalid_batch_size",
+            format!("A batch must have between 1 and {} tasks", MAX_BATCH_TASKS),
+        ));
+    }
+    let errors = indexed_errors(tasks.iter().map(validate_task), "Task");
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let user_id = validate_token(authorization)?;
+    let task_ids = add_tasks(user_id, tasks.into_inner(), &db).await?;
+    for task_id in &task_ids {
+        hub.publish(user_id, TaskEventKind::TaskCreated, Some(*task_id));
+    }
+    let tasks = get_tasks_by_ids(user_id, &task_ids, &db).await?;
+
+    Ok(HttpResponse::Created().json(tasks))
+}
+
+/* The same limit as batches, for the same reason */
+const MAX_BULK_OPERATIONS: usize = 100;
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = BulkRequest,
+    responses(
+        (status = 200, description = "What happened to each operation, in the order they were sent", body = BulkResponse),
+        (status = 400, description = "No operations, or more than 100", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields in some of the updates; nothing was applied", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/bulk")]
+pub async fn post_tasks_bulk(
+    req: HttpRequest,
+    request: Json<BulkRequest>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
     let authorization = req.headers().get("Authorization");
 
-    match validate_token(authorization) {
-        Ok(user_id) => match start_task_and_save_time(task_id, user_id, &db).await {
-            Ok(has_started_task) => {
-                if has_started_task {
-                    HttpResponse::Accepted().body("Started")
-                } else {
-                    HttpResponse::Conflict().body("Couldn't be started")
+    let request = request.into_inner();
+    if request.operations.is_empty() || request.operations.len() > MAX_BULK_OPERATIONS {
+        return Err(ApiError::bad_request(
+            "invalid_batch_size",
+            format!(
+                "A bulk request must have between 1 and {} operations",
+                MAX_BULK_OPERATIONS
+            ),
+        ));
+    }
+    let errors = indexed_errors(
+        request.operations.iter().map(|operation| match operation {
+            BulkOperation::Update { changes, .. } => validate_task_update(changes),
+            _ => Vec::new(),
+        }),
+        "Operation",
+    );
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let user_id = validate_token(authorization)?;
+    let task_ids: Vec<i32> = request
+        .operations
+        .iter()
+        .map(BulkOperation::task_id)
+        .collect();
+    let (committed, outcomes) = apply_bulk_operations(
+        user_id,
+        request.operations,
+        request.mode == BulkMode::BestEffort,
+        &db,
+    )
+    .await?;
+
+    let results = outcomes
+        .into_iter()
+        .zip(task_ids)
+        .enumerate()
+        .map(|(index, (outcome, task_id))| {
+            let (status, error) = match outcome {
+                BulkOutcome::Applied(event) if committed => {
+                    if let Some(event) = event {
+                        hub.publish(user_id, event, Some(task_id));
+                    }
+                    (BulkItemStatus::Applied, None)
                 }
+                BulkOutcome::Applied(_) => (BulkItemStatus::RolledBack, None),
+                BulkOutcome::Failed(error) => (
+                    BulkItemStatus::Failed,
+                    Some(ApiError::from(error).into_bulk_item()),
+                ),
+                BulkOutcome::Skipped => (BulkItemStatus::Skipped, None),
+            };
+            BulkItemResult {
+                index,
+                task_id,
+                status,
+                error,
             }
-            Err(error) => error.message(),
-        },
-        Err(error) => error.message(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(BulkResponse { committed, results }))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 200, description = "The task", body = ResponseTask),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}")]
+pub async fn get_task(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let task = get_task_by_user(task_id.into_inner(), user_id, &db)
+        .await
+        .map_err(|error| match error {
+            sqlx::Error::RowNotFound => ApiError::from(TaskError::InvalidId),
+            error => ApiError::from(error),
+        })?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = TaskUpdate,
+    responses(
+        (status = 200, description = "Task updated", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task or project with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task has billed time, so its project, billable flag and hourly rate can't change", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/tasks/{task_id}")]
+pub async fn patch_task(
+    task_id: Path<i32>,
+    update: Json<TaskUpdate>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let errors = validate_task_update(&update);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let user_id = validate_token(authorization)?;
+    update_task(task_id, user_id, update.into_inner(), &db).await?;
+    hub.publish(user_id, TaskEventKind::TaskUpdated, Some(task_id));
+
+    Ok(HttpResponse::Ok().body("Task updated"))
+}
+
+const DEFAULT_SEARCH_RESULTS: usize = 20;
+
+const MAX_SEARCH_RESULTS: usize = 100;
+
+#[utoipa::path(
+    tag = "search",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "The matching tasks, best matches first", body = Vec<SearchResult>),
+        (status = 400, description = "No word of at least 3 characters, or an invalid date", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/search")]
+pub async fn search(
+    query: Query<SearchQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let terms = search_terms(&query.q);
+    let boolean = boolean_query(&terms);
+    if boolean.is_empty() {
+        return Err(ApiError::bad_request(
+            "invalid_query",
+            format!(
+                "The query needs a word of at least {} characters",
+                MIN_TERM_LEN
+            ),
+        ));
+    }
+    let date = |seconds: Option<i64>| {
+        seconds
+            .map(OffsetDateTime::from_unix_timestamp)
+            .transpose()
+            .map_err(|_| ApiError::bad_request("invalid_date", "The date is out of range"))
+    };
+    let updated_after = date(query.updated_after)?;
+    let updated_before = date(query.updated_before)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_RESULTS)
+        .min(MAX_SEARCH_RESULTS);
+    let archived = query.archived.unwrap_or(ArchiveFilter::Include);
+
+    let user_id = validate_token(authorization)?;
+    let matches = search_task_ids(
+        user_id,
+        &boolean,
+        query.project_id,
+        updated_after,
+        updated_before,
+        query.status,
+        archived,
+        limit as i64,
+        &db,
+    )
+    .await?;
+    let task_ids: Vec<i32> = matches.iter().map(|(task_id, _)| *task_id).collect();
+    let mut tasks = get_tasks_by_ids(user_id, &task_ids, &db).await?;
+    let comments = get_comment_bodies(user_id, &task_ids, &db).await?;
+
+    let results: Vec<SearchResult> = matches
+        .into_iter()
+        .filter_map(|(task_id, score)| {
+            let index = tasks.iter().position(|task| task.id == task_id)?;
+            Some((tasks.swap_remove(index), score))
+        })
+        .map(|(task, score)| {
+            let bodies: Vec<String> = comments
+                .iter()
+                .filter(|(task_id, _)| *task_id == task.id)
+                .map(|(_, body)| body.clone())
+                .collect();
+            SearchResult {
+                highlights: highlights(&task, &bodies, &terms),
+                task,
+                score,
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/* Large enough for a long time offline, small enough to keep the transaction short */
+const MAX_SYNC_CHANGES: usize = 1000;
+
+#[utoipa::path(
+    tag = "sync",
+    request_body = SyncRequest,
+    responses(
+        (status = 200, description = "What changed on the server after the cursor, and the records the client sent", body = SyncResponse),
+        (status = 400, description = "More than 1000 changes", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/sync")]
+pub async fn post_sync(
+    req: HttpRequest,
+    request: Json<SyncRequest>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let request = request.into_inner();
+
+    if request.tasks.len() + request.time_entries.len() > MAX_SYNC_CHANGES {
+        return Err(ApiError::bad_request(
+            "too_many_changes",
+            format!("A sync can't have more than {} changes", MAX_SYNC_CHANGES),
+        ));
+    }
+
+    let sent: Vec<(SyncEntity, String)> = request
+        .tasks
+        .iter()
+        .map(|change| (SyncEntity::Task, change.uid.clone()))
+        .chain(
+            request
+                .time_entries
+                .iter()
+                .map(|change| (SyncEntity::TimeEntry, change.uid.clone())),
+        )
+        .collect();
+
+    let rejected = apply_sync_changes(user_id, request.tasks, request.time_entries, &db).await?;
+    if rejected.len() < sent.len() {
+        // The other devices of the user fetch everything again, rather than get an event per change.
+        hub.publish(user_id, TaskEventKind::Resync, None);
+    }
+
+    // Taken before reading the records, so what changes meanwhile comes again in the next sync.
+    let cursor = current_sync_token(user_id, &db).await?;
+    let changes = match request.cursor {
+        Some(cursor) => Some(get_changes_since(user_id, cursor, &db).await?),
+        None => None,
+    };
+    let tasks = get_tasks_by_user(user_id, &db).await?;
+    let entries = get_time_entries_by_user(user_id, &db).await?;
+    let clocks = get_sync_clocks_by_user(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(sync_response(
+        cursor, changes, &sent, tasks, entries, clocks, rejected,
+    )))
+}
+
+#[utoipa::path(
+    tag = "reports",
+    params(EstimateReportQuery),
+    responses(
+        (status = 200, description = "Estimation accuracy overall, per period and per tag", body = EstimateReport),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/reports/estimates")]
+pub async fn estimates_report(
+    query: Query<EstimateReportQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let tasks = get_tasks_by_user(user_id, &db).await?;
+    let settings = get_user_settings(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(estimate_report(
+        &tasks,
+        query.period.unwrap_or(ReportPeriod::Week),
+        &UserClock::from(&settings),
+    )))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 202, description = "Started", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task is running already", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/start_task/{task_id}")]
+pub async fn start_task(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if start_task_and_save_time(task_id, user_id, &db).await? {
+        hub.publish(user_id, TaskEventKind::TimerStarted, Some(task_id));
+        Ok(HttpResponse::Accepted().body("Started"))
+    } else {
+        Err(ApiError::conflict(
+            "task_not_started",
+            "Couldn't be started",
+        ))
     }
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 202, description = "Task finished", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task isn't running", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
 #[patch("/finish_task/{task_id}")]
-pub async fn finish_task(task_id: Path<i32>, req: HttpRequest, db: Data<Db>) -> impl Responder {
+pub async fn finish_task(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
     let task_id = task_id.into_inner();
     let authorization = req.headers().get("Authorization");
 
-    match validate_token(authorization) {
-        Ok(user_id) => match finish_task_and_save_time(task_id, user_id, &db).await {
-            Ok(updated) => {
-                if updated {
-                    HttpResponse::Accepted().body("Task finished")
-                } else {
-                    HttpResponse::Conflict().body("Task already finished")
-                }
-            }
-            Err(err) => err.message(),
-        },
-        Err(error) => error.message(),
+    let user_id = validate_token(authorization)?;
+
+    if finish_task_and_save_time(task_id, user_id, &db).await? {
+        hub.publish(user_id, TaskEventKind::TimerStopped, Some(task_id));
+        Ok(HttpResponse::Accepted().body("Task finished"))
+    } else {
+        Err(ApiError::conflict(
+            "task_already_finished",
+            "Task already finished",
+        ))
+    }
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 204, description = "Task moved to the trash"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task has billed time", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/tasks/{task_id}")]
+pub async fn delete_task_by_id(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if delete_task(task_id, user_id, &db).await?.rows_affected() == 1 {
+        hub.publish(user_id, TaskEventKind::TaskDeleted, Some(task_id));
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(TaskError::InvalidId.into())
+    }
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = TaskMove,
+    responses(
+        (status = 200, description = "The moved task, with its new position", body = ResponseTask),
+        (status = 400, description = "Neither or both of before and after, or the task itself", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with one of the provided ids", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The tasks are in different projects", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/{task_id}/move")]
+pub async fn move_task_by_id(
+    task_id: Path<i32>,
+    target: Json<TaskMove>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let (reference_id, after) = match (target.before, target.after) {
+        (Some(before), None) => (before, false),
+        (None, Some(after)) => (after, true),
+        _ => {
+            return Err(ApiError::bad_request(
+                "invalid_move",
+                "Either before or after has to be set",
+            ))
+        }
+    };
+    if reference_id == task_id {
+        return Err(ApiError::bad_request(
+            "invalid_move",
+            "A task can't be moved next to itself",
+        ));
+    }
+
+    let user_id = validate_token(authorization)?;
+    move_task(task_id, user_id, reference_id, after, &db).await?;
+    hub.publish(user_id, TaskEventKind::TaskUpdated, Some(task_id));
+    let task = get_task_by_user(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 200, description = "The archived task", body = ResponseTask),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task is running", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/{task_id}/archive")]
+pub async fn archive_task_by_id(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if archive_task(task_id, user_id, &db).await? {
+        hub.publish(user_id, TaskEventKind::TaskArchived, Some(task_id));
+    }
+    let task = get_task_by_user(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 200, description = "The task, back in the lists", body = ResponseTask),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/{task_id}/unarchive")]
+pub async fn unarchive_task_by_id(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if unarchive_task(task_id, user_id, &db).await? {
+        hub.publish(user_id, TaskEventKind::TaskUnarchived, Some(task_id));
+    }
+    let task = get_task_by_user(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    request_body = ArchiveFinished,
+    responses(
+        (status = 200, description = "The tasks that were archived", body = ArchivedTasks),
+        (status = 400, description = "Invalid date", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/archive")]
+pub async fn archive_finished(
+    req: HttpRequest,
+    body: Json<ArchiveFinished>,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let finished_before = OffsetDateTime::from_unix_timestamp(body.finished_before)
+        .map_err(|_| ApiError::bad_request("invalid_date", "The date is out of range"))?;
+
+    let user_id = validate_token(authorization)?;
+    let task_ids = archive_finished_tasks(user_id, finished_before, &db).await?;
+    for task_id in &task_ids {
+        hub.publish(user_id, TaskEventKind::TaskArchived, Some(*task_id));
+    }
+
+    Ok(HttpResponse::Ok().json(ArchivedTasks { task_ids }))
+}
+
+#[utoipa::path(
+    tag = "trash",
+    responses(
+        (status = 200, description = "The deleted tasks that haven't been purged yet, most recently deleted first", body = Vec<TrashedTask>),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/trash")]
+pub async fn get_trash(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let retention = Duration::days(retention_days());
+    let tasks: Vec<TrashedTask> = get_trashed_tasks(user_id, &db)
+        .await?
+        .into_iter()
+        .map(|(task, deleted_at)| TrashedTask {
+            task,
+            deleted_at: deleted_at.unix_timestamp(),
+            purge_at: (deleted_at + retention).unix_timestamp(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(tasks))
+}
+
+fn trashed_task_not_found() -> ApiError {
+    ApiError::not_found(
+        "task_not_in_trash",
+        "There is no task with the provided id in the trash",
+    )
+}
+
+#[utoipa::path(
+    tag = "trash",
+    responses(
+        (status = 200, description = "The restored task", body = ResponseTask),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id in the trash", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/trash/{task_id}/restore")]
+pub async fn restore_trashed_task(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if !restore_task(task_id, user_id, &db).await? {
+        return Err(trashed_task_not_found());
+    }
+    hub.publish(user_id, TaskEventKind::TaskRestored, Some(task_id));
+    let task = get_task_by_user(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(task))
+}
+
+#[utoipa::path(
+    tag = "trash",
+    responses(
+        (status = 204, description = "Task deleted for good, with its tracked time"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id in the trash", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/trash/{task_id}")]
+pub async fn purge_trashed_task(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if purge_task(task_id.into_inner(), user_id, &db).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(trashed_task_not_found())
+    }
+}
+
+#[utoipa::path(
+    tag = "trash",
+    responses(
+        (status = 204, description = "Every task in the trash deleted for good"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/trash")]
+pub async fn delete_trash(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    empty_trash(user_id, &db).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 200, description = "Every tracked session of the task", body = Vec<TaskSession>),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/sessions")]
+pub async fn get_sessions(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let sessions: Vec<TaskSession> = get_task_sessions(task_id, user_id, &db)
+        .await?
+        .into_iter()
+        .map(TaskSession::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 200, description = "The session", body = TaskSession),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no session with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/sessions/{session_id}")]
+pub async fn get_session(
+    path: Path<(i32, i32)>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let (task_id, session_id) = path.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    match get_task_session(session_id, task_id, user_id, &db).await? {
+        Some(session) => Ok(HttpResponse::Ok().json(TaskSession::from(session))),
+        None => Err(ApiError::not_found(
+            "session_not_found",
+            "There is no session with the provided id",
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 201, description = "The timer is running, the new session is at Location", body = TaskSession),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The task is running already", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/{task_id}/sessions")]
+pub async fn post_session(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if !start_task_and_save_time(task_id, user_id, &db).await? {
+        return Err(ApiError::conflict(
+            "task_not_started",
+            "Couldn't be started",
+        ));
+    }
+    hub.publish(user_id, TaskEventKind::TimerStarted, Some(task_id));
+
+    match get_running_task_session(task_id, user_id, &db).await? {
+        Some(session) => Ok(HttpResponse::Created()
+            .insert_header((
+                header::LOCATION,
+                format!("{}/tasks/{}/sessions/{}", V1, task_id, session.id),
+            ))
+            .json(TaskSession::from(session))),
+        None => Err(ApiError::internal()),
+    }
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    responses(
+        (status = 200, description = "The timer is stopped, this is the finished session", body = TaskSession),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id, or its timer isn't running", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/tasks/{task_id}/sessions/current")]
+pub async fn patch_current_session(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let not_running = || ApiError::not_found("session_not_found", "The task isn't running");
+
+    let session = match get_running_task_session(task_id, user_id, &db).await? {
+        Some(session) => session,
+        None => {
+            // Fails with task_not_found when the task doesn't exist at all.
+            get_task_sessions(task_id, user_id, &db).await?;
+            return Err(not_running());
+        }
+    };
+
+    if !finish_task_and_save_time(task_id, user_id, &db).await? {
+        return Err(not_running());
+    }
+    hub.publish(user_id, TaskEventKind::TimerStopped, Some(task_id));
+
+    match get_task_session(session.id, task_id, user_id, &db).await? {
+        Some(session) => Ok(HttpResponse::Ok().json(TaskSession::from(session))),
+        None => Err(not_running()),
+    }
+}
+
+#[utoipa::path(
+    tag = "focus",
+    request_body = NewFocusSession,
+    responses(
+        (status = 201, description = "Focus session started", body = FocusSession),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "There is a focus session running already", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Work or break length out of range", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/focus/{task_id}")]
+pub async fn start_focus(
+    task_id: Path<i32>,
+    settings: Json<NewFocusSession>,
+    req: HttpRequest,
+    db: Data<Db>,
+    events: Data<FocusEvents>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let work_minutes = settings.work_minutes.unwrap_or(25);
+    let break_minutes = settings.break_minutes.unwrap_or(5);
+
+    let errors = validate_focus_settings(work_minutes, break_minutes);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
     }
+
+    let user_id = validate_token(authorization)?;
+    let session = start_focus_session(
+        task_id,
+        user_id,
+        i64::from(work_minutes) * 60,
+        i64::from(break_minutes) * 60,
+        &db,
+    )
+    .await?;
+
+    hub.publish(user_id, TaskEventKind::TimerStarted, Some(task_id));
+    events.publish(FocusTransition {
+        user_id,
+        focus_session_id: session.id,
+        task_id,
+        phase: session.phase.clone(),
+        completed_pomodoros: session.completed_pomodoros,
+        at: session.phase_started_at.unix_timestamp(),
+    });
+
+    Ok(HttpResponse::Created().json(FocusSession::from(session)))
+}
+
+#[utoipa::path(
+    tag = "focus",
+    responses(
+        (status = 200, description = "The running focus session", body = FocusSession),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no focus session running", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/focus")]
+pub async fn get_focus(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    match get_active_focus_session(user_id, &db).await? {
+        Some(session) => Ok(HttpResponse::Ok().json(FocusSession::from(session))),
+        None => Err(ApiError::not_found(
+            "focus_session_not_found",
+            "There is no focus session running",
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "focus",
+    responses(
+        (status = 200, description = "Focus session stopped", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no focus session running", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/focus")]
+pub async fn stop_focus(
+    req: HttpRequest,
+    db: Data<Db>,
+    events: Data<FocusEvents>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    match stop_focus_session(user_id, &db).await? {
+        Some(session) => {
+            if session.phase == "work" {
+                hub.publish(user_id, TaskEventKind::TimerStopped, Some(session.task_id));
+            }
+            events.publish(FocusTransition {
+                user_id,
+                focus_session_id: session.id,
+                task_id: session.task_id,
+                phase: "stopped".to_string(),
+                completed_pomodoros: session.completed_pomodoros,
+                at: OffsetDateTime::now_utc().unix_timestamp(),
+            });
+            Ok(HttpResponse::Ok().body("Focus session stopped"))
+        }
+        None => Err(ApiError::not_found(
+            "focus_session_not_found",
+            "There is no focus session running",
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "focus",
+    responses(
+        (status = 200, description = "Server-sent events, one per focus transition", body = FocusTransition, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/focus/events")]
+pub async fn focus_events(
+    req: HttpRequest,
+    events: Data<FocusEvents>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events.stream(user_id)))
+}
+
+/* Browsers can't set headers on EventSource and WebSocket connections, so the token can come in
+the query too */
+fn query_token_user(req: &HttpRequest, token: Option<&str>) -> Result<i32, ApiError> {
+    let token = token.and_then(|token| HeaderValue::from_str(token).ok());
+    let authorization = req.headers().get("Authorization").or(token.as_ref());
+
+    Ok(validate_token(authorization)?)
+}
+
+/* Nor on the requests of img and a elements, which get a download token for one attachment in the
+query instead. A link that leaks is only good for that file, and not for long */
+fn download_token_user(
+    req: &HttpRequest,
+    token: Option<&str>,
+    attachment_id: i32,
+) -> Result<i32, ApiError> {
+    let authorization = req.headers().get("Authorization");
+    match (authorization, token) {
+        (None, Some(token)) => match verify_download_token(token) {
+            Ok(token) if token.claims.attachment_id == attachment_id => Ok(token.claims.id_user),
+            _ => Err(VerificationError::InvalidToken.into()),
+        },
+        _ => Ok(validate_token(authorization)?),
+    }
+}
+
+#[utoipa::path(
+    tag = "events",
+    params(EventStreamQuery),
+    responses(
+        (status = 200, description = "Server-sent events, one per change to the tasks of the user", body = TaskEvent, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/events")]
+pub async fn task_events(
+    query: Query<EventStreamQuery>,
+    req: HttpRequest,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = query_token_user(&req, query.token.as_deref())?;
+
+    // EventSource sends the id of the last event it got when it reconnects.
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or(query.last_event_id);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(sse_stream(hub.subscribe(user_id, last_event_id))))
+}
+
+#[utoipa::path(
+    tag = "events",
+    params(EventStreamQuery),
+    responses(
+        (status = 101, description = "A WebSocket that gets a JSON text message per change to the tasks of the user", body = TaskEvent),
+        (status = 400, description = "Not a WebSocket handshake", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/events/ws")]
+pub async fn task_events_ws(
+    query: Query<EventStreamQuery>,
+    req: HttpRequest,
+    body: Payload,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = query_token_user(&req, query.token.as_deref())?;
+
+    let (response, session, messages) = actix_ws::handle(&req, body)
+        .map_err(|error| ApiError::bad_request("invalid_handshake", error.to_string()))?;
+    serve_websocket(
+        session,
+        messages,
+        hub.subscribe(user_id, query.last_event_id),
+    );
+
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "focus",
+    responses(
+        (status = 200, description = "Completed pomodoros per task and per day", body = PomodoroStats),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/focus/stats")]
+pub async fn focus_stats(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let settings = get_user_settings(user_id, &db).await?;
+    let stats = get_pomodoro_stats(user_id, &UserClock::from(&settings), &db).await?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[utoipa::path(
+    tag = "billing",
+    request_body = NewProject,
+    responses(
+        (status = 201, description = "The created project", body = Project, headers(("Location" = String, description = "The URL of the project"))),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/projects")]
+pub async fn post_project(
+    req: HttpRequest,
+    project: Json<NewProject>,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let project_id = add_project(user_id, project.into_inner(), &db)
+        .await?
+        .last_insert_id() as i32;
+    let project = get_project_by_user(project_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("{}/projects/{}", V1, project_id)))
+        .json(project))
+}
+
+/// POST /projects as it was before the API was versioned, answering with every project of the
+/// user. Only mounted at the root, for the clients that still use it.
+#[post("/projects")]
+pub async fn post_project_legacy(
+    req: HttpRequest,
+    project: Json<NewProject>,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    add_project(user_id, project.into_inner(), &db).await?;
+    let projects = get_projects_by_user(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(projects))
+}
+
+#[utoipa::path(
+    tag = "billing",
+    responses(
+        (status = 200, description = "The project", body = Project),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no project with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/projects/{project_id}")]
+pub async fn get_project(
+    project_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let project = get_project_by_user(project_id.into_inner(), user_id, &db)
+        .await
+        .map_err(|error| match error {
+            sqlx::Error::RowNotFound => ApiError::from(TaskError::InvalidProjectId),
+            error => ApiError::from(error),
+        })?;
+
+    Ok(HttpResponse::Ok().json(project))
+}
+
+#[utoipa::path(
+    tag = "billing",
+    responses(
+        (status = 200, description = "Every project of the user", body = Vec<Project>),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/projects")]
+pub async fn get_projects(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let projects = get_projects_by_user(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(projects))
+}
+
+#[utoipa::path(
+    tag = "billing",
+    request_body = TagUpdate,
+    responses(
+        (status = 200, description = "Tag updated", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no tag with the provided name", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/tags/{name}")]
+pub async fn patch_tag(
+    name: Path<String>,
+    update: Json<TagUpdate>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if update_tag(&name, user_id, update.into_inner(), &db).await? {
+        Ok(HttpResponse::Ok().body("Tag updated"))
+    } else {
+        Err(ApiError::not_found(
+            "tag_not_found",
+            "There is no tag with the provided name",
+        ))
+    }
+}
+
+fn invoice_response(
+    invoice: &Invoice,
+    format: Option<InvoiceFormat>,
+    clock: &UserClock,
+) -> HttpResponse {
+    match format.unwrap_or(InvoiceFormat::Json) {
+        InvoiceFormat::Json => HttpResponse::Ok().json(invoice),
+        InvoiceFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"invoice.csv\"",
+            ))
+            .body(render_csv(invoice)),
+        InvoiceFormat::Html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(render_html(invoice, clock)),
+    }
+}
+
+/// Either the explicit `from` and `to` of the query or its named period, in the user's time zone.
+fn invoice_period(
+    query: &InvoiceQuery,
+    clock: &UserClock,
+) -> Result<(OffsetDateTime, OffsetDateTime), ApiError> {
+    let invalid = || ApiError::bad_request("invalid_period", "Invalid invoice period");
+
+    let (from, to) = match (query.from, query.to, query.period) {
+        (Some(from), Some(to), None) => (from, to),
+        (None, None, Some(period)) => clock.period_range(period),
+        _ => return Err(invalid()),
+    };
+    let from = OffsetDateTime::from_unix_timestamp(from).map_err(|_| invalid())?;
+    let to = OffsetDateTime::from_unix_timestamp(to).map_err(|_| invalid())?;
+
+    if from < to {
+        Ok((from, to))
+    } else {
+        Err(invalid())
+    }
+}
+
+#[utoipa::path(
+    tag = "billing",
+    params(InvoiceQuery),
+    responses(
+        (status = 200, description = "Invoice preview, as JSON, CSV or HTML", body = Invoice),
+        (status = 400, description = "Invalid invoice period, or amounts too large to bill at once", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/reports/invoice")]
+pub async fn invoice_preview(
+    query: Query<InvoiceQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let clock = UserClock::from(&get_user_settings(user_id, &db).await?);
+    let (from, to) = invoice_period(&query, &clock)?;
+
+    let invoice = preview_invoice(user_id, &query.client, from, to, &db).await?;
+
+    Ok(invoice_response(&invoice, query.format, &clock))
+}
+
+#[utoipa::path(
+    tag = "billing",
+    params(InvoiceQuery),
+    responses(
+        (status = 200, description = "Issued invoice, as JSON, CSV or HTML", body = Invoice),
+        (status = 400, description = "Invalid invoice period, or amounts too large to bill at once", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no unbilled time in that period", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/reports/invoice")]
+pub async fn post_invoice(
+    query: Query<InvoiceQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let clock = UserClock::from(&get_user_settings(user_id, &db).await?);
+    let (from, to) = invoice_period(&query, &clock)?;
+
+    match issue_invoice(user_id, &query.client, from, to, &db).await? {
+        Some(invoice) => Ok(invoice_response(&invoice, query.format, &clock)),
+        None => Err(ApiError::not_found(
+            "no_unbilled_time",
+            "There is no unbilled time in that period",
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "billing",
+    params(InvoiceFormatQuery),
+    responses(
+        (status = 200, description = "Invoice, as JSON, CSV or HTML", body = Invoice),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no invoice with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/invoices/{invoice_id}")]
+pub async fn get_invoice_by_id(
+    invoice_id: Path<i32>,
+    query: Query<InvoiceFormatQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    match get_invoice(invoice_id.into_inner(), user_id, &db).await? {
+        Some(invoice) => {
+            let settings = get_user_settings(user_id, &db).await?;
+            Ok(invoice_response(
+                &invoice,
+                query.format,
+                &UserClock::from(&settings),
+            ))
+        }
+        None => Err(ApiError::not_found(
+            "invoice_not_found",
+            "There is no invoice with the provided id",
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "settings",
+    responses(
+        (status = 200, description = "Settings of the user", body = UserSettings),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/settings")]
+pub async fn get_settings(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let settings = get_user_settings(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+#[utoipa::path(
+    tag = "settings",
+    request_body = SettingsUpdate,
+    responses(
+        (status = 200, description = "Updated settings", body = UserSettings),
+        (status = 400, description = "Unknown time zone or invalid locale", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/settings")]
+pub async fn patch_settings(
+    update: Json<SettingsUpdate>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    if let Some(time_zone) = &update.time_zone {
+        if !is_valid_time_zone(time_zone) {
+            return Err(ApiError::bad_request(
+                "invalid_time_zone",
+                "Unknown time zone",
+            ));
+        }
+    }
+    if let Some(locale) = &update.locale {
+        if !is_valid_locale(locale) {
+            return Err(ApiError::bad_request("invalid_locale", "Invalid locale"));
+        }
+    }
+
+    let user_id = validate_token(authorization)?;
+
+    let mut settings = get_user_settings(user_id, &db).await?;
+    let update = update.into_inner();
+    if let Some(time_zone) = update.time_zone {
+        settings.time_zone = time_zone;
+    }
+    if let Some(locale) = update.locale {
+        settings.locale = locale;
+    }
+    if let Some(week_start) = update.week_start {
+        settings.week_start = week_start;
+    }
+    if let Some(date_format) = update.date_format {
+        settings.date_format = date_format;
+    }
+    if let Some(auto_archive_days) = update.auto_archive_days {
+        settings.auto_archive_days = Some(auto_archive_days).filter(|days| *days > 0);
+    }
+
+    save_user_settings(user_id, &settings, &db).await?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+fn calendar_response(tasks: &[ResponseTask]) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(render_calendar(
+            tasks,
+            OffsetDateTime::now_utc().unix_timestamp(),
+        ))
+}
+
+#[utoipa::path(
+    tag = "calendar",
+    responses(
+        (status = 200, description = "Tasks and tracked time as iCalendar", body = String, content_type = "text/calendar"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/calendar.ics")]
+pub async fn get_calendar(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let tasks = get_tasks_by_user(user_id, &db).await?;
+
+    Ok(calendar_response(&tasks))
+}
+
+#[utoipa::path(
+    tag = "calendar",
+    responses(
+        (status = 201, description = "A new feed URL, the previous one stops working", body = CalendarFeed),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/calendar/feed")]
+pub async fn post_calendar_feed(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let token = rotate_calendar_feed(user_id, &db).await?;
+
+    let connection = req.connection_info();
+    Ok(HttpResponse::Created().json(CalendarFeed {
+        url: format!(
+            "{}://{}{}/calendar/feed/{}.ics",
+            connection.scheme(),
+            connection.host(),
+            V1,
+            token
+        ),
+    }))
+}
+
+#[utoipa::path(
+    tag = "calendar",
+    responses(
+        (status = 200, description = "Calendar feed revoked", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no calendar feed", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/calendar/feed")]
+pub async fn delete_calendar_feed(
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if revoke_calendar_feed(user_id, &db).await? {
+        Ok(HttpResponse::Ok().body("Calendar feed revoked"))
+    } else {
+        Err(ApiError::not_found(
+            "calendar_feed_not_found",
+            "There is no calendar feed",
+        ))
+    }
+}
+
+/* Calendar apps can't send a JWT, the secret token in the URL is the authentication */
+#[utoipa::path(
+    tag = "calendar",
+    responses(
+        (status = 200, description = "Tasks and tracked time as iCalendar", body = String, content_type = "text/calendar"),
+        (status = 404, description = "There is no calendar feed with that token", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[get("/calendar/feed/{token}.ics")]
+pub async fn get_calendar_feed(
+    token: Path<String>,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    match get_calendar_feed_user(&token, &db).await? {
+        Some(user_id) => {
+            let tasks = get_tasks_by_user(user_id, &db).await?;
+            Ok(calendar_response(&tasks))
+        }
+        None => Err(ApiError::not_found(
+            "calendar_feed_not_found",
+            "There is no calendar feed with that token",
+        )),
+    }
+}
+
+fn smart_list_not_found() -> ApiError {
+    ApiError::not_found(
+        "list_not_found",
+        "There is no saved list with the provided id",
+    )
+}
+
+fn duplicate_list_name(error: sqlx::Error) -> ApiError {
+    match duplicate_key(&error).as_deref() {
+        Some("smart_lists_name") => {
+            ApiError::duplicate_field("name", "There is already a list with that name")
+        }
+        _ => ApiError::from(error),
+    }
+}
+
+#[utoipa::path(
+    tag = "lists",
+    request_body = NewSmartList,
+    responses(
+        (status = 201, description = "The filter was saved; its tasks are at GET /tasks?filter=list:<name>", body = SmartList, headers(("Location" = String, description = "The URL of the list"))),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "There is already a list with that name", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields, such as a filter that can't be parsed", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/lists")]
+pub async fn post_smart_list(
+    list: Json<NewSmartList>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    let errors = validate_smart_list(Some(&list.name), Some(&list.filter));
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let list = NewSmartList {
+        name: list.name.trim().to_string(),
+        filter: list.filter.trim().to_string(),
+    };
+    let list = add_smart_list(user_id, list, &db)
+        .await
+        .map_err(duplicate_list_name)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("{}/lists/{}", V1, list.id)))
+        .json(list))
+}
+
+#[utoipa::path(
+    tag = "lists",
+    responses(
+        (status = 200, description = "The saved lists of the user, by name", body = [SmartList]),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/lists")]
+pub async fn get_smart_lists(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let lists = get_smart_lists_by_user(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+#[utoipa::path(
+    tag = "lists",
+    request_body = SmartListUpdate,
+    responses(
+        (status = 200, description = "Updated list", body = SmartList),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no list with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "There is already a list with that name", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields, such as a filter that can't be parsed", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/lists/{list_id}")]
+pub async fn patch_smart_list(
+    list_id: Path<i32>,
+    update: Json<SmartListUpdate>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    let errors = validate_smart_list(update.name.as_deref(), update.filter.as_deref());
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let update = SmartListUpdate {
+        name: update.name.as_deref().map(|name| name.trim().to_string()),
+        filter: update
+            .filter
+            .as_deref()
+            .map(|filter| filter.trim().to_string()),
+    };
+    match update_smart_list(list_id.into_inner(), user_id, update, &db)
+        .await
+        .map_err(duplicate_list_name)?
+    {
+        Some(list) => Ok(HttpResponse::Ok().json(list)),
+        None => Err(smart_list_not_found()),
+    }
+}
+
+#[utoipa::path(
+    tag = "lists",
+    responses(
+        (status = 204, description = "List deleted; its tasks are left as they are"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no list with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/lists/{list_id}")]
+pub async fn delete_smart_list_by_id(
+    list_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if delete_smart_list(list_id.into_inner(), user_id, &db).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(smart_list_not_found())
+    }
+}
+
+fn comment_not_found() -> ApiError {
+    ApiError::not_found(
+        "comment_not_found",
+        "There is no comment with the provided id on the task",
+    )
+}
+
+#[utoipa::path(
+    tag = "comments",
+    responses(
+        (status = 200, description = "The comments of the task, oldest first", body = [Comment]),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/comments")]
+pub async fn get_task_comments(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if is_an_invalid_task_id(task_id, user_id, &db).await? {
+        return Err(TaskError::InvalidId.into());
+    }
+    let comments = get_comments(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    tag = "comments",
+    request_body = NewComment,
+    responses(
+        (status = 201, description = "Comment added", body = Comment, headers(("Location" = String, description = "The URL of the comment"))),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id, or no such comment to answer on it", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "The body is blank or too long", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/{task_id}/comments")]
+pub async fn post_task_comment(
+    task_id: Path<i32>,
+    comment: Json<NewComment>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    let errors = validate_comment(&comment.body);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+    if is_an_invalid_task_id(task_id, user_id, &db).await? {
+        return Err(TaskError::InvalidId.into());
+    }
+    // Replies stay on the task of the comment they answer.
+    if let Some(parent_id) = comment.parent_id {
+        if get_comment(parent_id, task_id, user_id, &db)
+            .await?
+            .is_none()
+        {
+            return Err(comment_not_found());
+        }
+    }
+
+    let comment_id = add_comment(task_id, user_id, &comment, &db).await?;
+    hub.publish(user_id, TaskEventKind::CommentAdded, Some(task_id));
+    let comment = get_comment(comment_id, task_id, user_id, &db)
+        .await?
+        .ok_or_else(comment_not_found)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((
+            header::LOCATION,
+            format!("{}/tasks/{}/comments/{}", V1, task_id, comment_id),
+        ))
+        .json(comment))
+}
+
+#[utoipa::path(
+    tag = "comments",
+    request_body = CommentUpdate,
+    responses(
+        (status = 200, description = "The edited comment", body = Comment),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no comment with the provided id on the task, or it was deleted", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "The body is blank or too long", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/tasks/{task_id}/comments/{comment_id}")]
+pub async fn patch_task_comment(
+    path: Path<(i32, i32)>,
+    update: Json<CommentUpdate>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let (task_id, comment_id) = path.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    let errors = validate_comment(&update.body);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+    if !update_comment(comment_id, task_id, user_id, &update.body, &db).await? {
+        return Err(comment_not_found());
+    }
+    hub.publish(user_id, TaskEventKind::CommentEdited, Some(task_id));
+    let comment = get_comment(comment_id, task_id, user_id, &db)
+        .await?
+        .ok_or_else(comment_not_found)?;
+
+    Ok(HttpResponse::Ok().json(comment))
+}
+
+#[utoipa::path(
+    tag = "comments",
+    responses(
+        (status = 204, description = "Comment deleted; one with replies stays in the thread without its body"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no comment with the provided id on the task", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/tasks/{task_id}/comments/{comment_id}")]
+pub async fn delete_task_comment(
+    path: Path<(i32, i32)>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let (task_id, comment_id) = path.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if delete_comment(comment_id, task_id, user_id, &db).await? {
+        hub.publish(user_id, TaskEventKind::CommentDeleted, Some(task_id));
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(comment_not_found())
+    }
+}
+
+const DEFAULT_ACTIVITY_ENTRIES: u32 = 50;
+
+const MAX_ACTIVITY_ENTRIES: u32 = 200;
+
+/* The page of the feed that the query asks for */
+async fn activity_page(
+    user_id: i32,
+    task_id: Option<i32>,
+    query: &ActivityQuery,
+    db: &Data<Db>,
+) -> Result<Vec<Activity>, ApiError> {
+    let before = query
+        .before
+        .map(OffsetDateTime::from_unix_timestamp)
+        .transpose()
+        .map_err(|_| ApiError::bad_request("invalid_date", "The date is out of range"))?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_ACTIVITY_ENTRIES)
+        .clamp(1, MAX_ACTIVITY_ENTRIES);
+
+    Ok(get_activity(user_id, task_id, before, limit as i64, db).await?)
+}
+
+#[utoipa::path(
+    tag = "activity",
+    params(ActivityQuery),
+    responses(
+        (status = 200, description = "What happened to the task, newest first", body = [Activity]),
+        (status = 400, description = "The date is out of range", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/activity")]
+pub async fn get_task_activity(
+    task_id: Path<i32>,
+    query: Query<ActivityQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if is_an_invalid_task_id(task_id, user_id, &db).await? {
+        return Err(TaskError::InvalidId.into());
+    }
+    let activity = activity_page(user_id, Some(task_id), &query, &db).await?;
+
+    Ok(HttpResponse::Ok().json(activity))
+}
+
+#[utoipa::path(
+    tag = "activity",
+    params(ActivityQuery),
+    responses(
+        (status = 200, description = "What happened to all the tasks of the user, newest first", body = [Activity]),
+        (status = 400, description = "The date is out of range", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/activity")]
+pub async fn get_user_activity(
+    query: Query<ActivityQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let activity = activity_page(user_id, None, &query, &db).await?;
+
+    Ok(HttpResponse::Ok().json(activity))
+}
+
+fn attachment_not_found() -> ApiError {
+    ApiError::not_found(
+        "attachment_not_found",
+        "There is no attachment with the provided id on the task",
+    )
+}
+
+fn invalid_upload(error: MultipartError) -> ApiError {
+    ApiError::bad_request("invalid_upload", error.to_string())
+}
+
+/* The file is gone from storage or it can't be reached; either way it is on the server */
+fn storage_error(error: io::Error) -> ApiError {
+    println!("{:#?}", error);
+    ApiError::internal()
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    responses(
+        (status = 200, description = "The space the attachments of the user take and the limits", body = AttachmentUsage),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/attachments/usage")]
+pub async fn get_attachment_usage_by_user(
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let usage = AttachmentUsage {
+        used: get_attachment_usage(user_id, &db.pool).await?,
+        quota: quota(),
+        max_file_size: max_file_size(),
+    };
+
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    responses(
+        (status = 200, description = "The attachments of the task, oldest first", body = [Attachment]),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/attachments")]
+pub async fn get_task_attachments(
+    task_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if is_an_invalid_task_id(task_id, user_id, &db).await? {
+        return Err(TaskError::InvalidId.into());
+    }
+    let attachments = get_attachments(task_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(attachments))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    request_body(description = "A form with the file in a part named \"file\"", content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "File attached", body = Attachment, headers(("Location" = String, description = "The URL of the attachment"))),
+        (status = 400, description = "The form can't be read or has no file", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no task with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 413, description = "The file is too large, or doesn't fit in the quota of the user", body = Problem, content_type = "application/problem+json"),
+        (status = 415, description = "Files of that type can't be attached", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/tasks/{task_id}/attachments")]
+pub async fn post_task_attachment(
+    task_id: Path<i32>,
+    mut form: Multipart,
+    req: HttpRequest,
+    db: Data<Db>,
+    storage: Data<dyn Storage>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let task_id = task_id.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if is_an_invalid_task_id(task_id, user_id, &db).await? {
+        return Err(TaskError::InvalidId.into());
+    }
+
+    // Other parts of the form are skipped.
+    let max_file_size = max_file_size();
+    let mut upload = None;
+    while let Some(mut field) = form.try_next().await.map_err(invalid_upload)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let filename = clean_filename(
+            field
+                .content_disposition()
+                .and_then(|disposition| disposition.get_filename()),
+        );
+        let declared = field
+            .content_type()
+            .map(|mime| mime.essence_str().to_string());
+
+        let mut content = BytesMut::new();
+        while let Some(chunk) = field.try_next().await.map_err(invalid_upload)? {
+            if (content.len() + chunk.len()) as i64 > max_file_size {
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "file_too_large",
+                    format!("Files can't be larger than {} bytes", max_file_size),
+                ));
+            }
+            content.extend_from_slice(&chunk);
+        }
+        upload = Some((filename, declared, content.freeze()));
+        break;
+    }
+    let (filename, declared, content) = upload.ok_or_else(|| {
+        ApiError::bad_request("missing_file", "The form has no part named \"file\"")
+    })?;
+
+    let content_type = attachment_type(&content, declared.as_deref()).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_type",
+            "Files of that type can't be attached",
+        )
+    })?;
+    let hash = hex::encode(Sha256::digest(&content));
+    let size = content.len() as i64;
+
+    // Checked again when attaching, this only saves storing a file that can't be attached.
+    let mut conn = db.pool.acquire().await?;
+    let exceeds = exceeds_attachment_quota(user_id, &hash, size, quota(), &mut conn).await?;
+    drop(conn);
+    if let Some(left) = exceeds {
+        return Err(AttachmentError::QuotaExceeded(left).into());
+    }
+
+    // The same content is only stored once, whoever attaches it. Uploads of it at the same time
+    // all store it, and a failed one leaves the file unused until it is swept.
+    if !claim_blob(&hash, size, content_type, &db).await? {
+        let is_image = content_type.starts_with("image/");
+        storage
+            .put(&blob_key(&hash), content.clone())
+            .await
+            .map_err(storage_error)?;
+
+        if is_image {
+            let preview = block(move || thumbnail(&content)).await.ok().flatten();
+            if let Some(preview) = preview {
+                match storage.put(&thumbnail_key(&hash), preview.into()).await {
+                    Ok(()) => {
+                        set_blob_thumbnail(&hash, &db).await?;
+                    }
+                    Err(error) => println!("{:#?}", error),
+                }
+            }
+        }
+        mark_blob_stored(&hash, &db).await?;
+    }
+
+    let attachment_id = add_attachment(task_id, user_id, &hash, &filename, quota(), &db).await?;
+    hub.publish(user_id, TaskEventKind::AttachmentAdded, Some(task_id));
+    let attachment = get_attachment(attachment_id, task_id, user_id, &db)
+        .await?
+        .ok_or_else(attachment_not_found)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((
+            header::LOCATION,
+            format!("{}/tasks/{}/attachments/{}", V1, task_id, attachment_id),
+        ))
+        .json(attachment))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    params(AttachmentQuery),
+    responses(
+        (status = 200, description = "The file, with its type", content_type = "application/octet-stream"),
+        (status = 206, description = "The bytes asked for with a Range header", content_type = "application/octet-stream"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no attachment with the provided id on the task", body = Problem, content_type = "application/problem+json"),
+        (status = 416, description = "The range starts past the end of the file"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/attachments/{attachment_id}/content")]
+pub async fn get_attachment_content(
+    path: Path<(i32, i32)>,
+    query: Query<AttachmentQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+    storage: Data<dyn Storage>,
+) -> Result<HttpResponse, ApiError> {
+    let (task_id, attachment_id) = path.into_inner();
+
+    let user_id = download_token_user(&req, query.token.as_deref(), attachment_id)?;
+    let attachment = get_attachment(attachment_id, task_id, user_id, &db)
+        .await?
+        .ok_or_else(attachment_not_found)?;
+
+    let size = attachment.size as u64;
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok());
+    let (mut response, range) = match byte_range(range, size) {
+        ByteRange::Whole => (HttpResponse::Ok(), None),
+        ByteRange::Part(range) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+            ));
+            (response, Some(range))
+        }
+        ByteRange::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish())
+        }
+    };
+    let content = storage
+        .get(&blob_key(&attachment.sha256), range)
+        .await
+        .map_err(storage_error)?;
+
+    // Images and PDFs open in the browser, anything else is downloaded.
+    let disposition = if attachment.content_type.starts_with("image/")
+        || attachment.content_type == "application/pdf"
+    {
+        DispositionType::Inline
+    } else {
+        DispositionType::Attachment
+    };
+
+    Ok(response
+        .content_type(attachment.content_type)
+        .insert_header(ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(attachment.filename)],
+        })
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::ETAG, format!("\"{}\"", attachment.sha256)))
+        .insert_header((
+            header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        ))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .body(content))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    params(AttachmentQuery),
+    responses(
+        (status = 200, description = "A preview of the image, at most 256 pixels wide and high", content_type = "image/png"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no attachment with the provided id on the task, or it has no thumbnail", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/tasks/{task_id}/attachments/{attachment_id}/thumbnail")]
+pub async fn get_attachment_thumbnail(
+    path: Path<(i32, i32)>,
+    query: Query<AttachmentQuery>,
+    req: HttpRequest,
+    db: Data<Db>,
+    storage: Data<dyn Storage>,
+) -> Result<HttpResponse, ApiError> {
+    let (task_id, attachment_id) = path.into_inner();
+
+    let user_id = download_token_user(&req, query.token.as_deref(), attachment_id)?;
+    let attachment = get_attachment(attachment_id, task_id, user_id, &db)
+        .await?
+        .ok_or_else(attachment_not_found)?;
+    if !attachment.has_thumbnail {
+        return Err(ApiError::not_found(
+            "thumbnail_not_found",
+            "The attachment has no thumbnail",
+        ));
+    }
+
+    let thumbnail = storage
+        .get(&thumbnail_key(&attachment.sha256), None)
+        .await
+        .map_err(storage_error)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header((header::ETAG, format!("\"{}\"", attachment.sha256)))
+        .insert_header((
+            header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        ))
+        .body(thumbnail))
+}
+
+#[utoipa::path(
+    tag = "attachments",
+    responses(
+        (status = 204, description = "Attachment deleted"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no attachment with the provided id on the task", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/tasks/{task_id}/attachments/{attachment_id}")]
+pub async fn delete_task_attachment(
+    path: Path<(i32, i32)>,
+    req: HttpRequest,
+    db: Data<Db>,
+    hub: Data<dyn EventHub>,
+) -> Result<HttpResponse, ApiError> {
+    let (task_id, attachment_id) = path.into_inner();
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if delete_attachment(attachment_id, task_id, user_id, &db).await? {
+        hub.publish(user_id, TaskEventKind::AttachmentDeleted, Some(task_id));
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(attachment_not_found())
+    }
+}
+
+/* Where a webhook URL leads is only looked up once it is a valid URL */
+async fn webhook_destination_errors(url: Option<&str>, errors: &[FieldError]) -> Vec<FieldError> {
+    let url = match url {
+        Some(url) if !private_networks_allowed() => url,
+        _ => return Vec::new(),
+    };
+    if errors.iter().any(|error| error.field == "url") {
+        return Vec::new();
+    }
+
+    match check_destination(url).await {
+        Ok(()) => Vec::new(),
+        Err(message) => vec![FieldError::new("url", "forbidden_destination", message)],
+    }
+}
+
+fn webhook_not_found() -> ApiError {
+    ApiError::not_found(
+        "webhook_not_found",
+        "There is no webhook with the provided id",
+    )
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "Webhook created, with the secret its requests are signed with", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/webhooks")]
+pub async fn post_webhook(
+    webhook: Json<NewWebhook>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    let mut errors = validate_webhook(Some(&webhook.url), Some(&webhook.events));
+    errors.extend(webhook_destination_errors(Some(&webhook.url), &errors).await);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    let webhook = add_webhook(user_id, webhook.into_inner(), &db).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("{}/webhooks/{}", V1, webhook.id)))
+        .json(webhook))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Webhooks of the user, without their secrets", body = [Webhook]),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/webhooks")]
+pub async fn get_webhooks(req: HttpRequest, db: Data<Db>) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let webhooks = get_webhooks_by_user(user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(
+        webhooks
+            .into_iter()
+            .map(Webhook::from)
+            .collect::<Vec<Webhook>>(),
+    ))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = WebhookUpdate,
+    responses(
+        (status = 200, description = "Updated webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no webhook with the provided id", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid fields", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[patch("/webhooks/{webhook_id}")]
+pub async fn patch_webhook(
+    webhook_id: Path<i32>,
+    update: Json<WebhookUpdate>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    let mut errors = validate_webhook(update.url.as_deref(), update.events.as_deref());
+    errors.extend(webhook_destination_errors(update.url.as_deref(), &errors).await);
+    if !errors.is_empty() {
+        return Err(ApiError::validation(errors));
+    }
+
+    match update_webhook(webhook_id.into_inner(), user_id, update.into_inner(), &db).await? {
+        Some(webhook) => Ok(HttpResponse::Ok().json(Webhook::from(webhook))),
+        None => Err(webhook_not_found()),
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 204, description = "Webhook deleted, with its delivery log"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no webhook with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[delete("/webhooks/{webhook_id}")]
+pub async fn delete_webhook_by_id(
+    webhook_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if delete_webhook(webhook_id.into_inner(), user_id, &db).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(webhook_not_found())
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "The latest deliveries of the webhook, newest first", body = [WebhookDelivery]),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no webhook with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[get("/webhooks/{webhook_id}/deliveries")]
+pub async fn get_deliveries(
+    webhook_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let webhook_id = webhook_id.into_inner();
+
+    if get_webhook(webhook_id, user_id, &db).await?.is_none() {
+        return Err(webhook_not_found());
+    }
+    let deliveries = get_webhook_deliveries(webhook_id, user_id, &db).await?;
+
+    Ok(HttpResponse::Ok().json(
+        deliveries
+            .into_iter()
+            .map(WebhookDelivery::from)
+            .collect::<Vec<WebhookDelivery>>(),
+    ))
+}
+
+/* The payload is sent again as it was, with the same id, so receivers can skip what they handled */
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 202, description = "A new delivery of the same payload, queued", body = WebhookDelivery),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no delivery with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/webhooks/{webhook_id}/deliveries/{delivery_id}/replay")]
+pub async fn replay_delivery(
+    path: Path<(i32, i64)>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let (webhook_id, delivery_id) = path.into_inner();
+
+    match replay_webhook_delivery(delivery_id, webhook_id, user_id, &db).await? {
+        Some(delivery) => Ok(HttpResponse::Accepted().json(WebhookDelivery::from(delivery))),
+        None => Err(ApiError::not_found(
+            "delivery_not_found",
+            "There is no delivery with the provided id",
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 202, description = "A webhook.ping event, queued for the webhook", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no webhook with the provided id", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/webhooks/{webhook_id}/ping")]
+pub async fn ping_webhook(
+    webhook_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<Db>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+    let webhook_id = webhook_id.into_inner();
+
+    if get_webhook(webhook_id, user_id, &db).await?.is_none() {
+        return Err(webhook_not_found());
+    }
+    enqueue_ping(webhook_id, &db).await?;
+
+    Ok(HttpResponse::Accepted().body("Ping queued"))
+}
+
+/* Clients send { query, variables, operationName }, or an array of them to run a batch */
+#[utoipa::path(
+    tag = "graphql",
+    request_body(content = serde_json::Value, description = "A GraphQL request, or an array of them"),
+    responses(
+        (status = 200, description = "The data and errors of the request, or of each one in a batch", body = serde_json::Value),
+        (status = 400, description = "The body isn't a GraphQL request, or the batch is too large", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("token" = [])),
+)]
+#[post("/graphql")]
+pub async fn post_graphql(
+    request: Json<BatchRequest>,
+    req: HttpRequest,
+    db: Data<Db>,
+    schema: Data<TaskSchema>,
+) -> Result<HttpResponse, ApiError> {
+    let authorization = req.headers().get("Authorization");
+
+    let user_id = validate_token(authorization)?;
+
+    if let BatchRequest::Batch(requests) = &*request {
+        if requests.len() > MAX_BATCH {
+            return Err(ApiError::bad_request(
+                "batch_too_large",
+                format!("A batch can't have more than {} requests", MAX_BATCH),
+            ));
+        }
+    }
+
+    let request = request.into_inner().data(Viewer::new(user_id, &db));
+
+    Ok(HttpResponse::Ok().json(schema.execute_batch(request).await))
+}
+
+#[utoipa::path(
+    tag = "graphql",
+    responses(
+        (status = 200, description = "The GraphQL schema, for code generators", body = String, content_type = "text/plain"),
+    ),
+)]
+#[get("/graphql/schema.graphql")]
+pub async fn get_graphql_schema(schema: Data<TaskSchema>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(schema.sdl())
+}
+
+#[utoipa::path(
+    tag = "graphql",
+    params(GraphQLSocketQuery),
+    responses(
+        (status = 101, description = "A WebSocket for subscriptions, speaking graphql-transport-ws or graphql-ws"),
+        (status = 400, description = "Not a WebSocket handshake, or no supported subprotocol", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Invalid token", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[get("/graphql/ws")]
+pub async fn graphql_ws(
+    query: Query<GraphQLSocketQuery>,
+    req: HttpRequest,
+    body: Payload,
+    db: Data<Db>,
+    schema: Data<TaskSchema>,
+) -> Result<HttpResponse, ApiError> {
+    let token = query
+        .token
+        .as_deref()
+        .and_then(|token| HeaderValue::from_str(token).ok());
+    let user_id = match req.headers().get("Authorization").or(token.as_ref()) {
+        Some(authorization) => Some(validate_token(Some(authorization))?),
+        None => None,
+    };
+
+    let protocol = websocket_protocol(req.headers().get(header::SEC_WEBSOCKET_PROTOCOL))
+        .ok_or_else(|| {
+            ApiError::bad_request(
+                "unsupported_protocol",
+                "Sec-WebSocket-Protocol has to be graphql-transport-ws or graphql-ws",
+            )
+        })?;
+
+    let (mut response, session, messages) = actix_ws::handle(&req, body)
+        .map_err(|error| ApiError::bad_request("invalid_handshake", error.to_string()))?;
+    response.headers_mut().insert(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(protocol.sec_websocket_protocol()),
+    );
+    serve_graphql_websocket(
+        schema.get_ref().clone(),
+        protocol,
+        session,
+        messages,
+        user_id,
+        db,
+    );
+
+    Ok(response)
 }
@@ -0,0 +1,126 @@
+use crate::model::{ResponseTask, SearchHighlight};
+
+/// At most this many words of a query are used.
+const MAX_TERMS: usize = 10;
+
+/// Words shorter than this aren't in the full-text indexes of MySQL (innodb_ft_min_token_size).
+pub const MIN_TERM_LEN: usize = 3;
+
+/// Characters shown before the first match of a snippet.
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Characters of a snippet, not counting the markup.
+const SNIPPET_LENGTH: usize = 160;
+
+/// The words of a search query, lowercased and without repeats. Anything that isn't a letter or a
+/// digit separates words, so no full-text operator gets through.
+pub fn search_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for word in query.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if !word.is_empty() && !terms.contains(&word) && terms.len() < MAX_TERMS {
+            terms.push(word);
+        }
+    }
+    terms
+}
+
+/// The query for MATCH ... AGAINST in boolean mode: every word that the indexes can have must be
+/// in the task, as a word or as the start of one. It is empty when none can be.
+pub fn boolean_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        .filter(|term| term.chars().count() >= MIN_TERM_LEN)
+        .map(|term| format!("+{}*", term))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Snippets of the fields of a task where the words of the query show up, and of the first of its
+/// comments where they do.
+pub fn highlights(
+    task: &ResponseTask,
+    comments: &[String],
+    terms: &[String],
+) -> Vec<SearchHighlight> {
+    let fields = [
+        ("name", task.name.clone()),
+        ("description", task.description.clone()),
+        ("tags", task.tags.join(", ")),
+    ];
+
+    let mut highlights: Vec<SearchHighlight> = fields
+        .into_iter()
+        .filter_map(|(field, text)| {
+            snippet(&text, terms).map(|snippet| SearchHighlight { field, snippet })
+        })
+        .collect();
+    if let Some(snippet) = comments.iter().find_map(|body| snippet(body, terms)) {
+        highlights.push(SearchHighlight {
+            field: "comments",
+            snippet,
+        });
+    }
+    highlights
+}
+
+/* A piece of the text around its first match, HTML escaped, with every matching word of the piece
+in <mark> */
+fn snippet(text: &str, terms: &[String]) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+
+    let mut words: Vec<(usize, usize)> = Vec::new();
+    let mut word_start = None;
+    for index in 0..=chars.len() {
+        let alphanumeric = chars.get(index).map_or(false, |c| c.is_alphanumeric());
+        match (word_start, alphanumeric) {
+            (None, true) => word_start = Some(index),
+            (Some(start), false) => {
+                let word = chars[start..index]
+                    .iter()
+                    .collect::<String>()
+                    .to_lowercase();
+                if terms.iter().any(|term| word.starts_with(term.as_str())) {
+                    words.push((start, index));
+                }
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let from = words.first()?.0.saturating_sub(SNIPPET_CONTEXT);
+    let to = (from + SNIPPET_LENGTH).min(chars.len());
+
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    let mut index = from;
+    for (start, end) in words.into_iter().filter(|(_, end)| *end <= to) {
+        escape_into(&mut snippet, &chars[index..start]);
+        snippet.push_str("<mark>");
+        escape_into(&mut snippet, &chars[start..end]);
+        snippet.push_str("</mark>");
+        index = end;
+    }
+    escape_into(&mut snippet, &chars[index..to]);
+    if to < chars.len() {
+        snippet.push('…');
+    }
+
+    Some(snippet)
+}
+
+fn escape_into(output: &mut String, chars: &[char]) {
+    for c in chars {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(*c),
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use crate::hashing::hmac_sha256_bytes;
+
+use actix_web::web::{block, Bytes};
+
+use async_trait::async_trait;
+
+use chrono::Utc;
+
+use rand::distributions::{Alphanumeric, DistString};
+
+use reqwest::{Client, StatusCode, Url};
+
+use sha2::{Digest, Sha256};
+
+use std::env::var;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Where the files of attachments are kept, by key. Keys are made of hex digits and slashes, so
+/// they are safe as paths and as URLs.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// It saves a file, replacing the one under the same key if there is one.
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()>;
+
+    /// The whole file, or only the bytes in `range`. A missing file is ErrorKind::NotFound.
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<Bytes>;
+
+    /// It removes a file. Removing one that isn't there isn't an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// The storage set up in the environment: S3 when ATTACHMENT_STORAGE is "s3", and a folder on
+/// this machine otherwise, ATTACHMENTS_DIR or "attachments".
+pub fn storage_from_env() -> Arc<dyn Storage> {
+    match var("ATTACHMENT_STORAGE").as_deref() {
+        Ok("s3") => Arc::new(S3Storage::from_env()),
+        _ => Arc::new(LocalStorage::new(
+            var("ATTACHMENTS_DIR").unwrap_or_else(|_| String::from("attachments")),
+        )),
+    }
+}
+
+fn blocking_error<E>(_: E) -> io::Error {
+    io::Error::new(ErrorKind::Other, "The file operation was cancelled")
+}
+
+/// Files in a folder of the machine that runs the server.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()> {
+        let path = self.root.join(key);
+        block(move || {
+            if let Some(folder) = path.parent() {
+                fs::create_dir_all(folder)?;
+            }
+            /* Written aside and renamed, so nobody reads half a file. Each put has its own, as
+            uploads of the same file at the same time all store it */
+            let partial = path.with_extension(format!(
+                "{}.partial",
+                Alphanumeric.sample_string(&mut rand::thread_rng(), 12)
+            ));
+            let written = fs::write(&partial, &content).and_then(|_| fs::rename(&partial, &path));
+            if written.is_err() {
+                let _ = fs::remove_file(&partial);
+            }
+            written
+        })
+        .await
+        .map_err(blocking_error)?
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<Bytes> {
+        let path = self.root.join(key);
+        block(move || {
+            let mut file = File::open(path)?;
+            let content = match range {
+                Some(range) => {
+                    file.seek(SeekFrom::Start(range.start))?;
+                    let mut content = vec![0; (range.end - range.start) as usize];
+                    file.read_exact(&mut content)?;
+                    content
+                }
+                None => {
+                    let mut content = Vec::new();
+                    file.read_to_end(&mut content)?;
+                    content
+                }
+            };
+            Ok(Bytes::from(content))
+        })
+        .await
+        .map_err(blocking_error)?
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self.root.join(key);
+        block(move || match fs::remove_file(path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        })
+        .await
+        .map_err(blocking_error)?
+    }
+}
+
+/// Files in a bucket of S3 or of a service with the same API, like MinIO, which can stand in for
+/// it on a development machine. Buckets are addressed by path, which every such service supports.
+pub struct S3Storage {
+    client: Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+impl S3Storage {
+    pub fn from_env() -> Self {
+        let region = var("S3_REGION").unwrap_or_else(|_| String::from("us-east-1"));
+        let endpoint =
+            var("S3_ENDPOINT").unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+
+        S3Storage {
+            client: Client::new(),
+            endpoint: Url::parse(&endpoint).expect("S3_ENDPOINT must be a URL"),
+            bucket: var("S3_BUCKET").expect("S3_BUCKET must be set"),
+            region,
+            access_key_id: var("S3_ACCESS_KEY_ID").expect("S3_ACCESS_KEY_ID must be set"),
+            secret_access_key: var("S3_SECRET_ACCESS_KEY")
+                .expect("S3_SECRET_ACCESS_KEY must be set"),
+        }
+    }
+
+    /* Signature Version 4 of a request with no query string, signing the host, the hash of the
+    payload and the date */
+    fn request(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        payload_hash: &str,
+    ) -> reqwest::RequestBuilder {
+        let path = format!("/{}/{}", self.bucket, key);
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or(""), port),
+            None => self.endpoint.host_str().unwrap_or("").to_string(),
+        };
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, host, payload_hash, timestamp, payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let secret = format!("AWS4{}", self.secret_access_key);
+        let key_date = hmac_sha256_bytes(secret.as_bytes(), date.as_bytes());
+        let key_region = hmac_sha256_bytes(&key_date, self.region.as_bytes());
+        let key_service = hmac_sha256_bytes(&key_region, b"s3");
+        let signing_key = hmac_sha256_bytes(&key_service, b"aws4_request");
+        let signature = hex::encode(hmac_sha256_bytes(&signing_key, string_to_sign.as_bytes()));
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+
+        self.client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header(
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                    self.access_key_id, scope, signature
+                ),
+            )
+    }
+}
+
+fn s3_error(error: reqwest::Error) -> io::Error {
+    io::Error::new(ErrorKind::Other, error)
+}
+
+fn s3_status(status: StatusCode) -> io::Result<()> {
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::NOT_FOUND => Err(io::Error::new(
+            ErrorKind::NotFound,
+            "There is no such object",
+        )),
+        status => Err(io::Error::new(
+            ErrorKind::Other,
+            format!("The storage answered {}", status),
+        )),
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()> {
+        let payload_hash = hex::encode(Sha256::digest(&content));
+        let response = self
+            .request(reqwest::Method::PUT, key, &payload_hash)
+            .body(content)
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        s3_status(response.status())
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> io::Result<Bytes> {
+        let mut request = self.request(reqwest::Method::GET, key, EMPTY_PAYLOAD_HASH);
+        if let Some(range) = range {
+            request = request.header("Range", format!("bytes={}-{}", range.start, range.end - 1));
+        }
+        let response = request.send().await.map_err(s3_error)?;
+        s3_status(response.status())?;
+
+        response.bytes().await.map_err(s3_error)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let response = self
+            .request(reqwest::Method::DELETE, key, EMPTY_PAYLOAD_HASH)
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        match s3_status(response.status()) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{
+        add_attachment, add_task, claim_blob, connect, exceeds_attachment_quota,
+        get_attachment_usage, insert_new_user, mark_blob_stored,
+    };
+    use crate::model::{AttachmentError, Db, NewTask, User};
+
+    use actix_web::http::Method;
+    use actix_web::web::{self, Data};
+    use actix_web::{rt::spawn, App, HttpRequest, HttpResponse, HttpServer};
+
+    use dotenv::dotenv;
+
+    use rand::distributions::{Alphanumeric, DistString};
+
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn random(length: usize) -> String {
+        Alphanumeric.sample_string(&mut rand::thread_rng(), length)
+    }
+
+    /* What every storage has to do, whatever keeps the files */
+    async fn round_trip(storage: &dyn Storage) {
+        let key = format!("blobs/ab/{}", random(16));
+        let missing = storage.get(&key, None).await.unwrap_err();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+        storage
+            .put(&key, Bytes::from_static(b"hello, world"))
+            .await
+            .unwrap();
+        assert_eq!(storage.get(&key, None).await.unwrap(), "hello, world");
+        assert_eq!(storage.get(&key, Some(7..12)).await.unwrap(), "world");
+
+        storage
+            .put(&key, Bytes::from_static(b"replaced"))
+            .await
+            .unwrap();
+        assert_eq!(storage.get(&key, None).await.unwrap(), "replaced");
+
+        storage.delete(&key).await.unwrap();
+        let deleted = storage.get(&key, None).await.unwrap_err();
+        assert_eq!(deleted.kind(), ErrorKind::NotFound);
+        storage.delete(&key).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn keeps_files_in_a_folder() {
+        let root = std::env::temp_dir().join(format!("attachments-{}", random(12)));
+        let storage = LocalStorage::new(&root);
+
+        round_trip(&storage).await;
+
+        storage
+            .put("blobs/cd/file", Bytes::from_static(b"content"))
+            .await
+            .unwrap();
+        let folder: Vec<_> = fs::read_dir(root.join("blobs/cd"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(folder, ["file"]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /* A bucket on this machine that answers like S3, and checks that requests are signed */
+    #[derive(Default)]
+    struct Bucket {
+        objects: Mutex<HashMap<String, Bytes>>,
+    }
+
+    async fn object(req: HttpRequest, body: Bytes, bucket: Data<Bucket>) -> HttpResponse {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("")
+                .to_string()
+        };
+        let authorization = header("Authorization");
+        let signed = authorization.starts_with("AWS4-HMAC-SHA256 Credential=test-key/")
+            && authorization.contains("/us-east-1/s3/aws4_request, ")
+            && authorization
+                .rsplit_once("Signature=")
+                .map_or(false, |(_, signature)| {
+                    signature.len() == 64 && signature.chars().all(|c| c.is_ascii_hexdigit())
+                })
+            && header("x-amz-content-sha256") == hex::encode(Sha256::digest(&body))
+            && header("x-amz-date").len() == 16;
+        if !signed {
+            return HttpResponse::Forbidden().finish();
+        }
+
+        let key = match req.path().strip_prefix("/bucket/") {
+            Some(key) => key.to_string(),
+            None => return HttpResponse::NotFound().finish(),
+        };
+        let mut objects = bucket.objects.lock().unwrap();
+        match *req.method() {
+            Method::PUT => {
+                objects.insert(key, body);
+                HttpResponse::Ok().finish()
+            }
+            Method::DELETE => {
+                objects.remove(&key);
+                HttpResponse::NoContent().finish()
+            }
+            Method::GET => match objects.get(&key) {
+                None => HttpResponse::NotFound().finish(),
+                Some(content) => match header("Range")
+                    .strip_prefix("bytes=")
+                    .and_then(|range| range.split_once('-'))
+                {
+                    Some((start, end)) => {
+                        let (start, end): (usize, usize) =
+                            (start.parse().unwrap(), end.parse().unwrap());
+                        HttpResponse::PartialContent().body(content.slice(start..end + 1))
+                    }
+                    None => HttpResponse::Ok().body(content.clone()),
+                },
+            },
+            _ => HttpResponse::MethodNotAllowed().finish(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn keeps_files_in_an_s3_bucket() {
+        let bucket = Data::new(Bucket::default());
+        let state = bucket.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(state.clone())
+                .default_service(web::to(object))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("the bucket should bind a port");
+        let address = server.addrs()[0];
+        spawn(server.run());
+
+        let storage = S3Storage {
+            client: Client::new(),
+            endpoint: Url::parse(&format!("http://{}", address)).unwrap(),
+            bucket: String::from("bucket"),
+            region: String::from("us-east-1"),
+            access_key_id: String::from("test-key"),
+            secret_access_key: String::from("test-secret"),
+        };
+
+        round_trip(&storage).await;
+        assert!(bucket.objects.lock().unwrap().is_empty());
+
+        let unsigned = Client::new()
+            .get(format!("http://{}/bucket/blobs/ab/file", address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unsigned.status(), StatusCode::FORBIDDEN);
+    }
+
+    async fn new_user(db: &Data<Db>) -> i32 {
+        let username = format!("storage-{}", random(12));
+        insert_new_user(
+            User {
+                email: format!("{}@example.com", username),
+                username,
+                password: String::from("correct horse battery staple"),
+            },
+            db,
+        )
+        .await
+        .expect("the user should be added")
+        .last_insert_id() as i32
+    }
+
+    async fn new_task(user_id: i32, db: &Data<Db>) -> i32 {
+        let task = NewTask {
+            uid: None,
+            name: String::from("With files"),
+            description: String::new(),
+            estimate: None,
+            tags: Vec::new(),
+            project_id: None,
+            billable: false,
+            hourly_rate: None,
+            due_at: None,
+            priority: None,
+            recurrence: None,
+        };
+        match add_task(user_id, task, db).await {
+            Ok(task_id) => task_id,
+            Err(_) => panic!("the task should be added"),
+        }
+    }
+
+    /* A file of `size` bytes that nobody has attached yet */
+    async fn new_blob(size: i64, db: &Data<Db>) -> String {
+        let hash = hex::encode(Sha256::digest(random(32).as_bytes()));
+        claim_blob(&hash, size, "text/plain", db).await.unwrap();
+        mark_blob_stored(&hash, db).await.unwrap();
+        hash
+    }
+
+    #[actix_web::test]
+    async fn counts_each_file_once_against_the_quota() {
+        dotenv().ok();
+        let database_url =
+            var("DATABASE_URL").expect("DATABASE_URL must be set as a environment variable");
+        let pool = connect(&database_url)
+            .await
+            .expect("Could not connect to database");
+        let db = Data::new(Db { pool });
+
+        let mut conn = db.pool.acquire().await.unwrap();
+
+        let user_id = new_user(&db).await;
+        let task_id = new_task(user_id, &db).await;
+        let quota = 100;
+
+        let first = new_blob(60, &db).await;
+        assert_eq!(
+            exceeds_attachment_quota(user_id, &first, 60, quota, &mut conn)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(matches!(
+            add_attachment(task_id, user_id, &first, "first.txt", quota, &db).await,
+            Ok(_)
+        ));
+        assert!(matches!(
+            add_attachment(task_id, user_id, &first, "again.txt", quota, &db).await,
+            Ok(_)
+        ));
+        assert_eq!(get_attachment_usage(user_id, &db.pool).await.unwrap(), 60);
+
+        let second = new_blob(50, &db).await;
+        assert_eq!(
+            exceeds_attachment_quota(user_id, &second, 50, quota, &mut conn)
+                .await
+                .unwrap(),
+            Some(40)
+        );
+        assert_eq!(
+            exceeds_attachment_quota(user_id, &second, 40, quota, &mut conn)
+                .await
+                .unwrap(),
+            None
+        );
+        /* Attached already, so it takes no more space even with the quota used up */
+        assert_eq!(
+            exceeds_attachment_quota(user_id, &first, 60, 60, &mut conn)
+                .await
+                .unwrap(),
+            None
+        );
+        /* The insert checks again, so a file that doesn't fit never gets attached */
+        assert!(matches!(
+            add_attachment(task_id, user_id, &second, "second.txt", quota, &db).await,
+            Err(AttachmentError::QuotaExceeded(40))
+        ));
+        assert_eq!(get_attachment_usage(user_id, &db.pool).await.unwrap(), 60);
+
+        /* Another user attaching the same file pays for it too, and nothing of the first changes */
+        let other_id = new_user(&db).await;
+        let other_task_id = new_task(other_id, &db).await;
+        assert_eq!(get_attachment_usage(other_id, &db.pool).await.unwrap(), 0);
+        assert!(matches!(
+            add_attachment(other_task_id, other_id, &first, "shared.txt", quota, &db).await,
+            Ok(_)
+        ));
+        assert_eq!(get_attachment_usage(other_id, &db.pool).await.unwrap(), 60);
+        assert_eq!(get_attachment_usage(user_id, &db.pool).await.unwrap(), 60);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::model::{
+    DBChange, DBSyncClock, DBTimeEntry, RejectedChange, ResponseTask, SyncEntity, SyncResponse,
+    SyncTask, SyncTimeEntry, TaskUpdate, Tombstone,
+};
+
+use actix_web::cookie::time::OffsetDateTime;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Mutex;
+
+/// The node name of the clocks the server makes, for changes that come through the rest of the API.
+const SERVER_NODE: &str = "server";
+
+/// Clocks further ahead of the server than this are refused, or a client with a wrong date would
+/// win every conflict for as long as it is off.
+const MAX_CLOCK_SKEW_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/* The last wall time and counter the server handed out or saw */
+static CLOCK: Mutex<(u64, u32)> = Mutex::new((0, 0));
+
+/// A hybrid logical clock: wall time in milliseconds, a counter that tells apart what happened in
+/// the same millisecond, and the node that made it, which breaks the remaining ties. It is written
+/// as `{millis:015}-{counter:010}-{node}`, so comparing the strings compares the clocks.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hlc {
+    millis: u64,
+    counter: u32,
+    node: String,
+}
+
+impl Hlc {
+    pub fn parse(hlc: &str) -> Option<Hlc> {
+        let mut parts = hlc.splitn(3, '-');
+        let millis = parts.next()?.parse().ok()?;
+        let counter = parts.next()?.parse().ok()?;
+        let node = parts.next()?;
+
+        if node.is_empty() || node.len() > 32 {
+            return None;
+        }
+
+        Some(Hlc {
+            millis,
+            counter,
+            node: node.to_string(),
+        })
+    }
+
+    /// Whether the clock is so far ahead of the server that it can't be right.
+    pub fn is_too_far_ahead(&self) -> bool {
+        self.millis > wall_millis() + MAX_CLOCK_SKEW_MILLIS
+    }
+}
+
+impl fmt::Display for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:015}-{:010}-{}", self.millis, self.counter, self.node)
+    }
+}
+
+fn wall_millis() -> u64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as u64
+}
+
+/// The next clock of the server, later than every clock it has made or seen.
+pub fn now() -> Hlc {
+    let wall = wall_millis();
+    let mut clock = CLOCK.lock().unwrap();
+
+    if wall > clock.0 {
+        *clock = (wall, 0);
+    } else {
+        clock.1 += 1;
+    }
+
+    Hlc {
+        millis: clock.0,
+        counter: clock.1,
+        node: SERVER_NODE.to_string(),
+    }
+}
+
+/// It moves the clock of the server past one a client sent, so the changes made through the rest
+/// of the API afterwards win over it.
+pub fn observe(hlc: &Hlc) {
+    let mut clock = CLOCK.lock().unwrap();
+    if (hlc.millis, hlc.counter) > *clock {
+        *clock = (hlc.millis, hlc.counter);
+    }
+}
+
+/// Whether a write stamped with `hlc` replaces the last one to a field. Fields that were never
+/// written with a clock lose against any.
+pub fn wins(hlc: &Hlc, field: &str, clocks: &[(String, String)]) -> bool {
+    clocks
+        .iter()
+        .find(|(name, _)| name == field)
+        .and_then(|(_, current)| Hlc::parse(current))
+        .map_or(true, |current| *hlc > current)
+}
+
+/// The fields of a task that are set in an update, named as in the clocks.
+pub fn task_update_fields(update: &TaskUpdate) -> Vec<&'static str> {
+    let fields = [
+        ("name", update.name.is_some()),
+        ("description", update.description.is_some()),
+        ("estimate", update.estimate.is_some()),
+        ("tags", update.tags.is_some()),
+        ("project_id", update.project_id.is_some()),
+        ("billable", update.billable.is_some()),
+        ("hourly_rate", update.hourly_rate.is_some()),
+        ("due_at", update.due_at.is_some()),
+        ("priority", update.priority.is_some()),
+        ("recurrence", update.recurrence.is_some()),
+    ];
+
+    fields
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(field, _)| *field)
+        .collect()
+}
+
+/// It keeps the fields of an update whose clock is later than the last write to them.
+pub fn winning_task_update(
+    update: TaskUpdate,
+    hlc: &Hlc,
+    clocks: &[(String, String)],
+) -> TaskUpdate {
+    let wins = |field| wins(hlc, field, clocks);
+
+    TaskUpdate {
+        name: update.name.filter(|_| wins("name")),
+        description: update.description.filter(|_| wins("description")),
+        estimate: update.estimate.filter(|_| wins("estimate")),
+        tags: update.tags.filter(|_| wins("tags")),
+        project_id: update.project_id.filter(|_| wins("project_id")),
+        billable: update.billable.filter(|_| wins("billable")),
+        hourly_rate: update.hourly_rate.filter(|_| wins("hourly_rate")),
+        due_at: update.due_at.filter(|_| wins("due_at")),
+        priority: update.priority.filter(|_| wins("priority")),
+        recurrence: update.recurrence.filter(|_| wins("recurrence")),
+    }
+}
+
+pub fn rejected(
+    entity: SyncEntity,
+    uid: &str,
+    code: &'static str,
+    message: impl Into<String>,
+) -> RejectedChange {
+    RejectedChange {
+        entity,
+        uid: uid.to_string(),
+        code,
+        message: message.into(),
+    }
+}
+
+/// It puts together what a client gets back from a sync: the records that changed after its
+/// cursor and the ones it just sent, with their clocks, and tombstones for the deleted ones.
+/// Without `changes`, which is the first sync, it gets every record and no tombstones.
+pub fn sync_response(
+    cursor: i64,
+    changes: Option<Vec<DBChange>>,
+    sent: &[(SyncEntity, String)],
+    tasks: Vec<ResponseTask>,
+    entries: Vec<DBTimeEntry>,
+    clocks: Vec<DBSyncClock>,
+    rejected: Vec<RejectedChange>,
+) -> SyncResponse {
+    // The last change to every record wins, so a record deleted after an update is a tombstone.
+    let wanted = changes.map(|changes| {
+        let mut wanted: BTreeMap<(SyncEntity, String), bool> = BTreeMap::new();
+        for change in changes {
+            wanted.insert(
+                (SyncEntity::from_db(&change.entity), change.uid),
+                change.deleted,
+            );
+        }
+        for record in sent {
+            wanted.entry(record.clone()).or_insert(false);
+        }
+        wanted
+    });
+
+    let mut clocks_by_record: BTreeMap<(SyncEntity, String), BTreeMap<String, String>> =
+        BTreeMap::new();
+    for clock in clocks {
+        clocks_by_record
+            .entry((SyncEntity::from_db(&clock.entity), clock.uid))
+            .or_default()
+            .insert(clock.field, clock.hlc);
+    }
+
+    let is_wanted = |entity: SyncEntity, uid: &str| match &wanted {
+        Some(wanted) => wanted.get(&(entity, uid.to_string())) == Some(&false),
+        None => true,
+    };
+    let mut clocks_of = |entity: SyncEntity, uid: &str| {
+        clocks_by_record
+            .remove(&(entity, uid.to_string()))
+            .unwrap_or_default()
+    };
+
+    let mut found = BTreeSet::new();
+    let mut sync_tasks = Vec::new();
+    for task in tasks {
+        if is_wanted(SyncEntity::Task, &task.uid) {
+            found.insert((SyncEntity::Task, task.uid.clone()));
+            sync_tasks.push(SyncTask {
+                clocks: clocks_of(SyncEntity::Task, &task.uid),
+                task,
+            });
+        }
+    }
+
+    let mut time_entries = Vec::new();
+    for entry in entries {
+        if is_wanted(SyncEntity::TimeEntry, &entry.uid) {
+            found.insert((SyncEntity::TimeEntry, entry.uid.clone()));
+            time_entries.push(SyncTimeEntry {
+                clocks: clocks_of(SyncEntity::TimeEntry, &entry.uid),
+                uid: entry.uid,
+                task_uid: entry.task_uid,
+                start_time: entry.start_time.unix_timestamp(),
+                finish_time: entry
+                    .finish_time
+                    .map(|finish_time| finish_time.unix_timestamp()),
+                billed: entry.billed,
+            });
+        }
+    }
+
+    // What was wanted and isn't there anymore is gone, even if it wasn't logged as deleted.
+    let tombstones = wanted
+        .unwrap_or_default()
+        .into_keys()
+        .filter(|record| !found.contains(record))
+        .map(|(entity, uid)| Tombstone { entity, uid })
+        .collect();
+
+    SyncResponse {
+        cursor,
+        hlc: now().to_string(),
+        tasks: sync_tasks,
+        time_entries,
+        tombstones,
+        rejected,
+    }
+}
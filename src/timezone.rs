@@ -0,0 +1,145 @@
+use crate::model::{DateFormat, NamedPeriod, UserSettings, Weekday};
+
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Everything the server needs to know to tell what "today" or "this week" means for a user.
+/// Times are stored and compared in UTC, only the boundaries of days, weeks and months depend on
+/// the user's time zone.
+pub struct UserClock {
+    pub time_zone: Tz,
+    pub week_start: Weekday,
+    pub date_format: DateFormat,
+}
+
+impl From<&UserSettings> for UserClock {
+    fn from(settings: &UserSettings) -> Self {
+        UserClock {
+            time_zone: settings.time_zone.parse().unwrap_or(Tz::UTC),
+            week_start: settings.week_start,
+            date_format: settings.date_format,
+        }
+    }
+}
+
+pub fn is_valid_time_zone(name: &str) -> bool {
+    name.parse::<Tz>().is_ok()
+}
+
+/// A loose check for BCP 47 tags such as `es`, `en-US` or `zh-Hant-TW`.
+pub fn is_valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language_is_valid = match subtags.next() {
+        Some(language) => {
+            (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_lowercase())
+        }
+        None => false,
+    };
+
+    language_is_valid
+        && subtags.all(|subtag| {
+            (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+impl UserClock {
+    /// Calendar day a timestamp falls on for the user.
+    pub fn local_date(&self, timestamp: i64) -> NaiveDate {
+        match self.time_zone.timestamp_opt(timestamp, 0).single() {
+            Some(date_time) => date_time.date_naive(),
+            None => NaiveDate::default(),
+        }
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.local_date(Utc::now().timestamp())
+    }
+
+    /// The user's wall clock time.
+    pub fn now(&self) -> NaiveDateTime {
+        Utc::now().with_timezone(&self.time_zone).naive_local()
+    }
+
+    /// Timestamp of a wall clock time of the user. A time skipped by daylight saving is moved
+    /// forward by the hour that was skipped.
+    pub fn timestamp(&self, local: NaiveDateTime) -> i64 {
+        match self.time_zone.from_local_datetime(&local).earliest() {
+            Some(date_time) => date_time.timestamp(),
+            None => self
+                .time_zone
+                .from_local_datetime(&(local + chrono::Duration::hours(1)))
+                .earliest()
+                .map_or_else(
+                    || Utc.from_utc_datetime(&local).timestamp(),
+                    |date_time| date_time.timestamp(),
+                ),
+        }
+    }
+
+    /// Timestamp of the first instant of a day for the user. Some zones skip midnight when daylight
+    /// saving starts, so the day then begins at the first hour that exists.
+    pub fn start_of_day(&self, date: NaiveDate) -> i64 {
+        (0..24)
+            .find_map(|hour| {
+                let local = date.and_hms_opt(hour, 0, 0)?;
+                self.time_zone.from_local_datetime(&local).earliest()
+            })
+            .map(|date_time| date_time.timestamp())
+            .unwrap_or_else(|| {
+                Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                    .timestamp()
+            })
+    }
+
+    pub fn start_of_week(&self, date: NaiveDate) -> NaiveDate {
+        let days_since_start =
+            (date.weekday().num_days_from_monday() + 7 - self.week_start_index()) % 7;
+        date - Days::new(days_since_start.into())
+    }
+
+    pub fn start_of_month(&self, date: NaiveDate) -> NaiveDate {
+        NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap()
+    }
+
+    /// Start (inclusive) and end (exclusive) timestamps of a period relative to the user's today.
+    pub fn period_range(&self, period: NamedPeriod) -> (i64, i64) {
+        let today = self.today();
+
+        let (start, end) = match period {
+            NamedPeriod::Today => (today, today + Days::new(1)),
+            NamedPeriod::Yesterday => (today - Days::new(1), today),
+            NamedPeriod::ThisWeek => {
+                let start = self.start_of_week(today);
+                (start, start + Days::new(7))
+            }
+            NamedPeriod::LastWeek => {
+                let end = self.start_of_week(today);
+                (end - Days::new(7), end)
+            }
+            NamedPeriod::ThisMonth => {
+                let start = self.start_of_month(today);
+                (start, start + Months::new(1))
+            }
+            NamedPeriod::LastMonth => {
+                let end = self.start_of_month(today);
+                (end - Months::new(1), end)
+            }
+        };
+
+        (self.start_of_day(start), self.start_of_day(end))
+    }
+
+    pub fn format_date(&self, date: NaiveDate) -> String {
+        let pattern = match self.date_format {
+            DateFormat::Iso => "%Y-%m-%d",
+            DateFormat::DayMonthYear => "%d/%m/%Y",
+            DateFormat::MonthDayYear => "%m/%d/%Y",
+            DateFormat::DottedDayMonthYear => "%d.%m.%Y",
+        };
+        date.format(pattern).to_string()
+    }
+
+    fn week_start_index(&self) -> u32 {
+        (self.week_start.iso() - 1) as u32
+    }
+}
@@ -0,0 +1,42 @@
+use crate::database::purge_expired_tasks;
+use crate::model::Db;
+
+use actix_web::rt::{spawn, time::interval};
+use actix_web::web::Data;
+
+use std::env::var;
+use std::time::Duration;
+
+/// How often the trash is checked for tasks that have been in it for too long.
+const PURGE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// Days a task stays in the trash when TRASH_RETENTION_DAYS isn't set.
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// Days tasks are kept in the trash before they are purged, from TRASH_RETENTION_DAYS.
+pub fn retention_days() -> i64 {
+    match var("TRASH_RETENTION_DAYS") {
+        Ok(days) => days
+            .parse::<i64>()
+            .expect("TRASH_RETENTION_DAYS must be a number of days"),
+        Err(_) => DEFAULT_RETENTION_DAYS,
+    }
+}
+
+/// It spawns the background task that purges the tasks that have been in the trash for longer
+/// than the retention period.
+pub fn spawn_trash_purger(db: Data<Db>) {
+    let retention_days = retention_days();
+
+    spawn(async move {
+        let mut ticker = interval(PURGE_EVERY);
+        loop {
+            ticker.tick().await;
+            match purge_expired_tasks(retention_days, &db).await {
+                Ok(0) => {}
+                Ok(purged) => println!("Purged {} tasks from the trash", purged),
+                Err(err) => println!("{:#?}", err),
+            }
+        }
+    });
+}
@@ -1,14 +1,20 @@
 use actix_web::http::header::HeaderValue;
 
+use serde::{Deserialize, Deserializer};
+
+use crate::hashing::generate_secret;
 use crate::jwt::verify_token;
-use crate::model::VerificationError;
+use crate::model::{TaskHistory, TaskStatus, VerificationError};
 
 pub fn validate_token(authorization: Option<&HeaderValue>) -> Result<i32, VerificationError> {
     match authorization {
         Some(token) => {
-            let token = verify_token(token.to_str().unwrap());
+            /* A header that isn't visible ASCII can't hold a token */
+            let token = token
+                .to_str()
+                .map_err(|_| VerificationError::InvalidToken)?;
 
-            match token {
+            match verify_token(token) {
                 Ok(user_token) => Ok(user_token.claims.id_user),
                 Err(_) => Err(VerificationError::InvalidToken),
             }
@@ -16,3 +22,48 @@ pub fn validate_token(authorization: Option<&HeaderValue>) -> Result<i32, Verifi
         None => Err(VerificationError::EmptyToken),
     }
 }
+
+/// Deserializes a field that can be missing, null or set, telling the first two apart. Use it
+/// together with `#[serde(default)]`.
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Seconds tracked in a task's history, counting a running segment up to `now`.
+pub fn tracked_seconds(history: &[TaskHistory], now: i64) -> i64 {
+    history
+        .iter()
+        .map(|segment| segment.finish_time.unwrap_or(now) - segment.start_time)
+        .sum()
+}
+
+pub fn task_status(history: &[TaskHistory]) -> TaskStatus {
+    if history.is_empty() {
+        TaskStatus::Pending
+    } else if history.iter().any(|segment| segment.finish_time.is_none()) {
+        TaskStatus::InProgress
+    } else {
+        TaskStatus::Finished
+    }
+}
+
+/// Trims, lowercases and deduplicates tag names, dropping the empty ones.
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().trim_start_matches('#').to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Uid for a task created without one.
+pub fn generate_task_uid() -> String {
+    format!("{}@surphury", generate_secret().to_lowercase())
+}
@@ -0,0 +1,322 @@
+use crate::filter::Filter;
+
+use crate::model::{NewTask, TaskUpdate, User, WebhookEvent};
+
+use serde::Serialize;
+
+use utoipa::ToSchema;
+
+/// Why a field of a request was rejected. `code` is stable, `message` is meant for people.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+const USERNAME_LENGTH: (usize, usize) = (3, 32);
+const PASSWORD_LENGTH: (usize, usize) = (8, 128);
+const EMAIL_MAX_LENGTH: usize = 254;
+
+/* Long passphrases are accepted without asking for a mix of characters */
+const PASSPHRASE_LENGTH: usize = 16;
+
+fn validate_username(username: &str, errors: &mut Vec<FieldError>) {
+    let length = username.chars().count();
+
+    if length < USERNAME_LENGTH.0 || length > USERNAME_LENGTH.1 {
+        errors.push(FieldError::new(
+            "username",
+            "length",
+            format!(
+                "The username must have between {} and {} characters",
+                USERNAME_LENGTH.0, USERNAME_LENGTH.1
+            ),
+        ));
+    }
+
+    let valid_characters = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    let starts_with_alphanumeric = username
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphanumeric());
+
+    if !valid_characters || !starts_with_alphanumeric {
+        errors.push(FieldError::new(
+            "username",
+            "charset",
+            "The username can only have letters, digits, '_', '-' and '.', and must start with a letter or digit",
+        ));
+    }
+}
+
+/// A deliberately loose check: one `@`, something before it and a domain with a dot after it.
+/// Whether the address exists can only be known by sending it an email.
+fn validate_email(email: &str, errors: &mut Vec<FieldError>) {
+    let is_valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains("..")
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    };
+
+    if email.len() > EMAIL_MAX_LENGTH {
+        errors.push(FieldError::new(
+            "email",
+            "length",
+            format!(
+                "The email can't be longer than {} characters",
+                EMAIL_MAX_LENGTH
+            ),
+        ));
+    } else if !is_valid {
+        errors.push(FieldError::new(
+            "email",
+            "format",
+            "The email isn't a valid address",
+        ));
+    }
+}
+
+fn validate_password(password: &str, username: &str, errors: &mut Vec<FieldError>) {
+    let length = password.chars().count();
+
+    if length < PASSWORD_LENGTH.0 || length > PASSWORD_LENGTH.1 {
+        errors.push(FieldError::new(
+            "password",
+            "length",
+            format!(
+                "The password must have between {} and {} characters",
+                PASSWORD_LENGTH.0, PASSWORD_LENGTH.1
+            ),
+        ));
+        return;
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|has_class| **has_class)
+    .count();
+
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        errors.push(FieldError::new(
+            "password",
+            "contains_username",
+            "The password can't contain the username",
+        ));
+    } else if length < PASSPHRASE_LENGTH && classes < 3 {
+        errors.push(FieldError::new(
+            "password",
+            "weak",
+            format!(
+                "The password must mix at least three of lowercase letters, uppercase letters, digits and symbols, or have {} characters or more",
+                PASSPHRASE_LENGTH
+            ),
+        ));
+    }
+}
+
+/// It checks every field of a new user, returning all the problems found at once.
+pub fn validate_user(user: &User) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    validate_username(&user.username, &mut errors);
+    validate_email(&user.email, &mut errors);
+    validate_password(&user.password, &user.username, &mut errors);
+
+    errors
+}
+
+const URL_MAX_LENGTH: usize = 2048;
+
+const LIST_NAME_MAX_LENGTH: usize = 255;
+
+const FILTER_MAX_LENGTH: usize = 1024;
+
+const COMMENT_MAX_LENGTH: usize = 10000;
+
+/// It checks the body of a comment, which can't be blank.
+pub fn validate_comment(body: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if body.trim().is_empty() || body.chars().count() > COMMENT_MAX_LENGTH {
+        errors.push(FieldError::new(
+            "body",
+            "length",
+            format!(
+                "The comment must have between 1 and {} characters",
+                COMMENT_MAX_LENGTH
+            ),
+        ));
+    }
+
+    errors
+}
+
+/// Estimates are stored as seconds in an INT column.
+const ESTIMATE_SECONDS: (i64, i64) = (0, i32::MAX as i64);
+
+fn validate_estimate(estimate: Option<i64>, errors: &mut Vec<FieldError>) {
+    if let Some(estimate) = estimate {
+        if estimate < ESTIMATE_SECONDS.0 || estimate > ESTIMATE_SECONDS.1 {
+            errors.push(FieldError::new(
+                "estimate",
+                "range",
+                format!(
+                    "The estimate must be between {} and {} seconds",
+                    ESTIMATE_SECONDS.0, ESTIMATE_SECONDS.1
+                ),
+            ));
+        }
+    }
+}
+
+/// It checks a task before it is created, by any of the ways of creating one.
+pub fn validate_task(task: &NewTask) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    validate_estimate(task.estimate, &mut errors);
+
+    errors
+}
+
+/// It checks the fields of a task update that are set, by any of the ways of changing a task.
+pub fn validate_task_update(update: &TaskUpdate) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    validate_estimate(update.estimate.flatten(), &mut errors);
+
+    errors
+}
+
+/// Work and break intervals last from a minute to a day.
+const FOCUS_MINUTES: (i32, i32) = (1, 24 * 60);
+
+/// It checks the lengths of the intervals of a focus session, in minutes.
+pub fn validate_focus_settings(work_minutes: i32, break_minutes: i32) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    for (field, minutes) in [
+        ("work_minutes", work_minutes),
+        ("break_minutes", break_minutes),
+    ] {
+        if minutes < FOCUS_MINUTES.0 || minutes > FOCUS_MINUTES.1 {
+            errors.push(FieldError::new(
+                field,
+                "range",
+                format!(
+                    "Intervals must last between {} and {} minutes",
+                    FOCUS_MINUTES.0, FOCUS_MINUTES.1
+                ),
+            ));
+        }
+    }
+
+    errors
+}
+
+/// It checks the fields of a saved list that are set. A saved filter can't use other lists, so
+/// using it never has to follow a chain of them.
+pub fn validate_smart_list(name: Option<&str>, filter: Option<&str>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if let Some(name) = name {
+        let length = name.trim().chars().count();
+        if length == 0 || length > LIST_NAME_MAX_LENGTH {
+            errors.push(FieldError::new(
+                "name",
+                "length",
+                format!(
+                    "The name must have between 1 and {} characters",
+                    LIST_NAME_MAX_LENGTH
+                ),
+            ));
+        }
+    }
+
+    if let Some(filter) = filter {
+        if filter.chars().count() > FILTER_MAX_LENGTH {
+            errors.push(FieldError::new(
+                "filter",
+                "length",
+                format!(
+                    "The filter can't have more than {} characters",
+                    FILTER_MAX_LENGTH
+                ),
+            ));
+        } else {
+            match Filter::parse(filter) {
+                Ok(filter) if !filter.lists().is_empty() => errors.push(FieldError::new(
+                    "filter",
+                    "nested_list",
+                    "A saved filter can't use other lists",
+                )),
+                Ok(_) => {}
+                Err(error) => errors.push(FieldError::new(
+                    "filter",
+                    "invalid_filter",
+                    error.to_string(),
+                )),
+            }
+        }
+    }
+
+    errors
+}
+
+/// It checks the fields of a webhook that are set, returning all the problems found at once.
+pub fn validate_webhook(url: Option<&str>, events: Option<&[WebhookEvent]>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if let Some(url) = url {
+        // Plain http is allowed, so webhooks can point at a stand-in on a development machine,
+        // where WEBHOOKS_ALLOW_PRIVATE_NETWORKS lets them reach it. Where the URL leads is
+        // checked apart, as it has to be resolved.
+        let host = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .and_then(|rest| rest.split(['/', '?', '#']).next())
+            .unwrap_or("");
+
+        if host.is_empty() || url.len() > URL_MAX_LENGTH || url.contains(char::is_whitespace) {
+            errors.push(FieldError::new(
+                "url",
+                "invalid_url",
+                "The URL must be an absolute http or https URL",
+            ));
+        }
+    }
+
+    if events.map_or(false, |events| events.is_empty()) {
+        errors.push(FieldError::new(
+            "events",
+            "empty",
+            "The webhook must subscribe to at least one event",
+        ));
+    }
+
+    errors
+}
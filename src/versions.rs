@@ -0,0 +1,49 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+
+use std::future::Future;
+
+/// Where the current version of the API is mounted. Every route is registered by
+/// `routes::configure`; a later version gets its own scope and table, reusing the handlers whose
+/// request and response shapes didn't change.
+pub const V1: &str = "/api/v1";
+
+/* When the unversioned paths were deprecated, as an RFC 9745 date */
+const LEGACY_DEPRECATED_AT: &str = "@1792368000";
+
+/* When the unversioned paths will stop working (RFC 8594) */
+const LEGACY_SUNSET: &str = "Mon, 19 Apr 2027 00:00:00 GMT";
+
+/// Middleware for the old unversioned paths, like `/tasks`, listed in `routes::configure_legacy`.
+/// They keep working as aliases of `/api/v1` but tell clients they are deprecated, when they go
+/// away and where to go instead.
+pub fn legacy_alias<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let successor = format!("<{}{}>; rel=\"successor-version\"", V1, req.path());
+    let response = srv.call(req);
+
+    async move {
+        let mut response = response.await?;
+        let headers = response.headers_mut();
+
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static(LEGACY_DEPRECATED_AT),
+        );
+        headers.insert(
+            HeaderName::from_static("sunset"),
+            HeaderValue::from_static(LEGACY_SUNSET),
+        );
+        if let Ok(link) = HeaderValue::from_str(&successor) {
+            headers.append(HeaderName::from_static("link"), link);
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,741 @@
+use crate::database::{
+    claim_webhook_delivery, enqueue_subscribed_deliveries, enqueue_webhook_delivery,
+    get_due_webhook_deliveries, get_task_by_user, record_webhook_failure, record_webhook_success,
+    take_webhook_events,
+};
+use crate::hashing::{generate_secret, hmac_sha256};
+use crate::model::{Db, DueDelivery, ResponseTask, WebhookEvent, WebhookPayload};
+
+use actix_web::cookie::time::OffsetDateTime;
+use actix_web::rt::{spawn, time::interval};
+use actix_web::web::Data;
+
+use futures_util::{stream, StreamExt};
+
+use hyper::client::connect::dns::Name;
+
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+
+use std::env::var;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::lookup_host;
+
+/// The event sent by /webhooks/{webhook_id}/ping.
+const PING: &str = "webhook.ping";
+
+/// How often the deliveries that are due are looked for.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How many deliveries are sent at most on every poll. They are sent at the same time, so a slow
+/// webhook only holds up its own deliveries.
+const BATCH: i64 = 20;
+
+/// How many task events are turned into deliveries at most in one transaction.
+const EVENT_BATCH: i64 = 100;
+
+/// How long a webhook has to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long other servers leave a delivery alone while it is sent. Longer than `TIMEOUT`.
+const LEASE_SECONDS: i64 = 60;
+
+/// Attempts before a delivery is given up. The retries are 30 seconds apart at first, doubling
+/// every time up to `MAX_RETRY_SECONDS`.
+const MAX_ATTEMPTS: i32 = 8;
+const FIRST_RETRY_SECONDS: i64 = 30;
+const MAX_RETRY_SECONDS: i64 = 6 * 60 * 60;
+
+/// Failed attempts in a row that disable a webhook, until its owner enables it again.
+const DISABLE_AFTER_FAILURES: i32 = 15;
+
+/// Why a URL can't be used. Webhook URLs come from users, who could otherwise make the server
+/// call the services next to it, like the metadata endpoint of cloud providers at 169.254.169.254.
+pub const FORBIDDEN_DESTINATION: &str =
+    "Webhooks can't be sent to this machine or to a private network";
+
+/// Whether webhooks may be sent to this machine and its private network, which is only meant for
+/// development machines: WEBHOOKS_ALLOW_PRIVATE_NETWORKS is "true".
+pub fn private_networks_allowed() -> bool {
+    var("WEBHOOKS_ALLOW_PRIVATE_NETWORKS").as_deref() == Ok("true")
+}
+
+/// Whether an address is this machine, a private or link-local network, or isn't one a public
+/// server can have.
+pub fn is_forbidden_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                /* Shared address space of carriers, 100.64.0.0/10 */
+                || (first == 100 && second & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_forbidden_address(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    /* Unique local, fc00::/7, and link-local, fe80::/10 */
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/* Whether the host of a URL is forbidden as it is written, without resolving it */
+fn is_forbidden_host(url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return true,
+    };
+
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_forbidden_address(ip),
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host == "localhost" || host.ends_with(".localhost")
+        }
+    }
+}
+
+/// It checks that a webhook URL leads outside of this machine and its private network, resolving
+/// its host. Returns why it doesn't.
+pub async fn check_destination(url: &str) -> Result<(), &'static str> {
+    let url = Url::parse(url).map_err(|_| "The URL must be an absolute http or https URL")?;
+    if is_forbidden_host(&url) {
+        return Err(FORBIDDEN_DESTINATION);
+    }
+
+    let host = url.host_str().unwrap_or("");
+    if host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+        return Ok(());
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses = lookup_host((host, port))
+        .await
+        .map_err(|_| "The host of the URL can't be resolved")?;
+    for address in addresses {
+        if is_forbidden_address(address.ip()) {
+            return Err(FORBIDDEN_DESTINATION);
+        }
+    }
+
+    Ok(())
+}
+
+/* The resolver of the webhook client. It leaves out forbidden addresses every time a host is
+resolved, so a host that was public when the webhook was registered can't be pointed at the
+private network later. */
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| !is_forbidden_address(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(FORBIDDEN_DESTINATION.into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The client deliveries are sent with. Redirects aren't followed, as they could lead anywhere.
+pub fn webhook_client(allow_private: bool) -> Client {
+    let mut builder = Client::builder().timeout(TIMEOUT).redirect(Policy::none());
+    if !allow_private {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+
+    builder.build().expect("Could not build the webhook client")
+}
+
+/// Seconds until the next attempt of a delivery that has been attempted `attempts` times, or None
+/// when it should be given up.
+pub fn retry_delay(attempts: i32) -> Option<i64> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    let doublings = (attempts - 1).clamp(0, 20) as u32;
+    Some((FIRST_RETRY_SECONDS << doublings).min(MAX_RETRY_SECONDS))
+}
+
+/// The `X-Surphury-Signature` header: when the request was signed and the HMAC-SHA256 of
+/// `{timestamp}.{body}` with the secret of the webhook. Receivers should recompute it, and refuse
+/// old timestamps so a captured request can't be replayed.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let signed = format!("{}.{}", timestamp, body);
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hmac_sha256(secret.as_bytes(), signed.as_bytes())
+    )
+}
+
+/// The body of a delivery, identified so receivers can tell retries and replays apart from new
+/// events.
+fn payload(
+    event: &str,
+    created_at: OffsetDateTime,
+    task_id: Option<i32>,
+    task: Option<ResponseTask>,
+) -> String {
+    serde_json::to_string(&WebhookPayload {
+        id: format!("evt_{}", generate_secret()),
+        event: event.to_string(),
+        created_at: created_at.unix_timestamp(),
+        task_id,
+        task,
+    })
+    .unwrap()
+}
+
+/// It queues the deliveries of the task events saved with the changes, for every webhook of their
+/// user that subscribes to them. Returns how many events were taken.
+pub async fn dispatch_events(db: &Data<Db>) -> Result<usize, sqlx::Error> {
+    let mut tx = db.pool.begin().await?;
+    let events = take_webhook_events(EVENT_BATCH, &mut tx).await?;
+
+    for event in &events {
+        let task = match event.task_id {
+            Some(task_id) if event.event != WebhookEvent::TaskDeleted.as_str() => {
+                get_task_by_user(task_id, event.user_id, db).await.ok()
+            }
+            _ => None,
+        };
+        let payload = payload(&event.event, event.created_at, event.task_id, task);
+        enqueue_subscribed_deliveries(event.user_id, &event.event, &payload, &mut tx).await?;
+    }
+    tx.commit().await?;
+
+    Ok(events.len())
+}
+
+/// It queues a `webhook.ping` for a webhook, whatever it subscribes to, so its owner can check the
+/// endpoint and the signature. Returns the id of the delivery.
+pub async fn enqueue_ping(webhook_id: i32, db: &Data<Db>) -> Result<i64, sqlx::Error> {
+    let payload = payload(PING, OffsetDateTime::now_utc(), None, None);
+    enqueue_webhook_delivery(webhook_id, PING, &payload, db).await
+}
+
+/// It spawns the background task that turns the task events saved with the changes into webhook
+/// deliveries. Events wait in the database until then, so none is lost when the server is busy or
+/// restarts.
+pub fn spawn_webhook_dispatcher(db: Data<Db>) {
+    spawn(async move {
+        let mut ticker = interval(POLL);
+        loop {
+            ticker.tick().await;
+            /* A full batch means there may be more waiting */
+            loop {
+                match dispatch_events(&db).await {
+                    Ok(taken) if taken as i64 == EVENT_BATCH => continue,
+                    Ok(_) => break,
+                    Err(err) => {
+                        println!("{:#?}", err);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// It spawns the background task that sends the deliveries that are due, retrying the ones that
+/// fail.
+pub fn spawn_webhook_worker(db: Data<Db>) {
+    let allow_private = private_networks_allowed();
+    let client = webhook_client(allow_private);
+
+    spawn(async move {
+        let mut ticker = interval(POLL);
+        loop {
+            ticker.tick().await;
+            match get_due_webhook_deliveries(BATCH, &db).await {
+                Ok(deliveries) => {
+                    stream::iter(&deliveries)
+                        .map(|delivery| deliver(&client, delivery, allow_private, &db))
+                        .buffer_unordered(BATCH as usize)
+                        .for_each(|result| async move {
+                            if let Err(err) = result {
+                                println!("{:#?}", err);
+                            }
+                        })
+                        .await;
+                }
+                Err(err) => println!("{:#?}", err),
+            }
+        }
+    });
+}
+
+async fn deliver(
+    client: &Client,
+    delivery: &DueDelivery,
+    allow_private: bool,
+    db: &Data<Db>,
+) -> Result<(), sqlx::Error> {
+    if !claim_webhook_delivery(delivery, LEASE_SECONDS, db).await? {
+        return Ok(());
+    }
+
+    /* Names are checked by the resolver of the client, addresses have to be checked here */
+    let forbidden =
+        !allow_private && Url::parse(&delivery.url).map_or(true, |url| is_forbidden_host(&url));
+    if forbidden {
+        return record_webhook_failure(
+            delivery,
+            None,
+            FORBIDDEN_DESTINATION,
+            retry_delay(delivery.attempts + 1),
+            DISABLE_AFTER_FAILURES,
+            db,
+        )
+        .await;
+    }
+
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    let response = client
+        .post(&delivery.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("User-Agent", "Surphury-Webhooks/1")
+        .header("X-Surphury-Event", &delivery.event)
+        .header("X-Surphury-Delivery", delivery.id.to_string())
+        .header(
+            "X-Surphury-Signature",
+            signature(&delivery.secret, timestamp, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+
+    let retry_in = retry_delay(delivery.attempts + 1);
+    match response {
+        Ok(response) if response.status().is_success() => {
+            record_webhook_success(delivery, response.status().as_u16() as i32, db).await
+        }
+        Ok(response) => {
+            let status = response.status();
+            record_webhook_failure(
+                delivery,
+                Some(status.as_u16() as i32),
+                &format!("The webhook answered {}", status),
+                retry_in,
+                DISABLE_AFTER_FAILURES,
+                db,
+            )
+            .await
+        }
+        Err(error) => {
+            record_webhook_failure(
+                delivery,
+                None,
+                &error.to_string(),
+                retry_in,
+                DISABLE_AFTER_FAILURES,
+                db,
+            )
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{
+        add_webhook, connect, get_webhook, get_webhook_deliveries, insert_new_user,
+    };
+    use crate::model::{NewWebhook, User};
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+    use dotenv::dotenv;
+
+    use rand::distributions::{Alphanumeric, DistString};
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    #[test]
+    fn signs_the_timestamp_and_the_body() {
+        let body = r#"{"id":"evt_1","type":"task.created"}"#;
+        let header = signature("whsec", 1760000000, body);
+
+        let (timestamp, mac) = header
+            .strip_prefix("t=")
+            .and_then(|header| header.split_once(",v1="))
+            .expect("the signature should be t=...,v1=...");
+        assert_eq!(timestamp, "1760000000");
+        assert_eq!(mac.len(), 64);
+        assert!(mac.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            mac,
+            hmac_sha256(b"whsec", format!("1760000000.{}", body).as_bytes())
+        );
+
+        assert_ne!(header, signature("other", 1760000000, body));
+        assert_ne!(header, signature("whsec", 1760000001, body));
+        assert_ne!(header, signature("whsec", 1760000000, "{}"));
+    }
+
+    #[test]
+    fn backs_off_exponentially_and_gives_up() {
+        let delays: Vec<Option<i64>> = (1..=MAX_ATTEMPTS).map(retry_delay).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(30),
+                Some(60),
+                Some(120),
+                Some(240),
+                Some(480),
+                Some(960),
+                Some(1920),
+                None
+            ]
+        );
+        assert_eq!(retry_delay(0), Some(FIRST_RETRY_SECONDS));
+        assert_eq!(retry_delay(MAX_ATTEMPTS + 1), None);
+    }
+
+    #[test]
+    fn forbids_local_and_private_addresses() {
+        for address in [
+            "127.0.0.1",
+            "127.1.2.3",
+            "10.0.0.1",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::1",
+            "::",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(
+                is_forbidden_address(address.parse().unwrap()),
+                "{} should be forbidden",
+                address
+            );
+        }
+
+        for address in [
+            "93.184.216.34",
+            "172.32.0.1",
+            "100.128.0.1",
+            "2606:2800:220:1::1",
+        ] {
+            assert!(
+                !is_forbidden_address(address.parse().unwrap()),
+                "{} should be allowed",
+                address
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn refuses_local_destinations_without_resolving_them() {
+        for url in [
+            "http://localhost/hook",
+            "http://LOCALHOST./hook",
+            "http://api.localhost:8080/hook",
+            "http://127.0.0.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.1.2.3/hook",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.0.1]/hook",
+        ] {
+            assert_eq!(
+                check_destination(url).await,
+                Err(FORBIDDEN_DESTINATION),
+                "{}",
+                url
+            );
+        }
+
+        assert!(check_destination("http://93.184.216.34/hook").await.is_ok());
+        assert!(check_destination("not a url").await.is_err());
+    }
+
+    /* A receiver for deliveries on this machine, answering with the given statuses in turn and
+    then 200, and keeping what it got */
+    struct Receiver {
+        statuses: Mutex<VecDeque<u16>>,
+        received: Mutex<Vec<(Option<String>, String)>>,
+    }
+
+    async fn receive(req: HttpRequest, body: String, receiver: Data<Receiver>) -> HttpResponse {
+        let signature = req
+            .headers()
+            .get("X-Surphury-Signature")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        receiver.received.lock().unwrap().push((signature, body));
+
+        let status = receiver.statuses.lock().unwrap().pop_front().unwrap_or(200);
+        HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap()).finish()
+    }
+
+    fn start_receiver(statuses: &[u16]) -> (String, Data<Receiver>) {
+        let receiver = Data::new(Receiver {
+            statuses: Mutex::new(statuses.iter().copied().collect()),
+            received: Mutex::new(Vec::new()),
+        });
+
+        let state = receiver.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(state.clone())
+                .default_service(web::to(receive))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("the receiver should bind a port");
+        let address = server.addrs()[0];
+        spawn(server.run());
+
+        (format!("http://{}/hook", address), receiver)
+    }
+
+    async fn setup(url: &str) -> (Data<Db>, i32, i32) {
+        dotenv().ok();
+        let database_url =
+            var("DATABASE_URL").expect("DATABASE_URL must be set as a environment variable");
+        let pool = connect(&database_url)
+            .await
+            .expect("Could not connect to database");
+        let db = Data::new(Db { pool });
+
+        let username = format!(
+            "webhooks-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 12)
+        );
+        let user_id = insert_new_user(
+            User {
+                email: format!("{}@example.com", username),
+                username,
+                password: String::from("correct horse battery staple"),
+            },
+            &db,
+        )
+        .await
+        .expect("the user should be added")
+        .last_insert_id() as i32;
+
+        let webhook = add_webhook(
+            user_id,
+            NewWebhook {
+                url: url.to_string(),
+                events: vec![WebhookEvent::TaskCreated],
+            },
+            &db,
+        )
+        .await
+        .expect("the webhook should be added");
+
+        (db, user_id, webhook.id)
+    }
+
+    /* The delivery as the worker would read it once it is due */
+    async fn due(
+        delivery_id: i64,
+        payload: &str,
+        webhook_id: i32,
+        user_id: i32,
+        db: &Data<Db>,
+    ) -> DueDelivery {
+        let webhook = get_webhook(webhook_id, user_id, db)
+            .await
+            .unwrap()
+            .expect("the webhook should exist");
+        let delivery = get_webhook_deliveries(webhook_id, user_id, db)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|delivery| delivery.id == delivery_id)
+            .expect("the delivery should exist");
+
+        DueDelivery {
+            id: delivery.id,
+            webhook_id,
+            url: webhook.url,
+            secret: webhook.secret,
+            event: delivery.event,
+            payload: payload.to_string(),
+            attempts: delivery.attempts,
+            next_attempt_at: delivery.next_attempt_at,
+        }
+    }
+
+    #[actix_web::test]
+    async fn retries_a_delivery_that_got_a_server_error() {
+        let (url, receiver) = start_receiver(&[500]);
+        let (db, user_id, webhook_id) = setup(&url).await;
+        let client = webhook_client(true);
+
+        let payload = r#"{"type":"webhook.ping"}"#;
+        let delivery_id = enqueue_webhook_delivery(webhook_id, PING, payload, &db)
+            .await
+            .unwrap();
+
+        let delivery = due(delivery_id, payload, webhook_id, user_id, &db).await;
+        deliver(&client, &delivery, true, &db).await.unwrap();
+
+        let failed = due(delivery_id, payload, webhook_id, user_id, &db).await;
+        let log = get_webhook_deliveries(webhook_id, user_id, &db)
+            .await
+            .unwrap();
+        assert_eq!(log[0].status, "pending");
+        assert_eq!(log[0].attempts, 1);
+        assert_eq!(log[0].last_status_code, Some(500));
+        let wait = failed.next_attempt_at - OffsetDateTime::now_utc();
+        assert!(wait.whole_seconds() > FIRST_RETRY_SECONDS - 10);
+        assert!(wait.whole_seconds() <= FIRST_RETRY_SECONDS);
+        let webhook = get_webhook(webhook_id, user_id, &db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(webhook.consecutive_failures, 1);
+        assert!(webhook.enabled);
+
+        /* The retry is sent once it is due, and the receiver takes it this time */
+        deliver(&client, &failed, true, &db).await.unwrap();
+
+        let log = get_webhook_deliveries(webhook_id, user_id, &db)
+            .await
+            .unwrap();
+        assert_eq!(log[0].status, "delivered");
+        assert_eq!(log[0].attempts, 2);
+        assert_eq!(log[0].last_status_code, Some(200));
+        assert!(log[0].delivered_at.is_some());
+        let webhook = get_webhook(webhook_id, user_id, &db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(webhook.consecutive_failures, 0);
+
+        /* Both attempts carried the same payload, signed with the secret of the webhook */
+        let received = receiver.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        for (signature, body) in received {
+            assert_eq!(body, payload);
+            let signature = signature.expect("deliveries should be signed");
+            let timestamp: i64 = signature
+                .strip_prefix("t=")
+                .and_then(|signature| signature.split(',').next())
+                .and_then(|timestamp| timestamp.parse().ok())
+                .unwrap();
+            assert_eq!(
+                signature,
+                super::signature(&webhook.secret, timestamp, payload)
+            );
+        }
+
+        /* An attempt that was claimed already isn't sent twice */
+        deliver(&client, &failed, true, &db).await.unwrap();
+        assert_eq!(receiver.received.lock().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn disables_a_webhook_that_keeps_failing() {
+        let (url, receiver) = start_receiver(&[500; DISABLE_AFTER_FAILURES as usize]);
+        let (db, user_id, webhook_id) = setup(&url).await;
+        let client = webhook_client(true);
+        let payload = "{}";
+
+        for sent in 1..=DISABLE_AFTER_FAILURES {
+            let delivery_id = enqueue_webhook_delivery(webhook_id, PING, payload, &db)
+                .await
+                .unwrap();
+            let delivery = due(delivery_id, payload, webhook_id, user_id, &db).await;
+            deliver(&client, &delivery, true, &db).await.unwrap();
+
+            let webhook = get_webhook(webhook_id, user_id, &db)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(webhook.consecutive_failures, sent);
+            assert_eq!(webhook.enabled, sent < DISABLE_AFTER_FAILURES);
+            assert_eq!(
+                webhook.disabled_at.is_some(),
+                sent == DISABLE_AFTER_FAILURES
+            );
+        }
+        assert_eq!(
+            receiver.received.lock().unwrap().len(),
+            DISABLE_AFTER_FAILURES as usize
+        );
+    }
+
+    #[actix_web::test]
+    async fn gives_up_after_the_last_attempt() {
+        let (url, _) = start_receiver(&[500]);
+        let (db, user_id, webhook_id) = setup(&url).await;
+        let client = webhook_client(true);
+
+        let payload = "{}";
+        let delivery_id = enqueue_webhook_delivery(webhook_id, PING, payload, &db)
+            .await
+            .unwrap();
+        let mut delivery = due(delivery_id, payload, webhook_id, user_id, &db).await;
+        delivery.attempts = MAX_ATTEMPTS - 1;
+        deliver(&client, &delivery, true, &db).await.unwrap();
+
+        let log = get_webhook_deliveries(webhook_id, user_id, &db)
+            .await
+            .unwrap();
+        assert_eq!(log[0].status, "failed");
+    }
+
+    #[actix_web::test]
+    async fn does_not_send_to_local_addresses() {
+        let (url, receiver) = start_receiver(&[]);
+        let (db, user_id, webhook_id) = setup(&url).await;
+
+        let payload = "{}";
+        let delivery_id = enqueue_webhook_delivery(webhook_id, PING, payload, &db)
+            .await
+            .unwrap();
+        let delivery = due(delivery_id, payload, webhook_id, user_id, &db).await;
+        deliver(&webhook_client(false), &delivery, false, &db)
+            .await
+            .unwrap();
+
+        assert!(receiver.received.lock().unwrap().is_empty());
+        let log = get_webhook_deliveries(webhook_id, user_id, &db)
+            .await
+            .unwrap();
+        assert_eq!(log[0].status, "pending");
+        assert_eq!(log[0].last_error.as_deref(), Some(FORBIDDEN_DESTINATION));
+    }
+}